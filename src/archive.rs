@@ -0,0 +1,708 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Extension, Query, State},
+    http::{header, StatusCode},
+    response::{Json, Response},
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+use crate::buckets::{BucketName, DEFAULT_BUCKET};
+use crate::config::Config;
+use crate::expiry::is_expired;
+use crate::models::{ArchiveLinkRequest, ArchiveLinkResponse, ArchiveRequest, ErrorResponse};
+use crate::sigv4::signatures_match;
+use crate::state::AppState;
+use crate::storage::Storage;
+use crate::utils::{content_disposition, safe_key};
+
+/// path of signed archive downloads on the public server (dot paths are never files)
+pub const ARCHIVE_LINK_PATH: &str = "/.archive";
+/// how long archive links stay valid unless asked otherwise (seconds)
+const DEFAULT_LINK_TTL_SECS: u64 = 60 * 60;
+/// longest an archive link can be valid (seconds)
+const MAX_LINK_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// formats archives can be downloaded in
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// limits for archive downloads and the key signing links to them
+#[derive(Clone, Debug)]
+pub struct ArchiveSettings {
+    /// most files one archive may hold
+    pub max_files: usize,
+    /// hmac key for links on the public server (None = links are disabled)
+    pub link_key: Option<Arc<Vec<u8>>>,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            max_files: 10_000,
+            link_key: None,
+        }
+    }
+}
+
+impl ArchiveSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_files: config.archive_max_files,
+            link_key: config
+                .archive_link_secret
+                .as_ref()
+                .map(|secret| Arc::new(secret.as_bytes().to_vec())),
+        }
+    }
+}
+
+/// a file going into an archive
+#[derive(Clone, Debug)]
+struct ArchiveEntry {
+    /// storage key
+    key: String,
+    /// path inside the archive
+    name: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+// what a signed link points at, serialized into the link itself
+#[derive(Deserialize, Serialize, Debug)]
+struct ArchiveLink {
+    bucket: String,
+    #[serde(flatten)]
+    archive: ArchiveRequest,
+    /// unix seconds
+    expires: i64,
+}
+
+/// query of a signed archive download
+#[derive(Deserialize, Debug)]
+pub struct ArchiveLinkQuery {
+    pub token: String,
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<ErrorResponse>) {
+    (status, Json(ErrorResponse { error: message.into() }))
+}
+
+// download files or a directory as one archive, streamed while it's being built
+pub async fn download_archive(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ArchiveRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let (entries, default_name) = collect_entries(&state, &request, false).await?;
+    tracing::info!("📦 Building {} archive of {} files", request.format.extension(), entries.len());
+    Ok(archive_response(state.storage.clone(), entries, request.format, request.name.as_deref(), &default_name))
+}
+
+// sign a link that downloads the archive from the public server without an api key
+pub async fn create_archive_link(
+    State(state): State<Arc<AppState>>,
+    Extension(BucketName(bucket)): Extension<BucketName>,
+    Json(request): Json<ArchiveLinkRequest>,
+) -> Result<Json<ArchiveLinkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(key) = &state.archives.link_key else {
+        return Err(error(StatusCode::NOT_FOUND, "Archive links are disabled (set ARCHIVE_LINK_SECRET)"));
+    };
+    let ttl = request.expires_in.unwrap_or(DEFAULT_LINK_TTL_SECS);
+    if ttl == 0 || ttl > MAX_LINK_TTL_SECS {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            format!("expires_in must be between 1 and {} seconds", MAX_LINK_TTL_SECS),
+        ));
+    }
+
+    // check now that there is something to download, the link resolves the files again when used
+    let (entries, _) = collect_entries(&state, &request.archive, true).await?;
+    let expires_at = Utc::now() + chrono::Duration::seconds(ttl as i64);
+    let link = ArchiveLink {
+        bucket,
+        archive: request.archive,
+        expires: expires_at.timestamp(),
+    };
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&link).expect("archive links serialize"));
+    let signature = sign(key, &payload);
+
+    tracing::info!("🔗 Signed archive link for {} files in bucket {}", entries.len(), link.bucket);
+    Ok(Json(ArchiveLinkResponse {
+        url: format!("{}?token={}.{}", ARCHIVE_LINK_PATH, payload, signature),
+        expires_at: expires_at.to_rfc3339(),
+        files: entries.len(),
+    }))
+}
+
+/// serve a signed archive link on the public server
+pub async fn download_signed_archive(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ArchiveLinkQuery>,
+) -> Result<Response, StatusCode> {
+    let key = state.archives.link_key.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let (payload, signature) = query.token.split_once('.').ok_or(StatusCode::FORBIDDEN)?;
+    if !signatures_match(&sign(key, payload), signature) {
+        tracing::warn!("🚫 Refusing archive link with a bad signature");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let link: ArchiveLink = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or(StatusCode::FORBIDDEN)?;
+    if link.expires <= Utc::now().timestamp() {
+        tracing::debug!("Archive link expired");
+        return Err(StatusCode::GONE);
+    }
+
+    let bucket_state = if link.bucket == DEFAULT_BUCKET {
+        state.clone()
+    } else {
+        state.buckets.get(&link.bucket).ok_or(StatusCode::NOT_FOUND)?.state.clone()
+    };
+    let (entries, default_name) = collect_entries(&bucket_state, &link.archive, true)
+        .await
+        .map_err(|(status, _)| status)?;
+    tracing::info!("📦 Serving archive link with {} files from bucket {}", entries.len(), link.bucket);
+    Ok(archive_response(
+        bucket_state.storage.clone(),
+        entries,
+        link.archive.format,
+        link.archive.name.as_deref(),
+        &default_name,
+    ))
+}
+
+fn sign(key: &[u8], payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// resolve a request to the files going into the archive, plus a name for the download
+//
+// hidden and expired files are left out; public links also leave out files with a download
+// limit so a link can't be used to get around it
+async fn collect_entries(
+    state: &AppState,
+    request: &ArchiveRequest,
+    public: bool,
+) -> Result<(Vec<ArchiveEntry>, String), (StatusCode, Json<ErrorResponse>)> {
+    let storage = state.storage.as_ref();
+    let mut entries = Vec::new();
+
+    let default_name = match (&request.dir, request.files.is_empty()) {
+        (Some(_), false) => return Err(error(StatusCode::BAD_REQUEST, "Give either files or dir, not both")),
+        (None, true) => return Err(error(StatusCode::BAD_REQUEST, "Nothing to archive: give files or dir")),
+        (None, false) => {
+            let mut seen = BTreeSet::new();
+            for filename in &request.files {
+                let key = safe_key(filename)
+                    .filter(|k| !k.is_empty())
+                    .ok_or_else(|| error(StatusCode::BAD_REQUEST, format!("Invalid filename: {}", filename)))?;
+                if !seen.insert(key.clone()) {
+                    continue;
+                }
+                let meta = storage.stat(&key).await.map_err(|e| match e.kind() {
+                    io::ErrorKind::NotFound => error(StatusCode::NOT_FOUND, format!("File not found: {}", key)),
+                    _ => {
+                        tracing::error!("Failed to stat {} for an archive: {}", key, e);
+                        error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read {}: {}", key, e))
+                    }
+                })?;
+                entries.push(ArchiveEntry {
+                    name: key.clone(),
+                    key,
+                    size: meta.size,
+                    modified: meta.modified,
+                });
+            }
+            "files".to_string()
+        }
+        (Some(dir), true) => {
+            let dir = safe_key(dir).ok_or_else(|| error(StatusCode::BAD_REQUEST, format!("Invalid directory: {}", dir)))?;
+            // everything lands in a folder named after the directory (or the bucket)
+            let folder = match dir.rsplit_once('/') {
+                Some((_, last)) => last.to_string(),
+                None if dir.is_empty() => state
+                    .files_dir
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "files".to_string()),
+                None => dir.clone(),
+            };
+            let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+            let listing = storage.list(&prefix, true).await.map_err(|e| {
+                tracing::error!("Failed to list {} for an archive: {}", prefix, e);
+                error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read directory: {}", e))
+            })?;
+            if listing.objects.is_empty() {
+                return Err(error(StatusCode::NOT_FOUND, format!("Directory not found or empty: {}", dir)));
+            }
+            for object in listing.objects {
+                let relative = &object.key[prefix.len()..];
+                entries.push(ArchiveEntry {
+                    name: format!("{}/{}", folder, relative),
+                    key: object.key,
+                    size: object.size,
+                    modified: object.modified,
+                });
+            }
+            folder
+        }
+    };
+
+    entries.retain(|entry| {
+        if entry.key.split('/').any(|segment| segment.starts_with('.')) {
+            return false;
+        }
+        let meta = state.metadata.get(&entry.key);
+        let limited = public && meta.max_downloads.is_some();
+        !is_expired(&meta) && !limited
+    });
+    if entries.is_empty() {
+        return Err(error(StatusCode::NOT_FOUND, "None of the files can be archived"));
+    }
+    if entries.len() > state.archives.max_files {
+        return Err(error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Archive would hold {} files, the limit is {}", entries.len(), state.archives.max_files),
+        ));
+    }
+    Ok((entries, default_name))
+}
+
+// the streaming response, with a length up front for the uncompressed formats
+fn archive_response(
+    storage: Arc<dyn Storage>,
+    entries: Vec<ArchiveEntry>,
+    format: ArchiveFormat,
+    name: Option<&str>,
+    default_name: &str,
+) -> Response {
+    let length = match format {
+        ArchiveFormat::Zip => Some(zip_length(&entries)),
+        ArchiveFormat::Tar => Some(tar_length(&entries)),
+        ArchiveFormat::TarGz => None,
+    };
+
+    // the archive is produced by a task feeding the response body, which stops when the client leaves
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(8);
+    tokio::spawn(async move {
+        let gzip = (format == ArchiveFormat::TarGz).then(|| GzEncoder::new(Vec::new(), Compression::default()));
+        let mut writer = ArchiveWriter { tx, gzip, written: 0 };
+        let result = match format {
+            ArchiveFormat::Zip => write_zip(storage.as_ref(), &entries, &mut writer).await,
+            ArchiveFormat::Tar | ArchiveFormat::TarGz => write_tar(storage.as_ref(), &entries, &mut writer).await,
+        };
+        let result = match result {
+            Ok(()) => writer.finish().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => tracing::debug!("Archive of {} files done", entries.len()),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => tracing::debug!("Archive download cancelled"),
+            Err(e) => {
+                // the client sees a truncated download rather than a corrupt archive
+                tracing::error!("Failed to build archive: {}", e);
+                let _ = writer.tx.send(Err(e)).await;
+            }
+        }
+    });
+    let body = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+
+    let filename = format!("{}.{}", download_name(name, default_name, format), format.extension());
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CONTENT_DISPOSITION, content_disposition(&filename))
+        .header(header::CACHE_CONTROL, "no-store");
+    if let Some(length) = length {
+        builder = builder.header(header::CONTENT_LENGTH, length);
+    }
+    builder.body(Body::from_stream(body)).unwrap()
+}
+
+// the filename a download is saved as, without the extension
+fn download_name(name: Option<&str>, default_name: &str, format: ArchiveFormat) -> String {
+    let name = name
+        .map(|n| n.rsplit(['/', '\\']).next().unwrap_or_default())
+        .map(|n| n.trim_end_matches(&format!(".{}", format.extension())))
+        .map(|n| n.chars().filter(|c| !c.is_control() && *c != '"').collect::<String>())
+        .map(|n| n.trim().trim_start_matches('.').to_string())
+        .filter(|n| !n.is_empty());
+    name.unwrap_or_else(|| default_name.to_string())
+}
+
+// sends archive bytes to the response body, gzipping them on the way if asked to
+struct ArchiveWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+    /// uncompressed bytes written so far (offsets inside the archive)
+    written: u64,
+}
+
+impl ArchiveWriter {
+    async fn write(&mut self, data: Bytes) -> io::Result<()> {
+        self.written += data.len() as u64;
+        let data = match &mut self.gzip {
+            Some(encoder) => {
+                encoder.write_all(&data)?;
+                let compressed = std::mem::take(encoder.get_mut());
+                if compressed.is_empty() {
+                    return Ok(());
+                }
+                Bytes::from(compressed)
+            }
+            None => data,
+        };
+        self.send(data).await
+    }
+
+    async fn send(&self, data: Bytes) -> io::Result<()> {
+        self.tx
+            .send(Ok(data))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.gzip.take() {
+            let rest = encoder.finish()?;
+            if !rest.is_empty() {
+                self.send(Bytes::from(rest)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // stream one file's content, returning its crc32
+    async fn copy_file(&mut self, storage: &dyn Storage, entry: &ArchiveEntry) -> io::Result<u32> {
+        let mut body = storage.get(&entry.key, None).await?.body;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut copied = 0u64;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            copied += chunk.len() as u64;
+            self.write(chunk).await?;
+        }
+        if copied != entry.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} changed size while being archived", entry.key),
+            ));
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// zip: entries are stored as-is with a data descriptor after each (the crc is only known once
+// the file went by), switching to zip64 fields for anything past 4GB or 65535 entries
+// ---------------------------------------------------------------------------
+
+const ZIP32_LIMIT: u64 = 0xFFFF_FFFF;
+/// utf-8 names and a data descriptor after the data
+const ZIP_FLAGS: u16 = 0x0800 | 0x0008;
+const ZIP_VERSION: u16 = 20;
+const ZIP64_VERSION: u16 = 45;
+/// made by unix, so the permissions in the external attributes count
+const ZIP_MADE_BY: u16 = (3 << 8) | ZIP64_VERSION;
+
+fn needs_zip64(entry: &ArchiveEntry) -> bool {
+    entry.size >= ZIP32_LIMIT
+}
+
+// modification time in ms-dos format (local time fields, 2 second resolution, 1980 at the earliest)
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    let time: DateTime<Utc> = modified.map(DateTime::from).unwrap_or_else(Utc::now);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let date = (((time.year() - 1980) as u16) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    let clock = ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    (clock, date)
+}
+
+fn local_header(entry: &ArchiveEntry) -> Vec<u8> {
+    let zip64 = needs_zip64(entry);
+    let (time, date) = dos_time(entry.modified);
+    let size32 = if zip64 { ZIP32_LIMIT as u32 } else { entry.size as u32 };
+    let extra_len: u16 = if zip64 { 20 } else { 0 };
+
+    let mut header = Vec::with_capacity(30 + entry.name.len() + extra_len as usize);
+    header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+    header.extend_from_slice(&(if zip64 { ZIP64_VERSION } else { ZIP_VERSION }).to_le_bytes());
+    header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // stored
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // crc, in the descriptor
+    header.extend_from_slice(&size32.to_le_bytes());
+    header.extend_from_slice(&size32.to_le_bytes());
+    header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&extra_len.to_le_bytes());
+    header.extend_from_slice(entry.name.as_bytes());
+    if zip64 {
+        header.extend_from_slice(&0x0001u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes());
+    }
+    header
+}
+
+fn data_descriptor(entry: &ArchiveEntry, crc: u32) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(24);
+    descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+    descriptor.extend_from_slice(&crc.to_le_bytes());
+    if needs_zip64(entry) {
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+    } else {
+        descriptor.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        descriptor.extend_from_slice(&(entry.size as u32).to_le_bytes());
+    }
+    descriptor
+}
+
+fn central_header(entry: &ArchiveEntry, crc: u32, offset: u64) -> Vec<u8> {
+    let zip64_size = needs_zip64(entry);
+    let zip64_offset = offset >= ZIP32_LIMIT;
+    let mut extra = Vec::new();
+    if zip64_size {
+        extra.extend_from_slice(&entry.size.to_le_bytes());
+        extra.extend_from_slice(&entry.size.to_le_bytes());
+    }
+    if zip64_offset {
+        extra.extend_from_slice(&offset.to_le_bytes());
+    }
+    if !extra.is_empty() {
+        let mut field = Vec::with_capacity(4 + extra.len());
+        field.extend_from_slice(&0x0001u16.to_le_bytes());
+        field.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        field.extend_from_slice(&extra);
+        extra = field;
+    }
+    let (time, date) = dos_time(entry.modified);
+    let size32 = if zip64_size { ZIP32_LIMIT as u32 } else { entry.size as u32 };
+    let offset32 = if zip64_offset { ZIP32_LIMIT as u32 } else { offset as u32 };
+    let version = if zip64_size || zip64_offset { ZIP64_VERSION } else { ZIP_VERSION };
+
+    let mut header = Vec::with_capacity(46 + entry.name.len() + extra.len());
+    header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    header.extend_from_slice(&ZIP_MADE_BY.to_le_bytes());
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&crc.to_le_bytes());
+    header.extend_from_slice(&size32.to_le_bytes());
+    header.extend_from_slice(&size32.to_le_bytes());
+    header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // comment
+    header.extend_from_slice(&0u16.to_le_bytes()); // disk
+    header.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+    header.extend_from_slice(&(0o100644u32 << 16).to_le_bytes());
+    header.extend_from_slice(&offset32.to_le_bytes());
+    header.extend_from_slice(entry.name.as_bytes());
+    header.extend_from_slice(&extra);
+    header
+}
+
+// end of central directory, preceded by the zip64 record and locator when anything overflows
+fn end_of_central_directory(entries: u64, cd_offset: u64, cd_size: u64) -> Vec<u8> {
+    let mut end = Vec::new();
+    let zip64 = entries >= 0xFFFF || cd_offset >= ZIP32_LIMIT || cd_size >= ZIP32_LIMIT;
+    if zip64 {
+        let record_offset = cd_offset + cd_size;
+        end.extend_from_slice(&0x06064b50u32.to_le_bytes());
+        end.extend_from_slice(&44u64.to_le_bytes());
+        end.extend_from_slice(&ZIP_MADE_BY.to_le_bytes());
+        end.extend_from_slice(&ZIP64_VERSION.to_le_bytes());
+        end.extend_from_slice(&0u32.to_le_bytes());
+        end.extend_from_slice(&0u32.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&cd_size.to_le_bytes());
+        end.extend_from_slice(&cd_offset.to_le_bytes());
+
+        end.extend_from_slice(&0x07064b50u32.to_le_bytes());
+        end.extend_from_slice(&0u32.to_le_bytes());
+        end.extend_from_slice(&record_offset.to_le_bytes());
+        end.extend_from_slice(&1u32.to_le_bytes());
+    }
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    end.extend_from_slice(&(entries.min(0xFFFF) as u16).to_le_bytes());
+    end.extend_from_slice(&(entries.min(0xFFFF) as u16).to_le_bytes());
+    end.extend_from_slice(&(cd_size.min(ZIP32_LIMIT) as u32).to_le_bytes());
+    end.extend_from_slice(&(cd_offset.min(ZIP32_LIMIT) as u32).to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    end
+}
+
+// exact size of the zip, laid out the same way write_zip writes it
+fn zip_length(entries: &[ArchiveEntry]) -> u64 {
+    let mut offset = 0u64;
+    let mut cd_size = 0u64;
+    for entry in entries {
+        cd_size += central_header(entry, 0, offset).len() as u64;
+        offset += local_header(entry).len() as u64 + entry.size + data_descriptor(entry, 0).len() as u64;
+    }
+    offset + cd_size + end_of_central_directory(entries.len() as u64, offset, cd_size).len() as u64
+}
+
+async fn write_zip(storage: &dyn Storage, entries: &[ArchiveEntry], writer: &mut ArchiveWriter) -> io::Result<()> {
+    let mut central = Vec::new();
+    for entry in entries {
+        let offset = writer.written;
+        writer.write(Bytes::from(local_header(entry))).await?;
+        let crc = writer.copy_file(storage, entry).await?;
+        writer.write(Bytes::from(data_descriptor(entry, crc))).await?;
+        central.extend_from_slice(&central_header(entry, crc, offset));
+    }
+    let cd_offset = writer.written;
+    let cd_size = central.len() as u64;
+    writer.write(Bytes::from(central)).await?;
+    writer
+        .write(Bytes::from(end_of_central_directory(entries.len() as u64, cd_offset, cd_size)))
+        .await
+}
+
+// ---------------------------------------------------------------------------
+// tar: ustar headers, with a pax header in front for long names and sizes past 8GB
+// ---------------------------------------------------------------------------
+
+const TAR_BLOCK: usize = 512;
+/// largest size the 11 octal digits of a ustar header hold
+const TAR_MAX_SIZE: u64 = 0o77777777777;
+
+// header block(s) of one file
+fn tar_header(entry: &ArchiveEntry) -> Vec<u8> {
+    let mtime = entry
+        .modified
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    let mut records = String::new();
+    if entry.name.len() > 100 {
+        records.push_str(&pax_record("path", &entry.name));
+    }
+    if entry.size > TAR_MAX_SIZE {
+        records.push_str(&pax_record("size", &entry.size.to_string()));
+    }
+
+    let mut blocks = Vec::new();
+    if !records.is_empty() {
+        blocks.extend_from_slice(&ustar_block("././@PaxHeader", records.len() as u64, mtime, b'x'));
+        blocks.extend_from_slice(records.as_bytes());
+        blocks.resize(blocks.len() + tar_padding(records.len() as u64), 0);
+    }
+    blocks.extend_from_slice(&ustar_block(&entry.name, entry.size.min(TAR_MAX_SIZE), mtime, b'0'));
+    blocks
+}
+
+// "<length> <key>=<value>\n" where the length counts itself
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + rest.to_string().len();
+    if length.to_string().len() != rest.to_string().len() {
+        length += 1;
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+fn ustar_block(name: &str, size: u64, mtime: u64, kind: u8) -> [u8; TAR_BLOCK] {
+    let mut block = [0u8; TAR_BLOCK];
+    // names past 100 bytes are cut (at a character boundary), the pax header has the full one
+    let mut cut = name.len().min(100);
+    while !name.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    block[..cut].copy_from_slice(&name.as_bytes()[..cut]);
+    let octal = |block: &mut [u8; TAR_BLOCK], at: usize, width: usize, value: u64| {
+        let digits = format!("{:0width$o}", value, width = width - 1);
+        block[at..at + width - 1].copy_from_slice(digits.as_bytes());
+    };
+    octal(&mut block, 100, 8, 0o644);
+    octal(&mut block, 108, 8, 0);
+    octal(&mut block, 116, 8, 0);
+    octal(&mut block, 124, 12, size);
+    octal(&mut block, 136, 12, mtime);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // the checksum is taken with its own field filled with spaces
+    block[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    block[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    block
+}
+
+fn tar_padding(size: u64) -> usize {
+    (TAR_BLOCK - (size % TAR_BLOCK as u64) as usize) % TAR_BLOCK
+}
+
+fn tar_length(entries: &[ArchiveEntry]) -> u64 {
+    let files: u64 = entries
+        .iter()
+        .map(|entry| tar_header(entry).len() as u64 + entry.size + tar_padding(entry.size) as u64)
+        .sum();
+    files + 2 * TAR_BLOCK as u64
+}
+
+async fn write_tar(storage: &dyn Storage, entries: &[ArchiveEntry], writer: &mut ArchiveWriter) -> io::Result<()> {
+    for entry in entries {
+        writer.write(Bytes::from(tar_header(entry))).await?;
+        writer.copy_file(storage, entry).await?;
+        let padding = tar_padding(entry.size);
+        if padding > 0 {
+            writer.write(Bytes::from(vec![0u8; padding])).await?;
+        }
+    }
+    // two empty blocks end the archive
+    writer.write(Bytes::from(vec![0u8; 2 * TAR_BLOCK])).await
+}
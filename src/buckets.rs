@@ -0,0 +1,138 @@
+use axum::body::Body;
+use axum::extract::{Extension, State};
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Json, Response};
+use std::sync::Arc;
+
+use crate::archive::ArchiveSettings;
+use crate::config::{BucketConfig, Config};
+use crate::deploy::DeploySettings;
+use crate::disk::DiskSettings;
+use crate::disposition::DispositionPolicy;
+use crate::extract::ExtractLimits;
+use crate::filetypes::UploadTypeRules;
+use crate::hooks::HookPipeline;
+use crate::fallback::FallbackSettings;
+use crate::listing::ListingSettings;
+use crate::caching::CacheRules;
+use crate::middleware::{ApiKeyName, ApiKeyRegistry, ADMIN_KEY_NAME};
+use crate::models::{BucketInfo, BucketListResponse};
+use crate::quota::QuotaSettings;
+use crate::scanning::ScanSettings;
+use crate::state::AppState;
+use crate::storage;
+use crate::versions::VersioningSettings;
+
+/// name of the bucket living directly in files_dir
+pub const DEFAULT_BUCKET: &str = "default";
+
+/// name of the bucket an admin request is for, set by require_bucket_access
+#[derive(Clone, Debug)]
+pub struct BucketName(pub String);
+
+/// a named, isolated namespace with its own files directory and state
+#[derive(Clone)]
+pub struct Bucket {
+    pub name: String,
+    /// served by the public server under /b/<name>/ and its hosts
+    pub public: bool,
+    pub hosts: Vec<String>,
+    pub state: Arc<AppState>,
+}
+
+impl Bucket {
+    /// build a bucket's state, sharing the instance-wide settings
+    ///
+    /// the bucket quota takes the place of the global one, api key quotas apply per bucket
+    pub fn from_config(config: &Config, bucket: &BucketConfig) -> Self {
+        let quotas = QuotaSettings {
+            global: bucket.quota,
+            ..QuotaSettings::from_config(config)
+        };
+        let state = AppState::new(bucket.root.clone())
+            .with_versioning(VersioningSettings::from_config(config))
+            .with_quotas(quotas)
+            .with_disk(DiskSettings::from_config(config))
+            .with_archives(ArchiveSettings::from_config(config))
+            .with_extract_limits(ExtractLimits::from_config(config))
+            .with_deploy_settings(DeploySettings::from_config(config))
+            .with_fallbacks(FallbackSettings::from_config(config))
+            .with_listings(ListingSettings::from_config(config))
+            .with_cache_rules(CacheRules::from_config(config))
+            .with_disposition(DispositionPolicy::from_config(config))
+            .with_upload_types(UploadTypeRules::from_config(config))
+            .with_scanning(ScanSettings::from_config(config))
+            .with_hooks(HookPipeline::from_config(config))
+            .with_storage(storage::from_config(config, &bucket.root, &bucket.name));
+
+        Self {
+            name: bucket.name.clone(),
+            public: bucket.public,
+            hosts: bucket.hosts.clone(),
+            state: Arc::new(state),
+        }
+    }
+}
+
+/// whether the named api key may use a bucket
+pub fn can_access(key: &str, registry: Option<&ApiKeyRegistry>, bucket: &str) -> bool {
+    if key == ADMIN_KEY_NAME {
+        return true;
+    }
+    registry
+        .and_then(|registry| registry.0.iter().find(|k| k.name == key))
+        .is_some_and(|k| k.buckets.is_empty() || k.buckets.iter().any(|b| b == bucket))
+}
+
+/// keep api keys scoped to other buckets out of this bucket's admin routes
+pub async fn require_bucket_access(
+    State(bucket): State<String>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let key = req
+        .extensions()
+        .get::<ApiKeyName>()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !can_access(&key.0, req.extensions().get::<ApiKeyRegistry>(), &bucket) {
+        tracing::warn!("🚫 API key {} is not allowed in bucket {}", key.0, bucket);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    req.extensions_mut().insert(BucketName(bucket));
+    Ok(next.run(req).await)
+}
+
+// list the buckets the calling api key can use
+pub async fn list_buckets(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKeyName>>,
+    registry: Option<Extension<ApiKeyRegistry>>,
+) -> Json<BucketListResponse> {
+    let key = key.map(|Extension(ApiKeyName(name))| name).unwrap_or_default();
+    let registry = registry.map(|Extension(registry)| registry);
+    let visible = |bucket: &str| can_access(&key, registry.as_ref(), bucket);
+
+    let mut buckets = Vec::new();
+    if visible(DEFAULT_BUCKET) {
+        buckets.push(BucketInfo {
+            name: DEFAULT_BUCKET.to_string(),
+            public: true,
+            hosts: Vec::new(),
+            files_dir: state.files_dir.to_string_lossy().to_string(),
+        });
+    }
+    buckets.extend(state.buckets.values().filter(|b| visible(&b.name)).map(|b| BucketInfo {
+        name: b.name.clone(),
+        public: b.public,
+        hosts: b.hosts.clone(),
+        files_dir: b.state.files_dir.to_string_lossy().to_string(),
+    }));
+
+    Json(BucketListResponse {
+        total: buckets.len(),
+        buckets,
+    })
+}
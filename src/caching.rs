@@ -0,0 +1,284 @@
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use dashmap::DashSet;
+use futures_util::StreamExt;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Semaphore;
+
+use crate::config::Config;
+use crate::metadata::{ContentHash, MetadataStore};
+use crate::state::AppState;
+use crate::storage::{http_date, ByteStream, Storage};
+use crate::utils::{matches_path, safe_key};
+
+/// a Cache-Control value for the paths matching a pattern
+#[derive(Clone, Debug)]
+pub struct CacheRule {
+    /// `*` glob over the whole path if it starts with '/', over the file name otherwise
+    pub pattern: String,
+    pub value: HeaderValue,
+}
+
+impl CacheRule {
+    /// whether the rule covers a decoded request path
+    pub fn matches(&self, path: &str) -> bool {
+        matches_path(&self.pattern, path)
+    }
+}
+
+/// Cache-Control headers by path for the public server, the first matching rule wins
+#[derive(Clone, Debug, Default)]
+pub struct CacheRules {
+    pub rules: Vec<CacheRule>,
+}
+
+impl CacheRules {
+    pub fn new(rules: &[(String, String)]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|(pattern, value)| match HeaderValue::from_str(value) {
+                Ok(value) => Some(CacheRule { pattern: pattern.clone(), value }),
+                Err(_) => {
+                    tracing::warn!("Ignoring cache rule for {} with an invalid Cache-Control value", pattern);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.cache_rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// the Cache-Control value for a decoded request path, if any rule covers it
+    pub fn value_for(&self, path: &str) -> Option<&HeaderValue> {
+        self.rules.iter().find(|rule| rule.matches(path)).map(|rule| &rule.value)
+    }
+}
+
+/// set Cache-Control on successful responses by the cache rules
+pub async fn apply_cache_rules(
+    State(rules): State<Arc<CacheRules>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    // directory requests are answered with their index file
+    let mut path = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
+    if path.ends_with('/') {
+        path.push_str("index.html");
+    }
+
+    let mut response = next.run(req).await;
+    let cacheable = response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED;
+    if cacheable && !response.headers().contains_key(header::CACHE_CONTROL) {
+        if let Some(value) = rules.value_for(&path) {
+            response.headers_mut().insert(header::CACHE_CONTROL, value.clone());
+        }
+    }
+    response
+}
+
+/// sha-256 of an object's content
+pub async fn hash_object(storage: &dyn Storage, key: &str) -> io::Result<ContentHash> {
+    let object = storage.get(key, None).await?;
+    let mut hasher = Sha256::new();
+    let mut body = object.body;
+    while let Some(chunk) = body.next().await {
+        hasher.update(&chunk?);
+    }
+    Ok(ContentHash {
+        sha256: hex::encode(hasher.finalize()),
+        size: object.meta.size,
+        modified: object.meta.modified.map(DateTime::<Utc>::from),
+    })
+}
+
+/// sha-256 of an upload, taken from its body while it's being written
+#[derive(Clone, Default)]
+pub(crate) struct UploadHasher(Arc<Mutex<Sha256>>);
+
+impl UploadHasher {
+    /// the same body, hashing every chunk on its way through
+    pub(crate) fn wrap(&self, body: ByteStream) -> ByteStream {
+        let hasher = self.0.clone();
+        Box::pin(body.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                hasher.lock().unwrap().update(chunk);
+            }
+        }))
+    }
+
+    /// hex digest of everything that went through
+    pub(crate) fn finish(&self) -> String {
+        hex::encode(self.0.lock().unwrap().clone().finalize())
+    }
+}
+
+/// hex sha-256 of content held in memory
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// put the hash taken while a file was written into its metadata, returning whether it needs
+// persisting; files written without one are hashed by the public server when first requested
+pub(crate) async fn record_content_hash(state: &AppState, key: &str, sha256: Option<&str>) -> bool {
+    let Some(sha256) = sha256 else {
+        return false;
+    };
+    match state.storage.stat(key).await {
+        Ok(meta) => {
+            let hash = ContentHash {
+                sha256: sha256.to_string(),
+                size: meta.size,
+                modified: meta.modified.map(DateTime::<Utc>::from),
+            };
+            state.metadata.update(key, |m| m.hash = Some(hash));
+            true
+        }
+        Err(e) => {
+            tracing::warn!("Failed to record the hash of {}: {}", key, e);
+            false
+        }
+    }
+}
+
+/// most files hashed in the background at once for the public server
+const MAX_BACKGROUND_HASHES: usize = 4;
+
+/// where the public server finds content hashes for strong etags
+pub struct ContentEtags {
+    pub storage: Arc<dyn Storage>,
+    pub metadata: Arc<MetadataStore>,
+    // keys being hashed in the background
+    hashing: Arc<DashSet<String>>,
+    // bounds the background reads any anonymous request can start
+    permits: Arc<Semaphore>,
+}
+
+impl ContentEtags {
+    pub fn new(storage: Arc<dyn Storage>, metadata: Arc<MetadataStore>) -> Self {
+        Self {
+            storage,
+            metadata,
+            hashing: Arc::new(DashSet::new()),
+            permits: Arc::new(Semaphore::new(MAX_BACKGROUND_HASHES)),
+        }
+    }
+
+    // hash a file nobody uploaded through us (or that changed since) for the next request,
+    // the hash stays in memory until the metadata is saved next; when enough files are
+    // already being hashed the request just goes without, a later one tries again
+    fn hash_later(&self, key: &str) {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            return;
+        };
+        if !self.hashing.insert(key.to_string()) {
+            return;
+        }
+        let (storage, metadata, hashing) = (self.storage.clone(), self.metadata.clone(), self.hashing.clone());
+        let key = key.to_string();
+        tokio::spawn(async move {
+            let _permit = permit;
+            match hash_object(storage.as_ref(), &key).await {
+                Ok(hash) => {
+                    tracing::trace!("Hashed {} for its etag", key);
+                    metadata.update(&key, |meta| meta.hash = Some(hash));
+                }
+                Err(e) => tracing::debug!("Failed to hash {}: {}", key, e),
+            }
+            hashing.remove(&key);
+        });
+    }
+}
+
+// whether the client's copy is current: If-None-Match if sent, If-Modified-Since otherwise
+fn is_not_modified(headers: &HeaderMap, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    if let Some(none_match) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        // weak comparison, as If-None-Match calls for
+        return none_match.trim() == "*"
+            || etag.is_some_and(|etag| none_match.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag));
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => DateTime::<Utc>::from(modified).timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+/// strong etags from content hashes, and conditional requests answered with them
+pub async fn serve_etags(
+    State(etags): State<Arc<ContentEtags>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+    let decoded = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
+    let Some(mut key) = safe_key(&decoded) else {
+        return next.run(req).await;
+    };
+    if decoded.ends_with('/') {
+        key = if key.is_empty() { "index.html".to_string() } else { format!("{}/index.html", key) };
+    }
+    let Ok(meta) = etags.storage.stat(&key).await else {
+        return next.run(req).await;
+    };
+
+    let etag = match etags.metadata.get(&key).hash {
+        Some(hash) if hash.matches(&meta) => Some(format!("\"{}\"", hash.sha256)),
+        _ => {
+            etags.hash_later(&key);
+            None
+        }
+    };
+
+    let headers = req.headers_mut();
+    if is_not_modified(headers, etag.as_deref(), meta.modified) {
+        let mut response = Response::builder().status(StatusCode::NOT_MODIFIED);
+        if let Some(etag) = &etag {
+            response = response.header(header::ETAG, etag);
+        }
+        if let Some(modified) = meta.modified {
+            response = response.header(header::LAST_MODIFIED, http_date(modified));
+        }
+        return response.body(Body::empty()).unwrap();
+    }
+
+    // the conditions are settled, the inner service shouldn't second-guess them
+    headers.remove(header::IF_NONE_MATCH);
+    headers.remove(header::IF_MODIFIED_SINCE);
+    // a range only applies to the content an If-Range etag names (dates are left to the inner service)
+    let if_range = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()).map(str::to_string);
+    if let Some(if_range) = if_range.filter(|v| v.starts_with('"') || v.starts_with("W/")) {
+        headers.remove(header::IF_RANGE);
+        if etag.as_deref() != Some(if_range.as_str()) {
+            headers.remove(header::RANGE);
+        }
+    }
+
+    let mut response = next.run(req).await;
+    let served = matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT);
+    if let (Some(etag), true) = (etag, served) {
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            response.headers_mut().insert(header::ETAG, value);
+        }
+    }
+    response
+}
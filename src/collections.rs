@@ -0,0 +1,227 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use std::sync::Arc;
+
+use crate::handlers::{delete_files, persist_metadata, DeleteMode};
+use crate::models::{
+    BatchDeleteResult, CollectionBulkAction, CollectionBulkRequest, CollectionBulkResponse,
+    CollectionFilesRequest, CollectionInfo, CollectionListResponse, DeleteResponse, ErrorResponse,
+};
+use crate::state::AppState;
+use crate::storage::exists;
+use crate::utils::sanitize_filename;
+
+// list all collections
+pub async fn list_collections(
+    State(state): State<Arc<AppState>>,
+) -> Json<CollectionListResponse> {
+    let mut collections: Vec<CollectionInfo> = state
+        .metadata
+        .collections
+        .iter()
+        .map(|c| CollectionInfo {
+            name: c.key().clone(),
+            files: c.value().iter().cloned().collect(),
+            total: c.value().len(),
+        })
+        .collect();
+    collections.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let total = collections.len();
+    Json(CollectionListResponse { collections, total })
+}
+
+// get a single collection
+pub async fn get_collection(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<CollectionInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let name = sanitize_filename(&name);
+    let files: Vec<String> = state
+        .metadata
+        .collections
+        .get(&name)
+        .map(|c| c.iter().cloned().collect())
+        .ok_or_else(|| collection_not_found(&name))?;
+
+    Ok(Json(CollectionInfo {
+        total: files.len(),
+        name,
+        files,
+    }))
+}
+
+// add files to a collection, creating it if needed
+pub async fn add_to_collection(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(payload): Json<CollectionFilesRequest>,
+) -> Result<Json<CollectionInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let name = sanitize_filename(&name);
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Invalid collection name".to_string(),
+            }),
+        ));
+    }
+
+    // only existing files can be grouped
+    let mut filenames = Vec::new();
+    for filename in &payload.filenames {
+        let sanitized_filename = sanitize_filename(filename);
+        if !exists(state.storage.as_ref(), &sanitized_filename).await.unwrap_or(false) {
+            tracing::warn!("Cannot add missing file {} to collection {}", sanitized_filename, name);
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: format!("File not found: {}", sanitized_filename),
+                }),
+            ));
+        }
+        filenames.push(sanitized_filename);
+    }
+
+    let files: Vec<String> = {
+        let mut collection = state.metadata.collections.entry(name.clone()).or_default();
+        collection.extend(filenames);
+        collection.iter().cloned().collect()
+    };
+
+    persist_metadata(&state, "collections").await?;
+    tracing::info!("📚 Collection {} now has {} files", name, files.len());
+
+    Ok(Json(CollectionInfo {
+        total: files.len(),
+        name,
+        files,
+    }))
+}
+
+// remove files from a collection (the files themselves are kept)
+pub async fn remove_from_collection(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(payload): Json<CollectionFilesRequest>,
+) -> Result<Json<CollectionInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let name = sanitize_filename(&name);
+
+    let files: Vec<String> = {
+        let mut collection = state
+            .metadata
+            .collections
+            .get_mut(&name)
+            .ok_or_else(|| collection_not_found(&name))?;
+        for filename in &payload.filenames {
+            collection.remove(&sanitize_filename(filename));
+        }
+        collection.iter().cloned().collect()
+    };
+
+    persist_metadata(&state, "collections").await?;
+    tracing::info!("📚 Collection {} now has {} files", name, files.len());
+
+    Ok(Json(CollectionInfo {
+        total: files.len(),
+        name,
+        files,
+    }))
+}
+
+// delete a collection (the files themselves are kept)
+pub async fn delete_collection(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let name = sanitize_filename(&name);
+    state
+        .metadata
+        .collections
+        .remove(&name)
+        .ok_or_else(|| collection_not_found(&name))?;
+
+    persist_metadata(&state, "collections").await?;
+    tracing::info!("🗑️  Deleted collection: {}", name);
+
+    Ok(Json(DeleteResponse {
+        success: true,
+        filename: name,
+    }))
+}
+
+// delete or expire every file in a collection
+pub async fn collection_bulk_action(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(payload): Json<CollectionBulkRequest>,
+) -> Result<Json<CollectionBulkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let name = sanitize_filename(&name);
+    let filenames: Vec<String> = state
+        .metadata
+        .collections
+        .get(&name)
+        .map(|c| c.iter().cloned().collect())
+        .ok_or_else(|| collection_not_found(&name))?;
+
+    tracing::debug!("Bulk {:?} on collection {} ({} files)", payload.action, name, filenames.len());
+
+    let (successful, failed, results) = match payload.action {
+        CollectionBulkAction::Delete => {
+            let response = delete_files(&state, filenames, DeleteMode::Trash).await;
+            // the collection is empty now, drop it too
+            state.metadata.collections.remove(&name);
+            persist_metadata(&state, "collections").await?;
+            (response.successful, response.failed, response.results)
+        }
+        CollectionBulkAction::Expire => {
+            let expires_at = payload.expires_at.unwrap_or_else(chrono::Utc::now);
+            let mut results = Vec::with_capacity(filenames.len());
+            for filename in filenames {
+                // members whose file is gone have nothing left to expire
+                let error = match exists(state.storage.as_ref(), &filename).await {
+                    Ok(true) => None,
+                    Ok(false) => Some(format!("File not found: {}", filename)),
+                    Err(e) => Some(format!("Failed to check {}: {}", filename, e)),
+                };
+                if error.is_none() {
+                    state.metadata.update(&filename, |meta| meta.expires_at = Some(expires_at));
+                } else {
+                    tracing::warn!("❌ Not expiring {} from collection {}: {:?}", filename, name, error);
+                }
+                results.push(BatchDeleteResult {
+                    filename,
+                    success: error.is_none(),
+                    error,
+                });
+            }
+            persist_metadata(&state, "collections").await?;
+            let successful = results.iter().filter(|r| r.success).count();
+            (successful, results.len() - successful, results)
+        }
+    };
+
+    tracing::info!("📚 Bulk {:?} on collection {}: {}/{} successful", payload.action, name, successful, results.len());
+
+    Ok(Json(CollectionBulkResponse {
+        collection: name,
+        action: payload.action,
+        total: results.len(),
+        successful,
+        failed,
+        results,
+    }))
+}
+
+fn collection_not_found(name: &str) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Collection not found: {}", name);
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("Collection not found: {}", name),
+        }),
+    )
+}
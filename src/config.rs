@@ -1,6 +1,168 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use sha2::{Sha256, Digest};
 
+use crate::quota::Quota;
+
+/// longest trash retention honored (100 years), longer ones are as good as forever
+pub const MAX_TRASH_RETENTION_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// an extra named api key for the admin api
+#[derive(Debug, Clone)]
+pub struct ApiKeyConfig {
+    /// name used for ownership and quota accounting
+    pub name: String,
+    /// sha256 of the key
+    pub key_hash: String,
+    /// storage quota for files uploaded with this key
+    pub quota: Quota,
+    /// buckets this key may use (empty = all of them)
+    pub buckets: Vec<String>,
+}
+
+/// a named bucket with its own files directory
+#[derive(Debug, Clone)]
+pub struct BucketConfig {
+    pub name: String,
+    /// directory the bucket's files live in
+    pub root: PathBuf,
+    /// whether the public server serves this bucket
+    pub public: bool,
+    /// host names that serve this bucket at the root of the public server
+    pub hosts: Vec<String>,
+    /// storage quota for the whole bucket
+    pub quota: Quota,
+}
+
+/// security header values, None keeps the default and an empty value leaves the header out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SecurityHeaderValues {
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    pub strict_transport_security: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+    pub cross_origin_opener_policy: Option<String>,
+    pub cross_origin_embedder_policy: Option<String>,
+    pub cross_origin_resource_policy: Option<String>,
+}
+
+impl SecurityHeaderValues {
+    /// these values with the ones set in `other` on top
+    pub fn merged(&self, other: &SecurityHeaderValues) -> Self {
+        let pick = |a: &Option<String>, b: &Option<String>| b.clone().or_else(|| a.clone());
+        Self {
+            content_security_policy: pick(&self.content_security_policy, &other.content_security_policy),
+            frame_options: pick(&self.frame_options, &other.frame_options),
+            strict_transport_security: pick(&self.strict_transport_security, &other.strict_transport_security),
+            referrer_policy: pick(&self.referrer_policy, &other.referrer_policy),
+            permissions_policy: pick(&self.permissions_policy, &other.permissions_policy),
+            cross_origin_opener_policy: pick(&self.cross_origin_opener_policy, &other.cross_origin_opener_policy),
+            cross_origin_embedder_policy: pick(&self.cross_origin_embedder_policy, &other.cross_origin_embedder_policy),
+            cross_origin_resource_policy: pick(&self.cross_origin_resource_policy, &other.cross_origin_resource_policy),
+        }
+    }
+}
+
+/// security headers set differently for the paths matching a pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityPathConfig {
+    pub name: String,
+    /// `*` glob over the whole path if it starts with '/', over the file name otherwise
+    pub pattern: String,
+    pub headers: SecurityHeaderValues,
+}
+
+/// something to do with uploads whose path matches a pattern once they're stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookConfig {
+    pub name: String,
+    /// `*` glob over the whole path if it starts with '/', over the file name otherwise
+    pub pattern: String,
+    /// shell command to run
+    pub command: Option<String>,
+    /// built-in step instead of a command, "tag:<tag>" or "webhook:<url>"
+    pub step: Option<String>,
+    /// longest a run may take (None = HOOK_TIMEOUT)
+    pub timeout_secs: Option<u64>,
+    /// runs after a failed one
+    pub retries: u32,
+}
+
+/// host names mapped to a directory on the public server
+#[derive(Debug, Clone)]
+pub struct VirtualHostConfig {
+    pub name: String,
+    /// host names answered by this virtual host
+    pub hosts: Vec<String>,
+    /// directory to serve (takes precedence over bucket)
+    pub root: Option<PathBuf>,
+    /// bucket to serve when no root is set (the default bucket if neither is)
+    pub bucket: Option<String>,
+    /// files tried in order for directory requests (empty = index.html)
+    pub index_files: Vec<String>,
+    /// Cache-Control sent with successful responses
+    pub cache_control: Option<String>,
+    /// send the security headers at all
+    pub security_headers: bool,
+    /// overrides for the default Content-Security-Policy / X-Frame-Options
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    /// deployed site to serve instead of the root's files
+    pub site: Option<String>,
+    /// overrides for the global SPA_DIRS / ERROR_PAGES
+    pub spa_dirs: Option<Vec<String>>,
+    pub error_pages: Option<bool>,
+    /// override for the global LISTING_DIRS
+    pub listing_dirs: Option<Vec<String>>,
+    /// override for the global DISPOSITION_SANDBOX
+    pub sandbox: Option<bool>,
+}
+
+/// an S3-compatible object store holding the files instead of local disk
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// e.g. http://localhost:9000 for MinIO
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// key prefix inside the s3 bucket, ends in '/' unless empty
+    pub prefix: String,
+    /// address the bucket in the path rather than the host name
+    pub path_style: bool,
+}
+
+/// juicebox's own S3-compatible api, served on a listener of its own
+#[derive(Clone)]
+pub struct S3ApiConfig {
+    pub host: String,
+    pub port: u16,
+    /// region clients sign their requests for
+    pub region: String,
+    /// access key id -> secret, the api key names and the keys themselves
+    pub credentials: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for S3ApiConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3ApiConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("region", &self.region)
+            .field("access_keys", &self.credentials.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// WebDAV listener for mounting the files as a network drive
+#[derive(Debug, Clone)]
+pub struct WebDavConfig {
+    pub host: String,
+    pub port: u16,
+}
+
 /// application configuration loaded from environment variables
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -20,10 +182,93 @@ pub struct Config {
     pub worker_threads: usize,
     /// api key for admin authentication (hashed)
     pub api_key_hash: String,
+    /// extra named api keys, each with an optional quota
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// storage quota for the whole files directory
+    pub global_quota: Quota,
+    /// extra buckets next to the default one in files_dir
+    pub buckets: Vec<BucketConfig>,
+    /// virtual hosts on the public server
+    pub vhosts: Vec<VirtualHostConfig>,
+    /// virtual host answering requests for unknown hosts (None = the default bucket)
+    pub default_vhost: Option<String>,
     /// cors allowed origins (comma-separated)
     pub cors_origins: Vec<String>,
     /// rate limit: requests per minute
     pub rate_limit_per_minute: u64,
+    /// how often the background task deletes expired files (seconds)
+    pub expiry_sweep_interval_secs: u64,
+    /// how long deleted files stay in the trash before being purged (seconds)
+    pub trash_retention_secs: u64,
+    /// how often the background task empties old trash items (seconds)
+    pub trash_purge_interval_secs: u64,
+    /// directories that keep previous versions on overwrite ("/" is the root, "*" is everything)
+    pub versioned_dirs: Vec<String>,
+    /// versions kept per file before the oldest get pruned (0 = unlimited)
+    pub max_versions: usize,
+    /// free disk space uploads must leave behind (bytes)
+    pub disk_reserve_bytes: u64,
+    /// the health check fails when free disk space drops below this (bytes)
+    pub health_min_free_bytes: u64,
+    /// keep files in an S3-compatible store (None = on local disk)
+    pub s3: Option<S3Config>,
+    /// serve an S3-compatible api (None = disabled)
+    pub s3_api: Option<S3ApiConfig>,
+    /// serve the files over WebDAV (None = disabled)
+    pub webdav: Option<WebDavConfig>,
+    /// secret signing archive download links for the public server (None = no links)
+    pub archive_link_secret: Option<String>,
+    /// most files a single archive download may hold
+    pub archive_max_files: usize,
+    /// most entries an uploaded archive may have
+    pub extract_max_entries: usize,
+    /// most bytes an uploaded archive may expand to
+    pub extract_max_bytes: u64,
+    /// releases kept per deployed site, the active one included
+    pub deploy_keep_releases: usize,
+    /// directories whose unknown paths serve their index.html ("/" = the whole root)
+    pub spa_dirs: Vec<String>,
+    /// answer public errors with the nearest <status>.html page
+    pub error_pages: bool,
+    /// directories without an index file that get a generated listing, "!dir" turns it off below dir
+    pub listing_dirs: Vec<String>,
+    /// name patterns left out of listings
+    pub listing_hide: Vec<String>,
+    /// (path pattern, Cache-Control value) pairs for the public server, first match wins
+    pub cache_rules: Vec<(String, String)>,
+    /// security headers for public responses, on top of the built-in defaults
+    pub security_headers: SecurityHeaderValues,
+    /// security header changes by path, first match wins
+    pub security_paths: Vec<SecurityPathConfig>,
+    /// send the security headers with admin responses too
+    pub admin_security_headers: bool,
+    /// mime types (`*` allowed) and file extensions always served as downloads
+    pub attachment_types: Vec<String>,
+    /// serve files from the public server under a sandbox Content-Security-Policy
+    pub sandbox_uploads: bool,
+    /// the sandbox policy (None = the built-in one)
+    pub sandbox_csp: Option<String>,
+    /// extensions uploads must have (empty = any) and may not have
+    pub upload_allow_extensions: Vec<String>,
+    pub upload_deny_extensions: Vec<String>,
+    /// mime types (`*` allowed) the content of uploads must have (empty = any) and may not have
+    pub upload_allow_types: Vec<String>,
+    pub upload_deny_types: Vec<String>,
+    /// clamd to scan uploads with, "tcp://host:port" or "unix:///path" (None = no scanning)
+    pub clamd_address: Option<String>,
+    pub scan_timeout_secs: u64,
+    /// where infected uploads go, outside every served directory
+    pub scan_quarantine_dir: PathBuf,
+    /// keep uploads the scan failed on instead of refusing them
+    pub scan_fail_open: bool,
+    /// hooks run on stored uploads, in order
+    pub hooks: Vec<HookConfig>,
+    /// longest a hook may run unless it says otherwise
+    pub hook_timeout_secs: u64,
+    /// files whose hooks run at the same time
+    pub hook_concurrency: usize,
+    /// wait before retrying a failed hook, doubled on every retry
+    pub hook_retry_delay_secs: u64,
 }
 
 impl Config {
@@ -46,6 +291,137 @@ impl Config {
             .filter(|s| !s.is_empty())
             .collect();
         
+        // parse extra api keys: name:key[:max_bytes[:max_files]], comma-separated
+        let parsed_keys: Vec<(ApiKeyConfig, String)> = std::env::var("API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| Self::parse_api_key(entry.trim()))
+            .collect();
+
+        // the s3 api signs with the keys themselves, so only keep them around when it's on
+        let s3_api = std::env::var("S3_API_PORT").ok().and_then(|p| p.trim().parse().ok()).map(|port| {
+            let mut credentials: HashMap<String, String> = parsed_keys
+                .iter()
+                .map(|(key, secret)| (key.name.clone(), secret.clone()))
+                .collect();
+            credentials.insert(crate::middleware::ADMIN_KEY_NAME.to_string(), api_key.clone());
+            S3ApiConfig {
+                host: std::env::var("S3_API_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+                port,
+                region: std::env::var("S3_API_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                credentials,
+            }
+        });
+        let api_keys = parsed_keys.into_iter().map(|(key, _)| key).collect();
+
+        let webdav = std::env::var("WEBDAV_PORT").ok().and_then(|p| p.trim().parse().ok()).map(|port| WebDavConfig {
+            host: std::env::var("WEBDAV_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+            port,
+        });
+
+        // parse versioned directories
+        let versioned_dirs = std::env::var("VERSIONED_DIRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        // parse single-page app directories
+        let spa_dirs = std::env::var("SPA_DIRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        // parse directory listing settings
+        let listing_dirs = std::env::var("LISTING_DIRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let listing_hide = std::env::var("LISTING_HIDE")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        // parse mime types and extensions forced to download
+        let attachment_types = std::env::var("DISPOSITION_ATTACHMENT")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        // parse upload type rules
+        let upload_list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+
+        // parse cache rules, "pattern=value" separated by ';' since values have commas of their own
+        let cache_rules = std::env::var("CACHE_RULES")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|rule| {
+                let (pattern, value) = rule.split_once('=')?;
+                let (pattern, value) = (pattern.trim(), value.trim());
+                (!pattern.is_empty() && !value.is_empty()).then(|| (pattern.to_string(), value.to_string()))
+            })
+            .collect();
+
+        // parse buckets, each configured through its own BUCKET_<NAME>_* vars
+        let buckets_dir: PathBuf = std::env::var("BUCKETS_DIR")
+            .unwrap_or_else(|_| "./buckets".to_string())
+            .into();
+        let buckets = std::env::var("BUCKETS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .filter_map(|name| Self::parse_bucket(&buckets_dir, name))
+            .collect();
+
+        // parse virtual hosts, each configured through its own VHOST_<NAME>_* vars
+        let vhosts = std::env::var("VHOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .map(Self::parse_vhost)
+            .collect();
+
+        // parse security header overrides by path, each configured through SECURITY_PATH_<NAME>_* vars
+        let security_paths = std::env::var("SECURITY_PATHS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .filter_map(Self::parse_security_path)
+            .collect();
+
+        // parse post-upload hooks, each configured through HOOK_<NAME>_* vars
+        let hooks = std::env::var("HOOKS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .filter_map(Self::parse_hook)
+            .collect();
+
+        let disk_reserve_bytes = std::env::var("DISK_RESERVE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024 * 1024 * 1024); // 1GB default
+
         Self {
             files_dir: std::env::var("FILES_DIR")
                 .unwrap_or_else(|_| "./files".to_string())
@@ -71,14 +447,323 @@ impl Config {
                 .and_then(|t| t.parse().ok())
                 .unwrap_or(8),
             api_key_hash,
+            api_keys,
+            buckets,
+            vhosts,
+            default_vhost: std::env::var("DEFAULT_VHOST")
+                .ok()
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty()),
+            global_quota: Quota {
+                max_bytes: std::env::var("QUOTA_MAX_BYTES").ok().and_then(|s| s.parse().ok()),
+                max_files: std::env::var("QUOTA_MAX_FILES").ok().and_then(|s| s.parse().ok()),
+            },
             cors_origins,
             rate_limit_per_minute: std::env::var("RATE_LIMIT_PER_MINUTE")
                 .ok()
                 .and_then(|r| r.parse().ok())
                 .unwrap_or(60),
+            expiry_sweep_interval_secs: std::env::var("EXPIRY_SWEEP_INTERVAL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60),
+            trash_retention_secs: std::env::var("TRASH_RETENTION")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(7 * 24 * 60 * 60) // 7 days default
+                .min(MAX_TRASH_RETENTION_SECS),
+            trash_purge_interval_secs: std::env::var("TRASH_PURGE_INTERVAL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60 * 60),
+            versioned_dirs,
+            max_versions: std::env::var("MAX_VERSIONS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10),
+            disk_reserve_bytes,
+            health_min_free_bytes: std::env::var("HEALTH_MIN_FREE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(disk_reserve_bytes),
+            s3: Self::parse_s3(),
+            s3_api,
+            webdav,
+            archive_link_secret: std::env::var("ARCHIVE_LINK_SECRET")
+                .ok()
+                .filter(|s| !s.trim().is_empty()),
+            archive_max_files: std::env::var("ARCHIVE_MAX_FILES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10_000),
+            extract_max_entries: std::env::var("EXTRACT_MAX_ENTRIES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10_000),
+            extract_max_bytes: std::env::var("EXTRACT_MAX_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2 * 1024 * 1024 * 1024), // 2GB default
+            deploy_keep_releases: std::env::var("DEPLOY_KEEP_RELEASES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(5),
+            spa_dirs,
+            error_pages: std::env::var("ERROR_PAGES")
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+                .unwrap_or(false),
+            listing_dirs,
+            listing_hide,
+            cache_rules,
+            security_headers: Self::parse_security_values("SECURITY_"),
+            security_paths,
+            admin_security_headers: std::env::var("SECURITY_HEADERS_ADMIN")
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+                .unwrap_or(false),
+            attachment_types,
+            sandbox_uploads: std::env::var("DISPOSITION_SANDBOX")
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+                .unwrap_or(false),
+            sandbox_csp: std::env::var("DISPOSITION_SANDBOX_CSP")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+            upload_allow_extensions: upload_list("UPLOAD_ALLOW_EXTENSIONS"),
+            upload_deny_extensions: upload_list("UPLOAD_DENY_EXTENSIONS"),
+            upload_allow_types: upload_list("UPLOAD_ALLOW_TYPES"),
+            upload_deny_types: upload_list("UPLOAD_DENY_TYPES"),
+            clamd_address: std::env::var("CLAMD_ADDRESS")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+            scan_timeout_secs: std::env::var("SCAN_TIMEOUT")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&n: &u64| n > 0)
+                .unwrap_or(60),
+            scan_quarantine_dir: std::env::var("SCAN_QUARANTINE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("./quarantine")),
+            scan_fail_open: std::env::var("SCAN_FAIL_OPEN")
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+                .unwrap_or(false),
+            hooks,
+            hook_timeout_secs: std::env::var("HOOK_TIMEOUT")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&n: &u64| n > 0)
+                .unwrap_or(300),
+            hook_concurrency: std::env::var("HOOK_CONCURRENCY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(2),
+            hook_retry_delay_secs: std::env::var("HOOK_RETRY_DELAY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(5),
+        }
+    }
+
+    // read the <PREFIX>CSP, <PREFIX>FRAME_OPTIONS, ... vars, empty ones included (they turn a header off)
+    fn parse_security_values(prefix: &str) -> SecurityHeaderValues {
+        let var = |suffix: &str| std::env::var(format!("{}{}", prefix, suffix)).ok().map(|v| v.trim().to_string());
+        SecurityHeaderValues {
+            content_security_policy: var("CSP"),
+            frame_options: var("FRAME_OPTIONS"),
+            strict_transport_security: var("HSTS"),
+            referrer_policy: var("REFERRER_POLICY"),
+            permissions_policy: var("PERMISSIONS_POLICY"),
+            cross_origin_opener_policy: var("COOP"),
+            cross_origin_embedder_policy: var("COEP"),
+            cross_origin_resource_policy: var("CORP"),
         }
     }
+
+    // read the SECURITY_PATH_<NAME>_* vars for one path override, None without a pattern
+    fn parse_security_path(name: String) -> Option<SecurityPathConfig> {
+        let prefix = format!("SECURITY_PATH_{}_", name.to_uppercase().replace('-', "_"));
+        let Some(pattern) = std::env::var(format!("{}PATTERN", prefix))
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+        else {
+            tracing::warn!("Ignoring security headers for {}: {}PATTERN is not set", name, prefix);
+            return None;
+        };
+        Some(SecurityPathConfig {
+            headers: Self::parse_security_values(&prefix),
+            pattern,
+            name,
+        })
+    }
+
+    // read the HOOK_<NAME>_* vars for one hook, None without something to run
+    fn parse_hook(name: String) -> Option<HookConfig> {
+        let prefix = format!("HOOK_{}_", name.to_uppercase().replace('-', "_"));
+        let var = |suffix: &str| {
+            std::env::var(format!("{}{}", prefix, suffix))
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let (command, step) = (var("COMMAND"), var("STEP"));
+        if command.is_some() == step.is_some() {
+            tracing::warn!("Ignoring hook {}: set one of {}COMMAND or {}STEP", name, prefix, prefix);
+            return None;
+        }
+        Some(HookConfig {
+            pattern: var("PATTERN").unwrap_or_else(|| "*".to_string()),
+            command,
+            step,
+            timeout_secs: var("TIMEOUT").and_then(|s| s.parse().ok()).filter(|&n: &u64| n > 0),
+            retries: var("RETRIES").and_then(|s| s.parse().ok()).unwrap_or(0),
+            name,
+        })
+    }
+
+    // read the S3_* vars when STORAGE_BACKEND=s3
+    fn parse_s3() -> Option<S3Config> {
+        let backend = std::env::var("STORAGE_BACKEND").unwrap_or_default().trim().to_lowercase();
+        if backend != "s3" {
+            if !backend.is_empty() && backend != "fs" {
+                tracing::warn!("Unknown STORAGE_BACKEND {}, keeping files on local disk", backend);
+            }
+            return None;
+        }
+
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let Some(bucket) = var("S3_BUCKET") else {
+            tracing::warn!("STORAGE_BACKEND=s3 needs S3_BUCKET, keeping files on local disk");
+            return None;
+        };
+        let region = var("S3_REGION").unwrap_or_else(|| "us-east-1".to_string());
+        let prefix = var("S3_PREFIX")
+            .map(|p| format!("{}/", p.trim_matches('/')))
+            .unwrap_or_default();
+
+        Some(S3Config {
+            endpoint: var("S3_ENDPOINT")
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region))
+                .trim_end_matches('/')
+                .to_string(),
+            access_key: var("S3_ACCESS_KEY").unwrap_or_default(),
+            secret_key: var("S3_SECRET_KEY").unwrap_or_default(),
+            path_style: var("S3_PATH_STYLE")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            region,
+            bucket,
+            prefix,
+        })
+    }
     
+    // parse a single API_KEYS entry (plus the key itself), skipping malformed ones
+    fn parse_api_key(entry: &str) -> Option<(ApiKeyConfig, String)> {
+        let mut parts = entry.split(':');
+        let name = parts.next()?.trim();
+        let key = parts.next()?.trim();
+        if name.is_empty() || key.is_empty() {
+            tracing::warn!("Ignoring malformed API_KEYS entry");
+            return None;
+        }
+
+        let limit = |part: Option<&str>| part.map(str::trim).filter(|p| !p.is_empty()).and_then(|p| p.parse().ok());
+        let config = ApiKeyConfig {
+            name: name.to_string(),
+            key_hash: Self::hash_api_key(key),
+            quota: Quota {
+                max_bytes: limit(parts.next()),
+                max_files: limit(parts.next()),
+            },
+            buckets: parts
+                .next()
+                .unwrap_or_default()
+                .split('|')
+                .map(|b| b.trim().to_lowercase())
+                .filter(|b| !b.is_empty())
+                .collect(),
+        };
+        Some((config, key.to_string()))
+    }
+
+    // read the BUCKET_<NAME>_* vars for one bucket
+    fn parse_bucket(buckets_dir: &std::path::Path, name: String) -> Option<BucketConfig> {
+        let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid || name == crate::buckets::DEFAULT_BUCKET {
+            tracing::warn!("Ignoring invalid bucket name: {}", name);
+            return None;
+        }
+
+        let prefix = format!("BUCKET_{}_", name.to_uppercase().replace('-', "_"));
+        let var = |suffix: &str| std::env::var(format!("{}{}", prefix, suffix)).ok();
+        let limit = |suffix: &str| var(suffix).and_then(|s| s.parse().ok());
+
+        Some(BucketConfig {
+            root: var("ROOT")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| buckets_dir.join(&name)),
+            public: var("PUBLIC")
+                .map(|v| !matches!(v.trim().to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            hosts: var("HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|h| h.trim().to_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect(),
+            quota: Quota {
+                max_bytes: limit("QUOTA_MAX_BYTES"),
+                max_files: limit("QUOTA_MAX_FILES"),
+            },
+            name,
+        })
+    }
+
+    // read the VHOST_<NAME>_* vars for one virtual host
+    fn parse_vhost(name: String) -> VirtualHostConfig {
+        let prefix = format!("VHOST_{}_", name.to_uppercase().replace('-', "_"));
+        let var = |suffix: &str| {
+            std::env::var(format!("{}{}", prefix, suffix))
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let list = |suffix: &str| -> Vec<String> {
+            var(suffix)
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+
+        VirtualHostConfig {
+            hosts: list("HOSTS").into_iter().map(|h| h.to_lowercase()).collect(),
+            root: var("ROOT").map(PathBuf::from),
+            bucket: var("BUCKET").map(|b| b.to_lowercase()),
+            index_files: list("INDEX"),
+            cache_control: var("CACHE_CONTROL"),
+            security_headers: var("SECURITY_HEADERS")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            content_security_policy: var("CSP"),
+            frame_options: var("FRAME_OPTIONS"),
+            site: var("SITE").map(|s| s.to_lowercase()),
+            spa_dirs: var("SPA_DIRS").map(|_| list("SPA_DIRS")),
+            error_pages: var("ERROR_PAGES").map(|v| matches!(v.to_lowercase().as_str(), "true" | "1" | "yes")),
+            listing_dirs: var("LISTING_DIRS").map(|_| list("LISTING_DIRS")),
+            sandbox: var("SANDBOX").map(|v| matches!(v.to_lowercase().as_str(), "true" | "1" | "yes")),
+            name,
+        }
+    }
+
     // hash api key using sha256
     pub fn hash_api_key(key: &str) -> String {
         let mut hasher = Sha256::new();
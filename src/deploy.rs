@@ -0,0 +1,406 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Multipart, OriginalUri, Path, Query, State},
+    http::{header, Request, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use dashmap::DashMap;
+use std::io;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+use tower_http::services::ServeDir;
+
+use crate::config::Config;
+use crate::extract::{install, receive_archive, stage_archive};
+use crate::handlers::{quota_error, write_error};
+use crate::models::{
+    ActivateReleaseRequest, DeleteResponse, DeployOptions, DeploymentResponse, ErrorResponse, SiteInfo,
+    SiteListResponse,
+};
+use crate::quota::reserve_upload;
+use crate::state::AppState;
+use crate::storage::{put_bytes, read_all, serve_prefixed, Listing, Storage};
+
+/// where deployed sites keep their releases in the storage backend
+pub const DEPLOYMENTS_DIR: &str = ".deployments";
+
+/// prefix deployed sites are served under on the public server
+pub const SITES_PATH: &str = "/.sites";
+
+/// how many releases each site keeps around for rollbacks
+#[derive(Clone, Copy, Debug)]
+pub struct DeploySettings {
+    pub keep_releases: usize,
+}
+
+impl Default for DeploySettings {
+    fn default() -> Self {
+        Self { keep_releases: 5 }
+    }
+}
+
+impl DeploySettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            keep_releases: config.deploy_keep_releases,
+        }
+    }
+}
+
+/// the release each deployed site currently serves
+///
+/// every site lives in `.deployments/<site>/` of the storage backend with its releases under
+/// `releases/<id>/` and the active release's id in `current`. the public server reads the
+/// pointer (cached after the first request), so switching releases is a single write and
+/// visitors never see two releases mixed
+pub struct Deployments {
+    storage: Arc<dyn Storage>,
+    current: DashMap<String, String>,
+    /// held while creating, switching and pruning releases
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl Deployments {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            current: DashMap::new(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// id of the release a site serves right now, read from its pointer on first use
+    pub async fn current_release(&self, site: &str) -> io::Result<Option<String>> {
+        if let Some(release) = self.current.get(site) {
+            return Ok(Some(release.clone()));
+        }
+        let pointer = match read_all(self.storage.as_ref(), &pointer_key(site)).await {
+            Ok(pointer) => pointer,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let release = String::from_utf8_lossy(&pointer).trim().to_string();
+        if !is_valid_name(&release) {
+            tracing::warn!("Ignoring broken deployment pointer for site {}", site);
+            return Ok(None);
+        }
+        self.current.insert(site.to_string(), release.clone());
+        Ok(Some(release))
+    }
+
+    /// all sites that have at least one release
+    pub async fn sites(&self) -> io::Result<Vec<String>> {
+        Ok(child_names(self.storage.list(&format!("{}/", DEPLOYMENTS_DIR), false).await?))
+    }
+
+    /// a site's releases, oldest first (ids sort by creation time)
+    pub async fn releases(&self, site: &str) -> io::Result<Vec<String>> {
+        Ok(child_names(self.storage.list(&format!("{}/releases/", site_key(site)), false).await?))
+    }
+
+    pub async fn site_info(&self, site: &str) -> io::Result<SiteInfo> {
+        Ok(SiteInfo {
+            name: site.to_string(),
+            current: self.current_release(site).await?,
+            releases: self.releases(site).await?,
+        })
+    }
+
+    // point a site at one of its releases, in storage first so a restart serves the same one
+    async fn switch(&self, site: &str, release: &str) -> io::Result<()> {
+        put_bytes(self.storage.as_ref(), &pointer_key(site), Bytes::from(release.to_string())).await?;
+        self.current.insert(site.to_string(), release.to_string());
+        Ok(())
+    }
+
+    // drop the oldest releases beyond `keep`, never the active one or the one just created
+    async fn prune(&self, site: &str, keep: usize, created: &str) -> io::Result<Vec<String>> {
+        let current = self.current_release(site).await?;
+        let releases = self.releases(site).await?;
+        let excess = releases.len().saturating_sub(keep);
+
+        let mut pruned = Vec::new();
+        let removable = releases
+            .into_iter()
+            .filter(|r| Some(r) != current.as_ref() && r != created);
+        for release in removable.take(excess) {
+            self.storage.delete_prefix(&format!("{}/", release_key(site, &release))).await?;
+            pruned.push(release);
+        }
+        Ok(pruned)
+    }
+}
+
+fn site_key(site: &str) -> String {
+    format!("{}/{}", DEPLOYMENTS_DIR, site)
+}
+
+fn pointer_key(site: &str) -> String {
+    format!("{}/current", site_key(site))
+}
+
+/// where a release's files are kept in storage
+pub(crate) fn release_key(site: &str, release: &str) -> String {
+    format!("{}/releases/{}", site_key(site), release)
+}
+
+/// whether a stored key is a file of a deployed release (and not a site's pointer)
+pub(crate) fn is_release_file(key: &str) -> bool {
+    let mut segments = key.split('/');
+    segments.next() == Some(DEPLOYMENTS_DIR) && segments.nth(1) == Some("releases") && segments.nth(1).is_some()
+}
+
+// valid names of the "directories" one level down, sorted
+fn child_names(listing: Listing) -> Vec<String> {
+    let mut names: Vec<String> = listing
+        .prefixes
+        .iter()
+        .filter_map(|prefix| prefix.trim_end_matches('/').rsplit('/').next())
+        .filter(|name| is_valid_name(name))
+        .map(String::from)
+        .collect();
+    names.sort();
+    names
+}
+
+/// site names and release ids: letters, digits, '-' and '_'
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn error(status: StatusCode, error: String) -> (StatusCode, Json<ErrorResponse>) {
+    (status, Json(ErrorResponse { error }))
+}
+
+fn site_name(site: String) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    let site = site.to_lowercase();
+    if !is_valid_name(&site) {
+        return Err(error(StatusCode::BAD_REQUEST, format!("Invalid site name: {}", site)));
+    }
+    Ok(site)
+}
+
+fn site_error(site: &str, e: io::Error) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::error!("Failed to update deployments of site {}: {}", site, e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update site {}", site))
+}
+
+// upload a zip or tar(.gz/.zst) archive as a new release of a site and (by default) switch to it
+pub async fn deploy_site(
+    State(state): State<Arc<AppState>>,
+    Path(site): Path<String>,
+    Query(mut options): Query<DeployOptions>,
+    mut multipart: Multipart,
+) -> Result<Json<DeploymentResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let site = site_name(site)?;
+    let archive = receive_archive(&state, &mut multipart, |name, value| match name {
+        "activate" => options.activate = !matches!(value.trim(), "false" | "0" | "no"),
+        _ => tracing::debug!("Ignoring unknown deploy field: {}", name),
+    })
+    .await?;
+    // releases count against the global quota like any other files
+    let (staging, extracted) = stage_archive(&state, archive, None).await?;
+    let _reservation = reserve_upload(&state, None, extracted.total_size, None)
+        .await
+        .map_err(|e| quota_error(&site, e))?;
+
+    let deployments = &state.deployments;
+    let _guard = deployments.lock.lock().await;
+    let existing = deployments.releases(&site).await.map_err(|e| site_error(&site, e))?;
+    let mut release = Utc::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+    // two deploys within the same millisecond still get their own releases
+    while existing.contains(&release) {
+        release.push('_');
+    }
+
+    let create = async {
+        install(&state, &staging.0, &release_key(&site, &release), &extracted, true).await?;
+        if options.activate {
+            deployments.switch(&site, &release).await?;
+        }
+        deployments.prune(&site, state.deploy.keep_releases, &release).await
+    };
+    let pruned = create.await.map_err(|e| site_error(&site, e))?;
+
+    tracing::info!(
+        "🚀 Deployed release {} of site {} ({} files, {} bytes){}",
+        release,
+        site,
+        extracted.files.len(),
+        extracted.total_size,
+        if options.activate { ", now live" } else { "" }
+    );
+    Ok(Json(DeploymentResponse {
+        success: true,
+        site,
+        release,
+        files: extracted.files.len(),
+        total_size: extracted.total_size,
+        active: options.activate,
+        pruned,
+        skipped: extracted.skipped,
+    }))
+}
+
+// list deployed sites
+pub async fn list_sites(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SiteListResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let deployments = &state.deployments;
+    let mut sites = Vec::new();
+    for site in deployments.sites().await.map_err(|e| site_error("*", e))? {
+        sites.push(deployments.site_info(&site).await.map_err(|e| site_error(&site, e))?);
+    }
+    Ok(Json(SiteListResponse { total: sites.len(), sites }))
+}
+
+// get a site's releases and the one it serves
+pub async fn get_site(
+    State(state): State<Arc<AppState>>,
+    Path(site): Path<String>,
+) -> Result<Json<SiteInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let site = site_name(site)?;
+    let info = state.deployments.site_info(&site).await.map_err(|e| site_error(&site, e))?;
+    if info.releases.is_empty() {
+        return Err(error(StatusCode::NOT_FOUND, format!("Site not found: {}", site)));
+    }
+    Ok(Json(info))
+}
+
+// switch a site to one of its releases
+pub async fn activate_release(
+    State(state): State<Arc<AppState>>,
+    Path(site): Path<String>,
+    Json(payload): Json<ActivateReleaseRequest>,
+) -> Result<Json<SiteInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let site = site_name(site)?;
+    let deployments = &state.deployments;
+    let _guard = deployments.lock.lock().await;
+
+    let releases = deployments.releases(&site).await.map_err(|e| site_error(&site, e))?;
+    if !releases.contains(&payload.release) {
+        return Err(error(
+            StatusCode::NOT_FOUND,
+            format!("Release {} of site {} not found", payload.release, site),
+        ));
+    }
+    deployments.switch(&site, &payload.release).await.map_err(|e| site_error(&site, e))?;
+
+    tracing::info!("🚀 Site {} now serves release {}", site, payload.release);
+    Ok(Json(SiteInfo { name: site, current: Some(payload.release), releases }))
+}
+
+// go back to the release before the active one
+pub async fn rollback_site(
+    State(state): State<Arc<AppState>>,
+    Path(site): Path<String>,
+) -> Result<Json<SiteInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let site = site_name(site)?;
+    let deployments = &state.deployments;
+    let _guard = deployments.lock.lock().await;
+
+    let releases = deployments.releases(&site).await.map_err(|e| site_error(&site, e))?;
+    let current = deployments.current_release(&site).await.map_err(|e| site_error(&site, e))?;
+    let Some(current) = current else {
+        return Err(error(StatusCode::NOT_FOUND, format!("Site {} has no active release", site)));
+    };
+    let Some(previous) = releases.iter().rev().find(|r| **r < current).cloned() else {
+        return Err(error(
+            StatusCode::CONFLICT,
+            format!("Site {} has no release before {}", site, current),
+        ));
+    };
+    deployments.switch(&site, &previous).await.map_err(|e| site_error(&site, e))?;
+
+    tracing::info!("⏪ Rolled site {} back from release {} to {}", site, current, previous);
+    Ok(Json(SiteInfo { name: site, current: Some(previous), releases }))
+}
+
+// stop serving a site and remove all its releases
+pub async fn delete_site(
+    State(state): State<Arc<AppState>>,
+    Path(site): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let site = site_name(site)?;
+    let deployments = &state.deployments;
+    let _guard = deployments.lock.lock().await;
+
+    let prefix = format!("{}/", site_key(&site));
+    let stored = deployments.storage.list(&prefix, true).await.map_err(|e| site_error(&site, e))?;
+    if stored.objects.is_empty() {
+        return Err(error(StatusCode::NOT_FOUND, format!("Site not found: {}", site)));
+    }
+    deployments.current.remove(&site);
+    deployments.storage.delete_prefix(&prefix).await.map_err(|e| write_error(&site, e))?;
+
+    tracing::info!("🗑️ Deleted site {}", site);
+    Ok(Json(DeleteResponse { success: true, filename: site }))
+}
+
+/// the site(s) a public router serves
+#[derive(Clone)]
+pub struct SiteTarget {
+    pub deployments: Arc<Deployments>,
+    /// a single site for virtual hosts, None to take the site from the first path segment
+    pub site: Option<String>,
+}
+
+/// serve a file of a site's active release
+pub async fn serve_site(
+    State(target): State<Arc<SiteTarget>>,
+    OriginalUri(original): OriginalUri,
+    mut req: Request<Body>,
+) -> Response {
+    let site = match &target.site {
+        Some(site) => site.clone(),
+        None => {
+            let path = req.uri().path().trim_start_matches('/').to_string();
+            // names no deploy could have used aren't looked up at all
+            let (site, rest) = match path.split_once('/') {
+                Some((site, _)) if !is_valid_name(&site.to_lowercase()) => return StatusCode::NOT_FOUND.into_response(),
+                Some(parts) => parts,
+                None if !is_valid_name(&path.to_lowercase()) => return StatusCode::NOT_FOUND.into_response(),
+                None => {
+                    // relative links inside the site need the trailing slash
+                    let location = format!("{}/", original.path());
+                    return (StatusCode::PERMANENT_REDIRECT, [(header::LOCATION, location)]).into_response();
+                }
+            };
+            let mut uri = format!("/{}", rest);
+            if let Some(query) = req.uri().query() {
+                uri = format!("{}?{}", uri, query);
+            }
+            let Ok(uri) = uri.parse() else {
+                return StatusCode::BAD_REQUEST.into_response();
+            };
+            *req.uri_mut() = uri;
+            site.to_lowercase()
+        }
+    };
+
+    let deployments = &target.deployments;
+    let release = match deployments.current_release(&site).await {
+        Ok(Some(release)) => release,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to read the active release of site {}: {}", site, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let release = release_key(&site, &release);
+
+    // local releases go through ServeDir (precompressed variants included), anything else
+    // is streamed from the storage backend
+    match deployments.storage.local_root() {
+        Some(root) => ServeDir::new(root.join(release))
+            .append_index_html_on_directories(true)
+            .precompressed_gzip()
+            .precompressed_br()
+            .oneshot(req)
+            .await
+            .map(|response| response.map(Body::new))
+            .unwrap_or_else(|e| match e {}),
+        None => serve_prefixed(deployments.storage.as_ref(), &format!("{}/", release), req).await,
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::state::AppState;
+
+/// how much free disk space uploads have to leave alone
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskSettings {
+    /// bytes that must stay free after an upload lands
+    pub reserve_bytes: u64,
+    /// the health check fails below this many free bytes
+    pub health_min_free_bytes: u64,
+}
+
+impl DiskSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            reserve_bytes: config.disk_reserve_bytes,
+            health_min_free_bytes: config.health_min_free_bytes,
+        }
+    }
+}
+
+/// free and total space of a filesystem
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct DiskSpace {
+    /// space available to us (excludes blocks reserved for root)
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// free and total space of the filesystem holding `path`
+///
+/// statvfs can stall on a busy or network filesystem, so it runs on the blocking pool
+pub async fn disk_space(path: &Path) -> std::io::Result<DiskSpace> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        Ok(DiskSpace {
+            free_bytes: fs4::available_space(&path)?,
+            total_bytes: fs4::total_space(&path)?,
+        })
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// bytes that in-flight uploads are still going to write
+pub fn pending_write_bytes(state: &AppState) -> u64 {
+    let single: u64 = state.pending_uploads.iter().map(|u| u.bytes).sum();
+    let chunked: u64 = state
+        .chunked_uploads
+        .iter()
+        .map(|u| {
+            let received = (u.received_chunks.len() as u64).saturating_mul(u.chunk_size as u64);
+            u.total_size.saturating_sub(received)
+        })
+        .sum();
+    single + chunked
+}
+
+/// bytes that can still be written without eating into the reserve, None for remote backends
+/// or when the free space can't be measured
+pub async fn writable_bytes(state: &AppState) -> Option<u64> {
+    let root = state.storage.local_root()?;
+    let space = disk_space(root)
+        .await
+        .map_err(|e| tracing::warn!("Failed to check free disk space: {}", e))
+        .ok()?;
+    Some(
+        space
+            .free_bytes
+            .saturating_sub(pending_write_bytes(state))
+            .saturating_sub(state.disk.reserve_bytes),
+    )
+}
+
+/// make sure `bytes` more can be written without eating into the reserve
+///
+/// in-flight uploads count as already written; uploads take their space through
+/// [`crate::quota::reserve`], which checks and registers them under one lock so two of them
+/// can't both fit into the same free bytes. returns the reason as the error if there isn't
+/// enough room
+pub async fn ensure_free_space(state: &AppState, bytes: u64) -> Result<(), String> {
+    // remote backends manage their own space
+    let Some(root) = state.storage.local_root() else {
+        return Ok(());
+    };
+    let space = match disk_space(root).await {
+        Ok(space) => space,
+        Err(e) => {
+            // don't refuse uploads just because statvfs is unsupported here
+            tracing::warn!("Failed to check free disk space: {}", e);
+            return Ok(());
+        }
+    };
+
+    let needed = bytes
+        .saturating_add(pending_write_bytes(state))
+        .saturating_add(state.disk.reserve_bytes);
+    if needed > space.free_bytes {
+        return Err(format!(
+            "Not enough disk space: {} bytes needed (including {} reserved), {} free",
+            needed, state.disk.reserve_bytes, space.free_bytes
+        ));
+    }
+    Ok(())
+}
+
+/// whether an io error means the disk filled up
+pub fn is_disk_full(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded
+    )
+}
@@ -0,0 +1,168 @@
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Json, Response};
+use percent_encoding::percent_decode_str;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::handlers::persist_metadata;
+use crate::metadata::{Disposition, MetadataStore};
+use crate::models::{DispositionRequest, DispositionResponse, ErrorResponse};
+use crate::state::AppState;
+use crate::storage::exists;
+use crate::utils::{content_disposition, matches_glob, safe_key, sanitize_filename};
+
+/// the sandbox Content-Security-Policy unless configured otherwise: no scripts, forms,
+/// plugins or same-origin access, while images, media and inline styles still render
+pub const DEFAULT_SANDBOX_CSP: &str =
+    "sandbox; default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'";
+
+/// which files the public server sends as downloads, and whether the rest are sandboxed
+#[derive(Clone, Debug)]
+pub struct DispositionPolicy {
+    /// lowercase mime types (`*` allowed, e.g. "image/*") and extensions without the dot
+    pub attachment: Vec<String>,
+    /// send the sandbox policy with files that aren't downloads
+    pub sandbox: bool,
+    pub sandbox_csp: HeaderValue,
+}
+
+impl Default for DispositionPolicy {
+    fn default() -> Self {
+        Self {
+            attachment: Vec::new(),
+            sandbox: false,
+            sandbox_csp: HeaderValue::from_static(DEFAULT_SANDBOX_CSP),
+        }
+    }
+}
+
+impl DispositionPolicy {
+    /// `attachment` entries with a '/' are mime types, the rest extensions (".html" or "html")
+    pub fn new(attachment: &[String], sandbox: bool) -> Self {
+        let attachment = attachment
+            .iter()
+            .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        Self { attachment, sandbox, ..Default::default() }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut policy = Self::new(&config.attachment_types, config.sandbox_uploads);
+        if let Some(csp) = &config.sandbox_csp {
+            match HeaderValue::from_str(csp) {
+                Ok(value) => policy.sandbox_csp = value,
+                Err(_) => tracing::warn!("Ignoring invalid DISPOSITION_SANDBOX_CSP, using the default"),
+            }
+        }
+        policy
+    }
+
+    /// this policy with a virtual host's sandbox setting
+    pub fn with_overrides(&self, sandbox: Option<bool>) -> Self {
+        Self { sandbox: sandbox.unwrap_or(self.sandbox), ..self.clone() }
+    }
+
+    /// whether a key served with a content type must be downloaded
+    pub fn is_attachment(&self, key: &str, content_type: Option<&str>) -> bool {
+        let name = key.rsplit('/').next().unwrap_or_default().to_lowercase();
+        let extension = name.rsplit_once('.').map(|(_, ext)| ext);
+        // parameters like charset don't matter
+        let mime = content_type.map(|ct| ct.split(';').next().unwrap_or_default().trim().to_lowercase());
+        self.attachment.iter().any(|entry| {
+            if entry.contains('/') {
+                mime.as_deref().is_some_and(|mime| matches_glob(entry, mime))
+            } else {
+                extension == Some(entry.as_str())
+            }
+        })
+    }
+
+    /// how a file is served, its own setting first
+    pub fn resolve(&self, key: &str, content_type: Option<&str>, own: Option<Disposition>) -> Disposition {
+        match own {
+            Some(disposition) => disposition,
+            None if self.is_attachment(key, content_type) => Disposition::Attachment,
+            None if self.sandbox => Disposition::Sandbox,
+            None => Disposition::Inline,
+        }
+    }
+}
+
+/// where the public server finds the policy and the files' own dispositions
+pub struct FileDispositions {
+    pub policy: DispositionPolicy,
+    pub metadata: Arc<MetadataStore>,
+}
+
+/// send files as downloads or under the sandbox policy, by the policy and their metadata
+pub async fn apply_disposition(
+    State(dispositions): State<Arc<FileDispositions>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = req.uri().path();
+    let is_read = req.method() == Method::GET || req.method() == Method::HEAD;
+    // directories are listings or indexes
+    if !is_read || path.ends_with('/') {
+        return next.run(req).await;
+    }
+    // hidden paths are left alone, deployed sites under /.sites included
+    let decoded = percent_decode_str(path).decode_utf8_lossy().to_string();
+    let Some(key) = safe_key(&decoded).filter(|key| !key.is_empty()) else {
+        return next.run(req).await;
+    };
+
+    let mut response = next.run(req).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let content_type = response.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let own = dispositions.metadata.get(&key).disposition;
+    match dispositions.policy.resolve(&key, content_type, own) {
+        Disposition::Inline => {}
+        Disposition::Attachment => {
+            let name = key.rsplit('/').next().unwrap_or_default();
+            if let Ok(value) = HeaderValue::from_str(&content_disposition(name)) {
+                tracing::trace!("📎 Serving {} as a download", key);
+                response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+            }
+        }
+        Disposition::Sandbox => {
+            // replaces whatever policy the security headers set
+            let csp = dispositions.policy.sandbox_csp.clone();
+            response.headers_mut().insert(header::CONTENT_SECURITY_POLICY, csp);
+        }
+    }
+    response
+}
+
+// set or clear how a file is served (null goes back to the policy)
+pub async fn set_disposition(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+    Json(payload): Json<DispositionRequest>,
+) -> Result<Json<DispositionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    if !exists(state.storage.as_ref(), &filename).await.unwrap_or(false) {
+        tracing::warn!("❌ Cannot set the disposition of missing file: {}", filename);
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ));
+    }
+
+    state.metadata.update(&filename, |meta| meta.disposition = payload.disposition);
+    persist_metadata(&state, "file metadata").await?;
+
+    tracing::info!("📎 Disposition of {} set to {:?}", filename, payload.disposition);
+    Ok(Json(DispositionResponse {
+        filename,
+        disposition: payload.disposition,
+    }))
+}
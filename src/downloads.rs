@@ -0,0 +1,176 @@
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::State;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use http_body::{Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::handlers::{delete_files, DeleteMode};
+use crate::state::AppState;
+use crate::utils::request_filename;
+
+/// enforce max_downloads on the public router and count completed downloads
+///
+/// only full GET responses count; HEAD requests and range probes pass through untouched
+pub async fn enforce_download_limits(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let Some(filename) = request_filename(req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+
+    let meta = state.metadata.get(&filename);
+    let Some(max_downloads) = meta.max_downloads else {
+        return Ok(next.run(req).await);
+    };
+
+    if meta.download_count >= max_downloads {
+        tracing::debug!("🔥 Refusing to serve burned file: {}", filename);
+        return Err(StatusCode::GONE);
+    }
+
+    let counts = req.method() == Method::GET && !req.headers().contains_key(header::RANGE);
+    if !counts {
+        return Ok(next.run(req).await);
+    }
+
+    // reserve a slot up front so concurrent downloads can't go over the limit
+    let guard = DownloadGuard::reserve(&state, &filename, meta.download_count, max_downloads)
+        .ok_or_else(|| {
+            tracing::debug!("All remaining downloads of {} are in progress", filename);
+            StatusCode::GONE
+        })?;
+
+    let response = next.run(req).await;
+    if response.status() != StatusCode::OK {
+        // 304s, 404s and friends don't use up a download (guard drop releases the slot)
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = CountedBody {
+        inner: body,
+        guard: Some(guard),
+    };
+    Ok(Response::from_parts(parts, Body::new(body)))
+}
+
+// holds a reserved download slot, released on drop
+struct DownloadGuard {
+    state: Arc<AppState>,
+    filename: String,
+}
+
+impl DownloadGuard {
+    fn reserve(state: &Arc<AppState>, filename: &str, downloaded: u64, max_downloads: u64) -> Option<Self> {
+        let mut active = state.active_downloads.entry(filename.to_string()).or_insert(0);
+        if downloaded + *active >= max_downloads {
+            return None;
+        }
+        *active += 1;
+
+        Some(Self {
+            state: state.clone(),
+            filename: filename.to_string(),
+        })
+    }
+
+    // the whole body went out, count it
+    fn complete(self) {
+        let updated = self
+            .state
+            .metadata
+            .update(&self.filename, |meta| meta.download_count += 1);
+        tracing::debug!(
+            "Download {}/{:?} of {} completed",
+            updated.download_count,
+            updated.max_downloads,
+            self.filename
+        );
+
+        let state = self.state.clone();
+        let filename = self.filename.clone();
+        let burn = updated
+            .max_downloads
+            .is_some_and(|max| updated.download_count >= max);
+
+        tokio::spawn(async move {
+            if burn {
+                burn_file(&state, &filename, updated.download_count).await;
+            } else if let Err(e) = state.metadata.persist().await {
+                tracing::warn!("Failed to persist download count for {}: {}", filename, e);
+            }
+        });
+    }
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        let now_idle = match self.state.active_downloads.get_mut(&self.filename) {
+            Some(mut active) => {
+                *active = active.saturating_sub(1);
+                *active == 0
+            }
+            None => false,
+        };
+        if now_idle {
+            self.state.active_downloads.remove_if(&self.filename, |_, active| *active == 0);
+        }
+    }
+}
+
+// delete a file that hit its download limit, leaving a tombstone so it answers 410
+async fn burn_file(state: &AppState, filename: &str, download_count: u64) {
+    let response = delete_files(state, vec![filename.to_string()], DeleteMode::Permanent).await;
+    if response.failed > 0 {
+        tracing::error!("Failed to delete burned file {}: {:?}", filename, response.results);
+    }
+
+    state.metadata.update(filename, |meta| {
+        meta.max_downloads = Some(download_count);
+        meta.download_count = download_count;
+    });
+    if let Err(e) = state.metadata.persist().await {
+        tracing::warn!("Failed to persist tombstone for {}: {}", filename, e);
+    }
+
+    tracing::info!("🔥 Burned {} after {} downloads", filename, download_count);
+}
+
+// response body that reports back once it has been streamed to the end
+struct CountedBody {
+    inner: Body,
+    guard: Option<DownloadGuard>,
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(None) = poll {
+            if let Some(guard) = self.guard.take() {
+                guard.complete();
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // keep hyper polling until we've seen the end ourselves
+        self.guard.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
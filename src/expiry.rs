@@ -0,0 +1,102 @@
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+
+use crate::handlers::{delete_files, DeleteMode};
+use crate::metadata::FileMetadata;
+use crate::models::UploadOptions;
+use crate::state::AppState;
+use crate::storage::exists;
+use crate::utils::{request_filename, sanitize_filename};
+
+/// work out when an upload should expire from its expires_at / ttl options
+pub fn resolve_expiry(options: &UploadOptions) -> Result<Option<DateTime<Utc>>, String> {
+    let now = Utc::now();
+    let expires_at = match (options.expires_at, options.ttl) {
+        (Some(_), Some(_)) => return Err("Specify either expires_at or ttl, not both".to_string()),
+        (Some(at), None) => at,
+        (None, Some(ttl)) => {
+            let ttl = i64::try_from(ttl)
+                .ok()
+                .and_then(Duration::try_seconds)
+                .ok_or_else(|| format!("Invalid ttl: {}", ttl))?;
+            now + ttl
+        }
+        (None, None) => return Ok(None),
+    };
+
+    if expires_at <= now {
+        return Err(format!("Expiry is in the past: {}", expires_at.to_rfc3339()));
+    }
+    Ok(Some(expires_at))
+}
+
+/// whether a file has passed its expiry time
+pub fn is_expired(meta: &FileMetadata) -> bool {
+    meta.expires_at.is_some_and(|at| at <= Utc::now())
+}
+
+/// answer 410 Gone for expired files, even before the sweeper got to them
+pub async fn enforce_expiry(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if let Some(filename) = request_filename(req.uri().path()) {
+        if is_expired(&state.metadata.get(&filename)) {
+            tracing::debug!("⌛ Refusing to serve expired file: {}", filename);
+            return Err(StatusCode::GONE);
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// delete every expired file, returns how many were removed
+pub async fn sweep_expired(state: &AppState) -> usize {
+    let expired: Vec<String> = state
+        .metadata
+        .files
+        .iter()
+        .filter(|entry| is_expired(entry.value()))
+        .map(|entry| entry.key().clone())
+        .collect();
+
+    if expired.is_empty() {
+        return 0;
+    }
+
+    tracing::debug!("Sweeping {} expired files", expired.len());
+    let response = delete_files(state, expired.clone(), DeleteMode::Permanent).await;
+
+    // files that are already gone from disk still need their metadata dropped
+    for result in response.results.iter().filter(|r| !r.success) {
+        let name = sanitize_filename(&result.filename);
+        if !exists(state.storage.as_ref(), &name).await.unwrap_or(true) {
+            state.metadata.remove_file(&result.filename);
+        }
+    }
+    if response.failed > 0 {
+        if let Err(e) = state.metadata.persist().await {
+            tracing::warn!("Failed to persist metadata after expiry sweep: {}", e);
+        }
+    }
+
+    tracing::info!("⌛ Expiry sweep removed {} files", response.successful);
+    response.successful
+}
+
+/// run the expiry sweeper in the background forever
+pub fn spawn_expiry_sweeper(state: Arc<AppState>, interval_secs: u64) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            sweep_expired(&state).await;
+        }
+    })
+}
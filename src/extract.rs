@@ -0,0 +1,609 @@
+use axum::{
+    extract::{Extension, Multipart, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures_util::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::disk::{ensure_free_space, writable_bytes};
+use crate::filetypes::unsupported_type;
+use crate::handlers::{
+    apply_upload_settings, check_content, insufficient_storage, quota_error, write_error, CHUNKS_DIR,
+};
+use crate::hooks::run_hooks;
+use crate::middleware::ApiKeyName;
+use crate::models::{ErrorResponse, ExtractOptions, ExtractResponse};
+use crate::quota::{remaining_bytes, reserve_upload, QuotaError};
+use crate::state::{AppState, UploadSettings};
+use crate::storage::{ByteStream, Storage};
+use crate::trash::move_to_trash;
+use crate::utils::safe_key;
+use crate::versions::snapshot_version;
+
+// how far ahead free space is checked while an archive is received
+const FREE_SPACE_CHECK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// zip-bomb defense: how much an uploaded archive may expand to
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractLimits {
+    /// most entries (files and directories) an archive may have
+    pub max_entries: usize,
+    /// most bytes all files together may take once extracted
+    pub max_bytes: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_bytes: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl ExtractLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_entries: config.extract_max_entries,
+            max_bytes: config.extract_max_bytes,
+        }
+    }
+}
+
+/// why an archive couldn't be extracted
+#[derive(Debug)]
+pub enum ExtractError {
+    /// not a zip or tar file at all
+    Unsupported,
+    /// a broken archive
+    Invalid(String),
+    /// an entry tries to escape the target directory
+    Unsafe(String),
+    /// more entries or bytes than the limits allow
+    TooLarge(String),
+    /// the files don't fit in what's left of the quotas or the disk
+    NoRoom(u64),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Unsupported => write!(f, "Not a zip, tar, tar.gz or tar.zst archive"),
+            ExtractError::Invalid(reason) => write!(f, "Invalid archive: {}", reason),
+            ExtractError::Unsafe(name) => write!(f, "Archive entry escapes the target directory: {}", name),
+            ExtractError::TooLarge(reason) => write!(f, "Archive too large: {}", reason),
+            ExtractError::NoRoom(room) => {
+                write!(f, "Not enough storage left: the archive expands to more than {} bytes", room)
+            }
+            ExtractError::Io(e) => write!(f, "Failed to extract archive: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(e: io::Error) -> Self {
+        ExtractError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ExtractError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => ExtractError::Io(e),
+            e => ExtractError::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl ExtractError {
+    pub(crate) fn into_response(self) -> (StatusCode, Json<ErrorResponse>) {
+        let status = match &self {
+            ExtractError::Unsupported => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ExtractError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ExtractError::Unsafe(_) => StatusCode::BAD_REQUEST,
+            ExtractError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ExtractError::NoRoom(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ExtractError::Io(e) => return write_error("archive", io::Error::new(e.kind(), e.to_string())),
+        };
+        tracing::warn!("📦 Rejecting archive upload: {}", self);
+        (status, Json(ErrorResponse { error: self.to_string() }))
+    }
+}
+
+/// what ended up in the staging directory
+#[derive(Debug, Default)]
+pub struct ExtractedArchive {
+    /// files by key relative to the staging directory, with their sizes
+    pub files: Vec<(String, u64)>,
+    pub total_size: u64,
+    pub skipped: Vec<String>,
+    /// what checking each file's content found (detected type, scan), by key
+    pub checked: BTreeMap<String, UploadSettings>,
+}
+
+/// archive formats we can extract, told apart by their first bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+fn detect_kind(file: &mut File) -> io::Result<Option<ArchiveKind>> {
+    let mut head = [0u8; 512];
+    let mut read = 0;
+    while read < head.len() {
+        let n = file.read(&mut head[read..])?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    file.rewind()?;
+
+    let kind = match head {
+        _ if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") => Some(ArchiveKind::Zip),
+        _ if head.starts_with(&[0x1f, 0x8b]) => Some(ArchiveKind::TarGz),
+        _ if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) => Some(ArchiveKind::TarZst),
+        _ if read == 512 && &head[257..262] == b"ustar" => Some(ArchiveKind::Tar),
+        _ => None,
+    };
+    Ok(kind)
+}
+
+// map an entry name to a key inside the target: Ok(None) for entries to leave out (hidden files,
+// macOS resource forks), an error for names climbing out with ".."
+fn entry_key(name: &str) -> Result<Option<String>, ExtractError> {
+    let normalized = name.replace('\\', "/");
+    let segments: Vec<&str> = normalized.split('/').filter(|s| !s.is_empty() && *s != ".").collect();
+    if segments.contains(&"..") || segments.first().is_some_and(|s| s.ends_with(':')) {
+        return Err(ExtractError::Unsafe(name.to_string()));
+    }
+    if segments.first() == Some(&"__MACOSX") {
+        return Ok(None);
+    }
+    Ok(safe_key(&segments.join("/")).filter(|key| !key.is_empty()))
+}
+
+// tracks the limits while entries get written out
+struct Extraction<'a> {
+    staging: &'a Path,
+    limits: ExtractLimits,
+    /// bytes left in the quotas and on the disk
+    room: u64,
+    entries: usize,
+    result: ExtractedArchive,
+}
+
+impl Extraction<'_> {
+    fn count_entry(&mut self) -> Result<(), ExtractError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(ExtractError::TooLarge(format!("more than {} entries", self.limits.max_entries)));
+        }
+        Ok(())
+    }
+
+    fn dir(&mut self, name: &str) -> Result<(), ExtractError> {
+        self.count_entry()?;
+        if let Some(key) = entry_key(name)? {
+            std::fs::create_dir_all(self.staging.join(key))?;
+        }
+        Ok(())
+    }
+
+    // whether `bytes` more still fit in the byte limit and the room
+    fn fits(&self, bytes: u64) -> Result<(), ExtractError> {
+        let total = self.result.total_size.saturating_add(bytes);
+        if total > self.limits.max_bytes {
+            return Err(ExtractError::TooLarge(format!("expands to more than {} bytes", self.limits.max_bytes)));
+        }
+        if total > self.room {
+            return Err(ExtractError::NoRoom(self.room));
+        }
+        Ok(())
+    }
+
+    // copy one file out of the archive, never reading more than the byte limit and the room allow
+    //
+    // `declared` is the size the archive claims, checked before anything gets written
+    fn file(&mut self, name: &str, declared: u64, reader: &mut dyn Read) -> Result<(), ExtractError> {
+        self.count_entry()?;
+        let Some(key) = entry_key(name)? else {
+            self.result.skipped.push(name.to_string());
+            return Ok(());
+        };
+        self.fits(declared)?;
+        let path = self.staging.join(&key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = File::create(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::IsADirectory | io::ErrorKind::AlreadyExists | io::ErrorKind::NotADirectory => {
+                ExtractError::Invalid(format!("conflicting entries for {}", key))
+            }
+            _ => ExtractError::Io(e),
+        })?;
+
+        let remaining = self.limits.max_bytes.min(self.room) - self.result.total_size;
+        let written = io::copy(&mut reader.take(remaining + 1), &mut out)?;
+        self.fits(written)?;
+        self.result.total_size += written;
+
+        // the same name twice: the later entry wins
+        if let Some(at) = self.result.files.iter().position(|(existing, _)| *existing == key) {
+            let (_, size) = self.result.files.remove(at);
+            self.result.total_size -= size;
+        }
+        self.result.files.push((key, written));
+        Ok(())
+    }
+
+    fn skip(&mut self, name: &str) -> Result<(), ExtractError> {
+        self.count_entry()?;
+        self.result.skipped.push(name.to_string());
+        Ok(())
+    }
+}
+
+/// extract a zip or tar(.gz/.zst) archive into an empty staging directory
+///
+/// the files may take at most `room` bytes on top of the limits; blocking, run it on the blocking pool
+pub fn extract_archive(
+    archive: &Path,
+    staging: &Path,
+    limits: ExtractLimits,
+    room: u64,
+) -> Result<ExtractedArchive, ExtractError> {
+    let mut file = File::open(archive)?;
+    let kind = detect_kind(&mut file)?.ok_or(ExtractError::Unsupported)?;
+    std::fs::create_dir_all(staging)?;
+    let mut extraction = Extraction {
+        staging,
+        limits,
+        room,
+        entries: 0,
+        result: ExtractedArchive::default(),
+    };
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))?;
+            // the central directory tells the entry count up front
+            if zip.len() > limits.max_entries {
+                return Err(ExtractError::TooLarge(format!("more than {} entries", limits.max_entries)));
+            }
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                let name = entry.name().to_string();
+                if entry.is_dir() {
+                    extraction.dir(&name)?;
+                } else if entry.is_symlink() {
+                    extraction.skip(&name)?;
+                } else {
+                    extraction.file(&name, entry.size(), &mut entry)?;
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz | ArchiveKind::TarZst => {
+            let reader: Box<dyn Read> = match kind {
+                ArchiveKind::TarGz => Box::new(flate2::read::GzDecoder::new(BufReader::new(file))),
+                ArchiveKind::TarZst => Box::new(zstd::Decoder::new(file)?),
+                _ => Box::new(BufReader::new(file)),
+            };
+            let mut tar = tar::Archive::new(reader);
+            let entries = tar.entries().map_err(|e| ExtractError::Invalid(e.to_string()))?;
+            for entry in entries {
+                let mut entry = entry.map_err(|e| ExtractError::Invalid(e.to_string()))?;
+                let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+                match entry.header().entry_type() {
+                    tar::EntryType::Directory => extraction.dir(&name)?,
+                    tar::EntryType::Regular | tar::EntryType::Continuous => {
+                        let declared = entry.header().size().unwrap_or_default();
+                        extraction.file(&name, declared, &mut entry)?
+                    }
+                    // pax and gnu long name headers are folded into the entries by the tar reader
+                    _ => extraction.skip(&name)?,
+                }
+            }
+        }
+    }
+
+    Ok(extraction.result)
+}
+
+/// a temporary file or directory, removed when dropped
+pub(crate) struct TempPath(pub(crate) PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+    }
+}
+
+fn bad_request(error: String) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Rejecting archive upload: {}", error);
+    (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))
+}
+
+/// where archives are received and extracted: the chunks directory of local storage, so
+/// extracted files are renamed into place; files_dir's for other backends, which get them copied
+fn work_dir(state: &AppState) -> PathBuf {
+    state.storage.local_root().unwrap_or(&state.files_dir).join(CHUNKS_DIR)
+}
+
+/// save the archive of a multipart upload to the work directory
+///
+/// text fields sent before the file go to `on_field`
+pub(crate) async fn receive_archive(
+    state: &AppState,
+    multipart: &mut Multipart,
+    mut on_field: impl FnMut(&str, String),
+) -> Result<TempPath, (StatusCode, Json<ErrorResponse>)> {
+    let work_dir = work_dir(state);
+    let archive = TempPath(work_dir.join(format!("archive-{}", Uuid::new_v4())));
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(format!("Failed to read multipart field: {}", e)))?
+    {
+        if field.file_name().is_none() {
+            let name = field.name().unwrap_or_default().to_string();
+            let value = field
+                .text()
+                .await
+                .map_err(|e| bad_request(format!("Failed to read form field {}: {}", name, e)))?;
+            on_field(&name, value);
+            continue;
+        }
+
+        // the archive goes to disk first, zips are read from the end
+        tokio::fs::create_dir_all(&work_dir).await.map_err(|e| write_error("archive", e))?;
+        let mut file = tokio::fs::File::create(&archive.0).await.map_err(|e| write_error("archive", e))?;
+        let (mut size, mut checked) = (0u64, 0u64);
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| bad_request(format!("Failed to read archive: {}", e)))?
+        {
+            size += chunk.len() as u64;
+            // the disk is asked for room a stretch ahead, not for every chunk
+            if size > checked {
+                let ahead = (size - checked).max(FREE_SPACE_CHECK_BYTES);
+                ensure_free_space(state, ahead).await.map_err(|e| insufficient_storage("archive", e))?;
+                checked += ahead;
+            }
+            file.write_all(&chunk).await.map_err(|e| write_error("archive", e))?;
+        }
+        file.flush().await.map_err(|e| write_error("archive", e))?;
+        tracing::debug!("Received archive of {} bytes", size);
+        return Ok(archive);
+    }
+    Err(bad_request("No archive provided".to_string()))
+}
+
+/// extract a received archive into a fresh staging directory in the work directory
+///
+/// the files have to fit in what's left of the global quota, `owner`'s quota and the disk
+pub(crate) async fn stage_archive(
+    state: &AppState,
+    archive: TempPath,
+    owner: Option<&str>,
+) -> Result<(TempPath, ExtractedArchive), (StatusCode, Json<ErrorResponse>)> {
+    let quota_room = remaining_bytes(state, owner)
+        .await
+        .map_err(|e| quota_error("archive", QuotaError::Io(e)))?;
+    let room = quota_room.unwrap_or(u64::MAX).min(writable_bytes(state).await.unwrap_or(u64::MAX));
+
+    // extract next to the files so the result can be renamed into place
+    let staging = TempPath(work_dir(state).join(format!("extract-{}", Uuid::new_v4())));
+    let (archive_path, staging_path, limits) = (archive.0.clone(), staging.0.clone(), state.extract);
+    let mut extracted = tokio::task::spawn_blocking(move || extract_archive(&archive_path, &staging_path, limits, room))
+        .await
+        .map_err(|e| write_error("archive", io::Error::other(e)))?
+        .map_err(ExtractError::into_response)?;
+    check_extracted(state, &staging.0, &mut extracted).await?;
+    Ok((staging, extracted))
+}
+
+// hold every extracted file to the rules single uploads get, one refused file refuses the archive
+async fn check_extracted(
+    state: &AppState,
+    staging: &Path,
+    extracted: &mut ExtractedArchive,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let in_archive = |key: &str, (status, Json(e)): (StatusCode, Json<ErrorResponse>)| {
+        (status, Json(ErrorResponse { error: format!("{}: {}", key, e.error) }))
+    };
+    for (key, _) in &extracted.files {
+        state
+            .upload_types
+            .check_extension(key)
+            .map_err(|e| in_archive(key, unsupported_type(key, e)))?;
+        let mut settings = UploadSettings::default();
+        let path = staging.join(key);
+        check_content(state, key, &mut settings, || staged_file(path.clone()))
+            .await
+            .map_err(|e| in_archive(key, e))?;
+        extracted.checked.insert(key.clone(), settings);
+    }
+    Ok(())
+}
+
+// a staged file's content, opened once it's read
+fn staged_file(path: PathBuf) -> ByteStream {
+    Box::pin(futures_util::stream::once(tokio::fs::File::open(path)).map_ok(ReaderStream::new).try_flatten())
+}
+
+// upload a zip or tar(.gz/.zst) archive and extract it into a directory
+// options (dir, replace) come from the query string or from form fields sent before the file
+pub async fn upload_archive(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKeyName>>,
+    Query(mut options): Query<ExtractOptions>,
+    mut multipart: Multipart,
+) -> Result<Json<ExtractResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let archive = receive_archive(&state, &mut multipart, |name, value| match name {
+        "dir" => options.dir = value,
+        "replace" => options.replace = matches!(value.trim(), "true" | "1" | "yes"),
+        _ => tracing::debug!("Ignoring unknown archive upload field: {}", name),
+    })
+    .await?;
+
+    let target = safe_key(&options.dir).ok_or_else(|| bad_request(format!("Invalid directory: {}", options.dir)))?;
+    if options.replace && target.is_empty() {
+        return Err(bad_request("replace needs a directory, the root can't be swapped".to_string()));
+    }
+    let owner = key.map(|Extension(ApiKeyName(name))| name);
+    let (staging, extracted) = stage_archive(&state, archive, owner.as_deref()).await?;
+
+    let _reservation = reserve_upload(&state, owner.as_deref(), extracted.total_size, None)
+        .await
+        .map_err(|e| quota_error(&target, e))?;
+
+    let replaced = install(&state, &staging.0, &target, &extracted, options.replace)
+        .await
+        .map_err(|e| write_error(&target, e))?;
+
+    for (key, _) in &extracted.files {
+        let settings = UploadSettings {
+            owner: owner.clone(),
+            ..extracted.checked.get(key).cloned().unwrap_or_default()
+        };
+        apply_upload_settings(&state, &join_key(&target, key), &settings);
+    }
+    if let Err(e) = state.metadata.persist().await {
+        tracing::warn!("Failed to persist metadata after extracting into {}: {}", target, e);
+    }
+    for (key, _) in &extracted.files {
+        run_hooks(&state, &join_key(&target, key));
+    }
+
+    tracing::info!(
+        "📦 Extracted {} files ({} bytes) into /{}{}",
+        extracted.files.len(),
+        extracted.total_size,
+        target,
+        if replaced { ", replacing the old content" } else { "" }
+    );
+    Ok(Json(ExtractResponse {
+        success: true,
+        dir: target,
+        files: extracted.files.len(),
+        total_size: extracted.total_size,
+        replaced,
+        skipped: extracted.skipped,
+    }))
+}
+
+fn join_key(dir: &str, key: &str) -> String {
+    if dir.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}/{}", dir, key)
+    }
+}
+
+/// move extracted files from the staging directory into `target`
+///
+/// with `replace` the old files the archive doesn't have go to the trash and the ones it
+/// overwrites are kept as versions where versioning is on, then the target directory is swapped
+/// for the staging one on local storage, or the new files are written over it elsewhere.
+/// returns whether an existing directory was replaced
+pub(crate) async fn install(
+    state: &AppState,
+    staging: &Path,
+    target: &str,
+    extracted: &ExtractedArchive,
+    replace: bool,
+) -> io::Result<bool> {
+    let storage = state.storage.as_ref();
+    let new_keys: BTreeSet<String> = extracted.files.iter().map(|(key, _)| join_key(target, key)).collect();
+
+    if replace {
+        let old = storage.list(&format!("{}/", target), true).await?.objects;
+        let replaced = !old.is_empty();
+        for object in &old {
+            if new_keys.contains(&object.key) {
+                snapshot_version(state, &object.key).await?;
+            } else {
+                move_to_trash(state, &object.key).await?;
+            }
+        }
+        match storage.local_root() {
+            Some(root) => {
+                swap_dir(&root.join(target), staging, &work_dir(state)).await?;
+                // moved on disk, not through the storage
+                state.stored.invalidate();
+            }
+            None => put_files(storage, staging, target, extracted).await?,
+        }
+        return Ok(replaced);
+    }
+
+    for (key, _) in &extracted.files {
+        let full_key = join_key(target, key);
+        snapshot_version(state, &full_key).await?;
+    }
+    match storage.local_root() {
+        Some(root) => {
+            for (key, _) in &extracted.files {
+                let dest = root.join(join_key(target, key));
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::rename(staging.join(key), &dest).await?;
+            }
+            state.stored.invalidate();
+        }
+        None => put_files(storage, staging, target, extracted).await?,
+    }
+    Ok(false)
+}
+
+// swap a directory for another one, keeping the old one until the new one is in place
+//
+// two renames, so for a moment nothing is at `dest`; requests landing then get a 404
+async fn swap_dir(dest: &Path, staging: &Path, work_dir: &Path) -> io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let old = TempPath(work_dir.join(format!("replaced-{}", Uuid::new_v4())));
+    let had_old = tokio::fs::metadata(dest).await.is_ok();
+    if had_old {
+        tokio::fs::rename(dest, &old.0).await?;
+    }
+    if let Err(e) = tokio::fs::rename(staging, dest).await {
+        // put the old content back rather than leave nothing there
+        if had_old {
+            let _ = tokio::fs::rename(&old.0, dest).await;
+        }
+        return Err(e);
+    }
+    tokio::task::spawn_blocking(move || drop(old));
+    Ok(())
+}
+
+// write staged files to a storage backend without a local directory
+async fn put_files(storage: &dyn Storage, staging: &Path, target: &str, extracted: &ExtractedArchive) -> io::Result<()> {
+    for (key, _) in &extracted.files {
+        storage.put(&join_key(target, key), staged_file(staging.join(key))).await?;
+    }
+    Ok(())
+}
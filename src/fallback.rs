@@ -0,0 +1,155 @@
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use percent_encoding::percent_decode_str;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::utils::safe_key;
+
+/// statuses that get a custom page when one exists
+pub const ERROR_PAGE_STATUSES: [StatusCode; 4] = [
+    StatusCode::FORBIDDEN,
+    StatusCode::NOT_FOUND,
+    StatusCode::GONE,
+    StatusCode::INTERNAL_SERVER_ERROR,
+];
+
+/// error pages are looked up at most this many directories deep, so a long path can't fan out
+/// into one lookup per segment
+pub const MAX_ERROR_PAGE_DEPTH: usize = 8;
+
+/// what the public server answers when there's no file for a path
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FallbackSettings {
+    /// directories whose unknown paths get the directory's index.html, for client-side routing
+    /// ("" = the whole root)
+    pub spa_dirs: Vec<String>,
+    /// answer errors with the nearest <status>.html, looking up from the requested directory
+    pub error_pages: bool,
+}
+
+impl FallbackSettings {
+    pub fn new(spa_dirs: &[String], error_pages: bool) -> Self {
+        let mut spa_dirs: Vec<String> = spa_dirs
+            .iter()
+            .filter_map(|dir| {
+                safe_key(dir).or_else(|| {
+                    tracing::warn!("Ignoring invalid SPA directory: {}", dir);
+                    None
+                })
+            })
+            .collect();
+        // the deepest directory wins
+        spa_dirs.sort_by_key(|dir| std::cmp::Reverse(dir.split('/').filter(|s| !s.is_empty()).count()));
+        Self { spa_dirs, error_pages }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.spa_dirs, config.error_pages)
+    }
+
+    /// these settings with a virtual host's overrides applied
+    pub fn with_overrides(&self, spa_dirs: Option<&[String]>, error_pages: Option<bool>) -> Self {
+        Self {
+            spa_dirs: spa_dirs.map(|dirs| Self::new(dirs, false).spa_dirs).unwrap_or_else(|| self.spa_dirs.clone()),
+            error_pages: error_pages.unwrap_or(self.error_pages),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.spa_dirs.is_empty() || self.error_pages
+    }
+
+    // the spa directory a path falls under, as the number of leading path segments it spans
+    fn spa_dir_for(&self, segments: &[String]) -> Option<usize> {
+        self.spa_dirs.iter().find_map(|dir| {
+            let dir: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+            let inside = segments.len() > dir.len() && dir.iter().zip(segments).all(|(a, b)| a == b);
+            inside.then_some(dir.len())
+        })
+    }
+}
+
+// a page the server itself picks, without the client's conditions or ranges
+fn page_request(method: &Method, headers: &HeaderMap, uri: &str) -> Option<Request<Body>> {
+    let mut req = Request::builder().method(method.clone()).uri(uri).body(Body::empty()).ok()?;
+    for (name, value) in headers {
+        let conditional = matches!(
+            *name,
+            header::RANGE | header::IF_RANGE | header::IF_NONE_MATCH | header::IF_MODIFIED_SINCE | header::IF_MATCH
+        );
+        if !conditional {
+            req.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    Some(req)
+}
+
+async fn fetch_page(next: Next, method: &Method, headers: &HeaderMap, uri: &str) -> Option<Response> {
+    let req = page_request(method, headers, uri)?;
+    let response = next.run(req).await;
+    (response.status() == StatusCode::OK).then_some(response)
+}
+
+/// serve spa index files and custom error pages in place of the inner router's errors
+pub async fn serve_fallbacks(
+    State(settings): State<Arc<FallbackSettings>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let method = req.method().clone();
+    if method != Method::GET && method != Method::HEAD {
+        return next.run(req).await;
+    }
+    let headers = req.headers().clone();
+    let path = req.uri().path().to_string();
+    let response = next.clone().run(req).await;
+    let status = response.status();
+
+    // raw segments for building page paths, decoded ones for matching directories
+    let raw: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let decoded: Vec<String> = raw.iter().map(|s| percent_decode_str(s).decode_utf8_lossy().to_string()).collect();
+
+    if status == StatusCode::NOT_FOUND {
+        if let Some(depth) = settings.spa_dir_for(&decoded) {
+            // missing assets stay 404, anything that looks like a route gets the app
+            let wants_html = headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|accept| accept.contains("text/html"));
+            let has_extension = decoded.last().is_some_and(|name| name.contains('.'));
+            if wants_html || !has_extension {
+                let index = format!("/{}", raw[..depth].iter().map(|s| format!("{}/", s)).collect::<String>());
+                if let Some(page) = fetch_page(next.clone(), &method, &headers, &format!("{}index.html", index)).await {
+                    tracing::trace!("Serving spa index {} for {}", index, path);
+                    return page;
+                }
+            }
+        }
+    }
+
+    if settings.error_pages && ERROR_PAGE_STATUSES.contains(&status) {
+        // the requested directory first, then up to the root
+        let dirs = if path.ends_with('/') { raw.len() } else { raw.len().saturating_sub(1) };
+        let dirs = dirs.min(MAX_ERROR_PAGE_DEPTH);
+        for depth in (0..=dirs).rev() {
+            let dir = format!("/{}", raw[..depth].iter().map(|s| format!("{}/", s)).collect::<String>());
+            let uri = format!("{}{}.html", dir, status.as_u16());
+            if let Some(mut page) = fetch_page(next.clone(), &method, &headers, &uri).await {
+                tracing::trace!("Serving error page {} for {}", uri, path);
+                *page.status_mut() = status;
+                let page_headers = page.headers_mut();
+                for name in [header::ETAG, header::LAST_MODIFIED, header::ACCEPT_RANGES, header::CACHE_CONTROL] {
+                    page_headers.remove(name);
+                }
+                page_headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+                return page;
+            }
+        }
+    }
+
+    response
+}
@@ -0,0 +1,131 @@
+use axum::http::StatusCode;
+use axum::Json;
+use futures_util::StreamExt;
+
+use crate::config::Config;
+use crate::models::ErrorResponse;
+use crate::storage::ByteStream;
+use crate::utils::matches_glob;
+
+/// bytes from the start of a file looked at to tell its type
+pub const SNIFF_LEN: usize = 8192;
+
+/// the type of some content by its magic bytes, whatever name or type the client gave it
+///
+/// content without a known signature is text/plain if it reads as text, application/octet-stream otherwise
+pub fn detect_type(head: &[u8]) -> String {
+    let head = &head[..head.len().min(SNIFF_LEN)];
+    if let Some(kind) = infer::get(head) {
+        // svg is xml as far as signatures go, but browsers run its scripts
+        if kind.mime_type() == "text/xml" && looks_like_svg(head) {
+            return "image/svg+xml".to_string();
+        }
+        return kind.mime_type().to_string();
+    }
+    if looks_like_svg(head) {
+        "image/svg+xml".to_string()
+    } else if is_text(head) {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+fn looks_like_svg(head: &[u8]) -> bool {
+    String::from_utf8_lossy(head).to_lowercase().contains("<svg")
+}
+
+// valid utf-8 without NULs, allowing for a character cut off at the end
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() - e.valid_up_to() < 4,
+    }
+}
+
+/// the first SNIFF_LEN bytes of a stream
+pub async fn read_head(mut stream: ByteStream) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    while head.len() < SNIFF_LEN {
+        match stream.next().await {
+            Some(chunk) => head.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+    head.truncate(SNIFF_LEN);
+    Ok(head)
+}
+
+/// which uploads are accepted, by extension and by the type of their content
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UploadTypeRules {
+    /// lowercase extensions without the dot, empty = any extension not denied
+    pub allow_extensions: Vec<String>,
+    pub deny_extensions: Vec<String>,
+    /// lowercase mime types (`*` allowed), empty = any type not denied
+    pub allow_types: Vec<String>,
+    pub deny_types: Vec<String>,
+}
+
+impl UploadTypeRules {
+    pub fn new(allow_extensions: &[String], deny_extensions: &[String], allow_types: &[String], deny_types: &[String]) -> Self {
+        let extensions = |list: &[String]| -> Vec<String> {
+            list.iter()
+                .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect()
+        };
+        let types = |list: &[String]| -> Vec<String> {
+            list.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect()
+        };
+        Self {
+            allow_extensions: extensions(allow_extensions),
+            deny_extensions: extensions(deny_extensions),
+            allow_types: types(allow_types),
+            deny_types: types(deny_types),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            &config.upload_allow_extensions,
+            &config.upload_deny_extensions,
+            &config.upload_allow_types,
+            &config.upload_deny_types,
+        )
+    }
+
+    /// refuse a filename by its extension ("" for names without one)
+    pub fn check_extension(&self, filename: &str) -> Result<(), String> {
+        let name = filename.rsplit('/').next().unwrap_or_default().to_lowercase();
+        let extension = name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+        let denied = self.deny_extensions.iter().any(|ext| ext == extension);
+        let allowed = self.allow_extensions.is_empty() || self.allow_extensions.iter().any(|ext| ext == extension);
+        if denied || !allowed {
+            return Err(match extension {
+                "" => "Files without an extension are not allowed".to_string(),
+                extension => format!("Extension .{} is not allowed", extension),
+            });
+        }
+        Ok(())
+    }
+
+    /// refuse a detected content type
+    pub fn check_type(&self, detected: &str) -> Result<(), String> {
+        let denied = self.deny_types.iter().any(|pattern| matches_glob(pattern, detected));
+        let allowed = self.allow_types.is_empty() || self.allow_types.iter().any(|pattern| matches_glob(pattern, detected));
+        if denied || !allowed {
+            return Err(format!("File type {} is not allowed (detected from its content)", detected));
+        }
+        Ok(())
+    }
+}
+
+// 415 for uploads the type rules refuse
+pub(crate) fn unsupported_type(filename: &str, reason: String) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("🚫 Rejecting upload of {}: {}", filename, reason);
+    (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(ErrorResponse { error: reason }))
+}
@@ -1,30 +1,45 @@
 use axum::{
-    extract::{Path, Multipart, State},
+    extract::{Extension, Path, Multipart, Query, State},
     http::StatusCode,
     response::Json,
 };
 use std::sync::Arc;
 use std::collections::HashSet;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 use crate::models::{
     BatchDeleteRequest, BatchDeleteResponse, BatchDeleteResult,
-    DeleteResponse, ErrorResponse, FileInfo, FileListResponse, 
-    StatsResponse, UploadResponse, ChunkedUploadInit, ChunkedUploadInitResponse,
+    DeleteResponse, ErrorResponse, FileInfo, FileListResponse, ListFilesQuery,
+    StatsResponse, UploadOptions, UploadResponse, ChunkedUploadInit, ChunkedUploadInitResponse,
     ChunkedUploadComplete, ChunkedUploadCompleteResponse,
 };
-use crate::state::{AppState, ChunkedUploadMetadata};
-use crate::utils::sanitize_filename;
+use crate::disk::{disk_space, ensure_free_space, is_disk_full};
+use crate::expiry::resolve_expiry;
+use crate::filetypes::{detect_type, read_head, unsupported_type};
+use crate::scanning::scan_upload;
+use crate::hooks::run_hooks;
+use crate::caching::{record_content_hash, sha256_hex, UploadHasher};
+use crate::middleware::ApiKeyName;
+use crate::quota::{reserve, reserve_upload, usage_report, QuotaError};
+use crate::state::{AppState, ChunkedUploadMetadata, UploadSettings};
+use crate::storage::{concat, exists, once, put_bytes, ByteStream};
+use crate::trash::move_to_trash;
+use crate::versions::snapshot_version;
+use crate::utils::{normalize_tag, sanitize_filename};
+
+/// hidden directory inside files_dir holding chunks of uploads in progress
+pub const CHUNKS_DIR: &str = ".chunks";
 
 // upload a file via multipart form data
+// options (expires_at, ttl, max_downloads, disposition) come from the query string or from form fields sent before the file
 pub async fn upload_file(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKeyName>>,
+    Query(mut options): Query<UploadOptions>,
     mut multipart: Multipart,
 ) -> Result<Json<UploadResponse>, (StatusCode, Json<ErrorResponse>)> {
     tracing::debug!("Processing file upload request");
-    
+
     while let Some(field) = multipart.next_field().await.map_err(|e| {
         tracing::error!("Failed to read multipart field: {}", e);
         (
@@ -34,23 +49,37 @@ pub async fn upload_file(
             }),
         )
     })? {
-        let filename = field.file_name().ok_or_else(|| {
-            tracing::warn!("Upload request missing filename");
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "No filename provided".to_string(),
-                }),
-            )
-        })?;
+        // plain form fields carry upload options
+        let Some(filename) = field.file_name().map(|f| f.to_string()) else {
+            let name = field.name().unwrap_or_default().to_string();
+            let value = field.text().await.map_err(|e| {
+                tracing::warn!("Failed to read form field {}: {}", name, e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: format!("Failed to read form field {}: {}", name, e),
+                    }),
+                )
+            })?;
+            apply_upload_field(&mut options, &name, &value)?;
+            continue;
+        };
 
         tracing::debug!("Receiving file: {}", filename);
 
+        let mut settings = resolve_upload_settings(&options).map_err(|e| {
+            tracing::warn!("Rejecting upload of {}: {}", filename, e);
+            (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }))
+        })?;
+        settings.owner = key.as_ref().map(|Extension(ApiKeyName(name))| name.clone());
+
         // sanitize filename to prevent directory traversal
-        let sanitized_filename = sanitize_filename(filename);
-        let file_path = state.files_dir.join(&sanitized_filename);
+        let sanitized_filename = sanitize_filename(&filename);
         tracing::trace!("Sanitized filename: {} -> {}", filename, sanitized_filename);
-        tracing::trace!("Target path: {:?}", file_path);
+        state
+            .upload_types
+            .check_extension(&sanitized_filename)
+            .map_err(|e| unsupported_type(&sanitized_filename, e))?;
 
         // read the file data
         let data = field.bytes().await.map_err(|e| {
@@ -66,36 +95,28 @@ pub async fn upload_file(
         let size = data.len() as u64;
         tracing::debug!("File size: {} bytes", size);
 
-        // write to disk
-        let mut file = fs::File::create(&file_path).await.map_err(|e| {
-            tracing::error!("Failed to create file {}: {}", sanitized_filename, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to create file: {}", e),
-                }),
-            )
-        })?;
-
-        file.write_all(&data).await.map_err(|e| {
-            tracing::error!("Failed to write to file {}: {}", sanitized_filename, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to write file: {}", e),
-                }),
-            )
-        })?;
+        // go by the content, not the type the client claims
+        check_content(&state, &sanitized_filename, &mut settings, || once(data.clone())).await?;
+
+        // hold the space until the file is written
+        let _reservation = reserve_upload(&state, settings.owner.as_deref(), size, Some(&sanitized_filename))
+            .await
+            .map_err(|e| quota_error(&sanitized_filename, e))?;
+
+        // written out of sight first, the old content is only kept as a version once that worked
+        settings.sha256 = Some(sha256_hex(&data));
+        let staged = staging_key();
+        let written = match put_bytes(state.storage.as_ref(), &staged, data).await {
+            Ok(_) => promote_staged(&state, &staged, &sanitized_filename).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            let _ = state.storage.delete(&staged).await;
+            return Err(write_error(&sanitized_filename, e));
+        }
 
-        file.sync_all().await.map_err(|e| {
-            tracing::error!("Failed to sync file {}: {}", sanitized_filename, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to sync file: {}", e),
-                }),
-            )
-        })?;
+        record_upload_metadata(&state, &sanitized_filename, &settings).await?;
+        run_hooks(&state, &sanitized_filename);
 
         tracing::info!("✅ Uploaded file: {} ({} bytes)", sanitized_filename, size);
 
@@ -103,6 +124,9 @@ pub async fn upload_file(
             success: true,
             filename: sanitized_filename,
             size,
+            expires_at: settings.expires_at.map(|t| t.to_rfc3339()),
+            max_downloads: settings.max_downloads,
+            detected_type: settings.detected_type,
         }));
     }
 
@@ -115,45 +139,310 @@ pub async fn upload_file(
     ))
 }
 
-// list all files in the files directory
-pub async fn list_files(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<FileListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    tracing::debug!("Listing files in directory: {:?}", state.files_dir);
-    let mut files = Vec::new();
+// parse a non-file multipart field into the upload options
+fn apply_upload_field(
+    options: &mut UploadOptions,
+    name: &str,
+    value: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let invalid = |e: String| {
+        tracing::warn!("Invalid upload option {}={}: {}", name, value, e);
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid {}: {}", name, e),
+            }),
+        )
+    };
+
+    match name {
+        "expires_at" => {
+            let at = chrono::DateTime::parse_from_rfc3339(value.trim()).map_err(|e| invalid(e.to_string()))?;
+            options.expires_at = Some(at.with_timezone(&chrono::Utc));
+        }
+        "ttl" => {
+            options.ttl = Some(value.trim().parse().map_err(|e: std::num::ParseIntError| invalid(e.to_string()))?);
+        }
+        "max_downloads" => {
+            options.max_downloads = Some(value.trim().parse().map_err(|e: std::num::ParseIntError| invalid(e.to_string()))?);
+        }
+        "disposition" => {
+            options.disposition = Some(value.parse().map_err(invalid)?);
+        }
+        _ => tracing::debug!("Ignoring unknown upload field: {}", name),
+    }
+    Ok(())
+}
+
+// validate upload options and turn them into settings to store with the file
+pub(crate) fn resolve_upload_settings(options: &UploadOptions) -> Result<UploadSettings, String> {
+    if options.max_downloads == Some(0) {
+        return Err("max_downloads must be at least 1".to_string());
+    }
+
+    Ok(UploadSettings {
+        expires_at: resolve_expiry(options)?,
+        max_downloads: options.max_downloads,
+        owner: None,
+        disposition: options.disposition,
+        detected_type: None,
+        scan: None,
+        sha256: None,
+    })
+}
+
+/// most parts a native multipart upload can have (S3's limit)
+pub const MAX_MULTIPART_PARTS: usize = 10_000;
 
-    let mut entries = fs::read_dir(&state.files_dir).await.map_err(|e| {
-        tracing::error!("Failed to read directory {:?}: {}", state.files_dir, e);
+// where the chunks of a chunked upload are kept until it completes
+fn chunks_prefix(upload_id: &str) -> String {
+    format!("{}/{}/", CHUNKS_DIR, upload_id)
+}
+
+// the keys chunks are stored under until the upload completes
+fn chunk_keys(upload_id: &str, total_chunks: usize) -> Vec<String> {
+    let chunks_prefix = chunks_prefix(upload_id);
+    (0..total_chunks).map(|n| format!("{}chunk_{}", chunks_prefix, n)).collect()
+}
+
+// the hidden key a chunked upload is assembled under, until it's been checked
+fn assembled_key(upload_id: &str) -> String {
+    format!("{}assembled", chunks_prefix(upload_id))
+}
+
+// throw away the chunks (or parts) of an abandoned chunked upload
+async fn discard_chunks(state: &AppState, upload_id: &str, upload: &ChunkedUploadMetadata) {
+    let assembled = assembled_key(upload_id);
+    if let Some(multipart_id) = &upload.multipart_id {
+        // once completed the parts are an object under the chunks prefix
+        if !exists(state.storage.as_ref(), &assembled).await.unwrap_or(false) {
+            if let Err(e) = state.storage.abort_multipart(&assembled, multipart_id).await {
+                tracing::warn!("Failed to abort multipart upload {}: {}", upload_id, e);
+            }
+        }
+    }
+    if let Err(e) = state.storage.delete_prefix(&chunks_prefix(upload_id)).await {
+        tracing::warn!("Failed to discard chunks of upload {}: {}", upload_id, e);
+    }
+}
+
+/// check content that's about to be kept: its sniffed type against the upload rules, then the malware scan
+///
+/// `content` gives the content as often as it's needed; the detected type and scan result go into
+/// `settings`. 415 and 422 refuse the content, 500 and 503 mean it couldn't be checked this time
+pub(crate) async fn check_content<F>(
+    state: &AppState,
+    filename: &str,
+    settings: &mut UploadSettings,
+    content: F,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)>
+where
+    F: Fn() -> ByteStream,
+{
+    check_content_type(state, filename, settings, content()).await?;
+    settings.scan = scan_upload(state, filename, content).await?;
+    Ok(())
+}
+
+// check the type of some content by its magic bytes, never the name or type the client gave it
+async fn check_content_type(
+    state: &AppState,
+    filename: &str,
+    settings: &mut UploadSettings,
+    content: ByteStream,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let head = read_head(content).await.map_err(|e| {
+        tracing::error!("Failed to read upload of {}: {}", filename, e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to read directory: {}", e),
+                error: format!("Failed to read upload: {}", e),
             }),
         )
     })?;
+    let detected_type = detect_type(&head);
+    tracing::debug!("Detected type of {}: {}", filename, detected_type);
+    state
+        .upload_types
+        .check_type(&detected_type)
+        .map_err(|e| unsupported_type(filename, e))?;
+    settings.detected_type = Some(detected_type);
+    Ok(())
+}
+
+/// a hidden key to write an upload to before it's checked and put in place
+pub(crate) fn staging_key() -> String {
+    format!("{}/{}", CHUNKS_DIR, Uuid::new_v4())
+}
+
+/// put a checked upload from its staging key in place, keeping the current file as a version first
+pub(crate) async fn promote_staged(state: &AppState, staged: &str, filename: &str) -> std::io::Result<()> {
+    snapshot_version(state, filename).await?;
+    state.storage.rename(staged, filename).await
+}
+
+// 507 for uploads that won't fit on disk
+pub(crate) fn insufficient_storage(filename: &str, reason: String) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("💽 Rejecting upload of {}: {}", filename, reason);
+    (StatusCode::INSUFFICIENT_STORAGE, Json(ErrorResponse { error: reason }))
+}
+
+// a failed write, 507 if the disk filled up regardless of our checks
+pub(crate) fn write_error(filename: &str, e: std::io::Error) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::error!("Failed to write file {}: {}", filename, e);
+    let status = if is_disk_full(&e) {
+        StatusCode::INSUFFICIENT_STORAGE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            error: format!("Failed to write file: {}", e),
+        }),
+    )
+}
 
-    while let Some(entry) = entries.next_entry().await.map_err(|e| {
-        tracing::error!("Failed to read directory entry: {}", e);
+// turn a refused quota reservation into a response
+pub(crate) fn quota_error(filename: &str, e: QuotaError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        QuotaError::NoSpace(reason) => return insufficient_storage(filename, reason),
+        QuotaError::Exceeded(_) => {
+            tracing::warn!("💾 Rejecting upload of {}: {}", filename, e);
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        QuotaError::Io(_) => {
+            tracing::error!("Failed to check quota for {}: {}", filename, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    (status, Json(ErrorResponse { error: e.to_string() }))
+}
+
+// save the metadata store after a change, `what` names what changed in the error ("tags", ...)
+pub(crate) async fn persist_metadata(state: &AppState, what: &str) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    state.metadata.persist().await.map_err(|e| {
+        tracing::error!("Failed to persist metadata ({}): {}", what, e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to read directory entry: {}", e),
+                error: format!("Failed to save {}: {}", what, e),
             }),
         )
-    })? {
-        let metadata = entry.metadata().await.map_err(|e| {
-            tracing::warn!("Failed to read metadata for entry: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read metadata: {}", e),
-                }),
-            )
-        })?;
+    })
+}
+
+// store per-file settings for a freshly written upload (replaces any from a previous version)
+pub(crate) async fn record_upload_metadata(
+    state: &AppState,
+    filename: &str,
+    settings: &UploadSettings,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let changed = apply_upload_settings(state, filename, settings);
+    // the new content's hash, for strong etags
+    let hashed = record_content_hash(state, filename, settings.sha256.as_deref()).await;
+    if !changed && !hashed {
+        return Ok(());
+    }
+
+    persist_metadata(state, "file metadata").await
+}
+
+// set a fresh upload's settings in memory, returning whether anything changed (and needs persisting)
+pub(crate) fn apply_upload_settings(state: &AppState, filename: &str, settings: &UploadSettings) -> bool {
+    let existing = state.metadata.get(filename);
+    if existing.expires_at == settings.expires_at
+        && existing.max_downloads == settings.max_downloads
+        && existing.download_count == 0
+        && existing.owner == settings.owner
+        && existing.disposition == settings.disposition
+        && existing.detected_type == settings.detected_type
+        && existing.scan == settings.scan
+    {
+        return false;
+    }
+
+    state.metadata.update(filename, |meta| {
+        meta.expires_at = settings.expires_at;
+        meta.max_downloads = settings.max_downloads;
+        meta.download_count = 0;
+        meta.owner = settings.owner.clone();
+        meta.disposition = settings.disposition;
+        meta.detected_type = settings.detected_type.clone();
+        meta.scan = settings.scan.clone();
+    });
+    true
+}
+
+// list all files in the files directory, optionally filtered by tag or collection
+pub async fn list_files(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListFilesQuery>,
+) -> Result<Json<FileListResponse>, (StatusCode, Json<ErrorResponse>)> {
+    tracing::debug!("Listing files in directory: {:?} (filter: {:?})", state.files_dir, query);
+    let mut files = Vec::new();
+
+    // resolve the collection filter up front
+    let collection_members = match &query.collection {
+        Some(name) => {
+            let name = sanitize_filename(name);
+            let members = state.metadata.collections.get(&name).map(|c| c.clone()).ok_or_else(|| {
+                tracing::warn!("Collection not found for listing: {}", name);
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse {
+                        error: format!("Collection not found: {}", name),
+                    }),
+                )
+            })?;
+            Some(members)
+        }
+        None => None,
+    };
+    let tag_filter = query.tag.as_deref().map(normalize_tag);
+
+    let listing = state.storage.list("", false).await.map_err(|e| {
+        tracing::error!("Failed to list files in {:?}: {}", state.files_dir, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to read directory: {}", e),
+            }),
+        )
+    })?;
+
+    // directories show up as key prefixes
+    let entries = listing
+        .objects
+        .into_iter()
+        .map(|object| (object.key, object.size, object.modified, false))
+        .chain(
+            listing
+                .prefixes
+                .into_iter()
+                .map(|prefix| (prefix.trim_end_matches('/').to_string(), 0, None, true)),
+        );
+
+    for (name, size, modified, is_dir) in entries {
+        // skip internal directories like .chunks and .meta
+        if name.starts_with('.') {
+            continue;
+        }
+
+        if let Some(members) = &collection_members {
+            if !members.contains(&name) {
+                continue;
+            }
+        }
 
-        let modified = metadata
-            .modified()
-            .ok()
+        let file_meta = state.metadata.get(&name);
+        if let Some(tag) = &tag_filter {
+            if !file_meta.tags.contains(tag) {
+                continue;
+            }
+        }
+
+        let modified = modified
             .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
             .map(|d| {
                 chrono::DateTime::from_timestamp(d.as_secs() as i64, 0)
@@ -162,14 +451,21 @@ pub async fn list_files(
             })
             .unwrap_or_else(|| "Unknown".to_string());
 
-        let name = entry.file_name().to_string_lossy().to_string();
-        tracing::trace!("Found file: {} ({} bytes)", name, metadata.len());
+        tracing::trace!("Found file: {} ({} bytes)", name, size);
 
         files.push(FileInfo {
+            collections: state.metadata.collections_of(&name),
             name,
-            size: metadata.len(),
+            size,
             modified,
-            is_dir: metadata.is_dir(),
+            is_dir,
+            tags: file_meta.tags.into_iter().collect(),
+            expires_at: file_meta.expires_at.map(|t| t.to_rfc3339()),
+            downloads: file_meta.download_count,
+            max_downloads: file_meta.max_downloads,
+            disposition: file_meta.disposition,
+            detected_type: file_meta.detected_type,
+            scan: file_meta.scan,
         });
     }
 
@@ -187,12 +483,18 @@ pub async fn delete_file(
     
     // sanitize filename to prevent directory traversal
     let sanitized_filename = sanitize_filename(&filename);
-    let file_path = state.files_dir.join(&sanitized_filename);
-    
-    tracing::trace!("Target path for deletion: {:?}", file_path);
 
     // check if file exists
-    if !file_path.exists() {
+    let found = exists(state.storage.as_ref(), &sanitized_filename).await.map_err(|e| {
+        tracing::error!("Failed to look up {}: {}", sanitized_filename, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to look up file: {}", e),
+            }),
+        )
+    })?;
+    if !found {
         tracing::warn!("File not found for deletion: {}", sanitized_filename);
         return Err((
             StatusCode::NOT_FOUND,
@@ -202,8 +504,8 @@ pub async fn delete_file(
         ));
     }
 
-    // delete the file
-    fs::remove_file(&file_path).await.map_err(|e| {
+    // move the file to the trash
+    let item = move_to_trash(&state, &sanitized_filename).await.map_err(|e| {
         tracing::error!("Failed to delete file {}: {}", sanitized_filename, e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -213,7 +515,11 @@ pub async fn delete_file(
         )
     })?;
 
-    tracing::info!("🗑️  Deleted file: {}", sanitized_filename);
+    if let Err(e) = state.metadata.persist().await {
+        tracing::warn!("Failed to persist metadata after deleting {}: {}", sanitized_filename, e);
+    }
+
+    tracing::info!("🗑️  Deleted file: {} (trash id {})", sanitized_filename, item.id);
 
     Ok(Json(DeleteResponse {
         success: true,
@@ -229,8 +535,8 @@ pub async fn get_stats(
     let mut total_files = 0;
     let mut total_size = 0u64;
 
-    let mut entries = fs::read_dir(&state.files_dir).await.map_err(|e| {
-        tracing::error!("Failed to read directory for stats: {}", e);
+    let listing = state.storage.list("", false).await.map_err(|e| {
+        tracing::error!("Failed to list files for stats: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
@@ -239,30 +545,32 @@ pub async fn get_stats(
         )
     })?;
 
-    while let Some(entry) = entries.next_entry().await.map_err(|e| {
+    for object in &listing.objects {
+        total_files += 1;
+        total_size += object.size;
+    }
+    
+    tracing::debug!("Stats: {} files, {} bytes total", total_files, total_size);
+
+    let disk = disk_space(&state.files_dir).await.map_err(|e| {
+        tracing::error!("Failed to read disk space: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to read directory entry: {}", e),
+                error: format!("Failed to read disk space: {}", e),
             }),
         )
-    })? {
-        let metadata = entry.metadata().await.map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read metadata: {}", e),
-                }),
-            )
-        })?;
+    })?;
 
-        if metadata.is_file() {
-            total_files += 1;
-            total_size += metadata.len();
-        }
-    }
-    
-    tracing::debug!("Stats: {} files, {} bytes total", total_files, total_size);
+    let (quota, key_quotas) = usage_report(&state).await.map_err(|e| {
+        tracing::error!("Failed to measure quota usage: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to measure quota usage: {}", e),
+            }),
+        )
+    })?;
 
     Ok(Json(StatsResponse {
         total_files,
@@ -273,16 +581,36 @@ pub async fn get_stats(
             .unwrap_or_else(|_| state.files_dir.clone())
             .to_string_lossy()
             .to_string(),
+        disk_free_bytes: disk.free_bytes,
+        disk_total_bytes: disk.total_bytes,
+        quota,
+        key_quotas,
     }))
 }
 
-// health check endpoint
-pub async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "status": "healthy",
+// health check endpoint, unhealthy (503) when the disk is nearly full
+pub async fn health_check(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let (healthy, disk) = match disk_space(&state.files_dir).await {
+        Ok(disk) => (disk.free_bytes >= state.disk.health_min_free_bytes, Some(disk)),
+        Err(e) => {
+            tracing::error!("Health check failed to read disk space: {}", e);
+            (false, None)
+        }
+    };
+    if !healthy {
+        tracing::warn!("💽 Health check failing: disk space low ({:?})", disk);
+    }
+
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({
+        "status": if healthy { "healthy" } else { "unhealthy" },
         "service": "juicebox-omega-admin",
         "timestamp": chrono::Utc::now().to_rfc3339(),
-    }))
+        "disk_free_bytes": disk.map(|d| d.free_bytes),
+        "disk_min_free_bytes": state.disk.health_min_free_bytes,
+    })))
 }
 
 // batch delete multiple files
@@ -290,19 +618,38 @@ pub async fn batch_delete_files(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<BatchDeleteRequest>,
 ) -> Json<BatchDeleteResponse> {
+    Json(delete_files(&state, payload.filenames, DeleteMode::Trash).await)
+}
+
+/// how delete_files gets rid of files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeleteMode {
+    /// move to the trash so the file can be restored (admin deletes)
+    Trash,
+    /// remove for good (expired and burned files)
+    Permanent,
+}
+
+// delete a list of files, collecting a result per file
+pub(crate) async fn delete_files(state: &AppState, filenames: Vec<String>, mode: DeleteMode) -> BatchDeleteResponse {
     let mut results = Vec::new();
     let mut successful = 0;
     let mut failed = 0;
 
-    for filename in payload.filenames {
+    for filename in filenames {
         // sanitize filename to prevent directory traversal like fucken .. and . and all that shit
         let sanitized_filename = sanitize_filename(&filename);
-        let file_path = state.files_dir.join(&sanitized_filename);
 
         // check if file exists and delete
-        match fs::remove_file(&file_path).await {
+        let deleted = match mode {
+            DeleteMode::Trash => move_to_trash(state, &sanitized_filename).await.map(|_| ()),
+            DeleteMode::Permanent => state.storage.delete(&sanitized_filename).await,
+        };
+        match deleted {
             Ok(_) => {
                 tracing::info!("🗑️  Batch deleted file: {}", sanitized_filename);
+                state.metadata.remove_file(&sanitized_filename);
+                state.hook_jobs.remove(&sanitized_filename);
                 successful += 1;
                 results.push(BatchDeleteResult {
                     filename: sanitized_filename,
@@ -322,6 +669,12 @@ pub async fn batch_delete_files(
         }
     }
 
+    if successful > 0 {
+        if let Err(e) = state.metadata.persist().await {
+            tracing::warn!("Failed to persist metadata after batch delete: {}", e);
+        }
+    }
+
     let total = results.len();
     tracing::info!(
         "📦 Batch delete completed: {}/{} successful",
@@ -329,49 +682,86 @@ pub async fn batch_delete_files(
         total
     );
 
-    Json(BatchDeleteResponse {
+    BatchDeleteResponse {
         total,
         successful,
         failed,
         results,
-    })
+    }
 }
 
 // initialize a chunked upload
 pub async fn init_chunked_upload(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKeyName>>,
     Json(payload): Json<ChunkedUploadInit>,
 ) -> Result<Json<ChunkedUploadInitResponse>, (StatusCode, Json<ErrorResponse>)> {
     tracing::debug!("Initializing chunked upload for file: {}", payload.filename);
     let upload_id = Uuid::new_v4().to_string();
     let sanitized_filename = sanitize_filename(&payload.filename);
+
+    let mut settings = resolve_upload_settings(&payload.options).map_err(|e| {
+        tracing::warn!("Rejecting chunked upload of {}: {}", sanitized_filename, e);
+        (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }))
+    })?;
+    settings.owner = key.map(|Extension(ApiKeyName(name))| name);
+    state
+        .upload_types
+        .check_extension(&sanitized_filename)
+        .map_err(|e| unsupported_type(&sanitized_filename, e))?;
     
     let total_chunks = (payload.total_size as f64 / payload.chunk_size as f64).ceil() as usize;
     tracing::debug!("Calculated {} chunks for size {} (chunk size {})", total_chunks, payload.total_size, payload.chunk_size);
     
-    let metadata = ChunkedUploadMetadata {
+    let owner = settings.owner.clone();
+    let mut metadata = ChunkedUploadMetadata {
         filename: sanitized_filename.clone(),
         total_size: payload.total_size,
         chunk_size: payload.chunk_size,
         total_chunks,
         received_chunks: HashSet::new(),
+        multipart_id: None,
+        settings,
     };
     
-    state.chunked_uploads.insert(upload_id.clone(), metadata);
-    
-    // create temporary directory for chunks lmaooo????
-    let chunks_dir = state.files_dir.join(".chunks").join(&upload_id);
-    tracing::trace!("Creating chunks directory: {:?}", chunks_dir);
+    // backends with native multipart uploads (s3) take the chunks as parts of a hidden object
+    let assembled = assembled_key(&upload_id);
+    if total_chunks <= MAX_MULTIPART_PARTS {
+        metadata.multipart_id = state
+            .storage
+            .create_multipart(&assembled, payload.chunk_size as u64)
+            .await
+            .map_err(|e| write_error(&sanitized_filename, e))?;
+    }
+    let multipart_id = metadata.multipart_id.clone();
+
+    // the declared size counts against the quota until the upload completes
+    let reserved = reserve(&state, owner.as_deref(), payload.total_size, Some(&sanitized_filename), || {
+        state.chunked_uploads.insert(upload_id.clone(), metadata);
+    })
+    .await;
+    if let Err(e) = reserved {
+        if let Some(multipart_id) = &multipart_id {
+            let _ = state.storage.abort_multipart(&assembled, multipart_id).await;
+        }
+        return Err(quota_error(&sanitized_filename, e));
+    }
     
-    fs::create_dir_all(&chunks_dir).await.map_err(|e| {
-        tracing::error!("Failed to create chunks directory: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to create chunks directory: {}", e),
-            }),
-        )
-    })?;
+    if multipart_id.is_none() {
+        // create temporary directory for chunks lmaooo????
+        let chunks_prefix = chunks_prefix(&upload_id);
+        tracing::trace!("Creating chunks directory: {}", chunks_prefix);
+
+        state.storage.create_dir(&chunks_prefix).await.map_err(|e| {
+            tracing::error!("Failed to create chunks directory: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create chunks directory: {}", e),
+                }),
+            )
+        })?;
+    }
     
     tracing::info!("📤 Initialized chunked upload: {} (ID: {})", sanitized_filename, upload_id);
     
@@ -390,17 +780,23 @@ pub async fn upload_chunk(
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
     tracing::trace!("Received chunk {} for upload {}", chunk_number, upload_id);
     
-    // verify upload exists
-    let mut metadata = state.chunked_uploads.get_mut(&upload_id).ok_or_else(|| {
-        tracing::warn!("Upload ID not found: {}", upload_id);
-        (
-            StatusCode::NOT_FOUND,
+    // verify upload exists, copying out what's needed so no map guard is held across awaits
+    let (filename, total_chunks, multipart_id) = state
+        .chunked_uploads
+        .get(&upload_id)
+        .map(|metadata| (metadata.filename.clone(), metadata.total_chunks, metadata.multipart_id.clone()))
+        .ok_or_else(|| upload_not_found(&upload_id))?;
+    
+    if chunk_number >= total_chunks {
+        tracing::warn!("Chunk {} out of range for upload {}", chunk_number, upload_id);
+        return Err((
+            StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: "Upload ID not found".to_string(),
+                error: format!("Chunk number must be below {}", total_chunks),
             }),
-        )
-    })?;
-    
+        ));
+    }
+
     // read chunk data
     let field = multipart.next_field().await.map_err(|e| {
         tracing::error!("Failed to read chunk data: {}", e);
@@ -430,19 +826,33 @@ pub async fn upload_chunk(
         )
     })?;
     
-    // write chunk to temporary file
-    let chunk_path = state.files_dir.join(".chunks").join(&upload_id).join(format!("chunk_{}", chunk_number));
-    let mut file = fs::File::create(&chunk_path).await.map_err(|e| {
-        tracing::error!("Failed to create chunk file: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to create chunk file: {}", e),
-            }),
-        )
-    })?;
-    
-    file.write_all(&data).await.map_err(|e| {
+    // the first chunk has the magic bytes, a type that isn't allowed ends the whole upload
+    if chunk_number == 0 {
+        let detected_type = detect_type(&data);
+        if let Err(e) = state.upload_types.check_type(&detected_type) {
+            if let Some((_, upload)) = state.chunked_uploads.remove(&upload_id) {
+                discard_chunks(&state, &upload_id, &upload).await;
+            }
+            return Err(unsupported_type(&filename, e));
+        }
+        let mut metadata = state.chunked_uploads.get_mut(&upload_id).ok_or_else(|| upload_not_found(&upload_id))?;
+        metadata.settings.detected_type = Some(detected_type);
+    }
+
+    // write chunk as a part of the final object, or to temporary storage
+    let written = match &multipart_id {
+        Some(multipart_id) => {
+            state
+                .storage
+                .put_part(&assembled_key(&upload_id), multipart_id, chunk_number as u32 + 1, data)
+                .await
+        }
+        None => {
+            let chunk_key = format!("{}chunk_{}", chunks_prefix(&upload_id), chunk_number);
+            put_bytes(state.storage.as_ref(), &chunk_key, data).await.map(|_| ())
+        }
+    };
+    written.map_err(|e| {
         tracing::error!("Failed to write chunk: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -452,20 +862,12 @@ pub async fn upload_chunk(
         )
     })?;
     
-    file.sync_all().await.map_err(|e| {
-        tracing::error!("Failed to sync chunk: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to sync chunk: {}", e),
-            }),
-        )
-    })?;
-    
-    // mark chunk as received
-    metadata.received_chunks.insert(chunk_number);
-    let received_count = metadata.received_chunks.len();
-    let total_chunks = metadata.total_chunks;
+    // mark chunk as received, unless the upload was cancelled or completed meanwhile
+    let received_count = {
+        let mut metadata = state.chunked_uploads.get_mut(&upload_id).ok_or_else(|| upload_not_found(&upload_id))?;
+        metadata.received_chunks.insert(chunk_number);
+        metadata.received_chunks.len()
+    };
     
     tracing::debug!("📦 Received chunk {}/{} for upload {}", chunk_number, total_chunks, upload_id);
     
@@ -477,6 +879,16 @@ pub async fn upload_chunk(
     })))
 }
 
+fn upload_not_found(upload_id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Upload ID not found: {}", upload_id);
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "Upload ID not found".to_string(),
+        }),
+    )
+}
+
 // complete a chunked upload by assembling all chunks
 pub async fn complete_chunked_upload(
     State(state): State<Arc<AppState>>,
@@ -485,7 +897,7 @@ pub async fn complete_chunked_upload(
     tracing::debug!("Completing chunked upload: {}", payload.upload_id);
     
     // get and remove metadata
-    let (_, metadata) = state.chunked_uploads.remove(&payload.upload_id).ok_or_else(|| {
+    let (_, mut metadata) = state.chunked_uploads.remove(&payload.upload_id).ok_or_else(|| {
         tracing::warn!("Upload ID not found for completion: {}", payload.upload_id);
         (
             StatusCode::NOT_FOUND,
@@ -510,62 +922,72 @@ pub async fn complete_chunked_upload(
         ));
     }
     
-    // assemble chunks into final file
-    let final_path = state.files_dir.join(&metadata.filename);
-    tracing::debug!("Assembling chunks into: {:?}", final_path);
-    
-    let mut final_file = fs::File::create(&final_path).await.map_err(|e| {
-        tracing::error!("Failed to create final file: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to create final file: {}", e),
-            }),
-        )
-    })?;
-    
-    let chunks_dir = state.files_dir.join(".chunks").join(&payload.upload_id);
-    
-    for chunk_num in 0..metadata.total_chunks {
-        let chunk_path = chunks_dir.join(format!("chunk_{}", chunk_num));
-        tracing::trace!("Reading chunk: {:?}", chunk_path);
-        
-        let chunk_data = fs::read(&chunk_path).await.map_err(|e| {
-            tracing::error!("Failed to read chunk {}: {}", chunk_num, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read chunk {}: {}", chunk_num, e),
-                }),
-            )
-        })?;
-        
-        final_file.write_all(&chunk_data).await.map_err(|e| {
-            tracing::error!("Failed to write chunk to final file: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to write chunk to final file: {}", e),
-                }),
-            )
-        })?;
+    // assembling writes a second copy next to the chunks; keep the upload so it can be retried
+    if let Err(reason) = ensure_free_space(&state, metadata.total_size).await {
+        let err = insufficient_storage(&metadata.filename, reason);
+        state.chunked_uploads.insert(payload.upload_id.clone(), metadata);
+        return Err(err);
     }
-    
-    final_file.sync_all().await.map_err(|e| {
-        tracing::error!("Failed to sync final file: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to sync final file: {}", e),
-            }),
-        )
-    })?;
-    
+
+    // native multipart parts become an object under the hidden key, nothing is public yet
+    // (a retry finds it already completed)
+    let assembled = assembled_key(&payload.upload_id);
+    if let Some(multipart_id) = &metadata.multipart_id {
+        let completed = match exists(state.storage.as_ref(), &assembled).await {
+            Ok(true) => Ok(()),
+            Ok(false) => state.storage.complete_multipart(&assembled, multipart_id).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = completed {
+            let err = write_error(&metadata.filename, e);
+            state.chunked_uploads.insert(payload.upload_id.clone(), metadata);
+            return Err(err);
+        }
+    }
+
+    // check the type again on the whole content, chunk 0 may have been sent again since;
+    // then scan it. An unreadable upload or a scanner that's down leaves it to be retried
+    let sources = match metadata.multipart_id {
+        Some(_) => vec![assembled.clone()],
+        None => chunk_keys(&payload.upload_id, metadata.total_chunks),
+    };
+    let checked = check_content(&state, &metadata.filename, &mut metadata.settings, || {
+        concat(state.storage.clone(), sources.clone())
+    })
+    .await;
+    if let Err(e) = checked {
+        if matches!(e.0, StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE) {
+            state.chunked_uploads.insert(payload.upload_id.clone(), metadata);
+        } else {
+            discard_chunks(&state, &payload.upload_id, &metadata).await;
+        }
+        return Err(e);
+    }
+
+    // assemble chunks into final file
+    tracing::debug!("Assembling chunks into: {}", metadata.filename);
+    let assembled_size = match &metadata.multipart_id {
+        Some(_) => state.storage.stat(&assembled).await.map(|meta| meta.size),
+        None => {
+            // native multipart parts never pass through here, those get hashed when first served
+            let hasher = UploadHasher::default();
+            let written = state.storage.put(&assembled, hasher.wrap(concat(state.storage.clone(), sources))).await;
+            metadata.settings.sha256 = Some(hasher.finish());
+            written
+        }
+    };
+    let placed = match assembled_size {
+        Ok(size) => promote_staged(&state, &assembled, &metadata.filename).await.map(|_| size),
+        Err(e) => Err(e),
+    };
+
     // Clean up chunks directory
     tracing::debug!("Cleaning up chunks directory");
-    let _ = fs::remove_dir_all(&chunks_dir).await;
-    
-    let final_size = final_file.metadata().await.map(|m| m.len()).unwrap_or(0);
+    let _ = state.storage.delete_prefix(&chunks_prefix(&payload.upload_id)).await;
+    let final_size = placed.map_err(|e| write_error(&metadata.filename, e))?;
+
+    record_upload_metadata(&state, &metadata.filename, &metadata.settings).await?;
+    run_hooks(&state, &metadata.filename);
     
     tracing::info!("✅ Completed chunked upload: {} ({} bytes)", metadata.filename, final_size);
     
@@ -573,6 +995,9 @@ pub async fn complete_chunked_upload(
         success: true,
         filename: metadata.filename,
         size: final_size,
+        expires_at: metadata.settings.expires_at.map(|t| t.to_rfc3339()),
+        max_downloads: metadata.settings.max_downloads,
+        detected_type: metadata.settings.detected_type,
     }))
 }
 
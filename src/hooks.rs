@@ -0,0 +1,611 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::config::{Config, HookConfig};
+use crate::metadata::FileMetadata;
+use crate::models::{ErrorResponse, HookJobListResponse, HookJobsQuery};
+use crate::state::AppState;
+use crate::storage::exists;
+use crate::utils::{matches_path, sanitize_filename};
+
+// most command output kept with a step, the end of it
+const OUTPUT_LIMIT: usize = 4096;
+
+// longest wait between retries, however far the doubling got
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+// most jobs kept around, finished ones are dropped oldest first beyond this
+const MAX_JOBS: usize = 1000;
+
+// most jobs waiting to start, uploads beyond this get a failed job instead
+const MAX_QUEUED_JOBS: usize = 1000;
+
+// most jobs started at once, running a step or waiting out a retry
+const MAX_ACTIVE_JOBS: usize = 64;
+
+/// what a hook does with a file
+#[derive(Clone, Debug, PartialEq)]
+pub enum HookAction {
+    /// `sh -c` with the file in JUICEBOX_FILE and a JSON description on stdin
+    Command(String),
+    /// add a tag to the file
+    Tag(String),
+    /// POST the JSON description to a url
+    Webhook(String),
+}
+
+impl HookAction {
+    /// a built-in step, "tag:<tag>" or "webhook:<url>"
+    pub fn parse_step(step: &str) -> Option<Self> {
+        let (kind, arg) = step.trim().split_once(':')?;
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return None;
+        }
+        match kind.trim().to_lowercase().as_str() {
+            "tag" => Some(Self::Tag(arg.to_string())),
+            "webhook" if arg.starts_with("http://") || arg.starts_with("https://") => Some(Self::Webhook(arg.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// one step of the pipeline, for the uploads matching its pattern
+#[derive(Clone, Debug)]
+pub struct Hook {
+    pub name: String,
+    /// `*` glob over the whole path if it starts with '/', over the file name otherwise
+    pub pattern: String,
+    pub action: HookAction,
+    pub timeout: Duration,
+    /// runs after a failed one
+    pub retries: u32,
+}
+
+impl Hook {
+    pub fn matches(&self, filename: &str) -> bool {
+        matches_path(&self.pattern, &format!("/{}", filename))
+    }
+}
+
+/// hooks run in the background on stored uploads, one after the other for each file
+#[derive(Clone, Debug)]
+pub struct HookPipeline {
+    pub hooks: Vec<Hook>,
+    /// wait before the first retry, doubled after that
+    pub retry_delay: Duration,
+    /// hook attempts running at the same time, retries waiting out their delay don't count
+    pub limit: Arc<Semaphore>,
+    /// shared by all webhook steps
+    pub client: reqwest::Client,
+    queue: mpsc::Sender<QueuedJob>,
+    // taken by the worker once the first job comes in
+    pending: Arc<Mutex<Option<mpsc::Receiver<QueuedJob>>>>,
+}
+
+// a job waiting for the worker, the state goes away with the last request using it
+#[derive(Debug)]
+struct QueuedJob {
+    state: Weak<AppState>,
+    filename: String,
+    id: String,
+}
+
+impl Default for HookPipeline {
+    fn default() -> Self {
+        Self::new(Vec::new(), 2, Duration::from_secs(5))
+    }
+}
+
+impl HookPipeline {
+    pub fn new(hooks: Vec<Hook>, concurrency: usize, retry_delay: Duration) -> Self {
+        let (queue, pending) = mpsc::channel(MAX_QUEUED_JOBS);
+        Self {
+            hooks,
+            retry_delay,
+            limit: Arc::new(Semaphore::new(concurrency.max(1))),
+            client: reqwest::Client::new(),
+            queue,
+            pending: Arc::new(Mutex::new(Some(pending))),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let hooks = config
+            .hooks
+            .iter()
+            .filter_map(|hook| Self::parse_hook(config, hook))
+            .collect();
+        Self::new(hooks, config.hook_concurrency, Duration::from_secs(config.hook_retry_delay_secs))
+    }
+
+    fn parse_hook(config: &Config, hook: &HookConfig) -> Option<Hook> {
+        let action = match (&hook.command, &hook.step) {
+            (Some(command), _) => HookAction::Command(command.clone()),
+            (None, Some(step)) => match HookAction::parse_step(step) {
+                Some(action) => action,
+                None => {
+                    tracing::warn!("Ignoring hook {}: unknown step {:?}", hook.name, step);
+                    return None;
+                }
+            },
+            (None, None) => return None,
+        };
+        Some(Hook {
+            name: hook.name.clone(),
+            pattern: hook.pattern.clone(),
+            action,
+            timeout: Duration::from_secs(hook.timeout_secs.unwrap_or(config.hook_timeout_secs)),
+            retries: hook.retries,
+        })
+    }
+
+    /// the hooks that run for a file, in order
+    pub fn matching(&self, filename: &str) -> Vec<&Hook> {
+        self.hooks.iter().filter(|hook| hook.matches(filename)).collect()
+    }
+
+    /// wait before retrying after the given failed attempt (1 = the first)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt.saturating_sub(1))
+            .and_then(|factor| self.retry_delay.checked_mul(factor))
+            .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    }
+}
+
+/// where a job or one of its steps is at
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    /// a step left out because an earlier one failed
+    Skipped,
+}
+
+/// one hook's part in a job
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HookStep {
+    pub hook: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// the end of the command's stdout and stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// the pipeline's latest run for a file
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HookJob {
+    pub id: String,
+    pub filename: String,
+    pub status: JobStatus,
+    pub steps: Vec<HookStep>,
+    pub queued_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// why none of the steps ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// what hooks get to know about the file, on stdin or as the webhook body
+#[derive(Serialize)]
+struct HookPayload<'a> {
+    hook: &'a str,
+    filename: &'a str,
+    /// the file on local disk (a temporary copy with non-local storage)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    size: u64,
+    metadata: FileMetadata,
+}
+
+// how a failed attempt went
+struct StepFailure {
+    exit_code: Option<i32>,
+    output: Option<String>,
+    error: String,
+}
+
+impl StepFailure {
+    fn new(error: impl ToString) -> Self {
+        Self { exit_code: None, output: None, error: error.to_string() }
+    }
+}
+
+/// queue the hooks matching a freshly stored file, replacing the file's previous job
+///
+/// returns None when no hook matches
+pub fn run_hooks(state: &Arc<AppState>, filename: &str) -> Option<HookJob> {
+    let steps: Vec<HookStep> = state
+        .hooks
+        .matching(filename)
+        .into_iter()
+        .map(|hook| HookStep {
+            hook: hook.name.clone(),
+            status: JobStatus::Queued,
+            attempts: 0,
+            exit_code: None,
+            output: None,
+            error: None,
+        })
+        .collect();
+    if steps.is_empty() {
+        return None;
+    }
+
+    let job = HookJob {
+        id: uuid::Uuid::new_v4().to_string(),
+        filename: filename.to_string(),
+        status: JobStatus::Queued,
+        steps,
+        queued_at: Utc::now(),
+        started_at: None,
+        finished_at: None,
+        error: None,
+    };
+    state.hook_jobs.insert(filename.to_string(), job.clone());
+    evict_jobs(state);
+
+    start_worker(state);
+    let queued = QueuedJob {
+        state: Arc::downgrade(state),
+        filename: filename.to_string(),
+        id: job.id.clone(),
+    };
+    if state.hooks.queue.try_send(queued).is_err() {
+        tracing::warn!("🪝 Too many hook jobs waiting, not running hooks for {}", filename);
+        update_job(state, filename, &job.id, |job| {
+            job.status = JobStatus::Failed;
+            job.finished_at = Some(Utc::now());
+            job.error = Some("Too many hook jobs waiting".to_string());
+            for step in &mut job.steps {
+                step.status = JobStatus::Skipped;
+            }
+        });
+        return state.hook_jobs.get(filename).map(|job| job.clone());
+    }
+    tracing::debug!("🪝 Queued {} hook(s) for {}", job.steps.len(), filename);
+    Some(job)
+}
+
+// one task per pipeline works through the queue, the first upload with hooks starts it
+fn start_worker(state: &AppState) {
+    let Some(pending) = state.hooks.pending.lock().unwrap().take() else {
+        return;
+    };
+    let jobs = futures_util::stream::unfold(pending, |mut pending| async move {
+        pending.recv().await.map(|job| (job, pending))
+    });
+    tokio::spawn(jobs.for_each_concurrent(MAX_ACTIVE_JOBS, |job| async move {
+        if let Some(state) = job.state.upgrade() {
+            run_job(state, job.filename, job.id).await;
+        }
+    }));
+}
+
+// keep at most MAX_JOBS jobs, dropping the ones that finished longest ago
+fn evict_jobs(state: &AppState) {
+    let excess = state.hook_jobs.len().saturating_sub(MAX_JOBS);
+    if excess == 0 {
+        return;
+    }
+    let mut finished: Vec<(DateTime<Utc>, String)> = state
+        .hook_jobs
+        .iter()
+        .filter_map(|job| job.finished_at.map(|at| (at, job.key().clone())))
+        .collect();
+    finished.sort();
+    for (_, filename) in finished.into_iter().take(excess) {
+        state.hook_jobs.remove(&filename);
+    }
+}
+
+// change a job, unless a newer one took its place
+fn update_job<F: FnOnce(&mut HookJob)>(state: &AppState, filename: &str, id: &str, f: F) -> bool {
+    match state.hook_jobs.get_mut(filename) {
+        Some(mut job) if job.id == id => {
+            f(&mut job);
+            true
+        }
+        _ => false,
+    }
+}
+
+async fn run_job(state: Arc<AppState>, filename: String, id: String) {
+    let hooks: Vec<Hook> = state.hooks.matching(&filename).into_iter().cloned().collect();
+    let mut failed = false;
+    for (i, hook) in hooks.iter().enumerate() {
+        if failed {
+            update_job(&state, &filename, &id, |job| job.steps[i].status = JobStatus::Skipped);
+            continue;
+        }
+
+        let mut attempt = 0;
+        let result = loop {
+            attempt += 1;
+            // a slot per attempt, so files waiting out a retry don't hold up the others
+            let Ok(permit) = state.hooks.limit.acquire().await else {
+                return;
+            };
+            if !update_job(&state, &filename, &id, |job| {
+                job.status = JobStatus::Running;
+                job.started_at.get_or_insert_with(Utc::now);
+                job.steps[i].status = JobStatus::Running;
+                job.steps[i].attempts = attempt;
+            }) {
+                tracing::debug!("🪝 Hooks for {} were queued again, dropping the old run", filename);
+                return;
+            }
+            let outcome = run_step(&state, &filename, hook).await;
+            drop(permit);
+            match outcome {
+                Ok(output) => break Ok(output),
+                Err(failure) if attempt > hook.retries => break Err(failure),
+                Err(failure) => {
+                    let delay = state.hooks.backoff(attempt);
+                    tracing::warn!("🪝 Hook {} failed on {} ({}), retrying in {:?}", hook.name, filename, failure.error, delay);
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        };
+
+        let still_current = update_job(&state, &filename, &id, |job| {
+            let step = &mut job.steps[i];
+            match &result {
+                Ok(output) => {
+                    step.status = JobStatus::Succeeded;
+                    step.output = output.clone();
+                    step.exit_code = output.as_ref().map(|_| 0);
+                    step.error = None;
+                }
+                Err(failure) => {
+                    step.status = JobStatus::Failed;
+                    step.exit_code = failure.exit_code;
+                    step.output = failure.output.clone();
+                    step.error = Some(failure.error.clone());
+                }
+            }
+        });
+        if !still_current {
+            tracing::debug!("🪝 Hooks for {} were queued again, dropping the old run", filename);
+            return;
+        }
+        if let Err(failure) = result {
+            tracing::error!("🪝 Hook {} failed on {}: {}", hook.name, filename, failure.error);
+            failed = true;
+        }
+    }
+
+    update_job(&state, &filename, &id, |job| {
+        job.status = if failed { JobStatus::Failed } else { JobStatus::Succeeded };
+        job.finished_at = Some(Utc::now());
+    });
+    if !failed {
+        tracing::info!("🪝 Ran {} hook(s) on {}", hooks.len(), filename);
+    }
+}
+
+// one attempt at a hook, Some(output) for commands
+async fn run_step(state: &AppState, filename: &str, hook: &Hook) -> Result<Option<String>, StepFailure> {
+    match &hook.action {
+        HookAction::Tag(tag) => {
+            if !exists(state.storage.as_ref(), filename).await.unwrap_or(false) {
+                return Err(StepFailure::new("File not found"));
+            }
+            state.metadata.update(filename, |meta| {
+                meta.tags.insert(tag.clone());
+            });
+            state.metadata.persist().await.map_err(StepFailure::new)?;
+            Ok(None)
+        }
+        HookAction::Webhook(url) => {
+            let size = state.storage.stat(filename).await.map_err(StepFailure::new)?.size;
+            let payload = HookPayload {
+                hook: &hook.name,
+                filename,
+                path: None,
+                size,
+                metadata: state.metadata.get(filename),
+            };
+            let body = serde_json::to_vec(&payload).map_err(StepFailure::new)?;
+            let response = state
+                .hooks
+                .client
+                .post(url)
+                .timeout(hook.timeout)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .map_err(StepFailure::new)?;
+            if !response.status().is_success() {
+                return Err(StepFailure::new(format!("{} answered {}", url, response.status())));
+            }
+            Ok(None)
+        }
+        HookAction::Command(command) => run_command(state, filename, hook, command).await.map(Some),
+    }
+}
+
+async fn run_command(state: &AppState, filename: &str, hook: &Hook, command: &str) -> Result<String, StepFailure> {
+    let size = state.storage.stat(filename).await.map_err(StepFailure::new)?.size;
+    // commands want a file, objects stored elsewhere get a temporary copy
+    let (path, copy) = match state.storage.local_root() {
+        Some(root) => (root.join(filename), None),
+        None => {
+            let copy = std::env::temp_dir().join(format!(
+                "juicebox-hook-{}-{}",
+                uuid::Uuid::new_v4().simple(),
+                filename.replace('/', "_")
+            ));
+            download(state, filename, &copy).await.map_err(StepFailure::new)?;
+            (copy.clone(), Some(copy))
+        }
+    };
+
+    let payload = HookPayload {
+        hook: &hook.name,
+        filename,
+        path: Some(path.to_string_lossy().to_string()),
+        size,
+        metadata: state.metadata.get(filename),
+    };
+    let result = spawn_command(hook, command, &path, size, &payload).await;
+    if let Some(copy) = copy {
+        let _ = tokio::fs::remove_file(copy).await;
+    }
+    result
+}
+
+async fn spawn_command(
+    hook: &Hook,
+    command: &str,
+    path: &std::path::Path,
+    size: u64,
+    payload: &HookPayload<'_>,
+) -> Result<String, StepFailure> {
+    let stdin = serde_json::to_vec(payload).map_err(StepFailure::new)?;
+    let mut process = tokio::process::Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .env("JUICEBOX_HOOK", &hook.name)
+        .env("JUICEBOX_FILENAME", payload.filename)
+        .env("JUICEBOX_FILE", path)
+        .env("JUICEBOX_SIZE", size.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // a timed out command is killed when it's dropped
+        .kill_on_drop(true);
+    if let Some(dir) = path.parent() {
+        process.current_dir(dir);
+    }
+
+    let mut child = process.spawn().map_err(StepFailure::new)?;
+    let mut child_stdin = child.stdin.take();
+    let output = tokio::time::timeout(hook.timeout, async move {
+        // commands are free to ignore stdin
+        if let Some(pipe) = child_stdin.as_mut() {
+            let _ = pipe.write_all(&stdin).await;
+        }
+        drop(child_stdin);
+        child.wait_with_output().await
+    })
+    .await
+    .map_err(|_| StepFailure::new(format!("Timed out after {:?}", hook.timeout)))?
+    .map_err(StepFailure::new)?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let text = tail(text.trim());
+    if output.status.success() {
+        return Ok(text);
+    }
+    Err(StepFailure {
+        exit_code: output.status.code(),
+        error: match output.status.code() {
+            Some(code) => format!("Exited with status {}", code),
+            None => "Killed by a signal".to_string(),
+        },
+        output: Some(text).filter(|text| !text.is_empty()),
+    })
+}
+
+// the last OUTPUT_LIMIT bytes, cut on a character boundary
+fn tail(text: &str) -> String {
+    let mut start = text.len().saturating_sub(OUTPUT_LIMIT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].to_string()
+}
+
+async fn download(state: &AppState, filename: &str, to: &std::path::Path) -> std::io::Result<()> {
+    let mut body = state.storage.get(filename, None).await?.body;
+    let mut file = tokio::fs::File::create(to).await?;
+    while let Some(chunk) = body.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await
+}
+
+// list the latest job of every file, newest first
+pub async fn list_hook_jobs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HookJobsQuery>,
+) -> Json<HookJobListResponse> {
+    let mut jobs: Vec<HookJob> = state
+        .hook_jobs
+        .iter()
+        .map(|job| job.value().clone())
+        .filter(|job| query.status.is_none_or(|status| job.status == status))
+        .collect();
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.queued_at));
+
+    let total = jobs.len();
+    Json(HookJobListResponse { jobs, total })
+}
+
+// the latest job for a file
+pub async fn get_hook_job(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+) -> Result<Json<HookJob>, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    match state.hook_jobs.get(&filename) {
+        Some(job) => Ok(Json(job.clone())),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "No hooks have run on this file".to_string(),
+            }),
+        )),
+    }
+}
+
+// run a file's hooks again, e.g. after fixing whatever made them fail
+pub async fn rerun_hooks(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+) -> Result<Json<HookJob>, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    if !exists(state.storage.as_ref(), &filename).await.unwrap_or(false) {
+        tracing::warn!("❌ Cannot run hooks on missing file: {}", filename);
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ));
+    }
+
+    match run_hooks(&state, &filename) {
+        Some(job) => Ok(Json(job)),
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No hooks match this file".to_string(),
+            }),
+        )),
+    }
+}
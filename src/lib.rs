@@ -5,3 +5,29 @@ pub mod middleware;
 pub mod utils;
 pub mod server;
 pub mod config;
+pub mod metadata;
+pub mod tags;
+pub mod collections;
+pub mod expiry;
+pub mod downloads;
+pub mod trash;
+pub mod versions;
+pub mod quota;
+pub mod disk;
+pub mod buckets;
+pub mod vhosts;
+pub mod storage;
+pub mod sigv4;
+pub mod s3api;
+pub mod webdav;
+pub mod archive;
+pub mod extract;
+pub mod deploy;
+pub mod fallback;
+pub mod redirects;
+pub mod listing;
+pub mod caching;
+pub mod disposition;
+pub mod filetypes;
+pub mod scanning;
+pub mod hooks;
@@ -0,0 +1,269 @@
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::models::{DirectoryEntry, DirectoryListing, DirectoryListingQuery, ListingSort};
+use crate::storage::Storage;
+use crate::utils::{matches_glob, safe_key};
+
+// characters escaped in the links of a listing
+const LINK_ESCAPE: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?').add(b'`').add(b'\\').add(b'{').add(b'}');
+
+/// which directories of the public server get a generated listing
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListingSettings {
+    /// directories listings are turned on (true) or off (false) for, subdirectories included,
+    /// deepest first ("" = the whole root)
+    pub dirs: Vec<(String, bool)>,
+    /// name patterns left out of listings (`*` matches anything), on top of hidden names
+    pub hide: Vec<String>,
+}
+
+impl ListingSettings {
+    /// `dirs` entries starting with '!' turn listings off below them
+    pub fn new(dirs: &[String], hide: &[String]) -> Self {
+        let mut dirs: Vec<(String, bool)> = dirs
+            .iter()
+            .filter_map(|dir| {
+                let (dir, enabled) = match dir.strip_prefix('!') {
+                    Some(dir) => (dir, false),
+                    None => (dir.as_str(), true),
+                };
+                let Some(dir) = safe_key(dir) else {
+                    tracing::warn!("Ignoring invalid listing directory: {}", dir);
+                    return None;
+                };
+                Some((dir, enabled))
+            })
+            .collect();
+        dirs.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.split('/').filter(|s| !s.is_empty()).count()));
+        Self { dirs, hide: hide.to_vec() }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.listing_dirs, &config.listing_hide)
+    }
+
+    /// these settings with a virtual host's directories instead
+    pub fn with_overrides(&self, dirs: Option<&[String]>) -> Self {
+        match dirs {
+            Some(dirs) => Self::new(dirs, &self.hide),
+            None => self.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dirs.iter().any(|(_, enabled)| *enabled)
+    }
+
+    /// whether a directory (a key without slashes around it, "" for the root) gets a listing
+    pub fn is_listed(&self, dir: &str) -> bool {
+        let segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+        self.dirs
+            .iter()
+            .find(|(listed, _)| {
+                let listed: Vec<&str> = listed.split('/').filter(|s| !s.is_empty()).collect();
+                listed.len() <= segments.len() && listed.iter().zip(&segments).all(|(a, b)| a == b)
+            })
+            .is_some_and(|(_, enabled)| *enabled)
+    }
+
+    /// hidden names (internal directories like .chunks and .meta included) are never listed
+    pub fn is_hidden(&self, name: &str) -> bool {
+        name.starts_with('.') || self.hide.iter().any(|pattern| matches_glob(pattern, name))
+    }
+}
+
+/// where to read directories from and which ones to list
+#[derive(Clone)]
+pub struct DirectoryListings {
+    pub storage: Arc<dyn Storage>,
+    pub settings: ListingSettings,
+}
+
+impl DirectoryListings {
+    // entries of a directory, None if there's no such directory
+    async fn read(&self, dir: &str) -> std::io::Result<Option<Vec<DirectoryEntry>>> {
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let listing = self.storage.list(&prefix, false).await?;
+
+        // an empty prefix only counts as a directory if one exists on disk
+        let exists = dir.is_empty()
+            || !listing.objects.is_empty()
+            || !listing.prefixes.is_empty()
+            || self.storage.local_root().is_some_and(|root| root.join(dir).is_dir());
+        if !exists {
+            return Ok(None);
+        }
+
+        let dirs = listing.prefixes.into_iter().map(|key| DirectoryEntry {
+            name: key[prefix.len()..].trim_end_matches('/').to_string(),
+            is_dir: true,
+            size: 0,
+            modified: None,
+        });
+        let files = listing.objects.into_iter().map(|object| DirectoryEntry {
+            name: object.key[prefix.len()..].to_string(),
+            is_dir: false,
+            size: object.size,
+            modified: object.modified.map(Into::into),
+        });
+        Ok(Some(dirs.chain(files).filter(|entry| !self.settings.is_hidden(&entry.name)).collect()))
+    }
+}
+
+/// list directories without an index file, as html or json
+pub async fn serve_listings(
+    State(listings): State<Arc<DirectoryListings>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = req.uri().path().to_string();
+    let is_read = req.method() == Method::GET || req.method() == Method::HEAD;
+    if !is_read || !path.ends_with('/') {
+        return next.run(req).await;
+    }
+    let decoded = percent_decode_str(&path).decode_utf8_lossy().to_string();
+    let Some(dir) = safe_key(&decoded) else {
+        return next.run(req).await;
+    };
+    if !listings.settings.is_listed(&dir) {
+        return next.run(req).await;
+    }
+
+    let query = Query::<DirectoryListingQuery>::try_from_uri(req.uri()).map(|q| q.0).unwrap_or_default();
+    let wants_json = match query.format.as_deref() {
+        Some(format) => format.eq_ignore_ascii_case("json"),
+        None => req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html")),
+    };
+
+    // an index file (or anything else the inner router has) wins
+    let response = next.run(req).await;
+    if response.status() != StatusCode::NOT_FOUND {
+        return response;
+    }
+    let mut entries = match listings.read(&dir).await {
+        Ok(Some(entries)) => entries,
+        Ok(None) => return response,
+        Err(e) => {
+            tracing::error!("Failed to list directory {:?}: {}", dir, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // bad sort options just get the default order
+    let sort = ListingSort::parse(query.sort.as_deref());
+    let descending = query.order.as_deref().is_some_and(|order| order.eq_ignore_ascii_case("desc"));
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            ListingSort::Name => Ordering::Equal,
+            ListingSort::Size => a.size.cmp(&b.size),
+            ListingSort::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        .then_with(|| a.name.cmp(&b.name));
+        // directories stay on top either way
+        b.is_dir.cmp(&a.is_dir).then(if descending { order.reverse() } else { order })
+    });
+
+    tracing::trace!("📂 Listing {} ({} entries)", decoded, entries.len());
+    let listing = DirectoryListing {
+        path: if dir.is_empty() { "/".to_string() } else { format!("/{}/", dir) },
+        total: entries.len(),
+        entries,
+    };
+    let mut response = if wants_json {
+        Json(listing).into_response()
+    } else {
+        let html = render_html(&listing, sort, descending);
+        ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+    };
+    response.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept"));
+    response
+}
+
+fn escape(value: &str) -> String {
+    quick_xml::escape::escape(value).to_string()
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// a styled page with relative links, so it works under any prefix the directory is served at
+fn render_html(listing: &DirectoryListing, sort: ListingSort, descending: bool) -> String {
+    let title = escape(&listing.path);
+    let header = |label: &str, column: ListingSort, key: &str| {
+        let active = sort == column;
+        let order = if active && !descending { "desc" } else { "asc" };
+        let arrow = match (active, descending) {
+            (false, _) => "",
+            (true, false) => " ▲",
+            (true, true) => " ▼",
+        };
+        format!("<th><a href=\"?sort={}&amp;order={}\">{}{}</a></th>", key, order, label, arrow)
+    };
+
+    let mut rows = String::new();
+    if listing.path != "/" {
+        rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in &listing.entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = format!("{}{}", utf8_percent_encode(&entry.name, LINK_ESCAPE), suffix);
+        let size = if entry.is_dir { "-".to_string() } else { format_size(entry.size) };
+        let modified = entry
+            .modified
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr><td><a href=\"./{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape(&href),
+            escape(&entry.name),
+            suffix,
+            size,
+            modified,
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>Index of {title}</title>\n<style>\n\
+         body {{ font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 60rem; padding: 0 1rem; color: #222; }}\n\
+         table {{ border-collapse: collapse; width: 100%; }}\n\
+         th, td {{ text-align: left; padding: .35rem .75rem; border-bottom: 1px solid #eee; }}\n\
+         td:nth-child(2), th:nth-child(2) {{ text-align: right; white-space: nowrap; }}\n\
+         a {{ color: #0366d6; text-decoration: none; }}\n\
+         a:hover {{ text-decoration: underline; }}\n\
+         th a {{ color: inherit; }}\n\
+         </style>\n</head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<thead><tr>{}{}{}</tr></thead>\n\
+         <tbody>\n{rows}</tbody>\n</table>\n</body>\n</html>\n",
+        header("Name", ListingSort::Name, "name"),
+        header("Size", ListingSort::Size, "size"),
+        header("Modified", ListingSort::Modified, "modified"),
+    )
+}
@@ -2,9 +2,33 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use juicebox_omega::archive::ArchiveSettings;
+use juicebox_omega::buckets::{Bucket, DEFAULT_BUCKET};
 use juicebox_omega::config::Config;
+use juicebox_omega::deploy::DeploySettings;
+use juicebox_omega::disk::DiskSettings;
+use juicebox_omega::disposition::DispositionPolicy;
+use juicebox_omega::expiry::spawn_expiry_sweeper;
+use juicebox_omega::extract::ExtractLimits;
+use juicebox_omega::filetypes::UploadTypeRules;
+use juicebox_omega::hooks::HookPipeline;
+use juicebox_omega::fallback::FallbackSettings;
+use juicebox_omega::listing::ListingSettings;
+use juicebox_omega::caching::CacheRules;
+use juicebox_omega::middleware::SecurityHeaders;
+use juicebox_omega::trash::spawn_trash_purger;
+use juicebox_omega::versions::VersioningSettings;
+use juicebox_omega::vhosts::VirtualHosts;
+use juicebox_omega::quota::QuotaSettings;
+use juicebox_omega::scanning::ScanSettings;
 use juicebox_omega::state::AppState;
-use juicebox_omega::server::{build_admin_router, build_public_router, print_startup_banner, start_servers};
+use juicebox_omega::s3api::S3Api;
+use juicebox_omega::webdav::WebDav;
+use juicebox_omega::storage;
+use juicebox_omega::server::{
+    build_admin_router, build_public_router, build_s3_router, build_webdav_router, print_startup_banner,
+    start_servers,
+};
 
 // use mimalloc as the global allocator 
 // 10-20% faster than system allocator
@@ -41,11 +65,90 @@ fn main() {
             tracing::info!("Created files directory at: {:?}", config.files_dir);
         }
 
+        // every bucket gets its own directory and state
+        let buckets: Vec<Bucket> = config
+            .buckets
+            .iter()
+            .map(|bucket| {
+                if !bucket.root.exists() {
+                    std::fs::create_dir_all(&bucket.root).expect("Failed to create bucket directory");
+                    tracing::info!("Created bucket {} at: {:?}", bucket.name, bucket.root);
+                }
+                Bucket::from_config(&config, bucket)
+            })
+            .collect();
+
+        // infected uploads must never land somewhere they'd be served from
+        let scanning = ScanSettings::from_config(&config);
+        if scanning.is_enabled() {
+            let served = std::iter::once(config.files_dir.as_path())
+                .chain(config.buckets.iter().map(|bucket| bucket.root.as_path()));
+            if let Err(e) = scanning.check_quarantine_dir(served) {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+
         // create shared state
-        let state = Arc::new(AppState::new(config.files_dir.clone()));
+        let state = Arc::new(
+            AppState::new(config.files_dir.clone())
+                .with_versioning(VersioningSettings::from_config(&config))
+                .with_quotas(QuotaSettings::from_config(&config))
+                .with_disk(DiskSettings::from_config(&config))
+                .with_archives(ArchiveSettings::from_config(&config))
+                .with_extract_limits(ExtractLimits::from_config(&config))
+                .with_deploy_settings(DeploySettings::from_config(&config))
+                .with_fallbacks(FallbackSettings::from_config(&config))
+                .with_listings(ListingSettings::from_config(&config))
+                .with_cache_rules(CacheRules::from_config(&config))
+                .with_security_headers(SecurityHeaders::from_config(&config))
+                .with_disposition(DispositionPolicy::from_config(&config))
+                .with_upload_types(UploadTypeRules::from_config(&config))
+                .with_scanning(scanning)
+                .with_hooks(HookPipeline::from_config(&config))
+                .with_storage(storage::from_config(&config, &config.files_dir, DEFAULT_BUCKET))
+                .with_buckets(buckets)
+                .with_vhosts(VirtualHosts::from_config(&config)),
+        );
+
+        let all_states = std::iter::once(state.clone())
+            .chain(state.buckets.values().map(|b| b.state.clone()));
+        for bucket_state in all_states {
+            // redirect rules kept in a remote backend can only be read once we're running
+            if bucket_state.storage.local_root().is_none() {
+                if let Err(errors) = bucket_state.redirects.reload().await {
+                    tracing::warn!("Failed to load redirect rules: {}", errors.join("; "));
+                }
+            }
+
+            // delete expired files in the background
+            spawn_expiry_sweeper(bucket_state.clone(), config.expiry_sweep_interval_secs);
+
+            // empty old items out of the trash
+            spawn_trash_purger(bucket_state, config.trash_retention_secs, config.trash_purge_interval_secs);
+        }
 
         // build routers
-        let public_app = build_public_router(&config.files_dir);
+        let public_app = build_public_router(state.clone());
+        let mut extra_apps = Vec::new();
+        if let Some(s3_api) = &config.s3_api {
+            let api = Arc::new(S3Api::new(state.clone(), s3_api, config.api_keys.clone()));
+            let addr = SocketAddr::from((
+                s3_api.host.parse::<std::net::IpAddr>()
+                    .expect("Invalid S3_API_HOST"),
+                s3_api.port
+            ));
+            extra_apps.push(("S3 api", build_s3_router(api, &config), addr));
+        }
+        if let Some(webdav) = &config.webdav {
+            let dav = Arc::new(WebDav::new(state.clone(), &config));
+            let addr = SocketAddr::from((
+                webdav.host.parse::<std::net::IpAddr>()
+                    .expect("Invalid WEBDAV_HOST"),
+                webdav.port
+            ));
+            extra_apps.push(("WebDAV", build_webdav_router(dav, &config), addr));
+        }
         let admin_app = build_admin_router(state, &config);
 
         // define addresses from config
@@ -64,6 +167,6 @@ fn main() {
         print_startup_banner(&config);
 
         // start both serverssss
-        start_servers(public_app, admin_app, public_addr, admin_addr).await;
+        start_servers(public_app, admin_app, public_addr, admin_addr, extra_apps).await;
     });
 }
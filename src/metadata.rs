@@ -0,0 +1,296 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::storage::ObjectMeta;
+
+/// hidden directory inside files_dir where juicebox keeps its bookkeeping
+pub const META_DIR: &str = ".meta";
+const META_FILE: &str = "metadata.json";
+
+/// metadata tracked for a single stored file
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FileMetadata {
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// when the file should stop being served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// burn the file after this many completed downloads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    /// completed (fully served) downloads so far
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub download_count: u64,
+    /// name of the api key that uploaded the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// sha-256 of the content, for strong etags on the public server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<ContentHash>,
+    /// how the public server presents the file, instead of the disposition policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disposition: Option<Disposition>,
+    /// mime type of the content by its magic bytes, taken on upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_type: Option<String>,
+    /// outcome of the malware scan on upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan: Option<ScanRecord>,
+}
+
+/// what a malware scan made of some content
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    Clean,
+    Infected,
+    /// the scanner couldn't be reached or gave up (kept only when scan failures let uploads through)
+    Error,
+}
+
+/// a malware scan of a file's content
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScanRecord {
+    pub status: ScanStatus,
+    /// the signature that matched, or the error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub scanned_at: DateTime<Utc>,
+}
+
+/// how a file is served to browsers
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    /// rendered in the browser as-is
+    Inline,
+    /// always downloaded under its name
+    Attachment,
+    /// rendered under the sandbox Content-Security-Policy
+    Sandbox,
+}
+
+impl std::str::FromStr for Disposition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "inline" => Ok(Self::Inline),
+            "attachment" => Ok(Self::Attachment),
+            "sandbox" => Ok(Self::Sandbox),
+            other => Err(format!("unknown disposition '{}' (inline, attachment or sandbox)", other)),
+        }
+    }
+}
+
+/// a content hash and the size and mtime of the content it was taken from
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContentHash {
+    /// lowercase hex
+    pub sha256: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+impl ContentHash {
+    /// whether the hash still describes an object, i.e. it hasn't been rewritten since
+    pub fn matches(&self, meta: &ObjectMeta) -> bool {
+        self.size == meta.size && self.modified == meta.modified.map(DateTime::<Utc>::from)
+    }
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl FileMetadata {
+    // nothing worth keeping on disk
+    fn is_empty(&self) -> bool {
+        *self == FileMetadata::default()
+    }
+}
+
+/// a deleted file waiting in the trash
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub original_name: String,
+    pub deleted_at: DateTime<Utc>,
+    pub size: u64,
+    /// metadata the file had when it was deleted, brought back on restore
+    #[serde(default)]
+    pub metadata: FileMetadata,
+    #[serde(default)]
+    pub collections: Vec<String>,
+}
+
+/// a previous version of a file kept after it was overwritten
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub id: String,
+    /// when this version stopped being the current one
+    pub archived_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// an infected upload kept in the quarantine directory
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuarantineItem {
+    pub id: String,
+    /// name it was uploaded under
+    pub filename: String,
+    /// where the content was put
+    pub path: PathBuf,
+    pub size: u64,
+    pub scan: ScanRecord,
+}
+
+// on-disk layout of the metadata file
+#[derive(Default, Serialize, Deserialize)]
+struct MetadataSnapshot {
+    #[serde(default)]
+    files: HashMap<String, FileMetadata>,
+    #[serde(default)]
+    collections: HashMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    trash: HashMap<String, TrashItem>,
+    #[serde(default)]
+    versions: HashMap<String, Vec<VersionInfo>>,
+    #[serde(default)]
+    quarantine: HashMap<String, QuarantineItem>,
+}
+
+/// file metadata and collections, persisted as json under files_dir/.meta
+pub struct MetadataStore {
+    path: PathBuf,
+    /// metadata keyed by sanitized filename
+    pub files: DashMap<String, FileMetadata>,
+    /// named collections mapping to the filenames they group
+    pub collections: DashMap<String, BTreeSet<String>>,
+    /// trashed files keyed by trash id
+    pub trash: DashMap<String, TrashItem>,
+    /// previous versions keyed by filename, oldest first
+    pub versions: DashMap<String, Vec<VersionInfo>>,
+    /// quarantined uploads keyed by quarantine id
+    pub quarantine: DashMap<String, QuarantineItem>,
+    // serializes writes so concurrent saves don't clobber each other
+    save_lock: Mutex<()>,
+}
+
+impl MetadataStore {
+    /// load the store for a files directory, starting empty if nothing is there yet
+    pub fn load(files_dir: &Path) -> Self {
+        let path = files_dir.join(META_DIR).join(META_FILE);
+
+        let snapshot = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice::<MetadataSnapshot>(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse metadata file {:?}, starting empty: {}", path, e);
+                MetadataSnapshot::default()
+            }),
+            Err(_) => MetadataSnapshot::default(),
+        };
+
+        Self {
+            path,
+            files: snapshot.files.into_iter().collect(),
+            collections: snapshot.collections.into_iter().collect(),
+            trash: snapshot.trash.into_iter().collect(),
+            versions: snapshot.versions.into_iter().collect(),
+            quarantine: snapshot.quarantine.into_iter().collect(),
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    /// get a copy of the metadata for a file
+    pub fn get(&self, filename: &str) -> FileMetadata {
+        self.files
+            .get(filename)
+            .map(|m| m.clone())
+            .unwrap_or_default()
+    }
+
+    /// apply a change to a file's metadata, dropping the entry if it ends up empty
+    pub fn update<F>(&self, filename: &str, f: F) -> FileMetadata
+    where
+        F: FnOnce(&mut FileMetadata),
+    {
+        let mut entry = self.files.entry(filename.to_string()).or_default();
+        f(&mut entry);
+        let updated = entry.clone();
+        drop(entry);
+
+        if updated.is_empty() {
+            self.files.remove(filename);
+        }
+        updated
+    }
+
+    /// forget everything about a file (used when it gets deleted)
+    pub fn remove_file(&self, filename: &str) {
+        self.files.remove(filename);
+        for mut collection in self.collections.iter_mut() {
+            collection.remove(filename);
+        }
+    }
+
+    /// names of the collections a file belongs to
+    pub fn collections_of(&self, filename: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .collections
+            .iter()
+            .filter(|c| c.value().contains(filename))
+            .map(|c| c.key().clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// write the current state to disk (temp file + rename so readers never see half a file)
+    pub async fn persist(&self) -> std::io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+
+        let snapshot = MetadataSnapshot {
+            files: self
+                .files
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+            collections: self
+                .collections
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+            trash: self
+                .trash
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+            versions: self
+                .versions
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+            quarantine: self
+                .quarantine
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+        };
+        let bytes = serde_json::to_vec_pretty(&snapshot)?;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, &bytes).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+
+        tracing::trace!("Persisted metadata for {} files", snapshot.files.len());
+        Ok(())
+    }
+}
@@ -1,13 +1,28 @@
-use axum::http::{HeaderValue, header, Request, StatusCode};
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, header, Request, StatusCode};
 use axum::response::Response;
 use axum::middleware::Next;
 use axum::body::Body;
+use percent_encoding::percent_decode_str;
+use std::sync::Arc;
 
-use crate::config::Config;
+use crate::config::{ApiKeyConfig, Config, SecurityHeaderValues, SecurityPathConfig};
+use crate::utils::matches_path;
+
+/// name given to the main ADMIN_API_KEY
+pub const ADMIN_KEY_NAME: &str = "admin";
+
+/// name of the api key a request authenticated with ("admin" for the main key)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKeyName(pub String);
+
+/// extra named api keys accepted next to the main admin key
+#[derive(Clone, Debug, Default)]
+pub struct ApiKeyRegistry(pub Vec<ApiKeyConfig>);
 
 // api key validation
 pub async fn validate_api_key(
-    req: Request<Body>,
+    mut req: Request<Body>,
     next: Next,
 ) -> Result<Response, StatusCode> {
     // extract api key hash from request extensions (set during router setup)
@@ -29,36 +44,161 @@ pub async fn validate_api_key(
     // hash the provided key and compare
     let provided_hash = Config::hash_api_key(provided_key);
     
-    if provided_hash != *api_key_hash {
-        tracing::warn!("🚫 Invalid API key attempt");
-        return Err(StatusCode::UNAUTHORIZED);
-    }
+    let key_name = if provided_hash == *api_key_hash {
+        ADMIN_KEY_NAME.to_string()
+    } else {
+        req.extensions()
+            .get::<ApiKeyRegistry>()
+            .and_then(|registry| registry.0.iter().find(|k| k.key_hash == provided_hash))
+            .map(|k| k.name.clone())
+            .ok_or_else(|| {
+                tracing::warn!("🚫 Invalid API key attempt");
+                StatusCode::UNAUTHORIZED
+            })?
+    };
     
-    tracing::debug!("API key validated successfully");
+    tracing::debug!("API key validated successfully ({})", key_name);
+    req.extensions_mut().insert(ApiKeyName(key_name));
     Ok(next.run(req).await)
 }
 
+/// security headers sent with public responses
+#[derive(Clone, Debug, PartialEq)]
+pub struct SecurityHeaders {
+    /// send them at all
+    pub enabled: bool,
+    /// header values, empty or None ones aren't sent
+    pub values: SecurityHeaderValues,
+    /// changes for the paths matching a pattern, first match wins
+    pub paths: Vec<SecurityPathConfig>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            values: SecurityHeaderValues {
+                content_security_policy: Some(
+                    "default-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:".to_string(),
+                ),
+                frame_options: Some("DENY".to_string()),
+                ..Default::default()
+            },
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// the defaults with the configured values and path overrides
+    pub fn from_config(config: &Config) -> Self {
+        let defaults = Self::default();
+        Self {
+            values: defaults.values.merged(&config.security_headers),
+            paths: config.security_paths.clone(),
+            ..defaults
+        }
+    }
+
+    /// these headers with a virtual host's overrides applied
+    pub fn with_overrides(&self, enabled: bool, csp: Option<&str>, frame_options: Option<&str>) -> Self {
+        let overrides = SecurityHeaderValues {
+            content_security_policy: csp.map(String::from),
+            frame_options: frame_options.map(String::from),
+            ..Default::default()
+        };
+        Self {
+            enabled: self.enabled && enabled,
+            values: self.values.merged(&overrides),
+            paths: self.paths.clone(),
+        }
+    }
+
+    /// the values in effect for a request path
+    pub fn values_for(&self, path: &str) -> SecurityHeaderValues {
+        match self.paths.iter().find(|rule| matches_path(&rule.pattern, path)) {
+            Some(rule) => self.values.merged(&rule.headers),
+            None => self.values.clone(),
+        }
+    }
+
+    /// set the headers for a request path on its response
+    pub fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        if !self.enabled {
+            return;
+        }
+
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+        let values = self.values_for(path);
+        let named = [
+            (header::CONTENT_SECURITY_POLICY, values.content_security_policy),
+            (header::X_FRAME_OPTIONS, values.frame_options),
+            (header::STRICT_TRANSPORT_SECURITY, values.strict_transport_security),
+            (header::REFERRER_POLICY, values.referrer_policy),
+            (HeaderName::from_static("permissions-policy"), values.permissions_policy),
+            (HeaderName::from_static("cross-origin-opener-policy"), values.cross_origin_opener_policy),
+            (HeaderName::from_static("cross-origin-embedder-policy"), values.cross_origin_embedder_policy),
+            (HeaderName::from_static("cross-origin-resource-policy"), values.cross_origin_resource_policy),
+        ];
+        for (name, value) in named {
+            match value.filter(|v| !v.is_empty()).and_then(|v| HeaderValue::from_str(&v).ok()) {
+                Some(value) => {
+                    headers.insert(name, value);
+                }
+                None => {
+                    headers.remove(name);
+                }
+            }
+        }
+    }
+}
+
 /// headers & shit
 pub async fn add_security_headers(
     req: Request<Body>,
     next: Next,
 ) -> Response {
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
     let mut response = next.run(req).await;
-    let headers = response.headers_mut();
-    
-    headers.insert(
-        header::X_CONTENT_TYPE_OPTIONS,
-        HeaderValue::from_static("nosniff"),
-    );
-    headers.insert(
-        header::X_FRAME_OPTIONS,
-        HeaderValue::from_static("DENY"),
-    );
-    headers.insert(
-        header::CONTENT_SECURITY_POLICY,
-        HeaderValue::from_static("default-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:"),
-    );
-    
+    SecurityHeaders::default().apply(&path, response.headers_mut());
+    response
+}
+
+/// like add_security_headers, with settings of our own
+pub async fn add_custom_security_headers(
+    State(security): State<Arc<SecurityHeaders>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
+    let mut response = next.run(req).await;
+    security.apply(&path, response.headers_mut());
     response
 }
 
+
+/// keep internal dot-directories (.chunks, .meta, ...) off the public server
+pub async fn deny_hidden_paths(
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let hidden = req
+        .uri()
+        .path()
+        .split('/')
+        .any(|segment| {
+            // %2e is an encoded dot, ServeDir would happily decode it
+            let hidden = segment.starts_with('.') || segment.to_ascii_lowercase().starts_with("%2e");
+            hidden && segment != ".well-known"
+        });
+
+    if hidden {
+        tracing::debug!("Refusing to serve hidden path: {}", req.uri().path());
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(next.run(req).await)
+}
@@ -1,4 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+
+use crate::metadata::{Disposition, ScanRecord, TrashItem, VersionInfo};
+use crate::hooks::{HookJob, JobStatus};
+use crate::quota::QuotaUsage;
+use crate::archive::ArchiveFormat;
 // boring shit ahead
 
 // information about a file in the file system
@@ -8,6 +15,26 @@ pub struct FileInfo {
     pub size: u64,
     pub modified: String,
     pub is_dir: bool,
+    pub tags: Vec<String>,
+    pub collections: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    pub downloads: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disposition: Option<Disposition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan: Option<ScanRecord>,
+}
+
+// query parameters for file listing endpoint
+#[derive(Deserialize, Debug, Default)]
+pub struct ListFilesQuery {
+    pub tag: Option<String>,
+    pub collection: Option<String>,
 }
 
 // response for file listing endpoint
@@ -17,12 +44,31 @@ pub struct FileListResponse {
     pub total: usize,
 }
 
+// optional settings for single and chunked uploads
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct UploadOptions {
+    /// absolute expiry time (rfc3339)
+    pub expires_at: Option<DateTime<Utc>>,
+    /// time to live in seconds, alternative to expires_at
+    pub ttl: Option<u64>,
+    /// delete the file after this many completed downloads
+    pub max_downloads: Option<u64>,
+    /// serve the file inline, as a download or sandboxed regardless of the policy
+    pub disposition: Option<Disposition>,
+}
+
 // response for file upload endpoint
 #[derive(Serialize, Debug)]
 pub struct UploadResponse {
     pub success: bool,
     pub filename: String,
     pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_type: Option<String>,
 }
 
 // response for file deletion endpoint
@@ -38,6 +84,13 @@ pub struct StatsResponse {
     pub total_files: usize,
     pub total_size: u64,
     pub files_dir: String,
+    /// space left on the filesystem holding files_dir
+    pub disk_free_bytes: u64,
+    pub disk_total_bytes: u64,
+    /// usage against the global quota
+    pub quota: QuotaUsage,
+    /// usage of each api key that has its own quota
+    pub key_quotas: BTreeMap<String, QuotaUsage>,
 }
 
 // generic error response
@@ -75,6 +128,8 @@ pub struct ChunkedUploadInit {
     pub filename: String,
     pub total_size: u64,
     pub chunk_size: usize,
+    #[serde(flatten)]
+    pub options: UploadOptions,
 }
 
 // response for chunked upload initialization
@@ -97,4 +152,368 @@ pub struct ChunkedUploadCompleteResponse {
     pub success: bool,
     pub filename: String,
     pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_type: Option<String>,
+}
+
+// request to add or remove tags on a file
+#[derive(Deserialize, Debug)]
+pub struct TagsRequest {
+    pub tags: Vec<String>,
+}
+
+// response with the current tags of a file
+#[derive(Serialize, Debug)]
+pub struct TagsResponse {
+    pub filename: String,
+    pub tags: Vec<String>,
+}
+
+// request for batch tag operation
+#[derive(Deserialize, Debug)]
+pub struct BatchTagRequest {
+    pub filenames: Vec<String>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+// result of tagging a single file in batch operation
+#[derive(Serialize, Debug)]
+pub struct BatchTagResult {
+    pub filename: String,
+    pub success: bool,
+    pub tags: Vec<String>,
+    pub error: Option<String>,
+}
+
+// response for batch tag operation
+#[derive(Serialize, Debug)]
+pub struct BatchTagResponse {
+    pub total: usize,
+    pub successful: usize,
+    pub failed: usize,
+    pub results: Vec<BatchTagResult>,
+}
+
+// request to add files to or remove files from a collection
+#[derive(Deserialize, Debug)]
+pub struct CollectionFilesRequest {
+    pub filenames: Vec<String>,
+}
+
+// a named collection and its files
+#[derive(Serialize, Debug)]
+pub struct CollectionInfo {
+    pub name: String,
+    pub files: Vec<String>,
+    pub total: usize,
+}
+
+// response for collection listing endpoint
+#[derive(Serialize, Debug)]
+pub struct CollectionListResponse {
+    pub collections: Vec<CollectionInfo>,
+    pub total: usize,
+}
+
+// what to do with every file in a collection
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectionBulkAction {
+    Delete,
+    Expire,
+}
+
+// request for a bulk operation on a collection
+#[derive(Deserialize, Debug)]
+pub struct CollectionBulkRequest {
+    pub action: CollectionBulkAction,
+    /// when to expire the files (defaults to now), ignored for delete
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// response for a bulk operation on a collection
+#[derive(Serialize, Debug)]
+pub struct CollectionBulkResponse {
+    pub collection: String,
+    pub action: CollectionBulkAction,
+    pub total: usize,
+    pub successful: usize,
+    pub failed: usize,
+    pub results: Vec<BatchDeleteResult>,
+}
+
+// response for trash listing endpoint
+#[derive(Serialize, Debug)]
+pub struct TrashListResponse {
+    pub items: Vec<TrashItem>,
+    pub total: usize,
+    pub total_size: u64,
+}
+
+// response for restoring a file from the trash
+#[derive(Serialize, Debug)]
+pub struct RestoreResponse {
+    pub success: bool,
+    pub id: String,
+    pub filename: String,
+}
+
+// response for emptying the trash
+#[derive(Serialize, Debug)]
+pub struct PurgeTrashResponse {
+    pub purged: usize,
+    pub failed: usize,
+}
+
+// response for version listing endpoint
+#[derive(Serialize, Debug)]
+pub struct VersionListResponse {
+    pub filename: String,
+    pub versions: Vec<VersionInfo>,
+    pub total: usize,
+}
+
+// request to prune previous versions of a file
+#[derive(Deserialize, Debug)]
+pub struct PruneVersionsRequest {
+    /// number of newest versions to keep
+    pub keep: Option<usize>,
+    /// drop versions archived more than this many seconds ago
+    pub older_than: Option<u64>,
+}
+
+// response for version pruning
+#[derive(Serialize, Debug)]
+pub struct PruneVersionsResponse {
+    pub filename: String,
+    pub pruned: usize,
+    pub remaining: usize,
+}
+
+// a bucket as shown by the admin api
+#[derive(Serialize, Debug)]
+pub struct BucketInfo {
+    pub name: String,
+    pub public: bool,
+    pub hosts: Vec<String>,
+    pub files_dir: String,
+}
+
+// buckets the caller can use
+#[derive(Serialize, Debug)]
+pub struct BucketListResponse {
+    pub buckets: Vec<BucketInfo>,
+    pub total: usize,
+}
+
+// files to bundle into an archive download: a list of files or a whole directory
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ArchiveRequest {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// directory to archive with everything below it ("" or "/" = the whole bucket)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// download filename without the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// request for a signed archive link on the public server
+#[derive(Deserialize, Debug)]
+pub struct ArchiveLinkRequest {
+    #[serde(flatten)]
+    pub archive: ArchiveRequest,
+    /// seconds the link stays valid
+    pub expires_in: Option<u64>,
+}
+
+// a signed archive link
+#[derive(Serialize, Debug)]
+pub struct ArchiveLinkResponse {
+    /// path and query on the public server
+    pub url: String,
+    pub expires_at: String,
+    pub files: usize,
+}
+
+// where and how to extract an uploaded archive
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ExtractOptions {
+    /// directory to extract into ("" = the root)
+    #[serde(default)]
+    pub dir: String,
+    /// swap the whole directory for the archive's content instead of merging into it
+    #[serde(default)]
+    pub replace: bool,
+}
+
+// response for an extracted archive upload
+#[derive(Serialize, Debug)]
+pub struct ExtractResponse {
+    pub success: bool,
+    pub dir: String,
+    pub files: usize,
+    pub total_size: u64,
+    pub replaced: bool,
+    /// entries left out: hidden files, links and other non-regular files
+    pub skipped: Vec<String>,
+}
+
+// a deployed site and its releases, oldest first
+#[derive(Serialize, Debug)]
+pub struct SiteInfo {
+    pub name: String,
+    /// release being served (None until one is activated)
+    pub current: Option<String>,
+    pub releases: Vec<String>,
+}
+
+// response for listing deployed sites
+#[derive(Serialize, Debug)]
+pub struct SiteListResponse {
+    pub sites: Vec<SiteInfo>,
+    pub total: usize,
+}
+
+// whether a new release goes live right away
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeployOptions {
+    #[serde(default = "default_activate")]
+    pub activate: bool,
+}
+
+fn default_activate() -> bool {
+    true
+}
+
+impl Default for DeployOptions {
+    fn default() -> Self {
+        Self { activate: true }
+    }
+}
+
+// response for a new release
+#[derive(Serialize, Debug)]
+pub struct DeploymentResponse {
+    pub success: bool,
+    pub site: String,
+    pub release: String,
+    pub files: usize,
+    pub total_size: u64,
+    pub active: bool,
+    /// old releases removed to stay within the kept count
+    pub pruned: Vec<String>,
+    /// entries left out of the release: hidden files, links and other non-regular files
+    pub skipped: Vec<String>,
+}
+
+// release to switch a site to
+#[derive(Deserialize, Debug)]
+pub struct ActivateReleaseRequest {
+    pub release: String,
+}
+
+// a redirect or rewrite rule
+#[derive(Serialize, Debug)]
+pub struct RedirectRuleInfo {
+    pub from: String,
+    pub to: String,
+    /// 301/302/303/307/308, or 200 for a rewrite
+    pub status: u16,
+    pub force: bool,
+    pub headers: BTreeMap<String, String>,
+}
+
+// the redirect rules in effect and the rules file they came from
+#[derive(Serialize, Debug)]
+pub struct RedirectRulesResponse {
+    pub rules: Vec<RedirectRuleInfo>,
+    pub total: usize,
+    pub text: String,
+}
+
+// how a public directory listing is sorted
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ListingSort {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+impl ListingSort {
+    /// the sort a query value asks for, the default one for anything unknown
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(|v| v.to_ascii_lowercase()).as_deref() {
+            Some("size") => Self::Size,
+            Some("modified") | Some("date") => Self::Modified,
+            _ => Self::Name,
+        }
+    }
+}
+
+// query parameters of a public directory listing
+#[derive(Deserialize, Debug, Default)]
+pub struct DirectoryListingQuery {
+    /// name (default), size or modified
+    pub sort: Option<String>,
+    /// asc (default) or desc
+    pub order: Option<String>,
+    /// json or html, instead of going by the Accept header
+    pub format: Option<String>,
+}
+
+// a file or directory in a public directory listing
+#[derive(Serialize, Debug)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+// a public directory listing
+#[derive(Serialize, Debug)]
+pub struct DirectoryListing {
+    /// the listed directory, decoded, starting and ending with '/'
+    pub path: String,
+    pub entries: Vec<DirectoryEntry>,
+    pub total: usize,
+}
+
+// request to set how a file is served, null to go back to the policy
+#[derive(Deserialize, Debug)]
+pub struct DispositionRequest {
+    pub disposition: Option<Disposition>,
+}
+
+// response for the disposition endpoint
+#[derive(Serialize, Debug)]
+pub struct DispositionResponse {
+    pub filename: String,
+    pub disposition: Option<Disposition>,
+}
+
+// query parameters for listing hook jobs
+#[derive(Deserialize, Debug, Default)]
+pub struct HookJobsQuery {
+    pub status: Option<JobStatus>,
+}
+
+// response for listing hook jobs
+#[derive(Serialize, Debug)]
+pub struct HookJobListResponse {
+    pub jobs: Vec<HookJob>,
+    pub total: usize,
 }
@@ -0,0 +1,481 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+use crate::deploy::is_release_file;
+use crate::disk::ensure_free_space;
+use crate::handlers::CHUNKS_DIR;
+use crate::metadata::META_DIR;
+use crate::state::AppState;
+use crate::storage::{ByteStream, Listing, ObjectMeta, ObjectStream, Storage};
+
+/// limits on stored data, unset means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_files.is_none()
+    }
+}
+
+/// global quota plus per api key quotas
+#[derive(Clone, Debug, Default)]
+pub struct QuotaSettings {
+    pub global: Quota,
+    /// keyed by api key name
+    pub per_key: HashMap<String, Quota>,
+}
+
+impl QuotaSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            global: config.global_quota,
+            per_key: config
+                .api_keys
+                .iter()
+                .filter(|k| !k.quota.is_unlimited())
+                .map(|k| (k.name.clone(), k.quota))
+                .collect(),
+        }
+    }
+}
+
+/// current usage measured against a quota
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct QuotaUsage {
+    pub used_bytes: u64,
+    pub used_files: u64,
+    /// space held for uploads that haven't finished yet
+    pub reserved_bytes: u64,
+    pub reserved_files: u64,
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl QuotaUsage {
+    // why an upload of `bytes` / `files` would go over the limits, if it would
+    fn exceeded_by(&self, bytes: u64, files: u64) -> Option<String> {
+        if let Some(max) = self.max_bytes {
+            let needed = self.used_bytes + self.reserved_bytes + bytes;
+            if needed > max {
+                return Some(format!("{} of {} bytes would be used", needed, max));
+            }
+        }
+        if let Some(max) = self.max_files {
+            let needed = self.used_files + self.reserved_files + files;
+            if needed > max {
+                return Some(format!("{} of {} files would be stored", needed, max));
+            }
+        }
+        None
+    }
+
+    // bytes that can still be stored, None without a byte limit
+    fn remaining_bytes(&self) -> Option<u64> {
+        self.max_bytes
+            .map(|max| max.saturating_sub(self.used_bytes.saturating_add(self.reserved_bytes)))
+    }
+}
+
+/// why a reservation was refused
+#[derive(Debug)]
+pub enum QuotaError {
+    /// the upload doesn't fit (507 Insufficient Storage)
+    Exceeded(String),
+    /// the disk doesn't have room for it (507 Insufficient Storage)
+    NoSpace(String),
+    /// usage couldn't be measured
+    Io(io::Error),
+}
+
+impl std::fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaError::Exceeded(reason) | QuotaError::NoSpace(reason) => write!(f, "{}", reason),
+            QuotaError::Io(e) => write!(f, "Failed to measure storage usage: {}", e),
+        }
+    }
+}
+
+/// an in-flight single upload holding quota, released on drop
+pub struct UploadReservation<'a> {
+    state: &'a AppState,
+    id: String,
+}
+
+impl Drop for UploadReservation<'_> {
+    fn drop(&mut self) {
+        self.state.pending_uploads.remove(&self.id);
+    }
+}
+
+/// space held by an in-flight single upload
+#[derive(Clone, Debug)]
+pub struct PendingUpload {
+    pub owner: Option<String>,
+    pub bytes: u64,
+}
+
+/// global usage plus usage of every api key with a quota
+pub async fn usage_report(state: &AppState) -> io::Result<(QuotaUsage, BTreeMap<String, QuotaUsage>)> {
+    let global = usage(state, None).await?;
+    let mut keys = BTreeMap::new();
+    for name in state.quotas.per_key.keys() {
+        keys.insert(name.clone(), usage(state, Some(name)).await?);
+    }
+    Ok((global, keys))
+}
+
+/// measure usage for the whole store (owner None) or for one api key
+///
+/// global bytes cover everything on disk (trash and old versions included), files only
+/// count visible files; per key usage covers the files that key uploaded
+pub async fn usage(state: &AppState, owner: Option<&str>) -> io::Result<QuotaUsage> {
+    let (used_bytes, used_files) = match owner {
+        None => state.stored.totals(state.storage.as_ref()).await?,
+        Some(owner) => {
+            let owned: Vec<String> = state
+                .metadata
+                .files
+                .iter()
+                .filter(|m| m.owner.as_deref() == Some(owner))
+                .map(|m| m.key().clone())
+                .collect();
+            let mut bytes = 0;
+            let mut files = 0;
+            for name in owned {
+                if let Ok(meta) = state.storage.stat(&name).await {
+                    bytes += meta.size;
+                    files += 1;
+                }
+            }
+            (bytes, files)
+        }
+    };
+
+    let belongs = |o: &Option<String>| owner.is_none() || o.as_deref() == owner;
+    let mut reserved_bytes = 0;
+    let mut reserved_files = 0;
+    for upload in state.pending_uploads.iter().filter(|u| belongs(&u.owner)) {
+        reserved_bytes += upload.bytes;
+        reserved_files += 1;
+    }
+    for upload in state.chunked_uploads.iter().filter(|u| belongs(&u.settings.owner)) {
+        reserved_bytes += upload.total_size;
+        reserved_files += 1;
+    }
+
+    let limits = match owner {
+        None => state.quotas.global,
+        Some(owner) => state.quotas.per_key.get(owner).copied().unwrap_or_default(),
+    };
+
+    Ok(QuotaUsage {
+        used_bytes,
+        used_files,
+        reserved_bytes,
+        reserved_files,
+        max_bytes: limits.max_bytes,
+        max_files: limits.max_files,
+    })
+}
+
+/// bytes that can still be stored before the global quota or `owner`'s runs out, None if neither limits bytes
+pub async fn remaining_bytes(state: &AppState, owner: Option<&str>) -> io::Result<Option<u64>> {
+    let mut remaining = None;
+    if state.quotas.global.max_bytes.is_some() {
+        remaining = usage(state, None).await?.remaining_bytes();
+    }
+    if let Some(owner) = owner.filter(|o| state.quotas.per_key.get(*o).is_some_and(|q| q.max_bytes.is_some())) {
+        let left = usage(state, Some(owner)).await?.remaining_bytes().unwrap_or(u64::MAX);
+        remaining = Some(remaining.map_or(left, |global: u64| global.min(left)));
+    }
+    Ok(remaining)
+}
+
+/// check the global and per key quotas and the free disk space for a new upload and reserve
+/// the space
+///
+/// `register` runs while the quota lock is held so the reservation it records can't race
+/// another upload's check; `replaces` is the file being overwritten, whose space is given back
+/// (on the quota only, the new content is written before the old one goes away)
+pub async fn reserve<T, F>(
+    state: &AppState,
+    owner: Option<&str>,
+    bytes: u64,
+    replaces: Option<&str>,
+    register: F,
+) -> Result<T, QuotaError>
+where
+    F: FnOnce() -> T,
+{
+    let _lock = state.quota_lock.lock().await;
+
+    ensure_free_space(state, bytes).await.map_err(QuotaError::NoSpace)?;
+
+    // overwriting gives back the old file's space (unless it's kept as a version)
+    let (freed_bytes, freed_files) = match replaces {
+        Some(name) if !state.versioning.is_versioned(name) => {
+            match state.storage.stat(name).await {
+                Ok(meta) => (meta.size, 1),
+                _ => (0, 0),
+            }
+        }
+        _ => (0, 0),
+    };
+    let bytes = bytes.saturating_sub(freed_bytes);
+    let files = 1 - freed_files;
+
+    if !state.quotas.global.is_unlimited() {
+        let global = usage(state, None).await.map_err(QuotaError::Io)?;
+        if let Some(reason) = global.exceeded_by(bytes, files) {
+            return Err(QuotaError::Exceeded(format!("Storage quota exceeded: {}", reason)));
+        }
+    }
+
+    if let Some(owner) = owner {
+        if state.quotas.per_key.contains_key(owner) {
+            let key_usage = usage(state, Some(owner)).await.map_err(QuotaError::Io)?;
+            if let Some(reason) = key_usage.exceeded_by(bytes, files) {
+                return Err(QuotaError::Exceeded(format!(
+                    "Storage quota for {} exceeded: {}",
+                    owner, reason
+                )));
+            }
+        }
+    }
+
+    Ok(register())
+}
+
+/// reserve space for a single (non-chunked) upload until the returned guard is dropped
+pub async fn reserve_upload<'a>(
+    state: &'a AppState,
+    owner: Option<&str>,
+    bytes: u64,
+    replaces: Option<&str>,
+) -> Result<UploadReservation<'a>, QuotaError> {
+    let id = uuid::Uuid::new_v4().to_string();
+    reserve(state, owner, bytes, replaces, || {
+        state.pending_uploads.insert(
+            id.clone(),
+            PendingUpload {
+                owner: owner.map(|o| o.to_string()),
+                bytes,
+            },
+        );
+    })
+    .await?;
+    Ok(UploadReservation { state, id })
+}
+
+/// running totals of what the storage holds, walked once on first use and then kept up to
+/// date by [`MeteredStorage`] as objects are written and removed
+///
+/// bytes cover everything but chunks and metadata (trash and old versions included), files
+/// only count visible files and deployed releases
+#[derive(Debug, Default)]
+pub struct StoredUsage {
+    totals: Mutex<Option<(u64, u64)>>,
+}
+
+impl StoredUsage {
+    /// bytes stored and number of files, counting them if nothing has been counted yet
+    pub async fn totals(&self, storage: &dyn Storage) -> io::Result<(u64, u64)> {
+        if let Some(totals) = *self.totals.lock().unwrap() {
+            return Ok(totals);
+        }
+        let counted = count_stored(storage).await?;
+        Ok(*self.totals.lock().unwrap().get_or_insert(counted))
+    }
+
+    /// forget the totals, for changes made behind the storage's back; the next check recounts
+    pub fn invalidate(&self) {
+        *self.totals.lock().unwrap() = None;
+    }
+
+    // nothing to keep up to date until someone has asked for the totals
+    fn is_counted(&self) -> bool {
+        self.totals.lock().unwrap().is_some()
+    }
+
+    fn add(&self, key: &str, bytes: u64) {
+        if let (Some((stored_bytes, stored_files)), Some(is_file)) = (self.totals.lock().unwrap().as_mut(), counts_as(key)) {
+            *stored_bytes += bytes;
+            *stored_files += is_file as u64;
+        }
+    }
+
+    fn remove(&self, key: &str, bytes: u64) {
+        if let (Some((stored_bytes, stored_files)), Some(is_file)) = (self.totals.lock().unwrap().as_mut(), counts_as(key)) {
+            *stored_bytes = stored_bytes.saturating_sub(bytes);
+            *stored_files = stored_files.saturating_sub(is_file as u64);
+        }
+    }
+}
+
+/// a storage backend that keeps [`StoredUsage`] up to date with what's written through it
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage>,
+    usage: Arc<StoredUsage>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn Storage>, usage: Arc<StoredUsage>) -> Self {
+        Self { inner, usage }
+    }
+
+    // size of what's at `key` now, if it counts and the totals are being kept
+    async fn counted_size(&self, key: &str) -> Option<u64> {
+        if !self.usage.is_counted() || counts_as(key).is_none() {
+            return None;
+        }
+        self.inner.stat(key).await.ok().map(|meta| meta.size)
+    }
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn put(&self, key: &str, body: ByteStream) -> io::Result<u64> {
+        let replaced = self.counted_size(key).await;
+        let written = self.inner.put(key, body).await?;
+        if let Some(size) = replaced {
+            self.usage.remove(key, size);
+        }
+        self.usage.add(key, written);
+        Ok(written)
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<ObjectStream> {
+        self.inner.get(key, range).await
+    }
+
+    async fn stat(&self, key: &str) -> io::Result<ObjectMeta> {
+        self.inner.stat(key).await
+    }
+
+    async fn list(&self, prefix: &str, recursive: bool) -> io::Result<Listing> {
+        self.inner.list(prefix, recursive).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let removed = self.counted_size(key).await;
+        self.inner.delete(key).await?;
+        if let Some(size) = removed {
+            self.usage.remove(key, size);
+        }
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let moved = if self.usage.is_counted() { self.inner.stat(from).await.ok() } else { None };
+        let replaced = self.counted_size(to).await;
+        self.inner.rename(from, to).await?;
+        match moved {
+            Some(meta) => {
+                self.usage.remove(from, meta.size);
+                if let Some(size) = replaced {
+                    self.usage.remove(to, size);
+                }
+                self.usage.add(to, meta.size);
+            }
+            // a directory (or something that changed meanwhile), count again next time
+            None => self.usage.invalidate(),
+        }
+        Ok(())
+    }
+
+    async fn rename_new(&self, from: &str, to: &str) -> io::Result<()> {
+        let moved = if self.usage.is_counted() { self.inner.stat(from).await.ok() } else { None };
+        self.inner.rename_new(from, to).await?;
+        match moved {
+            Some(meta) => {
+                self.usage.remove(from, meta.size);
+                self.usage.add(to, meta.size);
+            }
+            None => self.usage.invalidate(),
+        }
+        Ok(())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+        let removed = if self.usage.is_counted() && counts_as(prefix).is_some() {
+            self.inner.list(prefix, true).await?.objects
+        } else {
+            Vec::new()
+        };
+        self.inner.delete_prefix(prefix).await?;
+        for object in removed {
+            self.usage.remove(&object.key, object.size);
+        }
+        Ok(())
+    }
+
+    async fn create_multipart(&self, key: &str, part_size: u64) -> io::Result<Option<String>> {
+        self.inner.create_multipart(key, part_size).await
+    }
+
+    async fn put_part(&self, key: &str, upload_id: &str, part_number: u32, data: Bytes) -> io::Result<()> {
+        self.inner.put_part(key, upload_id, part_number, data).await
+    }
+
+    async fn complete_multipart(&self, key: &str, upload_id: &str) -> io::Result<u64> {
+        let replaced = self.counted_size(key).await;
+        let written = self.inner.complete_multipart(key, upload_id).await?;
+        if let Some(size) = replaced {
+            self.usage.remove(key, size);
+        }
+        self.usage.add(key, written);
+        Ok(written)
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        self.inner.abort_multipart(key, upload_id).await
+    }
+
+    async fn create_dir(&self, prefix: &str) -> io::Result<()> {
+        self.inner.create_dir(prefix).await
+    }
+
+    async fn remove_dir(&self, prefix: &str) -> io::Result<()> {
+        self.inner.remove_dir(prefix).await
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        self.inner.local_root()
+    }
+}
+
+// how an object counts towards usage: None if it doesn't (chunks are already reserved),
+// otherwise whether it's a file on top of its bytes
+fn counts_as(key: &str) -> Option<bool> {
+    let top = key.split('/').next().unwrap_or_default();
+    if top == CHUNKS_DIR || top == META_DIR {
+        return None;
+    }
+    Some(is_release_file(key) || !key.split('/').any(|segment| segment.starts_with('.')))
+}
+
+// total bytes stored and number of files, by walking the whole storage
+async fn count_stored(storage: &dyn Storage) -> io::Result<(u64, u64)> {
+    let mut bytes = 0;
+    let mut files = 0;
+
+    for object in storage.list("", true).await?.objects {
+        if let Some(is_file) = counts_as(&object.key) {
+            bytes += object.size;
+            files += is_file as u64;
+        }
+    }
+    Ok((bytes, files))
+}
@@ -0,0 +1,395 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, State},
+    http::{header, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::metadata::META_DIR;
+use crate::models::{ErrorResponse, RedirectRuleInfo, RedirectRulesResponse};
+use crate::state::AppState;
+use crate::storage::{put_bytes, read_all, Storage};
+
+/// rules file of a files directory, inside the metadata directory
+pub const REDIRECTS_FILE: &str = "redirects";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `:name`, one path segment
+    Param(String),
+    /// `*` at the end, any number of segments
+    Splat,
+}
+
+/// one line of the rules file: `/from /to [status][!] [Header=value ...]`
+#[derive(Clone, Debug)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    /// a redirect status, or 200 for a rewrite served from `to` under the original path
+    pub status: StatusCode,
+    /// apply even when a file exists at the path
+    pub force: bool,
+    /// added to the responses this rule produces
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pattern: Vec<Segment>,
+}
+
+// split a line into whitespace separated tokens, double quotes keep spaces in a token
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut in_token = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            '#' if !quoted && !in_token => break,
+            c => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+impl RedirectRule {
+    /// parse one line of the rules file, None for blank lines and comments
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let tokens = tokenize(line)?;
+        let mut tokens = tokens.into_iter();
+        let Some(from) = tokens.next() else {
+            return Ok(None);
+        };
+        let to = tokens.next().ok_or("missing target")?;
+        if !from.starts_with('/') {
+            return Err(format!("source must be a path: {}", from));
+        }
+
+        let segments: Vec<&str> = from.split('/').filter(|s| !s.is_empty()).collect();
+        let mut pattern = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            pattern.push(match *segment {
+                "*" if i == segments.len() - 1 => Segment::Splat,
+                "*" => return Err("* is only allowed at the end of the source".to_string()),
+                s if s.starts_with(':') && s.len() > 1 => Segment::Param(s[1..].to_string()),
+                s => Segment::Literal(percent_decode_str(s).decode_utf8_lossy().to_string()),
+            });
+        }
+
+        let mut status = StatusCode::MOVED_PERMANENTLY;
+        let mut force = false;
+        let mut headers = Vec::new();
+        for token in tokens {
+            if let Some((name, value)) = token.split_once('=') {
+                let name = HeaderName::try_from(name).map_err(|_| format!("invalid header name: {}", name))?;
+                let value = HeaderValue::try_from(value).map_err(|_| format!("invalid value for {}", name))?;
+                headers.push((name, value));
+                continue;
+            }
+            if !headers.is_empty() {
+                return Err(format!("status must come before headers: {}", token));
+            }
+            let (code, bang) = match token.strip_suffix('!') {
+                Some(code) => (code, true),
+                None => (token.as_str(), false),
+            };
+            status = code
+                .parse::<u16>()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .filter(|s| *s == StatusCode::OK || matches!(s.as_u16(), 301 | 302 | 303 | 307 | 308))
+                .ok_or_else(|| format!("unsupported status: {}", token))?;
+            force = bang;
+        }
+
+        if status == StatusCode::OK && !to.starts_with('/') {
+            return Err(format!("rewrites need a local path: {}", to));
+        }
+        Ok(Some(Self { from, to, status, force, headers, pattern }))
+    }
+
+    // match a request path, giving the captured placeholders (raw, still percent-encoded)
+    fn captures(&self, path: &str) -> Option<HashMap<&str, String>> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut captures = HashMap::new();
+        for (i, part) in self.pattern.iter().enumerate() {
+            match part {
+                Segment::Splat => {
+                    captures.insert("splat", segments.get(i..).unwrap_or_default().join("/"));
+                    return Some(captures);
+                }
+                Segment::Param(name) => {
+                    captures.insert(name.as_str(), segments.get(i)?.to_string());
+                }
+                Segment::Literal(literal) => {
+                    let segment = segments.get(i)?;
+                    if percent_decode_str(segment).decode_utf8_lossy() != literal.as_str() {
+                        return None;
+                    }
+                }
+            }
+        }
+        (segments.len() == self.pattern.len()).then_some(captures)
+    }
+
+    // the target with placeholders filled in
+    fn target(&self, captures: &HashMap<&str, String>) -> String {
+        let mut target = String::with_capacity(self.to.len());
+        let mut rest = self.to.as_str();
+        while let Some(at) = rest.find(':') {
+            target.push_str(&rest[..at]);
+            let name_len = rest[at + 1..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len() - at - 1);
+            let name = &rest[at + 1..at + 1 + name_len];
+            match captures.get(name) {
+                Some(value) => target.push_str(value),
+                None => target.push_str(&rest[at..at + 1 + name_len]),
+            }
+            rest = &rest[at + 1 + name_len..];
+        }
+        target.push_str(rest);
+        target
+    }
+
+    pub fn info(&self) -> RedirectRuleInfo {
+        RedirectRuleInfo {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            status: self.status.as_u16(),
+            force: self.force,
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// parse a whole rules file, collecting every bad line
+pub fn parse_rules(text: &str) -> Result<Vec<RedirectRule>, Vec<String>> {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        match RedirectRule::parse(line) {
+            Ok(Some(rule)) => rules.push(rule),
+            Ok(None) => {}
+            Err(e) => errors.push(format!("line {}: {}", number + 1, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors)
+    }
+}
+
+/// the redirect rules of a bucket, swapped as a whole on reload
+pub struct RedirectRules {
+    storage: Arc<dyn Storage>,
+    rules: RwLock<Arc<Vec<RedirectRule>>>,
+}
+
+impl RedirectRules {
+    /// load `.meta/redirects`, skipping bad lines so a typo doesn't take every rule down
+    ///
+    /// read right away from local storage, other backends start without rules until `reload`
+    pub fn load(storage: Arc<dyn Storage>) -> Self {
+        let text = storage
+            .local_root()
+            .and_then(|root| std::fs::read_to_string(root.join(rules_key())).ok())
+            .unwrap_or_default();
+        let rules: Vec<RedirectRule> = text
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                RedirectRule::parse(line)
+                    .map_err(|e| tracing::warn!("Skipping redirect rule on line {} of {}: {}", number + 1, rules_key(), e))
+                    .ok()
+                    .flatten()
+            })
+            .collect();
+        if !rules.is_empty() {
+            tracing::info!("↪️ Loaded {} redirect rules from {}", rules.len(), rules_key());
+        }
+        Self {
+            storage,
+            rules: RwLock::new(Arc::new(rules)),
+        }
+    }
+
+    /// the rules in effect right now
+    pub fn current(&self) -> Arc<Vec<RedirectRule>> {
+        self.rules.read().unwrap().clone()
+    }
+
+    fn replace(&self, rules: Vec<RedirectRule>) {
+        *self.rules.write().unwrap() = Arc::new(rules);
+    }
+
+    // the rules file as stored, empty if there is none
+    async fn read(&self) -> std::io::Result<String> {
+        match read_all(self.storage.as_ref(), &rules_key()).await {
+            Ok(text) => Ok(String::from_utf8_lossy(&text).to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// read the rules file again, keeping the current rules if it has errors
+    pub async fn reload(&self) -> Result<usize, Vec<String>> {
+        let text = self
+            .read()
+            .await
+            .map_err(|e| vec![format!("failed to read rules: {}", e)])?;
+        let rules = parse_rules(&text)?;
+        let count = rules.len();
+        self.replace(rules);
+        Ok(count)
+    }
+
+    /// write a new rules file and switch to it
+    pub async fn save(&self, text: &str) -> Result<usize, Vec<String>> {
+        let rules = parse_rules(text)?;
+        let count = rules.len();
+        put_bytes(self.storage.as_ref(), &rules_key(), Bytes::from(text.to_string()))
+            .await
+            .map_err(|e| vec![format!("failed to save rules: {}", e)])?;
+        self.replace(rules);
+        Ok(count)
+    }
+
+    /// the rules file as written
+    pub async fn text(&self) -> String {
+        self.read().await.unwrap_or_default()
+    }
+}
+
+fn rules_key() -> String {
+    format!("{}/{}", META_DIR, REDIRECTS_FILE)
+}
+
+fn with_headers(mut response: Response, rule: &RedirectRule) -> Response {
+    for (name, value) in &rule.headers {
+        response.headers_mut().insert(name.clone(), value.clone());
+    }
+    response
+}
+
+/// apply the first matching redirect or rewrite rule
+///
+/// rules without `!` only kick in when there's no file at the path
+pub async fn apply_redirects(
+    State(rules): State<Arc<RedirectRules>>,
+    OriginalUri(original): OriginalUri,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+    let rules = rules.current();
+    let path = req.uri().path().to_string();
+    let Some((rule, captures)) = rules.iter().find_map(|rule| rule.captures(&path).map(|c| (rule, c))) else {
+        return next.run(req).await;
+    };
+
+    let (parts, body) = req.into_parts();
+    if !rule.force {
+        let response = next.clone().run(Request::from_parts(parts.clone(), body)).await;
+        if response.status() != StatusCode::NOT_FOUND {
+            return response;
+        }
+    }
+
+    let mut target = rule.target(&captures);
+    if let (Some(query), false) = (parts.uri.query(), target.contains('?')) {
+        target = format!("{}?{}", target, query);
+    }
+
+    if rule.status != StatusCode::OK {
+        // nested routers (buckets under /b/<name>) see the path without their prefix
+        if target.starts_with('/') {
+            let prefix = original.path().strip_suffix(path.as_str()).unwrap_or_default();
+            target = format!("{}{}", prefix, target);
+        }
+        tracing::debug!("↪️ Redirecting {} to {} ({})", path, target, rule.status);
+        let response = match HeaderValue::try_from(target) {
+            Ok(location) => (rule.status, [(header::LOCATION, location)]).into_response(),
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        return with_headers(response, rule);
+    }
+
+    let Ok(uri) = target.parse() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    tracing::debug!("↪️ Rewriting {} to {}", path, target);
+    let mut rewritten = Request::from_parts(parts, Body::empty());
+    *rewritten.uri_mut() = uri;
+    with_headers(next.run(rewritten).await, rule)
+}
+
+fn rules_response(state: &AppState, text: String) -> Json<RedirectRulesResponse> {
+    let rules: Vec<RedirectRuleInfo> = state.redirects.current().iter().map(RedirectRule::info).collect();
+    Json(RedirectRulesResponse { total: rules.len(), rules, text })
+}
+
+fn invalid_rules(errors: Vec<String>) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Rejecting redirect rules: {}", errors.join("; "));
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: format!("Invalid redirect rules: {}", errors.join("; ")),
+        }),
+    )
+}
+
+// list the redirect rules in effect
+pub async fn list_redirects(State(state): State<Arc<AppState>>) -> Json<RedirectRulesResponse> {
+    let text = state.redirects.text().await;
+    rules_response(&state, text)
+}
+
+// replace the rules file with the request body
+pub async fn update_redirects(
+    State(state): State<Arc<AppState>>,
+    body: String,
+) -> Result<Json<RedirectRulesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let count = state.redirects.save(&body).await.map_err(invalid_rules)?;
+    tracing::info!("↪️ Saved {} redirect rules", count);
+    Ok(rules_response(&state, body))
+}
+
+// pick up changes made to the rules file by hand
+pub async fn reload_redirects(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<RedirectRulesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let count = state.redirects.reload().await.map_err(invalid_rules)?;
+    tracing::info!("↪️ Reloaded {} redirect rules", count);
+    let text = state.redirects.text().await;
+    Ok(rules_response(&state, text))
+}
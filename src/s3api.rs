@@ -0,0 +1,1525 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Extension, Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use dashmap::DashMap;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+use crate::buckets::{can_access, DEFAULT_BUCKET};
+use crate::caching::UploadHasher;
+use crate::config::{ApiKeyConfig, S3ApiConfig};
+use crate::disk::{ensure_free_space, is_disk_full};
+use crate::filetypes::unsupported_type;
+use crate::handlers::{check_content, promote_staged, record_upload_metadata, staging_key, CHUNKS_DIR};
+use crate::hooks::run_hooks;
+use crate::middleware::{ApiKeyName, ApiKeyRegistry};
+use crate::models::ErrorResponse;
+use crate::quota::{reserve_upload, QuotaError};
+use crate::sigv4::{self, CanonicalRequest, Scope, ALGORITHM, AMZ_DATE_FORMAT, UNSIGNED_PAYLOAD};
+use crate::state::{AppState, UploadSettings};
+use crate::storage::{check_key, concat, etag, http_date, requested_range, ByteStream, ObjectMeta};
+use crate::trash::move_to_trash;
+
+/// how far a request's signing time may be from ours
+const MAX_CLOCK_SKEW_SECS: i64 = 15 * 60;
+/// longest a presigned url may stay valid (7 days, same as s3)
+const MAX_PRESIGNED_EXPIRY_SECS: i64 = 7 * 24 * 60 * 60;
+/// most keys returned by one listing page
+const MAX_KEYS: usize = 1000;
+/// largest single chunk accepted in an aws-chunked body
+const MAX_PAYLOAD_CHUNK: usize = 16 * 1024 * 1024;
+
+const XML_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+const STREAMING_SIGNED_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+const STREAMING_SIGNED_PAYLOAD_TRAILER: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER";
+const STREAMING_UNSIGNED_PAYLOAD_TRAILER: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+// bucket sub-resources we don't implement
+const UNSUPPORTED_SUBRESOURCES: &[&str] = &[
+    "acl", "cors", "encryption", "lifecycle", "logging", "notification", "object-lock", "policy",
+    "replication", "tagging", "website",
+];
+
+/// everything the s3 listener needs: the buckets it exposes and who may sign requests
+pub struct S3Api {
+    /// every bucket by name, the default one included
+    buckets: BTreeMap<String, Arc<AppState>>,
+    region: String,
+    /// api key name (the access key id) -> api key (the secret)
+    credentials: HashMap<String, String>,
+    registry: ApiKeyRegistry,
+    /// multipart uploads in progress, by upload id
+    uploads: DashMap<String, MultipartUpload>,
+}
+
+#[derive(Clone)]
+struct MultipartUpload {
+    bucket: String,
+    key: String,
+    owner: String,
+    initiated: DateTime<Utc>,
+    parts: BTreeMap<u32, UploadedPart>,
+}
+
+#[derive(Clone)]
+struct UploadedPart {
+    size: u64,
+    etag: String,
+    modified: DateTime<Utc>,
+}
+
+impl S3Api {
+    pub fn new(state: Arc<AppState>, config: &S3ApiConfig, api_keys: Vec<ApiKeyConfig>) -> Self {
+        let mut buckets: BTreeMap<String, Arc<AppState>> = state
+            .buckets
+            .values()
+            .map(|bucket| (bucket.name.clone(), bucket.state.clone()))
+            .collect();
+        buckets.insert(DEFAULT_BUCKET.to_string(), state);
+
+        Self {
+            buckets,
+            region: config.region.clone(),
+            credentials: config.credentials.clone(),
+            registry: ApiKeyRegistry(api_keys),
+            uploads: DashMap::new(),
+        }
+    }
+
+    // a bucket the signer is allowed into
+    fn bucket(&self, name: &str, signer: &Signer) -> Result<&Arc<AppState>, S3Error> {
+        let state = self.buckets.get(name).ok_or_else(|| S3Error::no_such_bucket(name))?;
+        if !can_access(&signer.key_name, Some(&self.registry), name) {
+            tracing::warn!("🚫 API key {} is not allowed in bucket {}", signer.key_name, name);
+            return Err(S3Error::access_denied());
+        }
+        Ok(state)
+    }
+}
+
+/// an error in the shape s3 clients expect
+#[derive(Clone, Debug)]
+pub struct S3Error {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into() }
+    }
+
+    fn access_denied() -> Self {
+        Self::new(StatusCode::FORBIDDEN, "AccessDenied", "Access Denied")
+    }
+
+    fn no_such_bucket(bucket: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchBucket", format!("The bucket {} does not exist", bucket))
+    }
+
+    fn no_such_key(key: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchKey", format!("The key {} does not exist", key))
+    }
+
+    fn no_such_upload(id: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchUpload", format!("The upload {} does not exist", id))
+    }
+
+    fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+
+    fn signature_mismatch() -> Self {
+        Self::new(
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch",
+            "The request signature we calculated does not match the signature you provided",
+        )
+    }
+
+    fn not_implemented() -> Self {
+        Self::new(StatusCode::NOT_IMPLEMENTED, "NotImplemented", "This operation is not supported")
+    }
+
+    fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "We encountered an internal error")
+    }
+}
+
+impl std::fmt::Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for S3Error {}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        let body = format!(
+            "<Error>{}{}</Error>",
+            element("Code", self.code),
+            element("Message", &self.message)
+        );
+        let mut response = xml_response(body);
+        *response.status_mut() = self.status;
+        response
+    }
+}
+
+// turn a storage error into the matching s3 error
+fn storage_error(key: &str, e: io::Error) -> S3Error {
+    // payload checks fail from inside the body stream, carrying their own error
+    if let Some(error) = e.get_ref().and_then(|inner| inner.downcast_ref::<S3Error>()) {
+        tracing::warn!("Rejected body for {}: {}", key, error);
+        return error.clone();
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => S3Error::no_such_key(key),
+        io::ErrorKind::InvalidInput => S3Error::invalid_argument(e.to_string()),
+        _ if is_disk_full(&e) => {
+            tracing::error!("Disk full while writing {}: {}", key, e);
+            S3Error::new(StatusCode::INSUFFICIENT_STORAGE, "InsufficientStorage", e.to_string())
+        }
+        _ => {
+            tracing::error!("Storage error for {}: {}", key, e);
+            S3Error::internal()
+        }
+    }
+}
+
+fn quota_error(key: &str, e: QuotaError) -> S3Error {
+    match e {
+        QuotaError::NoSpace(reason) => insufficient_storage(key, reason),
+        QuotaError::Exceeded(_) => {
+            tracing::warn!("💾 Rejecting upload of {}: {}", key, e);
+            S3Error::new(StatusCode::INSUFFICIENT_STORAGE, "QuotaExceeded", e.to_string())
+        }
+        QuotaError::Io(_) => {
+            tracing::error!("Failed to check quota for {}: {}", key, e);
+            S3Error::internal()
+        }
+    }
+}
+
+fn insufficient_storage(key: &str, reason: String) -> S3Error {
+    tracing::warn!("💾 Rejecting upload of {}: {}", key, reason);
+    S3Error::new(StatusCode::INSUFFICIENT_STORAGE, "InsufficientStorage", reason)
+}
+
+// ---------------------------------------------------------------------------
+// authentication
+// ---------------------------------------------------------------------------
+
+/// the api key that signed a request, and what it promised about the body
+#[derive(Clone)]
+pub struct Signer {
+    key_name: String,
+    signing_key: Vec<u8>,
+    scope: Scope,
+    time: DateTime<Utc>,
+    signature: String,
+    payload: Payload,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Payload {
+    Unsigned,
+    /// hex sha-256 of the whole body
+    Sha256(String),
+    /// aws-chunked, every chunk signed
+    SignedChunks,
+    /// aws-chunked without chunk signatures
+    UnsignedChunks,
+}
+
+/// check the SigV4 signature (header or presigned url) of every request on the s3 listener
+pub async fn authenticate(State(api): State<Arc<S3Api>>, mut req: Request, next: Next) -> Response {
+    match verify_request(&api, &req) {
+        Ok(signer) => {
+            tracing::debug!("S3 request signed by {}", signer.key_name);
+            req.extensions_mut().insert(ApiKeyName(signer.key_name.clone()));
+            req.extensions_mut().insert(signer);
+            next.run(req).await
+        }
+        Err(e) => {
+            tracing::warn!("🚫 Rejected s3 request {} {}: {}", req.method(), req.uri().path(), e);
+            e.into_response()
+        }
+    }
+}
+
+// credential, signed headers, signature, signing time and (presigned only) expiry of a request
+struct SignatureParts {
+    credential: String,
+    signed_headers: String,
+    signature: String,
+    amz_date: String,
+    expires: Option<i64>,
+}
+
+fn verify_request(api: &S3Api, req: &Request) -> Result<Signer, S3Error> {
+    let headers = req.headers();
+    let params = query_params(req.uri());
+    let presigned = params.iter().any(|(name, _)| name == "X-Amz-Algorithm");
+
+    let parts = if presigned {
+        presigned_signature(&params)?
+    } else {
+        header_signature(headers)?
+    };
+
+    // Credential=<access key>/<date>/<region>/<service>/aws4_request
+    let malformed = || {
+        S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "AuthorizationHeaderMalformed",
+            format!("Malformed credential: {}", parts.credential),
+        )
+    };
+    let fields: Vec<&str> = parts.credential.split('/').collect();
+    let [access_key, date, region, service, "aws4_request"] = fields[..] else {
+        return Err(malformed());
+    };
+    let secret = api.credentials.get(access_key).ok_or_else(|| {
+        S3Error::new(
+            StatusCode::FORBIDDEN,
+            "InvalidAccessKeyId",
+            "The access key id you provided does not exist in our records",
+        )
+    })?;
+    if region != api.region {
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "AuthorizationHeaderMalformed",
+            format!("The region '{}' is wrong; expecting '{}'", region, api.region),
+        ));
+    }
+    if service != "s3" {
+        return Err(malformed());
+    }
+
+    let time = NaiveDateTime::parse_from_str(&parts.amz_date, AMZ_DATE_FORMAT)
+        .map(|t| t.and_utc())
+        .map_err(|_| S3Error::new(StatusCode::FORBIDDEN, "AccessDenied", "Invalid or missing x-amz-date"))?;
+    if time.format("%Y%m%d").to_string() != date {
+        return Err(S3Error::signature_mismatch());
+    }
+    check_request_time(time, parts.expires)?;
+
+    let signed_names: Vec<&str> = parts.signed_headers.split(';').collect();
+    if !signed_names.contains(&"host") {
+        return Err(S3Error::new(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "The host header must be signed",
+        ));
+    }
+    let mut signed_headers = Vec::new();
+    for name in signed_names {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        signed_headers.push((name.to_string(), values.join(",")));
+    }
+
+    let payload_hash = if presigned {
+        UNSIGNED_PAYLOAD.to_string()
+    } else {
+        headers
+            .get("x-amz-content-sha256")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| S3Error::new(StatusCode::BAD_REQUEST, "InvalidRequest", "Missing x-amz-content-sha256"))?
+            .to_string()
+    };
+    let payload = match payload_hash.as_str() {
+        UNSIGNED_PAYLOAD => Payload::Unsigned,
+        STREAMING_SIGNED_PAYLOAD | STREAMING_SIGNED_PAYLOAD_TRAILER => Payload::SignedChunks,
+        STREAMING_UNSIGNED_PAYLOAD_TRAILER => Payload::UnsignedChunks,
+        hash if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            Payload::Sha256(hash.to_ascii_lowercase())
+        }
+        _ => return Err(S3Error::invalid_argument("Invalid x-amz-content-sha256")),
+    };
+
+    let query: Vec<(String, String)> = params
+        .into_iter()
+        .filter(|(name, _)| !(presigned && name == "X-Amz-Signature"))
+        .collect();
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
+    let canonical = CanonicalRequest {
+        method: req.method().to_string(),
+        path: sigv4::uri_encode(&path, false),
+        query: sigv4::canonical_query(&query),
+        headers: signed_headers,
+        payload_hash,
+    };
+    let scope = Scope {
+        date: date.to_string(),
+        region: region.to_string(),
+        service: service.to_string(),
+    };
+    let expected = sigv4::signature(secret, &scope, time, &canonical);
+    if !sigv4::signatures_match(&expected, &parts.signature) {
+        return Err(S3Error::signature_mismatch());
+    }
+
+    Ok(Signer {
+        key_name: access_key.to_string(),
+        signing_key: sigv4::signing_key(secret, &scope),
+        scope,
+        time,
+        signature: parts.signature,
+        payload,
+    })
+}
+
+// Authorization: AWS4-HMAC-SHA256 Credential=..., SignedHeaders=..., Signature=...
+fn header_signature(headers: &HeaderMap) -> Result<SignatureParts, S3Error> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| S3Error::new(StatusCode::FORBIDDEN, "AccessDenied", "Anonymous access is not allowed"))?;
+    let malformed = || {
+        S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "AuthorizationHeaderMalformed",
+            "The authorization header is malformed",
+        )
+    };
+    let fields = authorization.strip_prefix(ALGORITHM).ok_or_else(malformed)?;
+
+    let mut credential = None;
+    let mut signed_headers = None;
+    let mut signature = None;
+    for field in fields.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) => credential = Some(value.to_string()),
+            Some(("SignedHeaders", value)) => signed_headers = Some(value.to_string()),
+            Some(("Signature", value)) => signature = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    Ok(SignatureParts {
+        credential: credential.ok_or_else(malformed)?,
+        signed_headers: signed_headers.ok_or_else(malformed)?,
+        signature: signature.ok_or_else(malformed)?,
+        amz_date: headers
+            .get("x-amz-date")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        expires: None,
+    })
+}
+
+// ?X-Amz-Algorithm=...&X-Amz-Credential=...&X-Amz-Date=...&X-Amz-Expires=...&X-Amz-SignedHeaders=...&X-Amz-Signature=...
+fn presigned_signature(params: &[(String, String)]) -> Result<SignatureParts, S3Error> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| {
+                S3Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "AuthorizationQueryParametersError",
+                    format!("Missing {}", name),
+                )
+            })
+    };
+    if param("X-Amz-Algorithm")? != ALGORITHM {
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "AuthorizationQueryParametersError",
+            "Unsupported signing algorithm",
+        ));
+    }
+    let expires = param("X-Amz-Expires")?
+        .parse::<i64>()
+        .ok()
+        .filter(|secs| (0..=MAX_PRESIGNED_EXPIRY_SECS).contains(secs))
+        .ok_or_else(|| {
+            S3Error::new(
+                StatusCode::BAD_REQUEST,
+                "AuthorizationQueryParametersError",
+                "X-Amz-Expires must be between 0 and 604800 seconds",
+            )
+        })?;
+
+    Ok(SignatureParts {
+        credential: param("X-Amz-Credential")?,
+        signed_headers: param("X-Amz-SignedHeaders")?,
+        signature: param("X-Amz-Signature")?,
+        amz_date: param("X-Amz-Date")?,
+        expires: Some(expires),
+    })
+}
+
+// signed requests are only good close to when they were signed, presigned urls until they expire
+fn check_request_time(time: DateTime<Utc>, expires: Option<i64>) -> Result<(), S3Error> {
+    let now = Utc::now();
+    let too_skewed = || {
+        S3Error::new(
+            StatusCode::FORBIDDEN,
+            "RequestTimeTooSkewed",
+            "The difference between the request time and the current time is too large",
+        )
+    };
+    if (time - now).num_seconds() > MAX_CLOCK_SKEW_SECS {
+        return Err(too_skewed());
+    }
+    match expires {
+        Some(expires) if (now - time).num_seconds() > expires => {
+            Err(S3Error::new(StatusCode::FORBIDDEN, "AccessDenied", "Request has expired"))
+        }
+        None if (now - time).num_seconds() > MAX_CLOCK_SKEW_SECS => Err(too_skewed()),
+        _ => Ok(()),
+    }
+}
+
+// decoded query parameters in order ('+' is a literal plus, as s3 clients sign it)
+fn query_params(uri: &Uri) -> Vec<(String, String)> {
+    let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().to_string();
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (decode(name), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn query_map(uri: &Uri) -> HashMap<String, String> {
+    query_params(uri).into_iter().collect()
+}
+
+// ---------------------------------------------------------------------------
+// request bodies
+// ---------------------------------------------------------------------------
+
+// the request body as the signer promised it, failing the stream if it doesn't hold up
+fn payload_stream(signer: &Signer, body: Body) -> ByteStream {
+    let raw: ByteStream = Box::pin(body.into_data_stream().map_err(io::Error::other));
+    match &signer.payload {
+        Payload::Unsigned => raw,
+        Payload::Sha256(expected) => verify_sha256(raw, expected.clone()),
+        Payload::SignedChunks => decode_chunks(raw, Some(signer.clone())),
+        Payload::UnsignedChunks => decode_chunks(raw, None),
+    }
+}
+
+fn payload_error(code: &'static str, message: &str) -> io::Error {
+    let status = if code == "SignatureDoesNotMatch" { StatusCode::FORBIDDEN } else { StatusCode::BAD_REQUEST };
+    io::Error::new(io::ErrorKind::InvalidData, S3Error::new(status, code, message))
+}
+
+// pass the body through, checking its sha-256 once it ends
+fn verify_sha256(body: ByteStream, expected: String) -> ByteStream {
+    let state = (body, Sha256::new(), false);
+    Box::pin(stream::unfold(state, move |(mut body, mut hasher, done)| {
+        let expected = expected.clone();
+        async move {
+            if done {
+                return None;
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    hasher.update(&chunk);
+                    Some((Ok(chunk), (body, hasher, false)))
+                }
+                Some(Err(e)) => Some((Err(e), (body, hasher, true))),
+                None => {
+                    let actual = hex::encode(std::mem::take(&mut hasher).finalize());
+                    if actual == expected {
+                        None
+                    } else {
+                        let e = payload_error("XAmzContentSHA256Mismatch", "The body doesn't match x-amz-content-sha256");
+                        Some((Err(e), (body, hasher, true)))
+                    }
+                }
+            }
+        }
+    }))
+}
+
+// aws-chunked decoding state
+struct ChunkDecoder {
+    body: ByteStream,
+    buffer: Vec<u8>,
+    /// set when chunk signatures are checked, with the signature of the previous chunk
+    signer: Option<Signer>,
+    done: bool,
+}
+
+// decode an aws-chunked body ("<hex size>[;chunk-signature=<sig>]\r\n<data>\r\n" ..., ending with a
+// zero-sized chunk), checking every chunk's signature when there is a signer
+fn decode_chunks(body: ByteStream, signer: Option<Signer>) -> ByteStream {
+    let decoder = ChunkDecoder { body, buffer: Vec::new(), signer, done: false };
+    Box::pin(stream::unfold(decoder, |mut decoder| async move {
+        if decoder.done {
+            return None;
+        }
+        match decoder.next_chunk().await {
+            Ok(Some(data)) => Some((Ok(data), decoder)),
+            Ok(None) => None,
+            Err(e) => {
+                decoder.done = true;
+                Some((Err(e), decoder))
+            }
+        }
+    }))
+}
+
+impl ChunkDecoder {
+    // read more of the body, false once it has ended
+    async fn fill(&mut self) -> io::Result<bool> {
+        match self.body.next().await {
+            Some(Ok(data)) => {
+                self.buffer.extend_from_slice(&data);
+                Ok(true)
+            }
+            Some(Err(e)) => Err(e),
+            None => Ok(false),
+        }
+    }
+
+    async fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        let incomplete = || payload_error("IncompleteBody", "The aws-chunked body ended early");
+
+        // chunk header
+        let line_end = loop {
+            if let Some(pos) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                break pos;
+            }
+            if self.buffer.len() > 4096 {
+                return Err(payload_error("InvalidRequest", "Invalid aws-chunked header"));
+            }
+            if !self.fill().await? {
+                return Err(incomplete());
+            }
+        };
+        let line = String::from_utf8_lossy(&self.buffer[..line_end]).to_string();
+        let (size, extension) = line.split_once(';').unwrap_or((&line, ""));
+        let size = usize::from_str_radix(size.trim(), 16)
+            .ok()
+            .filter(|size| *size <= MAX_PAYLOAD_CHUNK)
+            .ok_or_else(|| payload_error("InvalidRequest", "Invalid aws-chunked chunk size"))?;
+
+        // chunk data and its trailing CRLF (the final empty chunk may be followed by trailers instead)
+        let data_start = line_end + 2;
+        let needed = if size == 0 { data_start } else { data_start + size + 2 };
+        while self.buffer.len() < needed {
+            if !self.fill().await? {
+                return Err(incomplete());
+            }
+        }
+        let data = Bytes::copy_from_slice(&self.buffer[data_start..data_start + size]);
+        if size > 0 && &self.buffer[data_start + size..needed] != b"\r\n" {
+            return Err(payload_error("InvalidRequest", "Invalid aws-chunked chunk"));
+        }
+
+        if let Some(signer) = &mut self.signer {
+            let signature = extension.trim().strip_prefix("chunk-signature=").unwrap_or_default();
+            let expected = sigv4::chunk_signature(&signer.signing_key, &signer.scope, signer.time, &signer.signature, &data);
+            if !sigv4::signatures_match(&expected, signature) {
+                return Err(payload_error("SignatureDoesNotMatch", "Chunk signature doesn't match"));
+            }
+            signer.signature = expected;
+        }
+
+        self.buffer.drain(..needed);
+        if size == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// responses
+// ---------------------------------------------------------------------------
+
+fn xml_response(body: String) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/xml")],
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body),
+    )
+        .into_response()
+}
+
+fn element(name: &str, value: &str) -> String {
+    format!("<{0}>{1}</{0}>", name, quick_xml::escape::escape(value))
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+fn modified(meta: &ObjectMeta) -> DateTime<Utc> {
+    meta.modified.map(DateTime::<Utc>::from).unwrap_or_else(Utc::now)
+}
+
+fn empty_response(status: StatusCode) -> Response {
+    status.into_response()
+}
+
+// ---------------------------------------------------------------------------
+// keys
+// ---------------------------------------------------------------------------
+
+// internal data (chunks, metadata, trash, versions, deployments, temp files) lives under dot
+// directories and is never reachable as an object, same as with safe_key
+fn is_reserved(key: &str) -> bool {
+    key.split('/').any(|segment| segment.starts_with('.'))
+}
+
+fn object_key(key: &str) -> Result<&str, S3Error> {
+    if key.is_empty() || key.len() > 1024 {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "KeyTooLongError", "Keys must be 1 to 1024 bytes long"));
+    }
+    if is_reserved(key) {
+        return Err(S3Error::new(StatusCode::FORBIDDEN, "AccessDenied", format!("The key {} is reserved", key)));
+    }
+    check_key(key).map_err(|e| S3Error::invalid_argument(e.to_string()))?;
+    Ok(key)
+}
+
+fn part_key(upload_id: &str, part_number: u32) -> String {
+    format!("{}/s3-{}/part_{:05}", CHUNKS_DIR, upload_id, part_number)
+}
+
+fn parts_prefix(upload_id: &str) -> String {
+    format!("{}/s3-{}/", CHUNKS_DIR, upload_id)
+}
+
+// decoded body size of a write, from x-amz-decoded-content-length for aws-chunked bodies
+fn content_length(headers: &HeaderMap, signer: &Signer) -> Result<u64, S3Error> {
+    let name = match signer.payload {
+        Payload::SignedChunks | Payload::UnsignedChunks => "x-amz-decoded-content-length",
+        _ => header::CONTENT_LENGTH.as_str(),
+    };
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| {
+            S3Error::new(StatusCode::LENGTH_REQUIRED, "MissingContentLength", format!("Missing {}", name))
+        })
+}
+
+// ---------------------------------------------------------------------------
+// service and bucket operations
+// ---------------------------------------------------------------------------
+
+/// ListBuckets: the buckets the signing key can use
+pub async fn list_all_buckets(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+) -> Response {
+    let created = timestamp(DateTime::<Utc>::from(UNIX_EPOCH));
+    let buckets: String = api
+        .buckets
+        .keys()
+        .filter(|name| can_access(&signer.key_name, Some(&api.registry), name))
+        .map(|name| format!("<Bucket>{}{}</Bucket>", element("Name", name), element("CreationDate", &created)))
+        .collect();
+    xml_response(format!(
+        "<ListAllMyBucketsResult xmlns=\"{}\"><Owner>{}</Owner><Buckets>{}</Buckets></ListAllMyBucketsResult>",
+        XML_NAMESPACE,
+        element("ID", &signer.key_name),
+        buckets
+    ))
+}
+
+/// GET on a bucket: object listings, multipart uploads or its location
+pub async fn get_bucket(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path(bucket): Path<String>,
+    uri: Uri,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    let query = query_map(&uri);
+
+    if query.contains_key("location") {
+        return Ok(xml_response(format!(
+            "<LocationConstraint xmlns=\"{}\">{}</LocationConstraint>",
+            XML_NAMESPACE,
+            quick_xml::escape::escape(&api.region)
+        )));
+    }
+    if query.contains_key("versioning") {
+        return Ok(xml_response(format!("<VersioningConfiguration xmlns=\"{}\"/>", XML_NAMESPACE)));
+    }
+    if query.contains_key("uploads") {
+        return Ok(list_multipart_uploads(&api, &bucket));
+    }
+    if UNSUPPORTED_SUBRESOURCES.iter().any(|name| query.contains_key(*name)) {
+        return Err(S3Error::not_implemented());
+    }
+
+    list_objects(state, &bucket, &query).await
+}
+
+/// HEAD on a bucket: whether it exists and the key may use it
+pub async fn head_bucket(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path(bucket): Path<String>,
+) -> Response {
+    match api.bucket(&bucket, &signer) {
+        Ok(_) => empty_response(StatusCode::OK),
+        Err(e) => empty_response(e.status),
+    }
+}
+
+/// CreateBucket: buckets come from the server config, so this only succeeds for existing ones
+pub async fn create_bucket(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path(bucket): Path<String>,
+) -> Result<Response, S3Error> {
+    match api.bucket(&bucket, &signer) {
+        Ok(_) => Ok(empty_response(StatusCode::OK)),
+        Err(e) if e.code == "NoSuchBucket" => Err(S3Error::new(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "Buckets can only be created in the server configuration",
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// DeleteBucket: not possible, buckets come from the server config
+pub async fn delete_bucket() -> S3Error {
+    S3Error::new(
+        StatusCode::FORBIDDEN,
+        "AccessDenied",
+        "Buckets can only be removed from the server configuration",
+    )
+}
+
+/// POST on a bucket: DeleteObjects
+pub async fn post_bucket(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path(bucket): Path<String>,
+    uri: Uri,
+    body: Bytes,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    if !query_map(&uri).contains_key("delete") {
+        return Err(S3Error::not_implemented());
+    }
+    delete_objects(state, &body).await
+}
+
+#[derive(Clone, Debug)]
+enum Entry {
+    Object(ObjectMeta),
+    Prefix(String),
+}
+
+impl Entry {
+    fn key(&self) -> &str {
+        match self {
+            Entry::Object(meta) => &meta.key,
+            Entry::Prefix(prefix) => prefix,
+        }
+    }
+}
+
+// ListObjects (v1) and ListObjectsV2
+async fn list_objects(state: &AppState, bucket: &str, query: &HashMap<String, String>) -> Result<Response, S3Error> {
+    let v2 = query.get("list-type").map(String::as_str) == Some("2");
+    let prefix = query.get("prefix").map(String::as_str).unwrap_or_default();
+    let delimiter = query.get("delimiter").map(String::as_str).filter(|d| !d.is_empty());
+    if delimiter.is_some_and(|d| d != "/") {
+        return Err(S3Error::invalid_argument("Only '/' is supported as a delimiter"));
+    }
+    let max_keys = match query.get("max-keys") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| S3Error::invalid_argument("max-keys must be a number"))?
+            .min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    let start_after = if v2 {
+        query.get("continuation-token").or_else(|| query.get("start-after"))
+    } else {
+        query.get("marker")
+    };
+    let url_encoded = query.get("encoding-type").map(String::as_str) == Some("url");
+
+    let listing = state
+        .storage
+        .list(prefix, delimiter.is_none())
+        .await
+        .map_err(|e| storage_error(prefix, e))?;
+    let mut entries: Vec<Entry> = listing
+        .objects
+        .into_iter()
+        .filter(|object| !is_reserved(&object.key))
+        .map(Entry::Object)
+        .chain(listing.prefixes.into_iter().filter(|p| !is_reserved(p)).map(Entry::Prefix))
+        .filter(|entry| start_after.is_none_or(|after| entry.key() > after.as_str()))
+        .collect();
+    entries.sort_by(|a, b| a.key().cmp(b.key()));
+
+    let truncated = entries.len() > max_keys;
+    entries.truncate(max_keys);
+    let encode = |key: &str| if url_encoded { sigv4::uri_encode(key, false) } else { key.to_string() };
+
+    let mut body = format!("<ListBucketResult xmlns=\"{}\">", XML_NAMESPACE);
+    body.push_str(&element("Name", bucket));
+    body.push_str(&element("Prefix", &encode(prefix)));
+    if let Some(delimiter) = delimiter {
+        body.push_str(&element("Delimiter", delimiter));
+    }
+    body.push_str(&element("MaxKeys", &max_keys.to_string()));
+    body.push_str(&element("IsTruncated", &truncated.to_string()));
+    if url_encoded {
+        body.push_str(&element("EncodingType", "url"));
+    }
+    let last_key = entries.last().map(|entry| entry.key().to_string());
+    if v2 {
+        body.push_str(&element("KeyCount", &entries.len().to_string()));
+        if let Some(token) = query.get("continuation-token") {
+            body.push_str(&element("ContinuationToken", token));
+        }
+        if let Some(start_after) = query.get("start-after") {
+            body.push_str(&element("StartAfter", &encode(start_after)));
+        }
+        if let (true, Some(last)) = (truncated, &last_key) {
+            body.push_str(&element("NextContinuationToken", last));
+        }
+    } else {
+        body.push_str(&element("Marker", &encode(query.get("marker").map(String::as_str).unwrap_or_default())));
+        if let (true, Some(last)) = (truncated, &last_key) {
+            body.push_str(&element("NextMarker", &encode(last)));
+        }
+    }
+
+    for entry in &entries {
+        match entry {
+            Entry::Object(meta) => body.push_str(&format!(
+                "<Contents>{}{}{}{}<StorageClass>STANDARD</StorageClass></Contents>",
+                element("Key", &encode(&meta.key)),
+                element("LastModified", &timestamp(modified(meta))),
+                element("ETag", &etag(meta)),
+                element("Size", &meta.size.to_string()),
+            )),
+            Entry::Prefix(prefix) => {
+                body.push_str(&format!("<CommonPrefixes>{}</CommonPrefixes>", element("Prefix", &encode(prefix))))
+            }
+        }
+    }
+    body.push_str("</ListBucketResult>");
+    Ok(xml_response(body))
+}
+
+#[derive(Deserialize)]
+struct DeleteRequest {
+    #[serde(rename = "Object", default)]
+    objects: Vec<DeleteRequestObject>,
+    #[serde(rename = "Quiet", default)]
+    quiet: bool,
+}
+
+#[derive(Deserialize)]
+struct DeleteRequestObject {
+    #[serde(rename = "Key")]
+    key: String,
+}
+
+// DeleteObjects: up to 1000 keys at once, each reported on its own
+async fn delete_objects(state: &AppState, body: &[u8]) -> Result<Response, S3Error> {
+    let request: DeleteRequest = quick_xml::de::from_reader(body)
+        .map_err(|e| S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", e.to_string()))?;
+    if request.objects.len() > MAX_KEYS {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", "At most 1000 keys can be deleted at once"));
+    }
+
+    let mut results = String::new();
+    for object in &request.objects {
+        match delete_object_key(state, &object.key).await {
+            Ok(()) if request.quiet => {}
+            Ok(()) => results.push_str(&format!("<Deleted>{}</Deleted>", element("Key", &object.key))),
+            Err(e) => results.push_str(&format!(
+                "<Error>{}{}{}</Error>",
+                element("Key", &object.key),
+                element("Code", e.code),
+                element("Message", &e.message)
+            )),
+        }
+    }
+    persist_metadata(state).await;
+
+    Ok(xml_response(format!("<DeleteResult xmlns=\"{}\">{}</DeleteResult>", XML_NAMESPACE, results)))
+}
+
+// ---------------------------------------------------------------------------
+// object operations
+// ---------------------------------------------------------------------------
+
+/// GET on an object: GetObject, or ListParts with ?uploadId
+pub async fn get_object(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path((bucket, key)): Path<(String, String)>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    let key = object_key(&key)?;
+    let query = query_map(&uri);
+    if let Some(upload_id) = query.get("uploadId") {
+        return list_parts(&api, &bucket, key, upload_id);
+    }
+    if UNSUPPORTED_SUBRESOURCES.iter().any(|name| query.contains_key(*name)) {
+        return Err(S3Error::not_implemented());
+    }
+
+    let meta = state.storage.stat(key).await.map_err(|e| storage_error(key, e))?;
+    let range = requested_range(&headers, meta.size).map_err(|_| {
+        S3Error::new(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "InvalidRange",
+            "The requested range is not satisfiable",
+        )
+    })?;
+    let object = state.storage.get(key, range.clone()).await.map_err(|e| storage_error(key, e))?;
+
+    let mut response = object_headers(&object.meta).body(Body::from_stream(object.body)).unwrap();
+    let length = object.range.end - object.range.start;
+    response.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    if range.is_some() {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        let content_range = format!("bytes {}-{}/{}", object.range.start, object.range.end - 1, object.meta.size);
+        response.headers_mut().insert(header::CONTENT_RANGE, HeaderValue::from_str(&content_range).unwrap());
+    }
+    Ok(response)
+}
+
+/// HEAD on an object: HeadObject
+pub async fn head_object(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Response {
+    let result = async {
+        let state = api.bucket(&bucket, &signer)?;
+        let key = object_key(&key)?;
+        state.storage.stat(key).await.map_err(|e| storage_error(key, e))
+    };
+    match result.await {
+        Ok(meta) => object_headers(&meta)
+            .header(header::CONTENT_LENGTH, meta.size)
+            .body(Body::empty())
+            .unwrap(),
+        Err(e) => empty_response(e.status),
+    }
+}
+
+fn object_headers(meta: &ObjectMeta) -> axum::http::response::Builder {
+    let content_type = mime_guess::from_path(&meta.key).first_or_octet_stream();
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type.as_ref())
+        .header(header::ETAG, etag(meta))
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = meta.modified {
+        builder = builder.header(header::LAST_MODIFIED, http_date(modified));
+    }
+    builder
+}
+
+/// PUT on an object: PutObject, CopyObject or UploadPart
+pub async fn put_object(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path((bucket, key)): Path<(String, String)>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    let key = object_key(&key)?;
+    let query = query_map(&uri);
+    if UNSUPPORTED_SUBRESOURCES.iter().any(|name| query.contains_key(*name)) {
+        return Err(S3Error::not_implemented());
+    }
+
+    if let (Some(upload_id), Some(part_number)) = (query.get("uploadId"), query.get("partNumber")) {
+        if headers.contains_key("x-amz-copy-source") {
+            return Err(S3Error::not_implemented());
+        }
+        return upload_part(&api, &signer, state, &bucket, key, upload_id, part_number, &headers, body).await;
+    }
+
+    if let Some(source) = headers.get("x-amz-copy-source") {
+        let source = source
+            .to_str()
+            .map_err(|_| S3Error::invalid_argument("Invalid x-amz-copy-source"))?;
+        return copy_object(&api, &signer, state, key, source).await;
+    }
+
+    let size = content_length(&headers, &signer)?;
+
+    // "directory" placeholders
+    if key.ends_with('/') {
+        if size > 0 {
+            return Err(S3Error::invalid_argument("Keys ending in '/' can't have contents"));
+        }
+        state.storage.create_dir(key).await.map_err(|e| storage_error(key, e))?;
+        return Ok(([(header::ETAG, "\"0-0\"")], StatusCode::OK).into_response());
+    }
+
+    let _reservation = reserve_upload(state, Some(&signer.key_name), size, Some(key))
+        .await
+        .map_err(|e| quota_error(key, e))?;
+
+    let meta = write_object(state, &signer, key, payload_stream(&signer, body), Some(size)).await?;
+    tracing::info!("✅ Uploaded {} to bucket {} over s3 ({} bytes)", key, bucket, meta.size);
+    Ok(([(header::ETAG, etag(&meta))], StatusCode::OK).into_response())
+}
+
+// write an object the way every upload path does: check, snapshot, store, record the owner
+async fn write_object(
+    state: &Arc<AppState>,
+    signer: &Signer,
+    key: &str,
+    body: ByteStream,
+    expected_size: Option<u64>,
+) -> Result<ObjectMeta, S3Error> {
+    state
+        .upload_types
+        .check_extension(key)
+        .map_err(|e| upload_refused(unsupported_type(key, e)))?;
+    let mut settings = UploadSettings {
+        owner: Some(signer.key_name.clone()),
+        ..Default::default()
+    };
+
+    // written out of sight first, it only replaces the current object once it's been checked
+    let staged = staging_key();
+    let hasher = UploadHasher::default();
+    let written = state.storage.put(&staged, hasher.wrap(body)).await;
+    settings.sha256 = Some(hasher.finish());
+    let placed = match written {
+        Ok(written) if expected_size.is_some_and(|size| size != written) => Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "IncompleteBody",
+            "The body didn't match the declared length",
+        )),
+        Ok(_) => match check_content(state, key, &mut settings, || stream_of(state, &staged)).await {
+            Ok(()) => promote_staged(state, &staged, key).await.map_err(|e| storage_error(key, e)),
+            Err(e) => Err(upload_refused(e)),
+        },
+        Err(e) => Err(storage_error(key, e)),
+    };
+    if let Err(e) = placed {
+        let _ = state.storage.delete(&staged).await;
+        return Err(e);
+    }
+
+    record_upload_metadata(state, key, &settings).await.map_err(|(_, e)| {
+        tracing::error!("Failed to record metadata for {}: {}", key, e.error);
+        S3Error::internal()
+    })?;
+    run_hooks(state, key);
+
+    state.storage.stat(key).await.map_err(|e| storage_error(key, e))
+}
+
+// the content of a stored object, read lazily
+fn stream_of(state: &AppState, key: &str) -> ByteStream {
+    concat(state.storage.clone(), vec![key.to_string()])
+}
+
+// an upload the content checks refused, as an s3 error
+fn upload_refused((status, Json(e)): (StatusCode, Json<ErrorResponse>)) -> S3Error {
+    match status {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => S3Error::new(status, "UnsupportedMediaType", e.error),
+        StatusCode::UNPROCESSABLE_ENTITY => S3Error::new(status, "InfectedContent", e.error),
+        StatusCode::SERVICE_UNAVAILABLE => S3Error::new(status, "ServiceUnavailable", e.error),
+        _ => S3Error::internal(),
+    }
+}
+
+// CopyObject: x-amz-copy-source is "[/]<bucket>/<key>", url-encoded
+async fn copy_object(
+    api: &S3Api,
+    signer: &Signer,
+    state: &Arc<AppState>,
+    key: &str,
+    source: &str,
+) -> Result<Response, S3Error> {
+    let source = percent_decode_str(source).decode_utf8_lossy().to_string();
+    let source = source.split_once("?versionId=").map_or(source.as_str(), |(path, _)| path);
+    let (source_bucket, source_key) = source
+        .trim_start_matches('/')
+        .split_once('/')
+        .ok_or_else(|| S3Error::invalid_argument("Invalid x-amz-copy-source"))?;
+    let source_state = api.bucket(source_bucket, signer)?;
+    let source_key = object_key(source_key)?;
+
+    let source_meta = source_state
+        .storage
+        .stat(source_key)
+        .await
+        .map_err(|e| storage_error(source_key, e))?;
+
+    // copying onto itself only rewrites metadata, which we don't keep
+    let meta = if Arc::ptr_eq(source_state, state) && source_key == key {
+        source_meta
+    } else {
+        let _reservation = reserve_upload(state, Some(&signer.key_name), source_meta.size, Some(key))
+            .await
+            .map_err(|e| quota_error(key, e))?;
+        let object = source_state
+            .storage
+            .get(source_key, None)
+            .await
+            .map_err(|e| storage_error(source_key, e))?;
+        write_object(state, signer, key, object.body, Some(source_meta.size)).await?
+    };
+    tracing::info!("✅ Copied {}/{} to {} over s3", source_bucket, source_key, key);
+
+    Ok(xml_response(format!(
+        "<CopyObjectResult>{}{}</CopyObjectResult>",
+        element("LastModified", &timestamp(modified(&meta))),
+        element("ETag", &etag(&meta))
+    )))
+}
+
+/// DELETE on an object: DeleteObject, or AbortMultipartUpload with ?uploadId
+pub async fn delete_object(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path((bucket, key)): Path<(String, String)>,
+    uri: Uri,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    let key = object_key(&key)?;
+    if let Some(upload_id) = query_map(&uri).get("uploadId") {
+        return abort_multipart_upload(&api, state, &bucket, key, upload_id).await;
+    }
+
+    delete_object_key(state, key).await?;
+    persist_metadata(state).await;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+// move an object to the trash, deleting a missing object succeeds like on s3
+async fn delete_object_key(state: &AppState, key: &str) -> Result<(), S3Error> {
+    let key = object_key(key)?;
+    match move_to_trash(state, key).await {
+        Ok(item) => {
+            tracing::info!("🗑️  Deleted {} over s3 (trash id {})", key, item.id);
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(storage_error(key, e)),
+    }
+}
+
+async fn persist_metadata(state: &AppState) {
+    if let Err(e) = state.metadata.persist().await {
+        tracing::warn!("Failed to persist metadata after s3 deletes: {}", e);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// multipart uploads
+// ---------------------------------------------------------------------------
+
+/// POST on an object: CreateMultipartUpload (?uploads) or CompleteMultipartUpload (?uploadId)
+pub async fn post_object(
+    State(api): State<Arc<S3Api>>,
+    Extension(signer): Extension<Signer>,
+    Path((bucket, key)): Path<(String, String)>,
+    uri: Uri,
+    body: Bytes,
+) -> Result<Response, S3Error> {
+    let state = api.bucket(&bucket, &signer)?;
+    let key = object_key(&key)?;
+    let query = query_map(&uri);
+
+    if query.contains_key("uploads") {
+        let upload_id = Uuid::new_v4().simple().to_string();
+        api.uploads.insert(
+            upload_id.clone(),
+            MultipartUpload {
+                bucket: bucket.clone(),
+                key: key.to_string(),
+                owner: signer.key_name.clone(),
+                initiated: Utc::now(),
+                parts: BTreeMap::new(),
+            },
+        );
+        tracing::info!("📦 Started multipart upload {} of {} in bucket {}", upload_id, key, bucket);
+        return Ok(xml_response(format!(
+            "<InitiateMultipartUploadResult xmlns=\"{}\">{}{}{}</InitiateMultipartUploadResult>",
+            XML_NAMESPACE,
+            element("Bucket", &bucket),
+            element("Key", key),
+            element("UploadId", &upload_id)
+        )));
+    }
+
+    if let Some(upload_id) = query.get("uploadId") {
+        return complete_multipart_upload(&api, &signer, state, &bucket, key, upload_id, &body).await;
+    }
+
+    Err(S3Error::not_implemented())
+}
+
+// the upload, if it exists and belongs to this object
+fn find_upload(api: &S3Api, bucket: &str, key: &str, upload_id: &str) -> Result<MultipartUpload, S3Error> {
+    api.uploads
+        .get(upload_id)
+        .filter(|upload| upload.bucket == bucket && upload.key == key)
+        .map(|upload| upload.clone())
+        .ok_or_else(|| S3Error::no_such_upload(upload_id))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_part(
+    api: &S3Api,
+    signer: &Signer,
+    state: &AppState,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    part_number: &str,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let part_number = part_number
+        .parse::<u32>()
+        .ok()
+        .filter(|n| (1..=10_000).contains(n))
+        .ok_or_else(|| S3Error::invalid_argument("Part numbers must be between 1 and 10000"))?;
+    find_upload(api, bucket, key, upload_id)?;
+
+    let size = content_length(headers, signer)?;
+    ensure_free_space(state, size).await.map_err(|e| insufficient_storage(key, e))?;
+
+    let part = part_key(upload_id, part_number);
+    let written = state
+        .storage
+        .put(&part, payload_stream(signer, body))
+        .await
+        .map_err(|e| storage_error(key, e))?;
+    if written != size {
+        let _ = state.storage.delete(&part).await;
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "IncompleteBody",
+            "The body didn't match the declared length",
+        ));
+    }
+    let meta = state.storage.stat(&part).await.map_err(|e| storage_error(key, e))?;
+    let etag = etag(&meta);
+
+    let mut upload = api.uploads.get_mut(upload_id).ok_or_else(|| S3Error::no_such_upload(upload_id))?;
+    upload.parts.insert(
+        part_number,
+        UploadedPart { size: written, etag: etag.clone(), modified: modified(&meta) },
+    );
+    tracing::debug!("Stored part {} of upload {} ({} bytes)", part_number, upload_id, written);
+    Ok(([(header::ETAG, etag)], StatusCode::OK).into_response())
+}
+
+#[derive(Deserialize)]
+struct CompleteRequest {
+    #[serde(rename = "Part", default)]
+    parts: Vec<CompleteRequestPart>,
+}
+
+#[derive(Deserialize)]
+struct CompleteRequestPart {
+    #[serde(rename = "PartNumber")]
+    part_number: u32,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+async fn complete_multipart_upload(
+    api: &S3Api,
+    signer: &Signer,
+    state: &Arc<AppState>,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    body: &[u8],
+) -> Result<Response, S3Error> {
+    let request: CompleteRequest = quick_xml::de::from_reader(body)
+        .map_err(|e| S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", e.to_string()))?;
+    find_upload(api, bucket, key, upload_id)?;
+    // take the upload so parts can't change while it's assembled
+    let (_, upload) = api.uploads.remove(upload_id).ok_or_else(|| S3Error::no_such_upload(upload_id))?;
+
+    let result = assemble_parts(signer, state, key, upload_id, &upload, &request).await;
+    let meta = match result {
+        Ok(meta) => meta,
+        Err(e) => {
+            // let the client fix its request and try again
+            api.uploads.insert(upload_id.to_string(), upload);
+            return Err(e);
+        }
+    };
+    if let Err(e) = state.storage.delete_prefix(&parts_prefix(upload_id)).await {
+        tracing::warn!("Failed to clean up parts of upload {}: {}", upload_id, e);
+    }
+    tracing::info!(
+        "✅ Completed multipart upload {} of {} in bucket {} ({} bytes)",
+        upload_id,
+        key,
+        bucket,
+        meta.size
+    );
+
+    Ok(xml_response(format!(
+        "<CompleteMultipartUploadResult xmlns=\"{}\">{}{}{}{}</CompleteMultipartUploadResult>",
+        XML_NAMESPACE,
+        element("Location", &format!("/{}/{}", bucket, key)),
+        element("Bucket", bucket),
+        element("Key", key),
+        element("ETag", &etag(&meta))
+    )))
+}
+
+async fn assemble_parts(
+    signer: &Signer,
+    state: &Arc<AppState>,
+    key: &str,
+    upload_id: &str,
+    upload: &MultipartUpload,
+    request: &CompleteRequest,
+) -> Result<ObjectMeta, S3Error> {
+    if request.parts.is_empty() {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", "No parts were listed"));
+    }
+    if request.parts.windows(2).any(|pair| pair[0].part_number >= pair[1].part_number) {
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "InvalidPartOrder",
+            "Parts must be listed in ascending order",
+        ));
+    }
+    let mut size = 0;
+    for part in &request.parts {
+        let uploaded = upload.parts.get(&part.part_number).filter(|uploaded| {
+            uploaded.etag.trim_matches('"') == part.etag.trim().trim_matches('"')
+        });
+        let Some(uploaded) = uploaded else {
+            return Err(S3Error::new(
+                StatusCode::BAD_REQUEST,
+                "InvalidPart",
+                format!("Part {} was not uploaded or its ETag doesn't match", part.part_number),
+            ));
+        };
+        size += uploaded.size;
+    }
+
+    let _reservation = reserve_upload(state, Some(&upload.owner), size, Some(key))
+        .await
+        .map_err(|e| quota_error(key, e))?;
+
+    let keys = request.parts.iter().map(|part| part_key(upload_id, part.part_number)).collect();
+    let body = concat(state.storage.clone(), keys);
+    write_object(state, signer, key, body, Some(size)).await
+}
+
+async fn abort_multipart_upload(
+    api: &S3Api,
+    state: &AppState,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+) -> Result<Response, S3Error> {
+    find_upload(api, bucket, key, upload_id)?;
+    api.uploads.remove(upload_id);
+    state
+        .storage
+        .delete_prefix(&parts_prefix(upload_id))
+        .await
+        .map_err(|e| storage_error(key, e))?;
+    tracing::info!("Aborted multipart upload {} of {}", upload_id, key);
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+fn list_parts(api: &S3Api, bucket: &str, key: &str, upload_id: &str) -> Result<Response, S3Error> {
+    let upload = find_upload(api, bucket, key, upload_id)?;
+    let parts: String = upload
+        .parts
+        .iter()
+        .map(|(number, part)| {
+            format!(
+                "<Part>{}{}{}{}</Part>",
+                element("PartNumber", &number.to_string()),
+                element("LastModified", &timestamp(part.modified)),
+                element("ETag", &part.etag),
+                element("Size", &part.size.to_string())
+            )
+        })
+        .collect();
+    Ok(xml_response(format!(
+        "<ListPartsResult xmlns=\"{}\">{}{}{}<IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+        XML_NAMESPACE,
+        element("Bucket", bucket),
+        element("Key", key),
+        element("UploadId", upload_id),
+        parts
+    )))
+}
+
+fn list_multipart_uploads(api: &S3Api, bucket: &str) -> Response {
+    let mut uploads: Vec<(String, MultipartUpload)> = api
+        .uploads
+        .iter()
+        .filter(|upload| upload.bucket == bucket)
+        .map(|upload| (upload.key().clone(), upload.value().clone()))
+        .collect();
+    uploads.sort_by(|a, b| (&a.1.key, a.1.initiated).cmp(&(&b.1.key, b.1.initiated)));
+
+    let uploads: String = uploads
+        .iter()
+        .map(|(id, upload)| {
+            format!(
+                "<Upload>{}{}{}</Upload>",
+                element("Key", &upload.key),
+                element("UploadId", id),
+                element("Initiated", &timestamp(upload.initiated))
+            )
+        })
+        .collect();
+    xml_response(format!(
+        "<ListMultipartUploadsResult xmlns=\"{}\">{}<IsTruncated>false</IsTruncated>{}</ListMultipartUploadsResult>",
+        XML_NAMESPACE,
+        element("Bucket", bucket),
+        uploads
+    ))
+}
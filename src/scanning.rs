@@ -0,0 +1,286 @@
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::Config;
+use crate::metadata::{QuarantineItem, ScanRecord, ScanStatus};
+use crate::models::ErrorResponse;
+use crate::state::AppState;
+use crate::storage::ByteStream;
+
+// most bytes sent to clamd in one INSTREAM chunk
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// where a clamd-compatible daemon listens
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClamdAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl ClamdAddress {
+    /// "tcp://host:port", "unix:///run/clamav/clamd.ctl", or a bare host:port or absolute socket path
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix://") {
+            return (!path.is_empty()).then(|| Self::Unix(PathBuf::from(path)));
+        }
+        if value.starts_with('/') {
+            return Some(Self::Unix(PathBuf::from(value)));
+        }
+        let addr = value.strip_prefix("tcp://").unwrap_or(value);
+        addr.contains(':').then(|| Self::Tcp(addr.to_string()))
+    }
+}
+
+/// what clamd made of some content
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    /// the signature that matched
+    Infected(String),
+}
+
+/// malware scanning of uploads through clamd
+#[derive(Clone, Debug)]
+pub struct ScanSettings {
+    /// None = uploads aren't scanned
+    pub clamd: Option<ClamdAddress>,
+    /// longest a whole scan may take
+    pub timeout: Duration,
+    /// where infected uploads are kept, outside anything that's served
+    pub quarantine_dir: PathBuf,
+    /// accept uploads when the scan itself fails, instead of refusing them
+    pub fail_open: bool,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            clamd: None,
+            timeout: Duration::from_secs(60),
+            quarantine_dir: PathBuf::from("./quarantine"),
+            fail_open: false,
+        }
+    }
+}
+
+impl ScanSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            clamd: config.clamd_address.as_deref().and_then(|address| {
+                let parsed = ClamdAddress::parse(address);
+                if parsed.is_none() {
+                    tracing::warn!("Ignoring invalid CLAMD_ADDRESS {:?}, uploads won't be scanned", address);
+                }
+                parsed
+            }),
+            timeout: Duration::from_secs(config.scan_timeout_secs),
+            quarantine_dir: config.scan_quarantine_dir.clone(),
+            fail_open: config.scan_fail_open,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.clamd.is_some()
+    }
+
+    /// stream content to clamd with INSTREAM
+    pub async fn scan(&self, body: ByteStream) -> io::Result<ScanVerdict> {
+        let Some(clamd) = &self.clamd else {
+            return Ok(ScanVerdict::Clean);
+        };
+        let reply = tokio::time::timeout(self.timeout, async {
+            match clamd {
+                ClamdAddress::Tcp(addr) => instream(tokio::net::TcpStream::connect(addr).await?, body).await,
+                #[cfg(unix)]
+                ClamdAddress::Unix(path) => instream(tokio::net::UnixStream::connect(path).await?, body).await,
+                #[cfg(not(unix))]
+                ClamdAddress::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets need a unix")),
+            }
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "scan timed out"))??;
+        parse_reply(&reply)
+    }
+
+    /// refuse a quarantine directory inside one that's served, where infected uploads could be downloaded
+    pub fn check_quarantine_dir<'a>(&self, served: impl IntoIterator<Item = &'a Path>) -> Result<(), String> {
+        let quarantine_dir = resolve(&self.quarantine_dir);
+        for root in served {
+            if quarantine_dir.starts_with(resolve(root)) {
+                return Err(format!(
+                    "SCAN_QUARANTINE_DIR {:?} is inside {:?}, which is served",
+                    self.quarantine_dir, root
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// keep infected content in the quarantine directory, with its scan result next to it
+    pub async fn quarantine(&self, filename: &str, mut body: ByteStream, record: &ScanRecord) -> io::Result<QuarantineItem> {
+        tokio::fs::create_dir_all(&self.quarantine_dir).await?;
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let name = format!("{}-{}-{}", record.scanned_at.format("%Y%m%dT%H%M%S"), &id[..8], filename.replace('/', "_"));
+        let path = self.quarantine_dir.join(&name);
+
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        #[derive(Serialize)]
+        struct Sidecar<'a> {
+            filename: &'a str,
+            scan: &'a ScanRecord,
+        }
+        let sidecar = serde_json::to_vec_pretty(&Sidecar { filename, scan: record })?;
+        tokio::fs::write(self.quarantine_dir.join(format!("{}.json", name)), sidecar).await?;
+        Ok(QuarantineItem {
+            id,
+            filename: filename.to_string(),
+            path,
+            size,
+            scan: record.clone(),
+        })
+    }
+}
+
+// an absolute path with `.` and `..` folded away, symlinks resolved as far as the path exists
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut lexical = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                lexical.pop();
+            }
+            other => lexical.push(other),
+        }
+    }
+
+    let mut existing = lexical.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.iter().rev().fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return lexical,
+        }
+    }
+}
+
+// zINSTREAM: length-prefixed chunks, a zero length to finish, then one NUL-terminated reply
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(mut conn: S, mut body: ByteStream) -> io::Result<String> {
+    let sent = async {
+        conn.write_all(b"zINSTREAM\0").await?;
+        while let Some(chunk) = body.next().await {
+            let chunk: Bytes = chunk?;
+            for piece in chunk.chunks(CHUNK_LEN) {
+                conn.write_all(&(piece.len() as u32).to_be_bytes()).await?;
+                conn.write_all(piece).await?;
+            }
+        }
+        conn.write_all(&[0; 4]).await?;
+        conn.flush().await
+    }
+    .await;
+
+    // clamd hangs up early when it refuses a stream (size limit), its reply says why
+    let mut reply = Vec::new();
+    let read = conn.read_to_end(&mut reply).await;
+    if reply.is_empty() {
+        sent?;
+        read?;
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "clamd closed the connection without a reply"));
+    }
+    Ok(String::from_utf8_lossy(&reply).trim_end_matches(['\0', '\n']).to_string())
+}
+
+// "stream: OK", "stream: Eicar-Signature FOUND" or "... ERROR"
+fn parse_reply(reply: &str) -> io::Result<ScanVerdict> {
+    let result = reply.split_once(": ").map(|(_, result)| result).unwrap_or(reply);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(io::Error::other(format!("clamd: {}", reply)))
+    }
+}
+
+/// scan an upload before it's kept, quarantining it if infected
+///
+/// `content` gives the upload's content, once for the scan and again for the quarantine;
+/// the result is the record to keep with the file, or the refusal to answer with
+pub(crate) async fn scan_upload<F>(
+    state: &AppState,
+    filename: &str,
+    content: F,
+) -> Result<Option<ScanRecord>, (StatusCode, Json<ErrorResponse>)>
+where
+    F: Fn() -> ByteStream,
+{
+    let settings = &state.scanning;
+    if !settings.is_enabled() {
+        return Ok(None);
+    }
+    let record = |status, detail| ScanRecord { status, detail, scanned_at: Utc::now() };
+
+    match settings.scan(content()).await {
+        Ok(ScanVerdict::Clean) => {
+            tracing::debug!("🛡️  {} is clean", filename);
+            Ok(Some(record(ScanStatus::Clean, None)))
+        }
+        Ok(ScanVerdict::Infected(signature)) => {
+            let infected = record(ScanStatus::Infected, Some(signature.clone()));
+            match settings.quarantine(filename, content(), &infected).await {
+                Ok(item) => {
+                    tracing::warn!("☣️  {} is infected ({}), quarantined as {:?}", filename, signature, item.path);
+                    state.metadata.quarantine.insert(item.id.clone(), item);
+                    if let Err(e) = state.metadata.persist().await {
+                        tracing::error!("Failed to persist quarantine record for {}: {}", filename, e);
+                    }
+                }
+                Err(e) => tracing::error!("☣️  {} is infected ({}) and couldn't be quarantined: {}", filename, signature, e),
+            }
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorResponse {
+                    error: format!("File is infected ({}) and was quarantined", signature),
+                }),
+            ))
+        }
+        Err(e) if settings.fail_open => {
+            tracing::warn!("Failed to scan {}, accepting it anyway: {}", filename, e);
+            Ok(Some(record(ScanStatus::Error, Some(e.to_string()))))
+        }
+        Err(e) => {
+            tracing::error!("Failed to scan {}: {}", filename, e);
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse {
+                    error: format!("Malware scan failed: {}", e),
+                }),
+            ))
+        }
+    }
+}
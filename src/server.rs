@@ -6,12 +6,14 @@ use axum::{
 use tower_http::{
     services::ServeDir,
     trace::TraceLayer,
-    compression::CompressionLayer,
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
     limit::RequestBodyLimitLayer,
     cors::CorsLayer,
 };
 use std::net::SocketAddr;
-use std::path::PathBuf;
 use std::sync::Arc;
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 
@@ -19,32 +21,210 @@ use crate::handlers::{
     batch_delete_files, delete_file, get_stats, health_check, list_files, upload_file,
     init_chunked_upload, upload_chunk, complete_chunked_upload,
 };
-use crate::middleware::{add_security_headers, validate_api_key};
+use crate::tags::{add_tags, batch_tag_files, remove_tags};
+use crate::collections::{
+    add_to_collection, collection_bulk_action, delete_collection, get_collection,
+    list_collections, remove_from_collection,
+};
+use crate::buckets::{list_buckets, require_bucket_access, DEFAULT_BUCKET};
+use crate::downloads::enforce_download_limits;
+use crate::expiry::enforce_expiry;
+use crate::trash::{delete_trash_item, empty_trash, list_trash, restore_trash_item};
+use crate::versions::{download_version, list_versions, prune_versions, restore_version};
+use crate::middleware::{
+    add_custom_security_headers, deny_hidden_paths, validate_api_key, ApiKeyRegistry, SecurityHeaders,
+};
+use crate::vhosts::{
+    add_cache_control, route_by_host, serve_index_files, HostRoutes, IndexFiles, VirtualHostSettings,
+};
+use crate::s3api::{
+    authenticate, create_bucket, delete_bucket, delete_object, get_bucket, get_object, head_bucket,
+    head_object, list_all_buckets, post_bucket, post_object, put_object, S3Api,
+};
+use crate::archive::{create_archive_link, download_archive, download_signed_archive, ARCHIVE_LINK_PATH};
+use crate::extract::upload_archive;
+use crate::deploy::{
+    activate_release, delete_site, deploy_site, get_site, list_sites, rollback_site, serve_site, SiteTarget,
+    SITES_PATH,
+};
+use crate::fallback::serve_fallbacks;
+use crate::caching::{apply_cache_rules, serve_etags, ContentEtags};
+use crate::disposition::{apply_disposition, set_disposition, FileDispositions};
+use crate::hooks::{get_hook_job, list_hook_jobs, rerun_hooks};
+use crate::listing::{serve_listings, DirectoryListings};
+use crate::redirects::{apply_redirects, list_redirects, reload_redirects, update_redirects};
+use crate::webdav::{handle_webdav, require_basic_auth, WebDav};
 use crate::state::AppState;
+use crate::storage::serve_object;
 use crate::utils::shutdown_signal;
 use crate::config::Config;
 
-// build public router
-pub fn build_public_router(files_dir: &PathBuf) -> Router {
-    tracing::debug!("Building public router for directory: {:?}", files_dir);
-    Router::new()
-        .fallback_service(
-            ServeDir::new(files_dir)
-                .append_index_html_on_directories(true)
-                .precompressed_gzip()
-                .precompressed_br()
-                .precompressed_deflate()
-                .precompressed_zstd()
-        )
-        .layer(axum::middleware::from_fn(add_security_headers))
+// build public router: the default bucket, public buckets under /b/<name>/ and virtual hosts by host name
+pub fn build_public_router(state: Arc<AppState>) -> Router {
+    tracing::debug!("Building public router for directory: {:?}", state.files_dir);
+    let defaults = Arc::new(VirtualHostSettings::default());
+    let security = state.security.clone();
+    // signed archive downloads are only served on the default host
+    let mut router = files_router(state.clone(), defaults.clone(), &security, true);
+    let mut routes = HostRoutes::default();
+
+    for bucket in state.buckets.values().filter(|b| b.public) {
+        tracing::debug!("Serving bucket {} from {:?}", bucket.name, bucket.state.files_dir);
+        let files = files_router(bucket.state.clone(), defaults.clone(), &security, false);
+        for host in &bucket.hosts {
+            routes.by_host.insert(host.clone(), files.clone());
+        }
+        router = router.nest_service(&format!("/b/{}", bucket.name), files);
+    }
+
+    for vhost in &state.vhosts.hosts {
+        let Some(vhost_state) = vhost.resolve(&state) else {
+            tracing::warn!("Virtual host {} points at an unknown or private bucket, skipping", vhost.name);
+            continue;
+        };
+        let settings = Arc::new(vhost.settings.clone());
+        let files = match &vhost.site {
+            Some(site) => {
+                tracing::debug!("Serving virtual host {} from deployed site {}", vhost.name, site);
+                let target = SiteTarget { deployments: vhost_state.deployments.clone(), site: Some(site.clone()) };
+                host_layers(site_router(target), &vhost_state, &settings, &security)
+            }
+            None => {
+                tracing::debug!("Serving virtual host {} from {:?}", vhost.name, vhost_state.files_dir);
+                files_router(vhost_state, settings, &security, false)
+            }
+        };
+        for host in &vhost.hosts {
+            routes.by_host.insert(host.clone(), files.clone());
+        }
+        if state.vhosts.fallback.as_deref() == Some(vhost.name.as_str()) {
+            routes.fallback = Some(files);
+        }
+    }
+
+    // archives are compressed already (or stored as-is on purpose, to keep their length known)
+    let compress_when = DefaultPredicate::new()
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(NotForContentType::const_new("application/x-tar"));
+
+    router
+        .layer(axum::middleware::from_fn_with_state(Arc::new(routes), route_by_host))
         .layer(CompressionLayer::new()
             .gzip(true)
             .br(true)
             .zstd(true)
+            .compress_when(compress_when)
         )
         .layer(TraceLayer::new_for_http())
 }
 
+// static files of a single bucket or virtual host
+fn files_router(
+    state: Arc<AppState>,
+    settings: Arc<VirtualHostSettings>,
+    security: &SecurityHeaders,
+    archive_links: bool,
+) -> Router {
+    // local files go through ServeDir (precompressed variants included), anything else
+    // is streamed from the storage backend
+    let router = match state.storage.local_root() {
+        Some(root) => Router::new().fallback_service(
+            ServeDir::new(root)
+                .append_index_html_on_directories(true)
+                .precompressed_gzip()
+                .precompressed_br()
+                .precompressed_deflate()
+                .precompressed_zstd()
+        ),
+        None => Router::new()
+            .fallback(serve_object)
+            .with_state(state.storage.clone()),
+    };
+    let etags = ContentEtags::new(state.storage.clone(), state.metadata.clone());
+    let mut router = router
+        .layer(axum::middleware::from_fn_with_state(Arc::new(etags), serve_etags))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), enforce_download_limits))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), enforce_expiry));
+
+    if !settings.index_files.is_empty() {
+        let index = IndexFiles {
+            storage: state.storage.clone(),
+            names: settings.index_files.clone(),
+        };
+        router = router.layer(axum::middleware::from_fn_with_state(Arc::new(index), serve_index_files));
+    }
+
+    let listings = state.listings.with_overrides(settings.listing_dirs.as_deref());
+    if listings.is_enabled() {
+        let listings = DirectoryListings { storage: state.storage.clone(), settings: listings };
+        router = router.layer(axum::middleware::from_fn_with_state(Arc::new(listings), serve_listings));
+    }
+
+    router = router.layer(axum::middleware::from_fn(deny_hidden_paths));
+
+    // deployed sites get past deny_hidden_paths through their own prefix
+    let sites = SiteTarget { deployments: state.deployments.clone(), site: None };
+    router = router.nest_service(SITES_PATH, site_router(sites));
+
+    // signed archive downloads, past deny_hidden_paths the same way but under the host's
+    // headers and disposition like any other download
+    if archive_links && state.archives.link_key.is_some() {
+        router = router.merge(
+            Router::new()
+                .route(ARCHIVE_LINK_PATH, get(download_signed_archive))
+                .with_state(state.clone()),
+        );
+    }
+
+    // outside the security headers, so the sandbox policy replaces theirs (always on, since
+    // files can be given a disposition of their own at any time)
+    let dispositions = FileDispositions {
+        policy: state.disposition.with_overrides(settings.sandbox),
+        metadata: state.metadata.clone(),
+    };
+    host_layers(router, &state, &settings, security)
+        .layer(axum::middleware::from_fn_with_state(Arc::new(dispositions), apply_disposition))
+}
+
+// the active release of one site, or of every site by its first path segment
+fn site_router(target: SiteTarget) -> Router {
+    Router::new()
+        .fallback(serve_site)
+        .with_state(Arc::new(target))
+        .layer(axum::middleware::from_fn(deny_hidden_paths))
+}
+
+// caching, redirects, error pages and headers every bucket or virtual host adds to its responses
+fn host_layers(
+    mut router: Router,
+    state: &AppState,
+    settings: &VirtualHostSettings,
+    security: &SecurityHeaders,
+) -> Router {
+    // innermost, so spa indexes and rewrites get the rules of the file actually served
+    if !state.cache_rules.is_empty() {
+        router = router.layer(axum::middleware::from_fn_with_state(Arc::new(state.cache_rules.clone()), apply_cache_rules));
+    }
+
+    // rules first, a rewrite that misses still gets the error page
+    router = router.layer(axum::middleware::from_fn_with_state(state.redirects.clone(), apply_redirects));
+
+    let fallbacks = state.fallbacks.with_overrides(settings.spa_dirs.as_deref(), settings.error_pages);
+    if fallbacks.is_enabled() {
+        router = router.layer(axum::middleware::from_fn_with_state(Arc::new(fallbacks), serve_fallbacks));
+    }
+
+    if let Some(cache_control) = settings.cache_control.clone() {
+        router = router.layer(axum::middleware::from_fn_with_state(cache_control, add_cache_control));
+    }
+
+    router.layer(axum::middleware::from_fn_with_state(
+        Arc::new(settings.security_headers(security)),
+        add_custom_security_headers,
+    ))
+}
+
 /// build admin router
 pub fn build_admin_router(state: Arc<AppState>, config: &Config) -> Router {
     tracing::debug!("Building admin router with max upload size: {} bytes", config.max_upload_size);
@@ -67,32 +247,144 @@ pub fn build_admin_router(state: Arc<AppState>, config: &Config) -> Router {
         ])
         .allow_origin(tower_http::cors::Any) // For development, should be stricter in prod
         .allow_headers(tower_http::cors::Any);
+    // every bucket gets the same routes: the default one under /admin, the rest under /admin/buckets/<name>
+    let mut router = Router::new()
+        .route("/admin/buckets", get(list_buckets))
+        .nest("/admin", bucket_admin_routes(state.clone(), DEFAULT_BUCKET));
+    for bucket in state.buckets.values() {
+        router = router.nest(
+            &format!("/admin/buckets/{}", bucket.name),
+            bucket_admin_routes(bucket.state.clone(), &bucket.name),
+        );
+    }
+
     // vroom vroom
-    Router::new()
-        .route("/admin/upload", post(upload_file))
-        .route("/admin/upload/chunk/init", post(init_chunked_upload))
-        .route("/admin/upload/chunk/:id/:num", post(upload_chunk))
-        .route("/admin/upload/chunk/complete", post(complete_chunked_upload))
-        .route("/admin/files", get(list_files))
-        .route("/admin/files/:filename", delete(delete_file))
-        .route("/admin/batch-delete", post(batch_delete_files))
-        .route("/admin/stats", get(get_stats))
-        .route("/admin/health", get(health_check))
+    router = router
         .layer(axum::middleware::from_fn(validate_api_key))
         .layer(Extension(config.api_key_hash.clone()))
+        .layer(Extension(ApiKeyRegistry(config.api_keys.clone())))
         .layer(RequestBodyLimitLayer::new(config.max_upload_size))
         .layer(GovernorLayer { config: governor_conf })
-        .layer(cors)
+        .layer(cors);
+
+    // same headers as the public server (rejections included), if asked for
+    if config.admin_security_headers {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            Arc::new(state.security.clone()),
+            add_custom_security_headers,
+        ));
+    }
+
+    router
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
 
-/// Start both public and admin servers
+// admin routes for one bucket, only reachable with keys allowed into it
+fn bucket_admin_routes<S>(state: Arc<AppState>, bucket: &str) -> Router<S> {
+    Router::new()
+        .route("/upload", post(upload_file))
+        .route("/upload/chunk/init", post(init_chunked_upload))
+        .route("/upload/chunk/:id/:num", post(upload_chunk))
+        .route("/upload/chunk/complete", post(complete_chunked_upload))
+        .route("/upload/archive", post(upload_archive))
+        .route("/files", get(list_files))
+        .route("/files/:filename", delete(delete_file))
+        .route("/batch-delete", post(batch_delete_files))
+        .route("/files/:filename/tags", post(add_tags).delete(remove_tags))
+        .route("/files/:filename/disposition", post(set_disposition))
+        .route("/files/:filename/hooks", get(get_hook_job).post(rerun_hooks))
+        .route("/hooks", get(list_hook_jobs))
+        .route("/batch-tag", post(batch_tag_files))
+        .route("/collections", get(list_collections))
+        .route("/collections/:name", get(get_collection).delete(delete_collection))
+        .route("/collections/:name/files", post(add_to_collection).delete(remove_from_collection))
+        .route("/collections/:name/bulk", post(collection_bulk_action))
+        .route("/trash", get(list_trash).delete(empty_trash))
+        .route("/trash/:id", delete(delete_trash_item))
+        .route("/trash/:id/restore", post(restore_trash_item))
+        .route("/files/:filename/versions", get(list_versions))
+        .route("/files/:filename/versions/prune", post(prune_versions))
+        .route("/files/:filename/versions/:id", get(download_version))
+        .route("/files/:filename/versions/:id/restore", post(restore_version))
+        .route("/archive", post(download_archive))
+        .route("/archive/link", post(create_archive_link))
+        .route("/sites", get(list_sites))
+        .route("/sites/:site", get(get_site).post(deploy_site).delete(delete_site))
+        .route("/sites/:site/activate", post(activate_release))
+        .route("/sites/:site/rollback", post(rollback_site))
+        .route("/redirects", get(list_redirects).post(update_redirects))
+        .route("/redirects/reload", post(reload_redirects))
+        .route("/stats", get(get_stats))
+        .route("/health", get(health_check))
+        .layer(axum::middleware::from_fn_with_state(bucket.to_string(), require_bucket_access))
+        .with_state(state)
+}
+
+/// build the s3-compatible api router, requests are signed with api keys instead of sent as headers
+pub fn build_s3_router(api: Arc<S3Api>, config: &Config) -> Router {
+    tracing::debug!("Building s3 api router");
+
+    // clients address buckets both with and without a trailing slash
+    let bucket_routes = get(get_bucket)
+        .head(head_bucket)
+        .put(create_bucket)
+        .post(post_bucket)
+        .delete(delete_bucket);
+
+    // sync tools fire many small requests at once, so allow far more than the admin api
+    let governor_conf = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_millisecond(10)
+            .burst_size(200)
+            .finish()
+            .unwrap(),
+    );
+
+    Router::new()
+        .route("/", get(list_all_buckets))
+        .route("/:bucket", bucket_routes.clone())
+        .route("/:bucket/", bucket_routes)
+        .route(
+            "/:bucket/*key",
+            get(get_object).head(head_object).put(put_object).post(post_object).delete(delete_object),
+        )
+        .layer(axum::middleware::from_fn_with_state(api.clone(), authenticate))
+        .layer(RequestBodyLimitLayer::new(config.max_upload_size))
+        .layer(GovernorLayer { config: governor_conf })
+        .layer(TraceLayer::new_for_http())
+        .with_state(api)
+}
+
+/// build the webdav router for mounting the files as a network drive, logins are api key name and key
+pub fn build_webdav_router(dav: Arc<WebDav>, config: &Config) -> Router {
+    tracing::debug!("Building webdav router");
+
+    // file managers send bursts of PROPFINDs while browsing, so the burst is generous
+    let governor_conf = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_millisecond(10)
+            .burst_size(200)
+            .finish()
+            .unwrap(),
+    );
+
+    Router::new()
+        .fallback(handle_webdav)
+        .layer(axum::middleware::from_fn_with_state(dav.clone(), require_basic_auth))
+        .layer(RequestBodyLimitLayer::new(config.max_upload_size))
+        .layer(GovernorLayer { config: governor_conf })
+        .layer(TraceLayer::new_for_http())
+        .with_state(dav)
+}
+
+/// Start the public and admin servers, plus the optional listeners (s3 api, webdav) by name
 pub async fn start_servers(
     public_app: Router,
     admin_app: Router,
     public_addr: SocketAddr,
     admin_addr: SocketAddr,
+    extra: Vec<(&'static str, Router, SocketAddr)>,
 ) {
     tracing::info!("Starting servers...");
     
@@ -123,6 +415,22 @@ pub async fn start_servers(
     .with_graceful_shutdown(shutdown_signal())
     .tcp_nodelay(true);
 
+    let mut extra_servers = Vec::new();
+    for (name, app, addr) in extra {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to bind {} server: {}", name, e));
+        tracing::debug!("{} listener bound to {}", name, addr);
+        let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_signal())
+            .tcp_nodelay(true);
+        extra_servers.push(async move {
+            if let Err(e) = server.await {
+                tracing::error!("{} server error: {}", name, e);
+            }
+        });
+    }
+
     // run servers
     tracing::info!("Servers running and ready to accept connections");
     let _ = tokio::join!(
@@ -135,7 +443,8 @@ pub async fn start_servers(
             if let Err(e) = admin_server.await {
                 tracing::error!("Admin server error: {}", e);
             }
-        }
+        },
+        futures_util::future::join_all(extra_servers)
     );
 }
 
@@ -145,7 +454,27 @@ pub fn print_startup_banner(config: &Config) {
     tracing::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     tracing::info!("📡 PUBLIC FILE SERVER: http://{}:{}", config.public_host, config.public_port);
     tracing::info!("🔐 ADMIN API SERVER: http://{}:{}", config.admin_host, config.admin_port);
+    if let Some(s3_api) = &config.s3_api {
+        tracing::info!("🪣 S3 API SERVER: http://{}:{} (region {})", s3_api.host, s3_api.port, s3_api.region);
+    }
+    if let Some(webdav) = &config.webdav {
+        tracing::info!("📂 WEBDAV SERVER: http://{}:{}", webdav.host, webdav.port);
+    }
     tracing::info!("📁 Serving files from: {:?}", config.files_dir.canonicalize().unwrap_or(config.files_dir.clone()));
+    if let Some(s3) = &config.s3 {
+        tracing::info!("☁️  Storing file contents in s3://{}/{} at {}", s3.bucket, s3.prefix, s3.endpoint);
+    }
+    for vhost in &config.vhosts {
+        tracing::info!("🌐 Virtual host {}: {}", vhost.name, vhost.hosts.join(", "));
+    }
+    for bucket in &config.buckets {
+        tracing::info!(
+            "🪣 Bucket {}: {:?} ({})",
+            bucket.name,
+            bucket.root,
+            if bucket.public { "public" } else { "private" }
+        );
+    }
     tracing::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
 }
 
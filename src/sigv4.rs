@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// the only signing algorithm we speak
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+/// payload hash used when the body isn't hashed up front
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+/// format of the x-amz-date header
+pub const AMZ_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// the parts of a request that get signed
+#[derive(Clone, Debug)]
+pub struct CanonicalRequest {
+    pub method: String,
+    /// uri-encoded path, e.g. "/bucket/my%20file.txt"
+    pub path: String,
+    /// query string as produced by `canonical_query`
+    pub query: String,
+    /// lowercase header names with trimmed values, sorted by name
+    pub headers: Vec<(String, String)>,
+    pub payload_hash: String,
+}
+
+impl CanonicalRequest {
+    /// names of the signed headers, ';' separated
+    pub fn signed_headers(&self) -> String {
+        self.headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";")
+    }
+
+    fn to_canonical_string(&self) -> String {
+        let headers: String = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.path,
+            self.query,
+            headers,
+            self.signed_headers(),
+            self.payload_hash
+        )
+    }
+}
+
+/// date, region and service a signature is valid for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    /// YYYYMMDD
+    pub date: String,
+    pub region: String,
+    pub service: String,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}/aws4_request", self.date, self.region, self.service)
+    }
+}
+
+/// percent-encode everything but unreserved characters (and '/' unless `encode_slash`)
+pub fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// encode and sort query parameters the way they are signed
+pub fn canonical_query(params: &[(String, String)]) -> String {
+    let mut encoded: Vec<(String, String)> = params
+        .iter()
+        .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+        .collect();
+    encoded.sort();
+    encoded
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// key derived from the secret that signs everything within `scope`
+pub fn signing_key(secret_key: &str, scope: &Scope) -> Vec<u8> {
+    [scope.date.as_str(), scope.region.as_str(), scope.service.as_str(), "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()))
+}
+
+/// hex signature of a request made at `time` within `scope`
+pub fn signature(secret_key: &str, scope: &Scope, time: DateTime<Utc>, request: &CanonicalRequest) -> String {
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        time.format(AMZ_DATE_FORMAT),
+        scope,
+        sha256_hex(request.to_canonical_string().as_bytes())
+    );
+    hex::encode(hmac_sha256(&signing_key(secret_key, scope), string_to_sign.as_bytes()))
+}
+
+/// hex signature of one chunk of an aws-chunked body, chained to the previous chunk's
+pub fn chunk_signature(
+    signing_key: &[u8],
+    scope: &Scope,
+    time: DateTime<Utc>,
+    previous: &str,
+    data: &[u8],
+) -> String {
+    let string_to_sign = format!(
+        "{}-PAYLOAD\n{}\n{}\n{}\n{}\n{}",
+        ALGORITHM,
+        time.format(AMZ_DATE_FORMAT),
+        scope,
+        previous,
+        sha256_hex(b""),
+        sha256_hex(data)
+    );
+    hex::encode(hmac_sha256(signing_key, string_to_sign.as_bytes()))
+}
+
+/// compare signatures without leaking how much of them matched
+pub fn signatures_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Authorization header value for a request
+pub fn authorization(
+    access_key: &str,
+    secret_key: &str,
+    scope: &Scope,
+    time: DateTime<Utc>,
+    request: &CanonicalRequest,
+) -> String {
+    format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM,
+        access_key,
+        scope,
+        request.signed_headers(),
+        signature(secret_key, scope, time, request)
+    )
+}
@@ -1,31 +1,258 @@
 use std::path::PathBuf;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 
+use crate::archive::ArchiveSettings;
+use crate::buckets::Bucket;
+use crate::deploy::{DeploySettings, Deployments};
+use crate::disposition::DispositionPolicy;
+use crate::disk::DiskSettings;
+use crate::extract::ExtractLimits;
+use crate::filetypes::UploadTypeRules;
+use crate::hooks::{HookJob, HookPipeline};
+use crate::fallback::FallbackSettings;
+use crate::listing::ListingSettings;
+use crate::caching::CacheRules;
+use crate::middleware::SecurityHeaders;
+use crate::metadata::{Disposition, MetadataStore, ScanRecord};
+use crate::quota::{MeteredStorage, PendingUpload, QuotaSettings, StoredUsage};
+use crate::redirects::RedirectRules;
+use crate::scanning::ScanSettings;
+use crate::storage::{FsStorage, Storage};
+use crate::versions::VersioningSettings;
+use crate::vhosts::VirtualHosts;
+
+/// per-file settings chosen at upload time
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UploadSettings {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_downloads: Option<u64>,
+    /// name of the api key that uploaded the file
+    pub owner: Option<String>,
+    pub disposition: Option<Disposition>,
+    /// mime type of the content by its magic bytes
+    pub detected_type: Option<String>,
+    pub scan: Option<ScanRecord>,
+    /// sha-256 of the content, taken while it was written (None when it never passed through us)
+    pub sha256: Option<String>,
+}
+
 /// metadata for a chunked upload in progress
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct ChunkedUploadMetadata {
     pub filename: String,
     pub total_size: u64,
     pub chunk_size: usize,
     pub total_chunks: usize,
     pub received_chunks: HashSet<usize>,
+    /// native multipart upload chunks go to as parts (None = chunks are stored separately)
+    pub multipart_id: Option<String>,
+    /// settings to apply once the upload completes
+    pub settings: UploadSettings,
 }
 
 /// shared application state
 #[derive(Clone)]
 pub struct AppState {
+    /// local directory for the metadata index (and the files themselves with the default storage)
     pub files_dir: PathBuf,
+    /// where file contents are stored
+    pub storage: Arc<dyn Storage>,
     /// track ongoing chunked uploads by upload_id
     pub chunked_uploads: DashMap<String, ChunkedUploadMetadata>,
+    /// tags, collections and other per-file metadata
+    pub metadata: Arc<MetadataStore>,
+    /// full downloads currently being served for files with a download limit
+    pub active_downloads: DashMap<String, u64>,
+    /// where overwritten files keep their previous versions
+    pub versioning: VersioningSettings,
+    /// storage limits, globally and per api key
+    pub quotas: QuotaSettings,
+    /// single uploads currently holding quota
+    pub pending_uploads: DashMap<String, PendingUpload>,
+    /// running totals of what the storage holds, for quota checks
+    pub stored: Arc<StoredUsage>,
+    /// held while checking quotas so two uploads can't both squeeze into the last bytes
+    pub quota_lock: Arc<tokio::sync::Mutex<()>>,
+    /// free disk space to keep
+    pub disk: DiskSettings,
+    /// extra buckets by name (only set on the default bucket's state)
+    pub buckets: Arc<BTreeMap<String, Bucket>>,
+    /// host names served from other roots on the public server
+    pub vhosts: Arc<VirtualHosts>,
+    /// limits and link signing for archive downloads
+    pub archives: ArchiveSettings,
+    /// how far uploaded archives may expand when extracted
+    pub extract: ExtractLimits,
+    /// releases of deployed static sites and the ones being served
+    pub deployments: Arc<Deployments>,
+    /// how many releases deployed sites keep
+    pub deploy: DeploySettings,
+    /// spa index files and error pages on the public server
+    pub fallbacks: FallbackSettings,
+    /// redirect and rewrite rules for the public server
+    pub redirects: Arc<RedirectRules>,
+    /// directories the public server lists when they have no index file
+    pub listings: ListingSettings,
+    /// Cache-Control headers by path on the public server
+    pub cache_rules: CacheRules,
+    /// security headers for public (and optionally admin) responses
+    pub security: SecurityHeaders,
+    /// which files the public server sends as downloads or sandboxes
+    pub disposition: DispositionPolicy,
+    /// extensions and content types uploads may have
+    pub upload_types: UploadTypeRules,
+    /// malware scanning of uploads
+    pub scanning: ScanSettings,
+    /// hooks run on stored uploads
+    pub hooks: HookPipeline,
+    /// latest hook job of each file, by file name
+    pub hook_jobs: DashMap<String, HookJob>,
 }
 
 impl AppState {
     /// create a new app state with the given files directory
     pub fn new(files_dir: PathBuf) -> Self {
+        let metadata = Arc::new(MetadataStore::load(&files_dir));
+        let stored = Arc::new(StoredUsage::default());
+        let storage: Arc<dyn Storage> =
+            Arc::new(MeteredStorage::new(Arc::new(FsStorage::new(files_dir.clone())), stored.clone()));
         Self {
+            deployments: Arc::new(Deployments::new(storage.clone())),
+            redirects: Arc::new(RedirectRules::load(storage.clone())),
+            storage,
             files_dir,
             chunked_uploads: DashMap::new(),
+            metadata,
+            active_downloads: DashMap::new(),
+            versioning: VersioningSettings::default(),
+            quotas: QuotaSettings::default(),
+            pending_uploads: DashMap::new(),
+            stored,
+            quota_lock: Arc::new(tokio::sync::Mutex::new(())),
+            disk: DiskSettings::default(),
+            buckets: Arc::new(BTreeMap::new()),
+            vhosts: Arc::new(VirtualHosts::default()),
+            archives: ArchiveSettings::default(),
+            extract: ExtractLimits::default(),
+            deploy: DeploySettings::default(),
+            fallbacks: FallbackSettings::default(),
+            listings: ListingSettings::default(),
+            cache_rules: CacheRules::default(),
+            security: SecurityHeaders::default(),
+            disposition: DispositionPolicy::default(),
+            upload_types: UploadTypeRules::default(),
+            scanning: ScanSettings::default(),
+            hooks: HookPipeline::default(),
+            hook_jobs: DashMap::new(),
         }
     }
+
+    /// keep file contents somewhere other than files_dir
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.stored = Arc::new(StoredUsage::default());
+        let storage: Arc<dyn Storage> = Arc::new(MeteredStorage::new(storage, self.stored.clone()));
+        self.deployments = Arc::new(Deployments::new(storage.clone()));
+        self.redirects = Arc::new(RedirectRules::load(storage.clone()));
+        self.storage = storage;
+        self
+    }
+
+    /// enable versioning for some directories
+    pub fn with_versioning(mut self, versioning: VersioningSettings) -> Self {
+        self.versioning = versioning;
+        self
+    }
+
+    /// enforce storage quotas
+    pub fn with_quotas(mut self, quotas: QuotaSettings) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// keep some disk space free
+    pub fn with_disk(mut self, disk: DiskSettings) -> Self {
+        self.disk = disk;
+        self
+    }
+
+    /// serve extra buckets next to this one
+    pub fn with_buckets(mut self, buckets: Vec<Bucket>) -> Self {
+        self.buckets = Arc::new(buckets.into_iter().map(|b| (b.name.clone(), b)).collect());
+        self
+    }
+
+    /// limit archive downloads and sign links to them
+    pub fn with_archives(mut self, archives: ArchiveSettings) -> Self {
+        self.archives = archives;
+        self
+    }
+
+    /// limit what uploaded archives may expand to
+    pub fn with_extract_limits(mut self, extract: ExtractLimits) -> Self {
+        self.extract = extract;
+        self
+    }
+
+    /// keep more or fewer releases of deployed sites
+    pub fn with_deploy_settings(mut self, deploy: DeploySettings) -> Self {
+        self.deploy = deploy;
+        self
+    }
+
+    /// serve spa index files and error pages for missing files
+    pub fn with_fallbacks(mut self, fallbacks: FallbackSettings) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    /// list directories without an index file on the public server
+    pub fn with_listings(mut self, listings: ListingSettings) -> Self {
+        self.listings = listings;
+        self
+    }
+
+    /// send Cache-Control headers by path on the public server
+    pub fn with_cache_rules(mut self, cache_rules: CacheRules) -> Self {
+        self.cache_rules = cache_rules;
+        self
+    }
+
+    /// send these security headers instead of the built-in ones
+    pub fn with_security_headers(mut self, security: SecurityHeaders) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// force downloads or sandbox uploaded files on the public server
+    pub fn with_disposition(mut self, disposition: DispositionPolicy) -> Self {
+        self.disposition = disposition;
+        self
+    }
+
+    /// only accept uploads with these extensions and content types
+    pub fn with_upload_types(mut self, upload_types: UploadTypeRules) -> Self {
+        self.upload_types = upload_types;
+        self
+    }
+
+    /// scan uploads with clamd before keeping them
+    pub fn with_scanning(mut self, scanning: ScanSettings) -> Self {
+        self.scanning = scanning;
+        self
+    }
+
+    /// run hooks on uploads once they're stored
+    pub fn with_hooks(mut self, hooks: HookPipeline) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// serve other roots for some host names on the public server
+    pub fn with_vhosts(mut self, vhosts: VirtualHosts) -> Self {
+        self.vhosts = Arc::new(vhosts);
+        self
+    }
 }
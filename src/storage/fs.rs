@@ -0,0 +1,224 @@
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use super::{check_key, ByteStream, Listing, ObjectMeta, ObjectStream, Storage};
+
+/// objects as files under a local directory
+#[derive(Clone, Debug)]
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    // write into a hidden temp file next to the target, renamed into place once complete
+    async fn write_temp(&self, path: &Path, mut body: ByteStream) -> io::Result<(PathBuf, u64)> {
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir).await?;
+        let tmp_path = dir.join(format!(".upload-{}", Uuid::new_v4()));
+
+        let result = async {
+            let mut file = fs::File::create(&tmp_path).await?;
+            let mut written = 0u64;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                file.write_all(&chunk).await?;
+                written += chunk.len() as u64;
+            }
+            file.sync_all().await?;
+            Ok(written)
+        }
+        .await;
+
+        match result {
+            Ok(written) => Ok((tmp_path, written)),
+            Err(e) => {
+                // don't leave a truncated file behind
+                let _ = fs::remove_file(&tmp_path).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn object_meta(key: String, meta: &std::fs::Metadata) -> ObjectMeta {
+    ObjectMeta {
+        key,
+        size: meta.len(),
+        modified: meta.modified().ok(),
+    }
+}
+
+fn not_found(key: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Not found: {}", key))
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn put(&self, key: &str, body: ByteStream) -> io::Result<u64> {
+        let path = self.path(key)?;
+        let (tmp_path, written) = self.write_temp(&path, body).await?;
+        if let Err(e) = fs::rename(&tmp_path, &path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+        Ok(written)
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<ObjectStream> {
+        let meta = self.stat(key).await?;
+        let mut file = fs::File::open(self.path(key)?).await?;
+
+        let range = match range {
+            Some(range) => range.start.min(meta.size)..range.end.min(meta.size),
+            None => 0..meta.size,
+        };
+        if range.start > 0 {
+            file.seek(SeekFrom::Start(range.start)).await?;
+        }
+        let len = range.end.saturating_sub(range.start);
+
+        Ok(ObjectStream {
+            meta,
+            range,
+            body: Box::pin(ReaderStream::new(file.take(len))),
+        })
+    }
+
+    async fn stat(&self, key: &str) -> io::Result<ObjectMeta> {
+        let meta = fs::metadata(self.path(key)?).await?;
+        if !meta.is_file() {
+            return Err(not_found(key));
+        }
+        Ok(object_meta(key.to_string(), &meta))
+    }
+
+    async fn list(&self, prefix: &str, recursive: bool) -> io::Result<Listing> {
+        // start at the directory part of the prefix, the rest filters names
+        let (dir_key, _) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+        if !dir_key.is_empty() {
+            check_key(dir_key)?;
+        }
+
+        let mut listing = Listing::default();
+        let mut stack = vec![dir_key.to_string()];
+        while let Some(dir_key) = stack.pop() {
+            let dir = if dir_key.is_empty() { self.root.clone() } else { self.root.join(&dir_key) };
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let key = if dir_key.is_empty() { name } else { format!("{}/{}", dir_key, name) };
+                let meta = entry.metadata().await?;
+
+                if meta.is_dir() {
+                    let dir_prefix = format!("{}/", key);
+                    if recursive && (dir_prefix.starts_with(prefix) || prefix.starts_with(&dir_prefix)) {
+                        stack.push(key);
+                    } else if !recursive && dir_prefix.starts_with(prefix) {
+                        listing.prefixes.push(dir_prefix);
+                    }
+                } else if meta.is_file() && key.starts_with(prefix) {
+                    listing.objects.push(object_meta(key, &meta));
+                }
+            }
+        }
+
+        listing.objects.sort_by(|a, b| a.key.cmp(&b.key));
+        listing.prefixes.sort();
+        Ok(listing)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key)?;
+        if fs::metadata(&path).await?.is_dir() {
+            return Err(not_found(key));
+        }
+        fs::remove_file(path).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let from_path = self.path(from)?;
+        let to_path = self.path(to)?;
+        if let Some(parent) = to_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(from_path, to_path).await
+    }
+
+    async fn rename_new(&self, from: &str, to: &str) -> io::Result<()> {
+        let from_path = self.path(from)?;
+        let to_path = self.path(to)?;
+        if let Some(parent) = to_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // linking refuses an existing target, unlike renaming
+        fs::hard_link(&from_path, &to_path).await?;
+        if let Err(e) = fs::remove_file(&from_path).await {
+            let _ = fs::remove_file(&to_path).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+        // whole directories go in one go
+        if let Some(dir_key) = prefix.strip_suffix('/') {
+            return match fs::remove_dir_all(self.path(dir_key)?).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+
+        for object in self.list(prefix, true).await?.objects {
+            self.delete(&object.key).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_dir(&self, prefix: &str) -> io::Result<()> {
+        fs::create_dir_all(self.path(prefix.trim_end_matches('/'))?).await
+    }
+
+    async fn remove_dir(&self, prefix: &str) -> io::Result<()> {
+        // collect the whole tree, then remove the deepest directories first
+        let mut dirs = vec![self.path(prefix.trim_end_matches('/'))?];
+        let mut next = 0;
+        while next < dirs.len() {
+            let mut entries = fs::read_dir(&dirs[next]).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(entry.path());
+                }
+            }
+            next += 1;
+        }
+        for dir in dirs.iter().rev() {
+            fs::remove_dir(dir).await?;
+        }
+        Ok(())
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::TryStreamExt;
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use super::{check_key, once, ByteStream, Listing, ObjectMeta, ObjectStream, Storage};
+
+/// objects kept in memory, for tests
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: RwLock<BTreeMap<String, (Bytes, SystemTime)>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn meta(key: &str, bytes: &Bytes, modified: SystemTime) -> ObjectMeta {
+        ObjectMeta {
+            key: key.to_string(),
+            size: bytes.len() as u64,
+            modified: Some(modified),
+        }
+    }
+}
+
+fn not_found(key: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Not found: {}", key))
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn put(&self, key: &str, body: ByteStream) -> io::Result<u64> {
+        check_key(key)?;
+        let chunks: Vec<Bytes> = body.try_collect().await?;
+        let bytes = Bytes::from(chunks.concat());
+        let size = bytes.len() as u64;
+        self.objects
+            .write()
+            .unwrap()
+            .insert(key.to_string(), (bytes, SystemTime::now()));
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<ObjectStream> {
+        let (bytes, modified) = self
+            .objects
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| not_found(key))?;
+
+        let size = bytes.len() as u64;
+        let range = match range {
+            Some(range) => range.start.min(size)..range.end.min(size).max(range.start.min(size)),
+            None => 0..size,
+        };
+        let body = once(bytes.slice(range.start as usize..range.end as usize));
+
+        Ok(ObjectStream {
+            meta: Self::meta(key, &bytes, modified),
+            range,
+            body,
+        })
+    }
+
+    async fn stat(&self, key: &str) -> io::Result<ObjectMeta> {
+        self.objects
+            .read()
+            .unwrap()
+            .get(key)
+            .map(|(bytes, modified)| Self::meta(key, bytes, *modified))
+            .ok_or_else(|| not_found(key))
+    }
+
+    async fn list(&self, prefix: &str, recursive: bool) -> io::Result<Listing> {
+        let objects = self.objects.read().unwrap();
+        let mut listing = Listing::default();
+
+        for (key, (bytes, modified)) in objects.range(prefix.to_string()..) {
+            let Some(rest) = key.strip_prefix(prefix) else {
+                break;
+            };
+            match rest.find('/') {
+                Some(slash) if !recursive => {
+                    let dir_prefix = format!("{}{}", prefix, &rest[..=slash]);
+                    if listing.prefixes.last() != Some(&dir_prefix) {
+                        listing.prefixes.push(dir_prefix);
+                    }
+                }
+                _ => listing.objects.push(Self::meta(key, bytes, *modified)),
+            }
+        }
+
+        Ok(listing)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.objects
+            .write()
+            .unwrap()
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| not_found(key))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        check_key(to)?;
+        let mut objects = self.objects.write().unwrap();
+        let object = objects.remove(from).ok_or_else(|| not_found(from))?;
+        objects.insert(to.to_string(), object);
+        Ok(())
+    }
+
+    async fn rename_new(&self, from: &str, to: &str) -> io::Result<()> {
+        check_key(to)?;
+        let mut objects = self.objects.write().unwrap();
+        if objects.contains_key(to) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to)));
+        }
+        let object = objects.remove(from).ok_or_else(|| not_found(from))?;
+        objects.insert(to.to_string(), object);
+        Ok(())
+    }
+}
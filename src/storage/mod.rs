@@ -0,0 +1,318 @@
+use async_trait::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use percent_encoding::percent_decode_str;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+mod fs;
+mod memory;
+mod s3;
+
+pub use fs::FsStorage;
+pub use memory::MemoryStorage;
+pub use s3::S3Storage;
+
+/// a stream of object contents
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// what we know about a stored object
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectMeta {
+    /// path relative to the storage root, '/' separated
+    pub key: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// an object being read, possibly just a range of it
+pub struct ObjectStream {
+    /// metadata of the whole object
+    pub meta: ObjectMeta,
+    /// the bytes being streamed (the whole object if no range was asked for)
+    pub range: Range<u64>,
+    pub body: ByteStream,
+}
+
+/// objects and "directories" under a prefix
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Listing {
+    pub objects: Vec<ObjectMeta>,
+    /// key prefixes one level down, ending in '/' (only for non-recursive listings)
+    pub prefixes: Vec<String>,
+}
+
+/// where file contents live
+///
+/// keys are '/' separated paths relative to the storage root; internal data uses dot-prefixed
+/// keys (".chunks/", ".trash/", ".versions/") next to the served files
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// write an object, replacing any existing one, returns the bytes written
+    ///
+    /// readers never see a partially written object
+    async fn put(&self, key: &str, body: ByteStream) -> io::Result<u64>;
+
+    /// read an object or a byte range of it (clamped to the object's size)
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<ObjectStream>;
+
+    /// look up an object, NotFound if there is none (directories don't count)
+    async fn stat(&self, key: &str) -> io::Result<ObjectMeta>;
+
+    /// objects whose key starts with `prefix`, either all of them or one level deep
+    async fn list(&self, prefix: &str, recursive: bool) -> io::Result<Listing>;
+
+    /// remove an object
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// move an object to a new key, replacing whatever is there
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// move an object to a new key, AlreadyExists if there's something there
+    ///
+    /// checked first by default, backends that can refuse in the move itself do so
+    async fn rename_new(&self, from: &str, to: &str) -> io::Result<()> {
+        match self.stat(to).await {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.rename(from, to).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// remove every object under a prefix
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+        for object in self.list(prefix, true).await?.objects {
+            match self.delete(&object.key).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// start a native multipart upload of `key` in parts of `part_size` bytes
+    ///
+    /// None if the backend has no multipart support (or can't use parts that small), in
+    /// which case chunks are stored as objects of their own and concatenated at the end
+    async fn create_multipart(&self, _key: &str, _part_size: u64) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// store one part (numbered from 1) of a multipart upload, replacing an earlier attempt
+    async fn put_part(&self, _key: &str, _upload_id: &str, _part_number: u32, _data: Bytes) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Multipart uploads are not supported"))
+    }
+
+    /// assemble the uploaded parts into the object, returns its size
+    async fn complete_multipart(&self, _key: &str, _upload_id: &str) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Multipart uploads are not supported"))
+    }
+
+    /// throw away a multipart upload and its parts
+    async fn abort_multipart(&self, _key: &str, _upload_id: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Multipart uploads are not supported"))
+    }
+
+    /// make sure objects can be written under a prefix (only directories need this)
+    async fn create_dir(&self, _prefix: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// remove the (empty) directories left under a prefix once its objects are gone
+    async fn remove_dir(&self, _prefix: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// directory on local disk holding the objects, if there is one
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// the configured backend for a bucket whose local directory is `root`
+pub fn from_config(config: &Config, root: &Path, bucket: &str) -> Arc<dyn Storage> {
+    match &config.s3 {
+        Some(s3) => Arc::new(S3Storage::new(s3, &format!("{}{}/", s3.prefix, bucket))),
+        None => Arc::new(FsStorage::new(root.to_path_buf())),
+    }
+}
+
+/// a stream yielding a single chunk
+pub fn once(bytes: Bytes) -> ByteStream {
+    Box::pin(stream::once(async move { Ok(bytes) }))
+}
+
+/// write an in-memory buffer
+pub async fn put_bytes(storage: &dyn Storage, key: &str, bytes: Bytes) -> io::Result<u64> {
+    storage.put(key, once(bytes)).await
+}
+
+/// read a whole object into memory
+pub async fn read_all(storage: &dyn Storage, key: &str) -> io::Result<Bytes> {
+    let object = storage.get(key, None).await?;
+    let chunks: Vec<Bytes> = object.body.try_collect().await?;
+    Ok(chunks.concat().into())
+}
+
+/// whether an object exists
+pub async fn exists(storage: &dyn Storage, key: &str) -> io::Result<bool> {
+    match storage.stat(key).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// stream several objects back to back, e.g. to assemble chunks
+pub fn concat(storage: Arc<dyn Storage>, keys: Vec<String>) -> ByteStream {
+    Box::pin(
+        stream::iter(keys)
+            .then(move |key| {
+                let storage = storage.clone();
+                async move { storage.get(&key, None).await.map(|object| object.body) }
+            })
+            .try_flatten(),
+    )
+}
+
+// reject keys that could escape the storage root
+pub(crate) fn check_key(key: &str) -> io::Result<()> {
+    let bad = key.starts_with('/')
+        || key.contains('\\')
+        || key.contains('\0')
+        || key.split('/').any(|segment| segment == ".." || segment == ".");
+    if bad {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid key: {}", key)));
+    }
+    Ok(())
+}
+
+/// serve objects straight from storage on the public server (for backends ServeDir can't read)
+pub async fn serve_object(
+    State(storage): State<Arc<dyn Storage>>,
+    req: Request<Body>,
+) -> Response {
+    serve_prefixed(storage.as_ref(), "", req).await
+}
+
+/// serve the object at `prefix` followed by the request path
+pub(crate) async fn serve_prefixed(storage: &dyn Storage, prefix: &str, req: Request<Body>) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+
+    let decoded = percent_decode_str(req.uri().path()).decode_utf8_lossy().to_string();
+    let mut key = decoded.trim_start_matches('/').to_string();
+    if key.is_empty() || key.ends_with('/') {
+        key.push_str("index.html");
+    }
+    if check_key(&key).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let key = format!("{}{}", prefix, key);
+
+    let meta = match storage.stat(&key).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to stat {}: {}", key, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let last_modified = meta.modified.map(http_date);
+    if let (Some(since), Some(modified)) = (req.headers().get(header::IF_MODIFIED_SINCE), &last_modified) {
+        if since.to_str().ok() == Some(modified.as_str()) {
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+    }
+
+    let range = match requested_range(req.headers(), meta.size) {
+        Ok(range) => range,
+        Err(()) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", meta.size))
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime_guess::from_path(&key).first_or_octet_stream().as_ref())
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = &last_modified {
+        builder = builder.header(header::LAST_MODIFIED, modified);
+    }
+    let length = match &range {
+        Some(range) => {
+            builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, meta.size),
+            );
+            range.end - range.start
+        }
+        None => meta.size,
+    };
+    builder = builder.header(header::CONTENT_LENGTH, length);
+
+    if req.method() == Method::HEAD {
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    match storage.get(&key, range).await {
+        Ok(object) => builder.body(Body::from_stream(object.body)).unwrap(),
+        Err(e) => {
+            tracing::error!("Failed to read {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// a single "bytes=" range, Err if it can't be satisfied, Ok(None) for the whole object
+pub(crate) fn requested_range(headers: &HeaderMap, size: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(value) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return Ok(None);
+    };
+    // multiple ranges aren't supported, serve the whole thing instead
+    let Some(spec) = value.strip_prefix("bytes=").filter(|s| !s.contains(',')) else {
+        return Ok(None);
+    };
+    let (start, end) = spec.trim().split_once('-').ok_or(())?;
+
+    let range = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        (Some(start), Some(end)) if start <= end => start..(end + 1).min(size),
+        (Some(start), None) if end.is_empty() => start..size,
+        (None, Some(suffix)) if start.is_empty() => size.saturating_sub(suffix)..size,
+        _ => return Err(()),
+    };
+    if range.start >= size || range.is_empty() {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+/// opaque etag derived from mtime and size, changes whenever the object is rewritten
+pub fn etag(meta: &ObjectMeta) -> String {
+    let nanos = meta
+        .modified
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", nanos, meta.size)
+}
+
+pub(crate) fn http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
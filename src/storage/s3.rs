@@ -0,0 +1,573 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, TryStreamExt};
+use serde::Deserialize;
+use std::io;
+use std::ops::Range;
+use std::time::SystemTime;
+
+use super::{check_key, ByteStream, Listing, ObjectMeta, ObjectStream, Storage};
+use crate::config::S3Config;
+use crate::sigv4::{self, CanonicalRequest, Scope, AMZ_DATE_FORMAT, UNSIGNED_PAYLOAD};
+
+/// parts smaller than this are refused by S3 (except the last one)
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+// part size used when splitting up streamed writes
+const PART_SIZE: usize = 16 * 1024 * 1024;
+// CopyObject refuses anything bigger, those get copied part by part
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const COPY_PART_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// objects in an S3-compatible store (AWS S3, MinIO, ...) under a key prefix
+#[derive(Clone)]
+pub struct S3Storage {
+    client: reqwest::Client,
+    config: S3Config,
+    /// prepended to every key, ends in '/' unless empty
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+struct ListBucketResult {
+    #[serde(rename = "Contents", default)]
+    contents: Vec<ListedObject>,
+    #[serde(rename = "CommonPrefixes", default)]
+    common_prefixes: Vec<CommonPrefix>,
+    #[serde(rename = "IsTruncated", default)]
+    is_truncated: bool,
+    #[serde(rename = "NextContinuationToken")]
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListedObject {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "Size")]
+    size: u64,
+    #[serde(rename = "LastModified")]
+    last_modified: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CommonPrefix {
+    #[serde(rename = "Prefix")]
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+struct InitiateMultipartUploadResult {
+    #[serde(rename = "UploadId")]
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+struct ListPartsResult {
+    #[serde(rename = "Part", default)]
+    parts: Vec<ListedPart>,
+    #[serde(rename = "IsTruncated", default)]
+    is_truncated: bool,
+    #[serde(rename = "NextPartNumberMarker")]
+    next_part_number_marker: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListedPart {
+    #[serde(rename = "PartNumber")]
+    part_number: u32,
+    #[serde(rename = "ETag")]
+    etag: String,
+    #[serde(rename = "Size")]
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct CopyPartResult {
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResult {
+    #[serde(rename = "Code")]
+    code: String,
+    #[serde(rename = "Message", default)]
+    message: String,
+}
+
+fn parse_xml<T: for<'de> Deserialize<'de>>(body: &[u8]) -> io::Result<T> {
+    quick_xml::de::from_reader(body)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected S3 response: {}", e)))
+}
+
+// some S3 calls answer 200 and put the error in the body
+fn check_error_body(body: &[u8]) -> io::Result<()> {
+    if let Ok(error) = quick_xml::de::from_reader::<_, ErrorResult>(body) {
+        return Err(io::Error::other(format!("S3 error {}: {}", error.code, error.message)));
+    }
+    Ok(())
+}
+
+// reqwest only knows the body's length, which is 0 for HEAD
+fn content_length(response: &reqwest::Response) -> u64 {
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
+}
+
+fn parse_http_date(value: Option<&HeaderValue>) -> Option<SystemTime> {
+    let value = value?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok().map(SystemTime::from)
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config, prefix: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config: config.clone(),
+            prefix: prefix.trim_start_matches('/').to_string(),
+        }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    // send a signed request for an object ("" for the bucket itself)
+    async fn send(
+        &self,
+        method: Method,
+        object_key: &str,
+        query: &[(&str, String)],
+        mut headers: HeaderMap,
+        body: Option<Bytes>,
+    ) -> io::Result<reqwest::Response> {
+        let bucket = sigv4::uri_encode(&self.config.bucket, true);
+        let key = sigv4::uri_encode(object_key, false);
+        let (base, path) = match self.config.path_style {
+            true => (self.config.endpoint.clone(), format!("/{}/{}", bucket, key)),
+            false => {
+                let (scheme, host) = self.config.endpoint.split_once("://").unwrap_or(("https", &self.config.endpoint));
+                (format!("{}://{}.{}", scheme, bucket, host), format!("/{}", key))
+            }
+        };
+        let params: Vec<(String, String)> = query.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        let query = sigv4::canonical_query(&params);
+
+        let mut url = format!("{}{}", base, path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        let url = reqwest::Url::parse(&url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format(AMZ_DATE_FORMAT).to_string();
+        headers.insert("x-amz-date", HeaderValue::from_str(&amz_date).unwrap());
+        headers.insert("x-amz-content-sha256", HeaderValue::from_static(UNSIGNED_PAYLOAD));
+
+        // sign the host plus every x-amz-* header
+        let mut signed: Vec<(String, String)> = headers
+            .iter()
+            .filter(|(name, _)| name.as_str().starts_with("x-amz-"))
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().trim().to_string()))
+            .collect();
+        signed.push(("host".to_string(), host));
+        signed.sort();
+
+        let request = CanonicalRequest {
+            method: method.to_string(),
+            path,
+            query,
+            headers: signed,
+            payload_hash: UNSIGNED_PAYLOAD.to_string(),
+        };
+        let scope = Scope {
+            date: now.format("%Y%m%d").to_string(),
+            region: self.config.region.clone(),
+            service: "s3".to_string(),
+        };
+        let authorization =
+            sigv4::authorization(&self.config.access_key, &self.config.secret_key, &scope, now, &request);
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&authorization).unwrap());
+
+        let mut builder = self.client.request(method.clone(), url).headers(headers);
+        builder = match body {
+            Some(body) => builder.header(header::CONTENT_LENGTH, body.len()).body(body),
+            None if method == Method::PUT || method == Method::POST => builder.header(header::CONTENT_LENGTH, 0),
+            None => builder,
+        };
+        let response = builder.send().await.map_err(io::Error::other)?;
+
+        let status = response.status();
+        if status.is_success() || status == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(response);
+        }
+
+        let body = response.bytes().await.unwrap_or_default();
+        let error = parse_xml::<ErrorResult>(&body).ok();
+        let message = match &error {
+            Some(error) => format!("S3 {} {} failed: {} {}", method, object_key, error.code, error.message),
+            None => format!("S3 {} {} failed: {}", method, object_key, status),
+        };
+        let kind = match status {
+            StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+            StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        Err(io::Error::new(kind, message))
+    }
+
+    async fn send_xml<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        object_key: &str,
+        query: &[(&str, String)],
+        headers: HeaderMap,
+        body: Option<Bytes>,
+    ) -> io::Result<T> {
+        let response = self.send(method, object_key, query, headers, body).await?;
+        let body = response.bytes().await.map_err(io::Error::other)?;
+        check_error_body(&body)?;
+        parse_xml(&body)
+    }
+
+    async fn start_upload(&self, object_key: &str) -> io::Result<String> {
+        let result: InitiateMultipartUploadResult = self
+            .send_xml(Method::POST, object_key, &[("uploads", String::new())], HeaderMap::new(), None)
+            .await?;
+        Ok(result.upload_id)
+    }
+
+    async fn upload_part(&self, object_key: &str, upload_id: &str, part_number: u32, data: Bytes) -> io::Result<String> {
+        let query = [("partNumber", part_number.to_string()), ("uploadId", upload_id.to_string())];
+        let response = self.send(Method::PUT, object_key, &query, HeaderMap::new(), Some(data)).await?;
+        Ok(response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    async fn finish_upload(&self, object_key: &str, upload_id: &str, etags: &[(u32, String)]) -> io::Result<()> {
+        let mut xml = String::from("<CompleteMultipartUpload>");
+        for (part_number, etag) in etags {
+            xml.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part_number,
+                quick_xml::escape::escape(etag.as_str())
+            ));
+        }
+        xml.push_str("</CompleteMultipartUpload>");
+
+        let query = [("uploadId", upload_id.to_string())];
+        let response = self.send(Method::POST, object_key, &query, HeaderMap::new(), Some(xml.into())).await?;
+        let body = response.bytes().await.map_err(io::Error::other)?;
+        check_error_body(&body)
+    }
+
+    async fn cancel_upload(&self, object_key: &str, upload_id: &str) -> io::Result<()> {
+        let query = [("uploadId", upload_id.to_string())];
+        self.send(Method::DELETE, object_key, &query, HeaderMap::new(), None).await?;
+        Ok(())
+    }
+
+    // every part uploaded so far, in order
+    async fn list_parts(&self, object_key: &str, upload_id: &str) -> io::Result<Vec<ListedPart>> {
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![("uploadId", upload_id.to_string())];
+            if let Some(marker) = &marker {
+                query.push(("part-number-marker", marker.clone()));
+            }
+            let result: ListPartsResult = self
+                .send_xml(Method::GET, object_key, &query, HeaderMap::new(), None)
+                .await?;
+            parts.extend(result.parts);
+            match result.next_part_number_marker {
+                Some(next) if result.is_truncated => marker = Some(next),
+                _ => break,
+            }
+        }
+        parts.sort_by_key(|part| part.part_number);
+        Ok(parts)
+    }
+
+    // upload whatever is left of `body` as parts after `first`, then complete
+    async fn put_multipart(&self, object_key: &str, first: Vec<u8>, mut body: ByteStream) -> io::Result<u64> {
+        let upload_id = self.start_upload(object_key).await?;
+
+        let result = async {
+            let mut etags = Vec::new();
+            let mut written = 0u64;
+            let mut buffer = first;
+            let mut done = false;
+            while !done {
+                while buffer.len() < PART_SIZE {
+                    match body.next().await {
+                        Some(chunk) => buffer.extend_from_slice(&chunk?),
+                        None => {
+                            done = true;
+                            break;
+                        }
+                    }
+                }
+                if buffer.is_empty() && !etags.is_empty() {
+                    break;
+                }
+                let part_number = etags.len() as u32 + 1;
+                written += buffer.len() as u64;
+                let etag = self
+                    .upload_part(object_key, &upload_id, part_number, Bytes::from(std::mem::take(&mut buffer)))
+                    .await?;
+                etags.push((part_number, etag));
+            }
+            self.finish_upload(object_key, &upload_id, &etags).await?;
+            Ok(written)
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = self.cancel_upload(object_key, &upload_id).await;
+        }
+        result
+    }
+
+    // server-side copy, part by part for objects CopyObject won't take
+    async fn copy(&self, from_key: &str, to_key: &str, size: u64) -> io::Result<()> {
+        let source = format!(
+            "/{}/{}",
+            sigv4::uri_encode(&self.config.bucket, true),
+            sigv4::uri_encode(from_key, false)
+        );
+        let source = HeaderValue::from_str(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let copy_source = HeaderName::from_static("x-amz-copy-source");
+
+        if size <= MAX_COPY_SIZE {
+            let mut headers = HeaderMap::new();
+            headers.insert(copy_source, source);
+            let response = self.send(Method::PUT, to_key, &[], headers, None).await?;
+            let body = response.bytes().await.map_err(io::Error::other)?;
+            return check_error_body(&body);
+        }
+
+        let upload_id = self.start_upload(to_key).await?;
+        let result = async {
+            let mut etags = Vec::new();
+            let mut start = 0;
+            while start < size {
+                let end = (start + COPY_PART_SIZE).min(size);
+                let part_number = etags.len() as u32 + 1;
+                let mut headers = HeaderMap::new();
+                headers.insert(copy_source.clone(), source.clone());
+                headers.insert(
+                    "x-amz-copy-source-range",
+                    HeaderValue::from_str(&format!("bytes={}-{}", start, end - 1)).unwrap(),
+                );
+                let query = [("partNumber", part_number.to_string()), ("uploadId", upload_id.clone())];
+                let result: CopyPartResult = self.send_xml(Method::PUT, to_key, &query, headers, None).await?;
+                etags.push((part_number, result.etag));
+                start = end;
+            }
+            self.finish_upload(to_key, &upload_id, &etags).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = self.cancel_upload(to_key, &upload_id).await;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, mut body: ByteStream) -> io::Result<u64> {
+        check_key(key)?;
+        let object_key = self.object_key(key);
+
+        // small objects go up in one request, anything bigger as a multipart upload
+        let mut buffer = Vec::new();
+        while buffer.len() < PART_SIZE {
+            match body.next().await {
+                Some(chunk) => buffer.extend_from_slice(&chunk?),
+                None => {
+                    let size = buffer.len() as u64;
+                    self.send(Method::PUT, &object_key, &[], HeaderMap::new(), Some(buffer.into()))
+                        .await?;
+                    return Ok(size);
+                }
+            }
+        }
+        self.put_multipart(&object_key, buffer, body).await
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<ObjectStream> {
+        check_key(key)?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(range) = &range {
+            if range.start >= range.end {
+                let meta = self.stat(key).await?;
+                let start = range.start.min(meta.size);
+                return Ok(ObjectStream { meta, range: start..start, body: Box::pin(futures_util::stream::empty()) });
+            }
+            headers.insert(
+                header::RANGE,
+                HeaderValue::from_str(&format!("bytes={}-{}", range.start, range.end - 1)).unwrap(),
+            );
+        }
+
+        let response = self.send(Method::GET, &self.object_key(key), &[], headers, None).await?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // the range starts past the end
+            let meta = self.stat(key).await?;
+            return Ok(ObjectStream {
+                range: meta.size..meta.size,
+                meta,
+                body: Box::pin(futures_util::stream::empty()),
+            });
+        }
+
+        let length = content_length(&response);
+        let content_range = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes "))
+            .and_then(|v| v.split_once('/'))
+            .and_then(|(span, total)| {
+                let (start, _) = span.split_once('-')?;
+                Some((start.parse::<u64>().ok()?, total.parse::<u64>().ok()?))
+            });
+        let (start, size) = match (response.status(), content_range) {
+            (StatusCode::PARTIAL_CONTENT, Some((start, total))) => (start, total),
+            _ => (0, length),
+        };
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            size,
+            modified: parse_http_date(response.headers().get(header::LAST_MODIFIED)),
+        };
+
+        Ok(ObjectStream {
+            meta,
+            range: start..start + length,
+            body: Box::pin(response.bytes_stream().map_err(io::Error::other)),
+        })
+    }
+
+    async fn stat(&self, key: &str) -> io::Result<ObjectMeta> {
+        check_key(key)?;
+        if key.is_empty() || key.ends_with('/') {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Not found: {}", key)));
+        }
+        let response = self.send(Method::HEAD, &self.object_key(key), &[], HeaderMap::new(), None).await?;
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: content_length(&response),
+            modified: parse_http_date(response.headers().get(header::LAST_MODIFIED)),
+        })
+    }
+
+    async fn list(&self, prefix: &str, recursive: bool) -> io::Result<Listing> {
+        let mut listing = Listing::default();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2".to_string()), ("prefix", self.object_key(prefix))];
+            if !recursive {
+                query.push(("delimiter", "/".to_string()));
+            }
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.clone()));
+            }
+            let result: ListBucketResult = self.send_xml(Method::GET, "", &query, HeaderMap::new(), None).await?;
+
+            for object in result.contents {
+                let Some(key) = object.key.strip_prefix(&self.prefix) else { continue };
+                // skip "folder" placeholders made by other tools
+                if key.is_empty() || key.ends_with('/') {
+                    continue;
+                }
+                listing.objects.push(ObjectMeta {
+                    key: key.to_string(),
+                    size: object.size,
+                    modified: object
+                        .last_modified
+                        .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                        .map(SystemTime::from),
+                });
+            }
+            for common in result.common_prefixes {
+                if let Some(dir_prefix) = common.prefix.strip_prefix(&self.prefix) {
+                    listing.prefixes.push(dir_prefix.to_string());
+                }
+            }
+
+            match result.next_continuation_token {
+                Some(next) if result.is_truncated => token = Some(next),
+                _ => break,
+            }
+        }
+        Ok(listing)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        // S3 happily deletes keys that don't exist, callers want to know
+        self.stat(key).await?;
+        self.send(Method::DELETE, &self.object_key(key), &[], HeaderMap::new(), None).await?;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        check_key(to)?;
+        let meta = self.stat(from).await?;
+        self.copy(&self.object_key(from), &self.object_key(to), meta.size).await?;
+        self.send(Method::DELETE, &self.object_key(from), &[], HeaderMap::new(), None).await?;
+        Ok(())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+        for object in self.list(prefix, true).await?.objects {
+            self.send(Method::DELETE, &self.object_key(&object.key), &[], HeaderMap::new(), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn create_multipart(&self, key: &str, part_size: u64) -> io::Result<Option<String>> {
+        check_key(key)?;
+        if part_size < MIN_PART_SIZE {
+            return Ok(None);
+        }
+        self.start_upload(&self.object_key(key)).await.map(Some)
+    }
+
+    async fn put_part(&self, key: &str, upload_id: &str, part_number: u32, data: Bytes) -> io::Result<()> {
+        self.upload_part(&self.object_key(key), upload_id, part_number, data).await?;
+        Ok(())
+    }
+
+    async fn complete_multipart(&self, key: &str, upload_id: &str) -> io::Result<u64> {
+        let object_key = self.object_key(key);
+        let parts = self.list_parts(&object_key, upload_id).await?;
+        let size = parts.iter().map(|part| part.size).sum();
+        let etags: Vec<(u32, String)> = parts.into_iter().map(|part| (part.part_number, part.etag)).collect();
+        self.finish_upload(&object_key, upload_id, &etags).await?;
+        Ok(size)
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        self.cancel_upload(&self.object_key(key), upload_id).await
+    }
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use std::sync::Arc;
+
+use crate::handlers::persist_metadata;
+use crate::models::{
+    BatchTagRequest, BatchTagResponse, BatchTagResult, ErrorResponse, TagsRequest, TagsResponse,
+};
+use crate::state::AppState;
+use crate::storage::exists;
+use crate::utils::{normalize_tag, sanitize_filename};
+
+// add tags to a file
+pub async fn add_tags(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+    Json(payload): Json<TagsRequest>,
+) -> Result<Json<TagsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    update_file_tags(&state, &filename, &payload.tags, &[]).await
+}
+
+// remove tags from a file
+pub async fn remove_tags(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+    Json(payload): Json<TagsRequest>,
+) -> Result<Json<TagsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    update_file_tags(&state, &filename, &[], &payload.tags).await
+}
+
+// add and remove tags on many files at once
+pub async fn batch_tag_files(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<BatchTagRequest>,
+) -> Json<BatchTagResponse> {
+    let mut results = Vec::new();
+    let mut successful = 0;
+    let mut failed = 0;
+
+    for filename in payload.filenames {
+        let sanitized_filename = sanitize_filename(&filename);
+
+        if !exists(state.storage.as_ref(), &sanitized_filename).await.unwrap_or(false) {
+            tracing::warn!("❌ Cannot tag missing file: {}", sanitized_filename);
+            failed += 1;
+            results.push(BatchTagResult {
+                filename: sanitized_filename,
+                success: false,
+                tags: Vec::new(),
+                error: Some("File not found".to_string()),
+            });
+            continue;
+        }
+
+        let tags = apply_tags(&state, &sanitized_filename, &payload.add, &payload.remove);
+        successful += 1;
+        results.push(BatchTagResult {
+            filename: sanitized_filename,
+            success: true,
+            tags,
+            error: None,
+        });
+    }
+
+    if let Err(e) = state.metadata.persist().await {
+        // tags are applied in memory, so report it but don't fail every file
+        tracing::error!("Failed to persist metadata after batch tag: {}", e);
+    }
+
+    let total = results.len();
+    tracing::info!("🏷️  Batch tag completed: {}/{} successful", successful, total);
+
+    Json(BatchTagResponse {
+        total,
+        successful,
+        failed,
+        results,
+    })
+}
+
+// shared path for the single-file tag endpoints
+async fn update_file_tags(
+    state: &AppState,
+    filename: &str,
+    add: &[String],
+    remove: &[String],
+) -> Result<Json<TagsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let sanitized_filename = sanitize_filename(filename);
+
+    if !exists(state.storage.as_ref(), &sanitized_filename).await.unwrap_or(false) {
+        tracing::warn!("File not found for tagging: {}", sanitized_filename);
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: format!("File not found: {}", sanitized_filename),
+            }),
+        ));
+    }
+
+    let tags = apply_tags(state, &sanitized_filename, add, remove);
+
+    persist_metadata(state, "tags").await?;
+
+    tracing::info!("🏷️  Tags for {}: {:?}", sanitized_filename, tags);
+
+    Ok(Json(TagsResponse {
+        filename: sanitized_filename,
+        tags,
+    }))
+}
+
+// update tags in memory and return the resulting set
+fn apply_tags(state: &AppState, filename: &str, add: &[String], remove: &[String]) -> Vec<String> {
+    let updated = state.metadata.update(filename, |meta| {
+        for tag in add.iter().map(|t| normalize_tag(t)).filter(|t| !t.is_empty()) {
+            meta.tags.insert(tag);
+        }
+        for tag in remove.iter().map(|t| normalize_tag(t)) {
+            meta.tags.remove(&tag);
+        }
+    });
+    updated.tags.into_iter().collect()
+}
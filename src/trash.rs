@@ -0,0 +1,267 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::Utc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::MAX_TRASH_RETENTION_SECS;
+use crate::handlers::persist_metadata;
+use crate::metadata::TrashItem;
+use crate::models::{DeleteResponse, ErrorResponse, PurgeTrashResponse, RestoreResponse, TrashListResponse};
+use crate::state::AppState;
+
+/// hidden directory inside files_dir holding trashed files
+pub const TRASH_DIR: &str = ".trash";
+
+// storage key of a trashed file
+fn trash_key(id: &str) -> String {
+    format!("{}/{}", TRASH_DIR, id)
+}
+
+/// move a file into the trash, remembering its metadata so it can be restored
+///
+/// the caller is responsible for persisting the metadata store afterwards
+pub async fn move_to_trash(state: &AppState, filename: &str) -> std::io::Result<TrashItem> {
+    let file_meta = state.storage.stat(filename).await?;
+
+    let id = Uuid::new_v4().to_string();
+    state.storage.rename(filename, &trash_key(&id)).await?;
+
+    let item = TrashItem {
+        id: id.clone(),
+        original_name: filename.to_string(),
+        deleted_at: Utc::now(),
+        size: file_meta.size,
+        metadata: state.metadata.get(filename),
+        collections: state.metadata.collections_of(filename),
+    };
+    state.metadata.remove_file(filename);
+    state.hook_jobs.remove(filename);
+    state.metadata.trash.insert(id, item.clone());
+
+    tracing::debug!("Moved {} to trash as {}", filename, item.id);
+    Ok(item)
+}
+
+/// permanently delete a trashed file
+pub async fn purge_trash_item(state: &AppState, id: &str) -> std::io::Result<TrashItem> {
+    let (_, item) = state.metadata.trash.remove(id).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "Trash item not found")
+    })?;
+
+    match state.storage.delete(&trash_key(id)).await {
+        Ok(_) => {}
+        // already gone, nothing left to purge
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            state.metadata.trash.insert(id.to_string(), item);
+            return Err(e);
+        }
+    }
+
+    Ok(item)
+}
+
+/// purge trash items older than the retention period, returns how many were removed
+pub async fn purge_expired_trash(state: &AppState, retention: chrono::Duration) -> usize {
+    // a retention reaching back before any representable date keeps everything
+    let Some(cutoff) = Utc::now().checked_sub_signed(retention) else {
+        return 0;
+    };
+    let expired: Vec<String> = state
+        .metadata
+        .trash
+        .iter()
+        .filter(|item| item.deleted_at <= cutoff)
+        .map(|item| item.key().clone())
+        .collect();
+
+    let mut purged = 0;
+    for id in expired {
+        match purge_trash_item(state, &id).await {
+            Ok(item) => {
+                tracing::debug!("Purged {} ({}) from trash", item.original_name, id);
+                purged += 1;
+            }
+            Err(e) => tracing::warn!("Failed to purge trash item {}: {}", id, e),
+        }
+    }
+
+    if purged > 0 {
+        if let Err(e) = state.metadata.persist().await {
+            tracing::warn!("Failed to persist metadata after trash purge: {}", e);
+        }
+        tracing::info!("🧹 Emptied {} items from trash", purged);
+    }
+    purged
+}
+
+/// empty old trash items in the background forever
+pub fn spawn_trash_purger(
+    state: Arc<AppState>,
+    retention_secs: u64,
+    interval_secs: u64,
+) -> tokio::task::JoinHandle<()> {
+    let retention = i64::try_from(retention_secs.min(MAX_TRASH_RETENTION_SECS))
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+        .unwrap_or(chrono::Duration::MAX);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            purge_expired_trash(&state, retention).await;
+        }
+    })
+}
+
+// list everything in the trash, newest first
+pub async fn list_trash(
+    State(state): State<Arc<AppState>>,
+) -> Json<TrashListResponse> {
+    let mut items: Vec<TrashItem> = state
+        .metadata
+        .trash
+        .iter()
+        .map(|item| item.value().clone())
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+
+    let total = items.len();
+    let total_size = items.iter().map(|item| item.size).sum();
+    Json(TrashListResponse {
+        items,
+        total,
+        total_size,
+    })
+}
+
+// put a trashed file back where it was
+pub async fn restore_trash_item(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<RestoreResponse>, (StatusCode, Json<ErrorResponse>)> {
+    tracing::debug!("Request to restore trash item: {}", id);
+
+    let item = state
+        .metadata
+        .trash
+        .get(&id)
+        .map(|item| item.clone())
+        .ok_or_else(|| trash_item_not_found(&id))?;
+
+    // refused by the move itself, so a file landing there meanwhile isn't overwritten
+    state
+        .storage
+        .rename_new(&trash_key(&id), &item.original_name)
+        .await
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::AlreadyExists {
+                tracing::warn!("Cannot restore {}: a file with that name exists", item.original_name);
+                return (
+                    StatusCode::CONFLICT,
+                    Json(ErrorResponse {
+                        error: format!("File already exists: {}", item.original_name),
+                    }),
+                );
+            }
+            tracing::error!("Failed to restore {} from trash: {}", item.original_name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to restore file: {}", e),
+                }),
+            )
+        })?;
+
+    state.metadata.trash.remove(&id);
+    state
+        .metadata
+        .update(&item.original_name, |meta| *meta = item.metadata.clone());
+    for collection in &item.collections {
+        state
+            .metadata
+            .collections
+            .entry(collection.clone())
+            .or_default()
+            .insert(item.original_name.clone());
+    }
+    persist_metadata(&state, "trash state").await?;
+
+    tracing::info!("♻️  Restored {} from trash", item.original_name);
+
+    Ok(Json(RestoreResponse {
+        success: true,
+        id,
+        filename: item.original_name,
+    }))
+}
+
+// permanently delete a single trash item
+pub async fn delete_trash_item(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let item = purge_trash_item(&state, &id).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            return trash_item_not_found(&id);
+        }
+        tracing::error!("Failed to purge trash item {}: {}", id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to purge trash item: {}", e),
+            }),
+        )
+    })?;
+    persist_metadata(&state, "trash state").await?;
+
+    tracing::info!("🔥 Purged {} from trash", item.original_name);
+
+    Ok(Json(DeleteResponse {
+        success: true,
+        filename: item.original_name,
+    }))
+}
+
+// permanently delete everything in the trash
+pub async fn empty_trash(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PurgeTrashResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let ids: Vec<String> = state
+        .metadata
+        .trash
+        .iter()
+        .map(|item| item.key().clone())
+        .collect();
+
+    let mut purged = 0;
+    let mut failed = 0;
+    for id in ids {
+        match purge_trash_item(&state, &id).await {
+            Ok(_) => purged += 1,
+            Err(e) => {
+                tracing::warn!("Failed to purge trash item {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    persist_metadata(&state, "trash state").await?;
+
+    tracing::info!("🧹 Emptied trash: {} purged, {} failed", purged, failed);
+
+    Ok(Json(PurgeTrashResponse { purged, failed }))
+}
+
+fn trash_item_not_found(id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Trash item not found: {}", id);
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("Trash item not found: {}", id),
+        }),
+    )
+}
@@ -1,4 +1,5 @@
 use tokio::signal;
+use percent_encoding::percent_decode_str;
 
 // sanitize filename to prevent directory traversal attacks
 pub fn sanitize_filename(filename: &str) -> String {
@@ -10,6 +11,78 @@ pub fn sanitize_filename(filename: &str) -> String {
         .to_string()
 }
 
+// map a public request path like "/report%20v2.pdf" to the stored filename
+// (only top-level files have metadata, so nested paths give None)
+pub fn request_filename(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path.trim_start_matches('/')).decode_utf8().ok()?;
+    if decoded.is_empty() || sanitize_filename(&decoded) != decoded {
+        return None;
+    }
+    Some(decoded.into_owned())
+}
+
+// map a request path like "/docs/report%20v2.pdf" to a storage key ("docs/report v2.pdf"),
+// None if it isn't valid utf-8 or any segment is hidden (dot-prefixed) or unsafe
+pub fn request_key(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    safe_key(&decoded)
+}
+
+// normalize a storage key given by a client ("docs//report.pdf" -> "docs/report.pdf"),
+// None if any segment is hidden (dot-prefixed) or unsafe
+pub fn safe_key(key: &str) -> Option<String> {
+    let segments: Vec<&str> = key.split('/').filter(|s| !s.is_empty()).collect();
+    let safe = segments
+        .iter()
+        .all(|s| !s.starts_with('.') && !s.contains('\\') && !s.chars().any(char::is_control));
+    safe.then(|| segments.join("/"))
+}
+
+// match a name or path against a pattern where `*` stands for any run of characters
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// match a request path against a path rule's pattern: the whole path if the pattern starts with '/',
+// the file name otherwise
+pub fn matches_path(pattern: &str, path: &str) -> bool {
+    if pattern.starts_with('/') {
+        matches_glob(pattern, path)
+    } else {
+        matches_glob(pattern, path.rsplit('/').next().unwrap_or_default())
+    }
+}
+
+// attachment header with an ascii fallback and the utf-8 name (RFC 6266)
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded = percent_encoding::utf8_percent_encode(filename, percent_encoding::NON_ALPHANUMERIC);
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+// normalize a tag so "Release", " release " and "release" are the same tag
+pub fn normalize_tag(tag: &str) -> String {
+    sanitize_filename(tag.trim()).to_lowercase()
+}
+
 // graceful shutdown handler
 pub async fn shutdown_signal() {
     let ctrl_c = async {
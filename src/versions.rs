@@ -0,0 +1,314 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{Json, Response},
+};
+use chrono::Utc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::handlers::persist_metadata;
+use crate::metadata::VersionInfo;
+use crate::models::{
+    ErrorResponse, PruneVersionsRequest, PruneVersionsResponse, RestoreResponse,
+    VersionListResponse,
+};
+use crate::state::AppState;
+use crate::utils::{content_disposition, sanitize_filename};
+
+/// hidden directory inside files_dir holding previous file versions
+pub const VERSIONS_DIR: &str = ".versions";
+
+/// which directories keep old versions when files get overwritten
+#[derive(Clone, Debug, Default)]
+pub struct VersioningSettings {
+    /// directories relative to files_dir ("/" is the root, "*" is everything)
+    pub directories: Vec<String>,
+    /// versions kept per file, older ones are pruned automatically (0 = unlimited)
+    pub max_versions: usize,
+}
+
+impl VersioningSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            directories: config.versioned_dirs.clone(),
+            max_versions: config.max_versions,
+        }
+    }
+
+    /// whether overwriting this file should keep the old content
+    pub fn is_versioned(&self, filename: &str) -> bool {
+        let dir = std::path::Path::new(filename)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.directories.iter().any(|d| {
+            let d = d.trim_matches('/');
+            d == "*" || d == dir.trim_matches('/')
+        })
+    }
+}
+
+// storage key of a kept version
+fn version_key(id: &str) -> String {
+    format!("{}/{}", VERSIONS_DIR, id)
+}
+
+/// keep the current content of a file as a version before it gets overwritten
+///
+/// does nothing if versioning is off for the file's directory or the file doesn't exist yet
+pub async fn snapshot_version(state: &AppState, filename: &str) -> std::io::Result<Option<VersionInfo>> {
+    if !state.versioning.is_versioned(filename) {
+        return Ok(None);
+    }
+    let version = archive_current(state, filename).await?;
+    if version.is_some() {
+        if state.versioning.max_versions > 0 {
+            prune(state, filename, Some(state.versioning.max_versions), None).await;
+        }
+        state.metadata.persist().await?;
+    }
+    Ok(version)
+}
+
+// copy the current file into the versions directory
+//
+// a copy, not a move: the live file stays in place until its replacement has landed, so a
+// failed overwrite never leaves the file missing
+async fn archive_current(state: &AppState, filename: &str) -> std::io::Result<Option<VersionInfo>> {
+    let file_meta = match state.storage.stat(filename).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let version = VersionInfo {
+        id: Uuid::new_v4().to_string(),
+        archived_at: Utc::now(),
+        size: file_meta.size,
+    };
+    let current = state.storage.get(filename, None).await?;
+    state.storage.put(&version_key(&version.id), current.body).await?;
+
+    state
+        .metadata
+        .versions
+        .entry(filename.to_string())
+        .or_default()
+        .push(version.clone());
+
+    tracing::debug!("📜 Kept previous version of {} as {}", filename, version.id);
+    Ok(Some(version))
+}
+
+// drop versions beyond `keep` (newest first) or archived before `older_than`, returns how many went
+async fn prune(
+    state: &AppState,
+    filename: &str,
+    keep: Option<usize>,
+    older_than: Option<chrono::Duration>,
+) -> usize {
+    // an age reaching back before any representable date leaves nothing too old
+    let cutoff = older_than.and_then(|age| Utc::now().checked_sub_signed(age));
+    let doomed: Vec<VersionInfo> = {
+        let Some(mut versions) = state.metadata.versions.get_mut(filename) else {
+            return 0;
+        };
+
+        let excess = keep.map(|keep| versions.len().saturating_sub(keep)).unwrap_or(0);
+        let mut doomed = Vec::new();
+        let mut kept = Vec::new();
+        for (i, version) in versions.drain(..).enumerate() {
+            let too_old = cutoff.is_some_and(|cutoff| version.archived_at <= cutoff);
+            if i < excess || too_old {
+                doomed.push(version);
+            } else {
+                kept.push(version);
+            }
+        }
+        *versions = kept;
+        doomed
+    };
+    state.metadata.versions.remove_if(filename, |_, v| v.is_empty());
+
+    for version in &doomed {
+        if let Err(e) = state.storage.delete(&version_key(&version.id)).await {
+            tracing::warn!("Failed to remove version {} of {}: {}", version.id, filename, e);
+        }
+    }
+
+    if !doomed.is_empty() {
+        tracing::debug!("Pruned {} versions of {}", doomed.len(), filename);
+    }
+    doomed.len()
+}
+
+// list previous versions of a file, newest first
+pub async fn list_versions(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+) -> Json<VersionListResponse> {
+    let filename = sanitize_filename(&filename);
+    let mut versions = state
+        .metadata
+        .versions
+        .get(&filename)
+        .map(|v| v.clone())
+        .unwrap_or_default();
+    versions.reverse();
+
+    Json(VersionListResponse {
+        total: versions.len(),
+        filename,
+        versions,
+    })
+}
+
+// download a specific previous version
+pub async fn download_version(
+    State(state): State<Arc<AppState>>,
+    Path((filename, id)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    let version = find_version(&state, &filename, &id)?;
+
+    let object = state
+        .storage
+        .get(&version_key(&version.id), None)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to open version {} of {}: {}", id, filename, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to open version: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, version.size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition(filename.rsplit('/').next().unwrap_or_default()),
+        )
+        .body(Body::from_stream(object.body))
+        .unwrap())
+}
+
+// make a previous version the current one (the current content becomes a version)
+pub async fn restore_version(
+    State(state): State<Arc<AppState>>,
+    Path((filename, id)): Path<(String, String)>,
+) -> Result<Json<RestoreResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    let version = find_version(&state, &filename, &id)?;
+
+    let internal_error = |e: std::io::Error| {
+        tracing::error!("Failed to restore version {} of {}: {}", id, filename, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to restore version: {}", e),
+            }),
+        )
+    };
+
+    // always keep what's there now, even if versioning got switched off since
+    archive_current(&state, &filename).await.map_err(internal_error)?;
+
+    state
+        .storage
+        .rename(&version_key(&version.id), &filename)
+        .await
+        .map_err(internal_error)?;
+
+    if let Some(mut versions) = state.metadata.versions.get_mut(&filename) {
+        versions.retain(|v| v.id != version.id);
+    }
+    state.metadata.persist().await.map_err(internal_error)?;
+
+    tracing::info!("⏪ Restored version {} of {}", version.id, filename);
+
+    Ok(Json(RestoreResponse {
+        success: true,
+        id: version.id,
+        filename,
+    }))
+}
+
+// prune previous versions by count and/or age
+pub async fn prune_versions(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+    Json(payload): Json<PruneVersionsRequest>,
+) -> Result<Json<PruneVersionsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let filename = sanitize_filename(&filename);
+    if payload.keep.is_none() && payload.older_than.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Specify keep and/or older_than".to_string(),
+            }),
+        ));
+    }
+
+    let older_than = match payload.older_than {
+        Some(secs) => Some(
+            i64::try_from(secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .ok_or_else(|| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Invalid older_than: {}", secs),
+                        }),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    let pruned = prune(&state, &filename, payload.keep, older_than).await;
+
+    persist_metadata(&state, "versions").await?;
+
+    let remaining = state
+        .metadata
+        .versions
+        .get(&filename)
+        .map(|v| v.len())
+        .unwrap_or(0);
+    tracing::info!("✂️  Pruned {} versions of {} ({} left)", pruned, filename, remaining);
+
+    Ok(Json(PruneVersionsResponse {
+        filename,
+        pruned,
+        remaining,
+    }))
+}
+
+fn find_version(
+    state: &AppState,
+    filename: &str,
+    id: &str,
+) -> Result<VersionInfo, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .metadata
+        .versions
+        .get(filename)
+        .and_then(|versions| versions.iter().find(|v| v.id == id).cloned())
+        .ok_or_else(|| {
+            tracing::warn!("Version {} of {} not found", id, filename);
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: format!("Version not found: {}", id),
+                }),
+            )
+        })
+}
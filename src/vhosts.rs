@@ -0,0 +1,260 @@
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderValue, Request, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Router;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+use crate::archive::ArchiveSettings;
+use crate::buckets::{Bucket, DEFAULT_BUCKET};
+use crate::caching::CacheRules;
+use crate::config::{Config, VirtualHostConfig};
+use crate::deploy::DeploySettings;
+use crate::disposition::DispositionPolicy;
+use crate::fallback::FallbackSettings;
+use crate::listing::ListingSettings;
+use crate::middleware::SecurityHeaders;
+use crate::state::AppState;
+use crate::storage::{exists, Storage};
+
+/// how a virtual host serves its files
+#[derive(Clone, Debug)]
+pub struct VirtualHostSettings {
+    /// files tried in order for directory requests (empty = index.html)
+    pub index_files: Vec<String>,
+    /// Cache-Control sent with successful responses that don't set one
+    pub cache_control: Option<HeaderValue>,
+    /// send the security headers at all
+    pub security_headers: bool,
+    /// overrides for the root's Content-Security-Policy / X-Frame-Options
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    /// spa directories and error pages of its own (None = the root's)
+    pub spa_dirs: Option<Vec<String>>,
+    pub error_pages: Option<bool>,
+    /// directories with listings of its own (None = the root's)
+    pub listing_dirs: Option<Vec<String>>,
+    /// sandbox its files or not (None = the root's setting)
+    pub sandbox: Option<bool>,
+}
+
+impl Default for VirtualHostSettings {
+    fn default() -> Self {
+        Self {
+            index_files: Vec::new(),
+            cache_control: None,
+            security_headers: true,
+            content_security_policy: None,
+            frame_options: None,
+            spa_dirs: None,
+            error_pages: None,
+            listing_dirs: None,
+            sandbox: None,
+        }
+    }
+}
+
+impl VirtualHostSettings {
+    pub fn from_config(vhost: &VirtualHostConfig) -> Self {
+        Self {
+            index_files: vhost
+                .index_files
+                .iter()
+                .filter(|name| !name.contains('/') && !name.starts_with('.'))
+                .cloned()
+                .collect(),
+            cache_control: vhost.cache_control.as_deref().and_then(|value| {
+                HeaderValue::from_str(value)
+                    .map_err(|_| tracing::warn!("Ignoring invalid Cache-Control for vhost {}", vhost.name))
+                    .ok()
+            }),
+            security_headers: vhost.security_headers,
+            content_security_policy: vhost.content_security_policy.clone(),
+            frame_options: vhost.frame_options.clone(),
+            spa_dirs: vhost.spa_dirs.clone(),
+            error_pages: vhost.error_pages,
+            listing_dirs: vhost.listing_dirs.clone(),
+            sandbox: vhost.sandbox,
+        }
+    }
+
+    /// the root's security headers with this host's overrides
+    pub fn security_headers(&self, root: &SecurityHeaders) -> SecurityHeaders {
+        root.with_overrides(
+            self.security_headers,
+            self.content_security_policy.as_deref(),
+            self.frame_options.as_deref(),
+        )
+    }
+}
+
+/// what a virtual host serves
+#[derive(Clone)]
+pub enum VirtualHostRoot {
+    /// a bucket's files (the default bucket included)
+    Bucket(String),
+    /// a directory of its own
+    Dir(Arc<AppState>),
+}
+
+/// a set of host names mapped to a root on the public server
+#[derive(Clone)]
+pub struct VirtualHost {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub root: VirtualHostRoot,
+    /// deployed site of the root to serve instead of its files
+    pub site: Option<String>,
+    pub settings: VirtualHostSettings,
+}
+
+impl VirtualHost {
+    /// a directory root serves its files under the instance-wide serving settings
+    pub fn from_config(config: &Config, vhost: &VirtualHostConfig) -> Self {
+        let root = match (&vhost.root, &vhost.bucket) {
+            (Some(dir), _) => VirtualHostRoot::Dir(Arc::new(
+                AppState::new(dir.clone())
+                    .with_archives(ArchiveSettings::from_config(config))
+                    .with_deploy_settings(DeploySettings::from_config(config))
+                    .with_fallbacks(FallbackSettings::from_config(config))
+                    .with_listings(ListingSettings::from_config(config))
+                    .with_cache_rules(CacheRules::from_config(config))
+                    .with_disposition(DispositionPolicy::from_config(config)),
+            )),
+            (None, Some(bucket)) => VirtualHostRoot::Bucket(bucket.clone()),
+            (None, None) => VirtualHostRoot::Bucket(DEFAULT_BUCKET.to_string()),
+        };
+
+        Self {
+            name: vhost.name.clone(),
+            hosts: vhost.hosts.clone(),
+            root,
+            site: vhost.site.clone(),
+            settings: VirtualHostSettings::from_config(vhost),
+        }
+    }
+
+    /// the state whose files this host serves, None for unknown or private buckets
+    pub fn resolve(&self, state: &Arc<AppState>) -> Option<Arc<AppState>> {
+        match &self.root {
+            VirtualHostRoot::Dir(dir) => Some(dir.clone()),
+            VirtualHostRoot::Bucket(name) if name == DEFAULT_BUCKET => Some(state.clone()),
+            VirtualHostRoot::Bucket(name) => state
+                .buckets
+                .get(name)
+                .filter(|bucket: &&Bucket| bucket.public)
+                .map(|bucket| bucket.state.clone()),
+        }
+    }
+}
+
+/// all virtual hosts plus the one answering unknown hosts
+#[derive(Clone, Default)]
+pub struct VirtualHosts {
+    pub hosts: Vec<VirtualHost>,
+    /// name of the virtual host serving requests no host matched (None = the default bucket)
+    pub fallback: Option<String>,
+}
+
+impl VirtualHosts {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            hosts: config.vhosts.iter().map(|vhost| VirtualHost::from_config(config, vhost)).collect(),
+            fallback: config.default_vhost.clone(),
+        }
+    }
+}
+
+/// routers for the public server picked by the request's host name
+#[derive(Clone, Default)]
+pub struct HostRoutes {
+    pub by_host: HashMap<String, Router>,
+    pub fallback: Option<Router>,
+}
+
+/// host name of a request, lowercase and without the port
+pub fn request_host(req: &Request<Body>) -> Option<String> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri().host())?;
+
+    // strip the port (but keep ipv6 literals intact)
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    Some(host.trim_end_matches('.').to_lowercase())
+}
+
+/// hand requests to the router of the host they're for
+pub async fn route_by_host(
+    State(routes): State<Arc<HostRoutes>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let router = request_host(&req)
+        .and_then(|host| routes.by_host.get(&host).cloned())
+        .or_else(|| routes.fallback.clone());
+    match router {
+        Some(router) => router.oneshot(req).await.unwrap_or_else(|e| match e {}),
+        None => next.run(req).await,
+    }
+}
+
+/// where to look for a host's index files
+#[derive(Clone)]
+pub struct IndexFiles {
+    pub storage: Arc<dyn Storage>,
+    pub names: Vec<String>,
+}
+
+/// serve the first index file that exists for directory requests
+pub async fn serve_index_files(
+    State(index): State<Arc<IndexFiles>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let path = req.uri().path().to_string();
+    if !path.ends_with('/') {
+        return Ok(next.run(req).await);
+    }
+
+    let decoded = percent_decode_str(&path).decode_utf8_lossy().to_string();
+    let dir = decoded.trim_start_matches('/');
+    for name in &index.names {
+        let key = format!("{}{}", dir, name);
+        if !exists(index.storage.as_ref(), &key).await.unwrap_or(false) {
+            continue;
+        }
+
+        let mut target = format!("{}{}", path, utf8_percent_encode(name, NON_ALPHANUMERIC));
+        if let Some(query) = req.uri().query() {
+            target = format!("{}?{}", target, query);
+        }
+        *req.uri_mut() = target.parse::<Uri>().map_err(|_| StatusCode::BAD_REQUEST)?;
+        tracing::trace!("Serving index file {} for {}", name, path);
+        break;
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// set a Cache-Control header on successful responses that don't have one
+pub async fn add_cache_control(
+    State(value): State<HeaderValue>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let mut response = next.run(req).await;
+    let cacheable = response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED;
+    if cacheable && !response.headers().contains_key(header::CACHE_CONTROL) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    response
+}
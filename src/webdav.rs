@@ -0,0 +1,1080 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures_util::TryStreamExt;
+use quick_xml::events::Event;
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::buckets::{can_access, DEFAULT_BUCKET};
+use crate::caching::UploadHasher;
+use crate::config::Config;
+use crate::disk::is_disk_full;
+use crate::filetypes::unsupported_type;
+use crate::handlers::{check_content, promote_staged, record_upload_metadata, staging_key};
+use crate::hooks::run_hooks;
+use crate::middleware::{ApiKeyName, ApiKeyRegistry, ADMIN_KEY_NAME};
+use crate::quota::{reserve_upload, QuotaError};
+use crate::sigv4::uri_encode;
+use crate::state::{AppState, UploadSettings};
+use crate::storage::{concat, etag, http_date, requested_range, ByteStream, ObjectMeta, Storage};
+use crate::trash::move_to_trash;
+use crate::utils::request_key;
+
+/// longest a lock is held without being refreshed
+const MAX_LOCK_TIMEOUT_SECS: u64 = 60 * 60;
+const DAV_NAMESPACE: &str = "DAV:";
+const ALLOWED_METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+/// everything the WebDAV listener needs: the buckets it exposes, who may log in and the lock table
+pub struct WebDav {
+    /// every bucket by name, the default one included
+    buckets: BTreeMap<String, Arc<AppState>>,
+    admin_key_hash: String,
+    registry: ApiKeyRegistry,
+    /// active locks by token
+    locks: DashMap<String, DavLock>,
+}
+
+#[derive(Clone, Debug)]
+struct DavLock {
+    /// decoded path of the locked resource without a trailing slash, e.g. "/default/docs"
+    path: String,
+    /// depth infinity, covers everything below the path too
+    infinite: bool,
+    exclusive: bool,
+    /// text of the <owner> element the client sent
+    owner: String,
+    key_name: String,
+    timeout: Duration,
+    expires: Instant,
+}
+
+impl DavLock {
+    fn covers(&self, path: &str) -> bool {
+        self.path == path || (self.infinite && is_below(path, &self.path))
+    }
+}
+
+// whether `path` is strictly inside the collection at `parent`
+fn is_below(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent).is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl WebDav {
+    pub fn new(state: Arc<AppState>, config: &Config) -> Self {
+        let mut buckets: BTreeMap<String, Arc<AppState>> = state
+            .buckets
+            .values()
+            .map(|bucket| (bucket.name.clone(), bucket.state.clone()))
+            .collect();
+        buckets.insert(DEFAULT_BUCKET.to_string(), state);
+
+        Self {
+            buckets,
+            admin_key_hash: config.api_key_hash.clone(),
+            registry: ApiKeyRegistry(config.api_keys.clone()),
+            locks: DashMap::new(),
+        }
+    }
+
+    // the api key a user name / password pair belongs to (the user name is the key's name)
+    fn key_name(&self, user: &str, password: &str) -> Option<String> {
+        let hash = Config::hash_api_key(password);
+        let name = if hash == self.admin_key_hash {
+            ADMIN_KEY_NAME
+        } else {
+            self.registry.0.iter().find(|k| k.key_hash == hash)?.name.as_str()
+        };
+        (name == user).then(|| name.to_string())
+    }
+
+    // a bucket the key is allowed into
+    fn bucket(&self, name: &str, key_name: &str) -> Result<&Arc<AppState>, StatusCode> {
+        let state = self.buckets.get(name).ok_or(StatusCode::NOT_FOUND)?;
+        if !can_access(key_name, Some(&self.registry), name) {
+            tracing::warn!("🚫 API key {} is not allowed in bucket {}", key_name, name);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(state)
+    }
+
+    // what a decoded path ("/bucket/some/key") points at, None for the root
+    fn locate<'a>(&'a self, path: &'a str, key_name: &str) -> Result<Option<Location<'a>>, StatusCode> {
+        let Some(rest) = path.strip_prefix('/') else {
+            return Ok(None);
+        };
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        let state = self.bucket(bucket, key_name)?;
+        Ok(Some(Location { bucket, state, key }))
+    }
+
+    fn active_locks(&self) -> Vec<(String, DavLock)> {
+        let now = Instant::now();
+        self.locks.retain(|_, lock| lock.expires > now);
+        self.locks.iter().map(|l| (l.key().clone(), l.value().clone())).collect()
+    }
+
+    // refuse to change `path` (and everything below it with `recursive`) unless the request
+    // carries the token of every lock involved, each held by the requesting key
+    fn check_locks(&self, path: &str, recursive: bool, headers: &HeaderMap, key_name: &str) -> Result<(), StatusCode> {
+        let submitted = submitted_tokens(headers);
+        let all_held = self
+            .active_locks()
+            .into_iter()
+            .filter(|(_, lock)| lock.covers(path) || (recursive && is_below(&lock.path, path)))
+            .all(|(token, lock)| submitted.contains(&token) && lock.key_name == key_name);
+        if all_held {
+            return Ok(());
+        }
+        tracing::debug!("{} is locked", path);
+        Err(StatusCode::LOCKED)
+    }
+
+    // drop the locks of a resource that's gone
+    fn forget_locks(&self, path: &str) {
+        self.locks.retain(|_, lock| lock.path != path && !is_below(&lock.path, path));
+    }
+}
+
+/// a path inside a bucket
+struct Location<'a> {
+    bucket: &'a str,
+    state: &'a Arc<AppState>,
+    /// storage key, "" for the bucket itself
+    key: &'a str,
+}
+
+/// what sits at a location
+enum Resource {
+    Collection,
+    File(ObjectMeta),
+}
+
+// lock tokens listed in the If header, e.g. (<opaquelocktoken:...>)
+fn submitted_tokens(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("if")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split('<').skip(1))
+        .filter_map(|part| part.split_once('>').map(|(token, _)| token.to_string()))
+        .filter(|token| token.starts_with("opaquelocktoken:"))
+        .collect()
+}
+
+/// check Basic credentials (api key name and api key) on everything but OPTIONS
+pub async fn require_basic_auth(State(dav): State<Arc<WebDav>>, mut req: Request, next: Next) -> Response {
+    if req.method() == Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    let credentials = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|v| base64::engine::general_purpose::STANDARD.decode(v.trim()).ok())
+        .and_then(|v| String::from_utf8(v).ok());
+
+    let key_name = match credentials {
+        Some(credentials) => {
+            let (user, password) = credentials.split_once(':').unwrap_or((&credentials, ""));
+            let key_name = dav.key_name(user, password);
+            if key_name.is_none() {
+                tracing::warn!("🚫 Invalid WebDAV login for {}", user);
+            }
+            key_name
+        }
+        // clients try without credentials first
+        None => None,
+    };
+
+    match key_name {
+        Some(key_name) => {
+            req.extensions_mut().insert(ApiKeyName(key_name));
+            next.run(req).await
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"juicebox\", charset=\"UTF-8\"")],
+        )
+            .into_response(),
+    }
+}
+
+/// answer any WebDAV request
+pub async fn handle_webdav(
+    State(dav): State<Arc<WebDav>>,
+    key: Option<Extension<ApiKeyName>>,
+    req: Request,
+) -> Response {
+    if req.method() == Method::OPTIONS {
+        return options();
+    }
+    let Some(Extension(ApiKeyName(key_name))) = key else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    // paths never reach hidden files: they don't exist for reads and can't be created
+    let reading = matches!(req.method().as_str(), "GET" | "HEAD" | "PROPFIND");
+    let Some(key) = request_key(req.uri().path()) else {
+        tracing::debug!("Refusing hidden WebDAV path: {}", req.uri().path());
+        return if reading { StatusCode::NOT_FOUND } else { StatusCode::FORBIDDEN }.into_response();
+    };
+    let path = if key.is_empty() { String::new() } else { format!("/{}", key) };
+
+    let method = req.method().clone();
+    let result = match method.as_str() {
+        "GET" | "HEAD" => get(&dav, &key_name, &path, req).await,
+        "PROPFIND" => propfind(&dav, &key_name, &path, req).await,
+        "PROPPATCH" => proppatch(&dav, &key_name, &path, req).await,
+        "PUT" => put(&dav, &key_name, &path, req).await,
+        "DELETE" => delete(&dav, &key_name, &path, req.headers()).await,
+        "MKCOL" => mkcol(&dav, &key_name, &path, req).await,
+        "COPY" | "MOVE" => copy_or_move(&dav, &key_name, &path, req.headers(), method == "MOVE").await,
+        "LOCK" => lock(&dav, &key_name, &path, req).await,
+        "UNLOCK" => unlock(&dav, &key_name, &path, req.headers()),
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    result.unwrap_or_else(|status| status.into_response())
+}
+
+fn options() -> Response {
+    (
+        [
+            (header::HeaderName::from_static("dav"), "1, 2"),
+            (header::ALLOW, ALLOWED_METHODS),
+            (header::HeaderName::from_static("ms-author-via"), "DAV"),
+        ],
+        StatusCode::OK,
+    )
+        .into_response()
+}
+
+// turn a storage error into a status, logging the unexpected ones
+fn io_status(key: &str, e: io::Error) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        _ if is_disk_full(&e) => {
+            tracing::error!("Disk full while writing {}: {}", key, e);
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        _ => {
+            tracing::error!("WebDAV storage error for {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn quota_status(key: &str, e: QuotaError) -> StatusCode {
+    match e {
+        QuotaError::Exceeded(_) | QuotaError::NoSpace(_) => {
+            tracing::warn!("💾 Rejecting upload of {}: {}", key, e);
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        QuotaError::Io(_) => {
+            tracing::error!("Failed to check quota for {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// the collection or file at a key, None if there's nothing
+async fn resource(storage: &dyn Storage, key: &str) -> Result<Option<Resource>, StatusCode> {
+    if key.is_empty() {
+        return Ok(Some(Resource::Collection));
+    }
+    match storage.stat(key).await {
+        Ok(meta) => Ok(Some(Resource::File(meta))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let exists = collection_exists(storage, key).await.map_err(|e| io_status(key, e))?;
+            Ok(exists.then_some(Resource::Collection))
+        }
+        Err(e) => Err(io_status(key, e)),
+    }
+}
+
+// local directories exist even when empty, elsewhere a collection is any prefix with objects under it
+async fn collection_exists(storage: &dyn Storage, key: &str) -> io::Result<bool> {
+    if let Some(root) = storage.local_root() {
+        return Ok(tokio::fs::metadata(root.join(key)).await.is_ok_and(|m| m.is_dir()));
+    }
+    let listing = storage.list(&format!("{}/", key), false).await?;
+    Ok(!listing.objects.is_empty() || !listing.prefixes.is_empty())
+}
+
+// whether the collection a new resource would go into exists
+async fn parent_exists(storage: &dyn Storage, key: &str) -> Result<bool, StatusCode> {
+    let parent = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+    Ok(matches!(resource(storage, parent).await?, Some(Resource::Collection)))
+}
+
+// ---------------------------------------------------------------------------
+// reading
+// ---------------------------------------------------------------------------
+
+async fn get(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?.ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    let meta = match resource(location.state.storage.as_ref(), location.key).await? {
+        Some(Resource::File(meta)) => meta,
+        Some(Resource::Collection) => return Err(StatusCode::METHOD_NOT_ALLOWED),
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+    let range = requested_range(req.headers(), meta.size).map_err(|_| StatusCode::RANGE_NOT_SATISFIABLE)?;
+
+    let content_type = mime_guess::from_path(&meta.key).first_or_octet_stream();
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type.as_ref())
+        .header(header::ETAG, etag(&meta))
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = meta.modified {
+        builder = builder.header(header::LAST_MODIFIED, http_date(modified));
+    }
+    if let Some(range) = &range {
+        builder = builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, meta.size));
+    }
+    let length = range.as_ref().map_or(meta.size, |range| range.end - range.start);
+    builder = builder.header(header::CONTENT_LENGTH, length);
+
+    if req.method() == Method::HEAD {
+        return Ok(builder.body(Body::empty()).unwrap());
+    }
+    let object = location
+        .state
+        .storage
+        .get(location.key, range)
+        .await
+        .map_err(|e| io_status(location.key, e))?;
+    Ok(builder.body(Body::from_stream(object.body)).unwrap())
+}
+
+/// a property name as (namespace, local name)
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PropName {
+    namespace: String,
+    name: String,
+}
+
+/// the parts of a request body we care about
+#[derive(Debug, Default)]
+struct DavBody {
+    /// local names of every DAV: element, e.g. "allprop", "exclusive"
+    elements: Vec<String>,
+    /// properties listed inside <prop> elements
+    props: Vec<PropName>,
+    /// text inside <owner>
+    owner: String,
+}
+
+impl DavBody {
+    fn has(&self, element: &str) -> bool {
+        self.elements.iter().any(|e| e == element)
+    }
+}
+
+fn parse_body(body: &[u8]) -> Result<DavBody, StatusCode> {
+    let mut reader = NsReader::from_reader(body);
+    reader.config_mut().trim_text(true);
+    let mut parsed = DavBody::default();
+    let mut open: Vec<PropName> = Vec::new();
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event().map_err(|e| {
+            tracing::debug!("Invalid WebDAV request body: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        let namespace = match namespace {
+            ResolveResult::Bound(ns) => String::from_utf8_lossy(ns.as_ref()).to_string(),
+            _ => String::new(),
+        };
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let element = PropName {
+                    namespace,
+                    name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+                };
+                if open.last().is_some_and(|parent| parent.namespace == DAV_NAMESPACE && parent.name == "prop") {
+                    parsed.props.push(element.clone());
+                }
+                if element.namespace == DAV_NAMESPACE {
+                    parsed.elements.push(element.name.clone());
+                }
+                if matches!(event, Event::Start(_)) {
+                    open.push(element);
+                }
+            }
+            Event::End(_) => {
+                open.pop();
+            }
+            Event::Text(text) if open.iter().any(|e| e.namespace == DAV_NAMESPACE && e.name == "owner") => {
+                let text = text.unescape().map_err(|_| StatusCode::BAD_REQUEST)?;
+                parsed.owner.push_str(&text);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(parsed)
+}
+
+// one entry of a PROPFIND response
+struct Entry {
+    /// decoded path, collections without the trailing slash
+    path: String,
+    collection: bool,
+    meta: Option<ObjectMeta>,
+}
+
+async fn propfind(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let depth = req.headers().get("depth").and_then(|v| v.to_str().ok()).unwrap_or("infinity");
+    let recurse = match depth {
+        "0" => false,
+        "1" => true,
+        _ => {
+            return Ok(xml_response(
+                StatusCode::FORBIDDEN,
+                "<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>".to_string(),
+            ))
+        }
+    };
+    let body = axum::body::to_bytes(req.into_body(), 64 * 1024)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let request = parse_body(&body)?;
+
+    let mut entries = Vec::new();
+    match dav.locate(path, key_name)? {
+        // the root holds the buckets this key may use
+        None => {
+            entries.push(Entry { path: String::new(), collection: true, meta: None });
+            if recurse {
+                entries.extend(
+                    dav.buckets
+                        .keys()
+                        .filter(|name| can_access(key_name, Some(&dav.registry), name))
+                        .map(|name| Entry { path: format!("/{}", name), collection: true, meta: None }),
+                );
+            }
+        }
+        Some(location) => {
+            let storage = location.state.storage.as_ref();
+            match resource(storage, location.key).await? {
+                None => return Err(StatusCode::NOT_FOUND),
+                Some(Resource::File(meta)) => {
+                    entries.push(Entry { path: path.to_string(), collection: false, meta: Some(meta) })
+                }
+                Some(Resource::Collection) => {
+                    entries.push(Entry { path: path.to_string(), collection: true, meta: None });
+                    if recurse {
+                        let prefix = if location.key.is_empty() { String::new() } else { format!("{}/", location.key) };
+                        let listing = storage.list(&prefix, false).await.map_err(|e| io_status(location.key, e))?;
+                        let visible = |key: &str| !key.split('/').any(|segment| segment.starts_with('.'));
+                        let bucket_path = format!("/{}", location.bucket);
+                        for dir in listing.prefixes.iter().filter(|p| visible(p)) {
+                            let path = format!("{}/{}", bucket_path, dir.trim_end_matches('/'));
+                            entries.push(Entry { path, collection: true, meta: None });
+                        }
+                        for object in listing.objects.into_iter().filter(|o| visible(&o.key)) {
+                            let path = format!("{}/{}", bucket_path, object.key);
+                            entries.push(Entry { path, collection: false, meta: Some(object) });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let locks = dav.active_locks();
+    let mut xml = String::from("<D:multistatus xmlns:D=\"DAV:\">");
+    for entry in &entries {
+        let props = live_props(entry, &locks);
+        xml.push_str("<D:response>");
+        xml.push_str(&format!("<D:href>{}</D:href>", escape(&href(&entry.path, entry.collection))));
+        if request.has("propname") {
+            let names: String = props.iter().map(|(name, _)| format!("<D:{}/>", name)).collect();
+            xml.push_str(&propstat(&names, StatusCode::OK));
+        } else if request.props.is_empty() {
+            // allprop, or an empty body which means the same
+            let values: String = props.iter().map(|(name, value)| dav_element(name, value)).collect();
+            xml.push_str(&propstat(&values, StatusCode::OK));
+        } else {
+            let mut found = String::new();
+            let mut missing = String::new();
+            for prop in &request.props {
+                let value = props
+                    .iter()
+                    .find(|(name, _)| prop.namespace == DAV_NAMESPACE && prop.name == *name);
+                match value {
+                    Some((name, value)) => found.push_str(&dav_element(name, value)),
+                    None => missing.push_str(&empty_element(prop)),
+                }
+            }
+            if !found.is_empty() {
+                xml.push_str(&propstat(&found, StatusCode::OK));
+            }
+            if !missing.is_empty() {
+                xml.push_str(&propstat(&missing, StatusCode::NOT_FOUND));
+            }
+        }
+        xml.push_str("</D:response>");
+    }
+    xml.push_str("</D:multistatus>");
+
+    Ok(xml_response(StatusCode::MULTI_STATUS, xml))
+}
+
+// live properties of a resource as (DAV: local name, inner xml)
+fn live_props(entry: &Entry, locks: &[(String, DavLock)]) -> Vec<(&'static str, String)> {
+    let name = entry.path.rsplit('/').next().unwrap_or_default();
+    let mut props = vec![
+        ("displayname", escape(name)),
+        ("resourcetype", if entry.collection { "<D:collection/>".to_string() } else { String::new() }),
+    ];
+    if let Some(meta) = &entry.meta {
+        let content_type = mime_guess::from_path(&meta.key).first_or_octet_stream();
+        props.push(("getcontentlength", meta.size.to_string()));
+        props.push(("getcontenttype", escape(content_type.as_ref())));
+        props.push(("getetag", escape(&etag(meta))));
+        if let Some(modified) = meta.modified {
+            props.push(("getlastmodified", http_date(modified)));
+            props.push(("creationdate", DateTime::<Utc>::from(modified).to_rfc3339()));
+        }
+    }
+    props.push((
+        "supportedlock",
+        "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+         <D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>"
+            .to_string(),
+    ));
+    let active: String = locks
+        .iter()
+        .filter(|(_, lock)| lock.covers(&entry.path))
+        .map(|(token, lock)| active_lock(token, lock))
+        .collect();
+    props.push(("lockdiscovery", active));
+    props
+}
+
+fn propstat(props: &str, status: StatusCode) -> String {
+    format!(
+        "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+        props, status
+    )
+}
+
+fn dav_element(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("<D:{}/>", name)
+    } else {
+        format!("<D:{0}>{1}</D:{0}>", name, value)
+    }
+}
+
+// a property we don't have, in its own namespace
+fn empty_element(prop: &PropName) -> String {
+    if prop.namespace == DAV_NAMESPACE {
+        format!("<D:{}/>", prop.name)
+    } else {
+        format!("<x:{} xmlns:x=\"{}\"/>", prop.name, escape(&prop.namespace))
+    }
+}
+
+fn escape(value: &str) -> String {
+    quick_xml::escape::escape(value).to_string()
+}
+
+// url of a decoded path, collections end in '/'
+fn href(path: &str, collection: bool) -> String {
+    let mut href = uri_encode(path, false);
+    if collection {
+        href.push('/');
+    }
+    if href.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{}", body),
+    )
+        .into_response()
+}
+
+// properties can't be stored, but clients (Windows in particular) give up on files whose
+// PROPPATCH fails, so report them as set
+async fn proppatch(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?.ok_or(StatusCode::FORBIDDEN)?;
+    if resource(location.state.storage.as_ref(), location.key).await?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    dav.check_locks(path, false, req.headers(), key_name)?;
+
+    let body = axum::body::to_bytes(req.into_body(), 64 * 1024)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let request = parse_body(&body)?;
+    let props: String = request.props.iter().map(empty_element).collect();
+    let collection = location.key.is_empty() || location.state.storage.stat(location.key).await.is_err();
+
+    Ok(xml_response(
+        StatusCode::MULTI_STATUS,
+        format!(
+            "<D:multistatus xmlns:D=\"DAV:\"><D:response><D:href>{}</D:href>{}</D:response></D:multistatus>",
+            escape(&href(path, collection)),
+            propstat(&props, StatusCode::OK)
+        ),
+    ))
+}
+
+// ---------------------------------------------------------------------------
+// writing
+// ---------------------------------------------------------------------------
+
+// declared body size, Finder sends X-Expected-Entity-Length with chunked uploads
+fn declared_size(headers: &HeaderMap) -> Option<u64> {
+    [header::CONTENT_LENGTH.as_str(), "x-expected-entity-length"]
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()))
+}
+
+async fn put(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?.ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    let (state, key) = (location.state, location.key);
+    let storage = state.storage.as_ref();
+
+    let existed = match resource(storage, key).await? {
+        Some(Resource::Collection) => return Err(StatusCode::METHOD_NOT_ALLOWED),
+        Some(Resource::File(_)) => true,
+        None => false,
+    };
+    if !parent_exists(storage, key).await? {
+        return Err(StatusCode::CONFLICT);
+    }
+    dav.check_locks(path, false, req.headers(), key_name)?;
+    // chunked bodies without a declared length can't be checked against quotas up front
+    let size = declared_size(req.headers())
+        .or_else(|| req.body().size_hint().exact())
+        .ok_or(StatusCode::LENGTH_REQUIRED)?;
+
+    let body: ByteStream = Box::pin(req.into_body().into_data_stream().map_err(io::Error::other));
+    let meta = write_file(state, key_name, key, body, size).await?;
+    tracing::info!("✅ Uploaded {} to bucket {} over WebDAV ({} bytes)", key, location.bucket, meta.size);
+
+    let status = if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED };
+    Ok((status, [(header::ETAG, etag(&meta))]).into_response())
+}
+
+// store a file the way every upload path does: check space, quota and content, snapshot, store, record the owner
+async fn write_file(
+    state: &Arc<AppState>,
+    owner: &str,
+    key: &str,
+    body: ByteStream,
+    size: u64,
+) -> Result<ObjectMeta, StatusCode> {
+    state.upload_types.check_extension(key).map_err(|e| unsupported_type(key, e).0)?;
+    let _reservation = reserve_upload(state, Some(owner), size, Some(key))
+        .await
+        .map_err(|e| quota_status(key, e))?;
+
+    let mut settings = UploadSettings {
+        owner: Some(owner.to_string()),
+        ..Default::default()
+    };
+
+    // written out of sight first, it only replaces the current file once it's been checked
+    let staged = staging_key();
+    let hasher = UploadHasher::default();
+    let written = state.storage.put(&staged, hasher.wrap(body)).await;
+    settings.sha256 = Some(hasher.finish());
+    let placed = match written {
+        Ok(written) if written != size => {
+            tracing::warn!("Upload of {} was {} bytes instead of {}", key, written, size);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Ok(_) => {
+            let content = || concat(state.storage.clone(), vec![staged.clone()]);
+            match check_content(state, key, &mut settings, content).await {
+                Ok(()) => promote_staged(state, &staged, key).await.map_err(|e| io_status(key, e)),
+                Err((status, _)) => Err(status),
+            }
+        }
+        Err(e) => Err(io_status(key, e)),
+    };
+    if let Err(status) = placed {
+        let _ = state.storage.delete(&staged).await;
+        return Err(status);
+    }
+
+    record_upload_metadata(state, key, &settings).await.map_err(|(status, _)| status)?;
+    run_hooks(state, key);
+    state.storage.stat(key).await.map_err(|e| io_status(key, e))
+}
+
+async fn delete(dav: &WebDav, key_name: &str, path: &str, headers: &HeaderMap) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?.ok_or(StatusCode::FORBIDDEN)?;
+    if location.key.is_empty() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let resource = resource(location.state.storage.as_ref(), location.key)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    dav.check_locks(path, true, headers, key_name)?;
+
+    delete_resource(location.state, location.key, &resource).await?;
+    if let Err(e) = location.state.metadata.persist().await {
+        tracing::warn!("Failed to persist metadata after deleting {}: {}", location.key, e);
+    }
+    dav.forget_locks(path);
+    tracing::info!("🗑️  Deleted {} over WebDAV", location.key);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// move a file, or everything in a collection, to the trash
+async fn delete_resource(state: &AppState, key: &str, resource: &Resource) -> Result<(), StatusCode> {
+    match resource {
+        Resource::File(_) => {
+            move_to_trash(state, key).await.map_err(|e| io_status(key, e))?;
+        }
+        Resource::Collection => {
+            let prefix = format!("{}/", key);
+            let listing = state.storage.list(&prefix, true).await.map_err(|e| io_status(key, e))?;
+            for object in listing.objects {
+                move_to_trash(state, &object.key).await.map_err(|e| io_status(&object.key, e))?;
+            }
+            state.storage.remove_dir(&prefix).await.map_err(|e| io_status(key, e))?;
+        }
+    }
+    Ok(())
+}
+
+async fn mkcol(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?.ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    if location.key.is_empty() {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if declared_size(req.headers()).is_some_and(|size| size > 0) {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let storage = location.state.storage.as_ref();
+    if resource(storage, location.key).await?.is_some() {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if !parent_exists(storage, location.key).await? {
+        return Err(StatusCode::CONFLICT);
+    }
+    dav.check_locks(path, false, req.headers(), key_name)?;
+
+    storage
+        .create_dir(&format!("{}/", location.key))
+        .await
+        .map_err(|e| io_status(location.key, e))?;
+    tracing::info!("📁 Created collection {} over WebDAV", location.key);
+    Ok(StatusCode::CREATED.into_response())
+}
+
+// the decoded path of a Destination header, which may be a full url or just a path
+fn destination(headers: &HeaderMap) -> Result<String, StatusCode> {
+    let value = headers
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let raw_path = match value.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+        None => value,
+    };
+    let key = request_key(raw_path).ok_or(StatusCode::FORBIDDEN)?;
+    Ok(if key.is_empty() { String::new() } else { format!("/{}", key) })
+}
+
+async fn copy_or_move(
+    dav: &WebDav,
+    key_name: &str,
+    path: &str,
+    headers: &HeaderMap,
+    is_move: bool,
+) -> Result<Response, StatusCode> {
+    let source = dav.locate(path, key_name)?.ok_or(StatusCode::FORBIDDEN)?;
+    let dest_path = destination(headers)?;
+    let dest = dav.locate(&dest_path, key_name)?.ok_or(StatusCode::FORBIDDEN)?;
+    if source.key.is_empty() || dest.key.is_empty() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // onto itself, into itself or over one of its parents
+    if dest_path == path || is_below(&dest_path, path) || is_below(path, &dest_path) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let overwrite = headers.get("overwrite").and_then(|v| v.to_str().ok()) != Some("F");
+    let depth = headers.get("depth").and_then(|v| v.to_str().ok()).unwrap_or("infinity");
+    let shallow = match depth {
+        "infinity" => false,
+        "0" if !is_move => true,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let source_storage = source.state.storage.as_ref();
+    let dest_storage = dest.state.storage.as_ref();
+    let copied = resource(source_storage, source.key).await?.ok_or(StatusCode::NOT_FOUND)?;
+    if !parent_exists(dest_storage, dest.key).await? {
+        return Err(StatusCode::CONFLICT);
+    }
+    if is_move {
+        dav.check_locks(path, true, headers, key_name)?;
+    }
+    dav.check_locks(&dest_path, true, headers, key_name)?;
+
+    let replaced = match resource(dest_storage, dest.key).await? {
+        Some(_) if !overwrite => return Err(StatusCode::PRECONDITION_FAILED),
+        Some(existing) => {
+            delete_resource(dest.state, dest.key, &existing).await?;
+            true
+        }
+        None => false,
+    };
+
+    match &copied {
+        Resource::File(meta) => transfer(&source, &dest, source.key, dest.key, meta.size, key_name, is_move).await?,
+        Resource::Collection => {
+            dest_storage
+                .create_dir(&format!("{}/", dest.key))
+                .await
+                .map_err(|e| io_status(dest.key, e))?;
+            if !shallow {
+                let prefix = format!("{}/", source.key);
+                let listing = source_storage.list(&prefix, true).await.map_err(|e| io_status(source.key, e))?;
+                for object in listing.objects {
+                    let relative = &object.key[prefix.len()..];
+                    let target = format!("{}/{}", dest.key, relative);
+                    transfer(&source, &dest, &object.key, &target, object.size, key_name, is_move).await?;
+                }
+            }
+            if is_move {
+                source_storage.remove_dir(&format!("{}/", source.key)).await.map_err(|e| io_status(source.key, e))?;
+            }
+        }
+    }
+
+    for state in [source.state, dest.state] {
+        if let Err(e) = state.metadata.persist().await {
+            tracing::warn!("Failed to persist metadata after WebDAV {}: {}", if is_move { "move" } else { "copy" }, e);
+        }
+    }
+    if is_move {
+        dav.forget_locks(path);
+    }
+    tracing::info!(
+        "{} {} to {} over WebDAV",
+        if is_move { "🚚 Moved" } else { "📄 Copied" },
+        path,
+        dest_path
+    );
+
+    let status = if replaced { StatusCode::NO_CONTENT } else { StatusCode::CREATED };
+    Ok(status.into_response())
+}
+
+// copy or move one file between locations (possibly in different buckets)
+async fn transfer(
+    source: &Location<'_>,
+    dest: &Location<'_>,
+    from: &str,
+    to: &str,
+    size: u64,
+    key_name: &str,
+    is_move: bool,
+) -> Result<(), StatusCode> {
+    let same_bucket = Arc::ptr_eq(source.state, dest.state);
+
+    if is_move && same_bucket {
+        // a rename keeps the file's settings, owner and collections, but not a refused extension
+        let state = source.state;
+        state.upload_types.check_extension(to).map_err(|e| unsupported_type(to, e).0)?;
+        state.storage.rename(from, to).await.map_err(|e| io_status(from, e))?;
+        let meta = state.metadata.get(from);
+        for name in state.metadata.collections_of(from) {
+            if let Some(mut collection) = state.metadata.collections.get_mut(&name) {
+                collection.insert(to.to_string());
+            }
+        }
+        state.metadata.remove_file(from);
+        state.metadata.update(to, |m| *m = meta);
+        state.hook_jobs.remove(from);
+        return Ok(());
+    }
+
+    let object = source.state.storage.get(from, None).await.map_err(|e| io_status(from, e))?;
+    if is_move {
+        // the file keeps its owner in the new bucket
+        let meta = source.state.metadata.get(from);
+        let owner = meta.owner.clone().unwrap_or_else(|| key_name.to_string());
+        write_file(dest.state, &owner, to, object.body, size).await?;
+        dest.state.metadata.update(to, |m| *m = meta);
+        source.state.storage.delete(from).await.map_err(|e| io_status(from, e))?;
+        source.state.metadata.remove_file(from);
+        source.state.hook_jobs.remove(from);
+    } else {
+        write_file(dest.state, key_name, to, object.body, size).await?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// locking
+// ---------------------------------------------------------------------------
+
+// requested lock lifetime from a Timeout header like "Second-600" or "Infinite, Second-4100000000"
+fn lock_timeout(headers: &HeaderMap) -> Duration {
+    let requested = headers
+        .get("timeout")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .and_then(|v| v.strip_prefix("Second-"))
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(MAX_LOCK_TIMEOUT_SECS);
+    Duration::from_secs(requested.clamp(1, MAX_LOCK_TIMEOUT_SECS))
+}
+
+fn active_lock(token: &str, lock: &DavLock) -> String {
+    let remaining = lock.expires.saturating_duration_since(Instant::now()).as_secs();
+    format!(
+        "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope>\
+         <D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>Second-{}</D:timeout>\
+         <D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+        if lock.exclusive { "<D:exclusive/>" } else { "<D:shared/>" },
+        if lock.infinite { "infinity" } else { "0" },
+        escape(&lock.owner),
+        remaining,
+        escape(token),
+        escape(&href(&lock.path, false)),
+    )
+}
+
+fn lock_response(status: StatusCode, token: &str, lock: &DavLock) -> Response {
+    let mut response = xml_response(
+        status,
+        format!(
+            "<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>",
+            active_lock(token, lock)
+        ),
+    );
+    if let Ok(value) = HeaderValue::from_str(&format!("<{}>", token)) {
+        response.headers_mut().insert("lock-token", value);
+    }
+    response
+}
+
+async fn lock(dav: &WebDav, key_name: &str, path: &str, req: Request) -> Result<Response, StatusCode> {
+    let location = dav.locate(path, key_name)?;
+    let headers = req.headers().clone();
+    let body = axum::body::to_bytes(req.into_body(), 64 * 1024)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let timeout = lock_timeout(&headers);
+
+    // an empty body refreshes a lock named in the If header
+    if body.iter().all(u8::is_ascii_whitespace) {
+        let submitted = submitted_tokens(&headers);
+        dav.active_locks();
+        let token = submitted
+            .iter()
+            .find(|token| {
+                dav.locks
+                    .get(*token)
+                    .is_some_and(|lock| lock.covers(path) && lock.key_name == key_name)
+            })
+            .ok_or(StatusCode::PRECONDITION_FAILED)?;
+        let mut lock = dav.locks.get_mut(token).ok_or(StatusCode::PRECONDITION_FAILED)?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+        return Ok(lock_response(StatusCode::OK, token, &lock));
+    }
+
+    let request = parse_body(&body)?;
+    if !request.has("lockinfo") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let infinite = match headers.get("depth").and_then(|v| v.to_str().ok()).unwrap_or("infinity") {
+        "infinity" => true,
+        "0" => false,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let new_lock = DavLock {
+        path: path.to_string(),
+        infinite,
+        exclusive: !request.has("shared"),
+        owner: request.owner.trim().to_string(),
+        key_name: key_name.to_string(),
+        timeout,
+        expires: Instant::now() + timeout,
+    };
+
+    // an exclusive lock can't overlap any other lock, a shared one only other shared ones
+    let conflict = dav.active_locks().into_iter().any(|(_, lock)| {
+        let overlaps = lock.covers(path) || (infinite && is_below(&lock.path, path));
+        overlaps && (lock.exclusive || new_lock.exclusive)
+    });
+    if conflict {
+        tracing::debug!("Refusing to lock {}, it's already locked", path);
+        return Err(StatusCode::LOCKED);
+    }
+
+    // locking a name nobody uses yet creates an empty file there
+    let mut status = StatusCode::OK;
+    if let Some(location) = location.filter(|l| !l.key.is_empty()) {
+        let storage = location.state.storage.as_ref();
+        if resource(storage, location.key).await?.is_none() {
+            if !parent_exists(storage, location.key).await? {
+                return Err(StatusCode::CONFLICT);
+            }
+            let empty: ByteStream = Box::pin(futures_util::stream::empty());
+            write_file(location.state, key_name, location.key, empty, 0).await?;
+            status = StatusCode::CREATED;
+        }
+    }
+
+    let token = format!("opaquelocktoken:{}", Uuid::new_v4());
+    dav.locks.insert(token.clone(), new_lock.clone());
+    tracing::debug!("🔒 {} locked {} ({})", key_name, path, token);
+    Ok(lock_response(status, &token, &new_lock))
+}
+
+fn unlock(dav: &WebDav, key_name: &str, path: &str, headers: &HeaderMap) -> Result<Response, StatusCode> {
+    let token = headers
+        .get("lock-token")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    dav.active_locks();
+    let removed = dav
+        .locks
+        .remove_if(&token, |_, lock| lock.covers(path) && (lock.key_name == key_name || key_name == ADMIN_KEY_NAME));
+    if removed.is_none() {
+        return Err(StatusCode::CONFLICT);
+    }
+    tracing::debug!("🔓 {} unlocked {}", key_name, path);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -0,0 +1,223 @@
+use juicebox_omega::archive::{create_archive_link, download_archive, ArchiveFormat, ArchiveSettings};
+use juicebox_omega::buckets::BucketName;
+use juicebox_omega::models::{ArchiveLinkRequest, ArchiveRequest};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Extension, State};
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use axum::Json;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+fn state_with_files(dir: &std::path::Path, archives: ArchiveSettings) -> Arc<AppState> {
+    let files = [
+        ("photos/2024/beach.jpg", "sand and sea"),
+        ("photos/2024/nested/deep.txt", "deep"),
+        ("photos/cover page.png", "cover"),
+        ("photos/.hidden", "secret"),
+        ("notes.txt", "remember the milk"),
+    ];
+    for (name, content) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    Arc::new(AppState::new(dir.to_path_buf()).with_archives(archives))
+}
+
+async fn body_bytes(response: Response) -> Vec<u8> {
+    to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+}
+
+fn u16_at(data: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([data[at], data[at + 1]]) as usize
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+// read a zip through its central directory, checking every entry's crc
+fn unzip(data: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let end = data.len() - 22;
+    assert_eq!(u32_at(data, end), 0x06054b50);
+    let count = u16_at(data, end + 10);
+    let mut at = u32_at(data, end + 16) as usize;
+
+    let mut files = BTreeMap::new();
+    for _ in 0..count {
+        assert_eq!(u32_at(data, at), 0x02014b50);
+        let crc = u32_at(data, at + 16);
+        let size = u32_at(data, at + 24) as usize;
+        let name_len = u16_at(data, at + 28);
+        let extra_len = u16_at(data, at + 30);
+        let offset = u32_at(data, at + 42) as usize;
+        let name = String::from_utf8(data[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+        assert_eq!(u32_at(data, offset), 0x04034b50);
+        let start = offset + 30 + u16_at(data, offset + 26) + u16_at(data, offset + 28);
+        let content = data[start..start + size].to_vec();
+        assert_eq!(crc32fast::hash(&content), crc, "{}", name);
+        assert_eq!(u32_at(data, start + size), 0x08074b50);
+        assert_eq!(u32_at(data, start + size + 4), crc);
+
+        files.insert(name, content);
+        at += 46 + name_len + extra_len;
+    }
+    files
+}
+
+// read a tar, following pax path records
+fn untar(data: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut at = 0;
+    let mut long_name = None;
+    while data[at..at + 512].iter().any(|&b| b != 0) {
+        let header = &data[at..at + 512];
+        let name = String::from_utf8_lossy(&header[..100]).trim_end_matches('\0').to_string();
+        let size = usize::from_str_radix(String::from_utf8_lossy(&header[124..135]).trim(), 8).unwrap();
+        let content = data[at + 512..at + 512 + size].to_vec();
+        at += 512 + size.div_ceil(512) * 512;
+        if header[156] == b'x' {
+            let records = String::from_utf8(content).unwrap();
+            long_name = records.lines().find_map(|r| r.split_once(" path=").map(|(_, p)| p.to_string()));
+            continue;
+        }
+        files.insert(long_name.take().unwrap_or(name), content);
+    }
+    files
+}
+
+#[tokio::test]
+async fn test_zip_archive_of_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = state_with_files(temp_dir.path(), ArchiveSettings::default());
+    state.metadata.update("photos/cover page.png", |m| m.expires_at = Some(chrono::Utc::now() - chrono::Duration::hours(1)));
+
+    let request = ArchiveRequest { dir: Some("photos".to_string()), ..Default::default() };
+    let response = download_archive(State(state.clone()), Json(request)).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/zip");
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"photos.zip\"; filename*=UTF-8''photos%2Ezip"
+    );
+    let length: usize = response.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+
+    let data = body_bytes(response).await;
+    assert_eq!(data.len(), length);
+    let files = unzip(&data);
+    // hidden and expired files stay out
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["photos/2024/beach.jpg", "photos/2024/nested/deep.txt"]);
+    assert_eq!(files["photos/2024/beach.jpg"], b"sand and sea");
+}
+
+#[tokio::test]
+async fn test_tar_archives_of_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = state_with_files(temp_dir.path(), ArchiveSettings::default());
+    let long_name = format!("{}/file.txt", "very-long-directory-name".repeat(5));
+    std::fs::create_dir_all(temp_dir.path().join(long_name.rsplit_once('/').unwrap().0)).unwrap();
+    std::fs::write(temp_dir.path().join(&long_name), "long").unwrap();
+
+    let files = vec!["notes.txt".to_string(), "photos/cover page.png".to_string(), long_name.clone()];
+    let request = ArchiveRequest {
+        files: files.clone(),
+        format: ArchiveFormat::Tar,
+        name: Some("Ünïcode \"report\".tar".to_string()),
+        ..Default::default()
+    };
+    let response = download_archive(State(state.clone()), Json(request)).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-tar");
+    let disposition = response.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().to_string();
+    assert!(disposition.starts_with("attachment; filename=\"_n_code report.tar\""), "{}", disposition);
+    let length: usize = response.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+    let data = body_bytes(response).await;
+    assert_eq!(data.len(), length);
+    let tar = untar(&data);
+    assert_eq!(tar.len(), 3);
+    assert_eq!(tar["photos/cover page.png"], b"cover");
+    assert_eq!(tar[&long_name], b"long");
+
+    // the gzipped variant has the same content, but no length up front
+    let request = ArchiveRequest { files, format: ArchiveFormat::TarGz, ..Default::default() };
+    let response = download_archive(State(state.clone()), Json(request)).await.unwrap();
+    assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+    assert!(response.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().contains("files.tar.gz"));
+    let mut unzipped = Vec::new();
+    flate2::read::GzDecoder::new(&body_bytes(response).await[..]).read_to_end(&mut unzipped).unwrap();
+    assert_eq!(untar(&unzipped), tar);
+}
+
+#[tokio::test]
+async fn test_archive_request_errors() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = state_with_files(temp_dir.path(), ArchiveSettings { max_files: 2, link_key: None });
+
+    let cases = [
+        (ArchiveRequest::default(), StatusCode::BAD_REQUEST),
+        (
+            ArchiveRequest { files: vec!["notes.txt".to_string()], dir: Some("photos".to_string()), ..Default::default() },
+            StatusCode::BAD_REQUEST,
+        ),
+        (ArchiveRequest { files: vec!["../etc/passwd".to_string()], ..Default::default() }, StatusCode::BAD_REQUEST),
+        (ArchiveRequest { files: vec![".meta/metadata.json".to_string()], ..Default::default() }, StatusCode::BAD_REQUEST),
+        (ArchiveRequest { files: vec!["missing.txt".to_string()], ..Default::default() }, StatusCode::NOT_FOUND),
+        (ArchiveRequest { dir: Some("nowhere".to_string()), ..Default::default() }, StatusCode::NOT_FOUND),
+        (ArchiveRequest { dir: Some("/".to_string()), ..Default::default() }, StatusCode::PAYLOAD_TOO_LARGE),
+    ];
+    for (request, status) in cases {
+        let err = download_archive(State(state.clone()), Json(request.clone())).await.unwrap_err();
+        assert_eq!(err.0, status, "{:?}", request);
+    }
+
+    // links need a signing key
+    let request = ArchiveLinkRequest {
+        archive: ArchiveRequest { files: vec!["notes.txt".to_string()], ..Default::default() },
+        expires_in: None,
+    };
+    let err = create_archive_link(State(state), Extension(BucketName("default".to_string())), Json(request))
+        .await
+        .unwrap_err();
+    assert_eq!(err.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_signed_archive_links() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let archives = ArchiveSettings { max_files: 100, link_key: Some(Arc::new(b"link-secret".to_vec())) };
+    let state = state_with_files(temp_dir.path(), archives);
+    // burn-after-reading files don't go out through links
+    state.metadata.update("photos/2024/nested/deep.txt", |m| m.max_downloads = Some(1));
+
+    let request = ArchiveLinkRequest {
+        archive: ArchiveRequest { dir: Some("photos/2024".to_string()), ..Default::default() },
+        expires_in: Some(600),
+    };
+    let link = create_archive_link(State(state.clone()), Extension(BucketName("default".to_string())), Json(request))
+        .await
+        .unwrap();
+    assert!(link.url.starts_with("/.archive?token="));
+    assert_eq!(link.files, 1);
+
+    let app = build_public_router(state.clone());
+    let get = |uri: String| Request::builder().uri(uri).header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(get(link.url.clone())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    // it gets the same security headers as any other download
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    assert!(response.headers().contains_key("x-frame-options"));
+    let files = unzip(&body_bytes(response).await);
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["2024/beach.jpg"]);
+
+    // tampering with the link breaks the signature
+    let tampered = link.url.replacen("token=", "token=x", 1);
+    let response = app.clone().oneshot(get(tampered)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app.oneshot(get("/.archive?token=nope".to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
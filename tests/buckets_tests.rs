@@ -0,0 +1,91 @@
+use juicebox_omega::buckets::{can_access, list_buckets, Bucket};
+use juicebox_omega::config::{ApiKeyConfig, BucketConfig, Config};
+use juicebox_omega::middleware::{ApiKeyName, ApiKeyRegistry};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Extension, State};
+use axum::http::{Request, StatusCode};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+fn bucket(root: &std::path::Path, name: &str, public: bool, hosts: &[&str]) -> Bucket {
+    let config = BucketConfig {
+        name: name.to_string(),
+        root: root.join(name),
+        public,
+        hosts: hosts.iter().map(|h| h.to_string()).collect(),
+        quota: Default::default(),
+    };
+    std::fs::create_dir_all(&config.root).unwrap();
+    Bucket::from_config(&Config::from_env(), &config)
+}
+
+fn registry() -> ApiKeyRegistry {
+    ApiKeyRegistry(vec![ApiKeyConfig {
+        name: "blogger".to_string(),
+        key_hash: Config::hash_api_key("blogkey"),
+        quota: Default::default(),
+        buckets: vec!["blog".to_string()],
+    }])
+}
+
+async fn fetch(app: &axum::Router, uri: &str, host: &str) -> (StatusCode, String) {
+    let request = Request::builder().uri(uri).header("Host", host).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_public_router_serves_buckets() {
+    let files = tempfile::tempdir().unwrap();
+    let buckets = tempfile::tempdir().unwrap();
+    std::fs::write(files.path().join("index.txt"), "default").unwrap();
+
+    let blog = bucket(buckets.path(), "blog", true, &["blog.example.com"]);
+    let internal = bucket(buckets.path(), "internal", false, &["internal.example.com"]);
+    std::fs::write(blog.state.files_dir.join("index.txt"), "blog").unwrap();
+    std::fs::write(internal.state.files_dir.join("index.txt"), "internal").unwrap();
+    std::fs::create_dir_all(blog.state.files_dir.join(".meta")).unwrap();
+    std::fs::write(blog.state.files_dir.join(".meta/metadata.json"), "{}").unwrap();
+
+    let state = Arc::new(AppState::new(files.path().to_path_buf()).with_buckets(vec![blog, internal]));
+    let app = build_public_router(state);
+
+    let ok = |body: &str| (StatusCode::OK, body.to_string());
+    assert_eq!(fetch(&app, "/index.txt", "localhost").await, ok("default"));
+    assert_eq!(fetch(&app, "/b/blog/index.txt", "localhost").await, ok("blog"));
+    assert_eq!(fetch(&app, "/index.txt", "Blog.Example.com:4848").await, ok("blog"));
+
+    // private buckets stay off the public server, hidden paths stay hidden inside buckets
+    assert_eq!(fetch(&app, "/b/internal/index.txt", "localhost").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/index.txt", "internal.example.com").await, ok("default"));
+    assert_eq!(fetch(&app, "/b/blog/.meta/metadata.json", "localhost").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_bucket_scoped_keys() {
+    let registry = registry();
+    assert!(can_access("admin", Some(&registry), "anything"));
+    assert!(can_access("blogger", Some(&registry), "blog"));
+    assert!(!can_access("blogger", Some(&registry), "default"));
+    assert!(!can_access("stranger", Some(&registry), "blog"));
+
+    let files = tempfile::tempdir().unwrap();
+    let buckets = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(files.path().to_path_buf()).with_buckets(vec![
+        bucket(buckets.path(), "blog", true, &[]),
+        bucket(buckets.path(), "shop", false, &[]),
+    ]));
+
+    let key = |name: &str| Some(Extension(ApiKeyName(name.to_string())));
+    let response = list_buckets(State(state.clone()), key("admin"), Some(Extension(registry.clone()))).await;
+    let names: Vec<_> = response.0.buckets.iter().map(|b| b.name.as_str()).collect();
+    assert_eq!(names, vec!["default", "blog", "shop"]);
+
+    let response = list_buckets(State(state), key("blogger"), Some(Extension(registry))).await;
+    assert_eq!(response.0.total, 1);
+    assert_eq!(response.0.buckets[0].name, "blog");
+}
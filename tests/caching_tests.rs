@@ -0,0 +1,218 @@
+use juicebox_omega::caching::CacheRules;
+use juicebox_omega::fallback::FallbackSettings;
+use juicebox_omega::handlers::complete_chunked_upload;
+use juicebox_omega::models::ChunkedUploadComplete;
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use juicebox_omega::storage::{put_bytes, ByteStream, Listing, MemoryStorage, ObjectMeta, ObjectStream, Storage};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::{Json, Router};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+use common::fetch;
+
+fn rules(list: &[(&str, &str)]) -> CacheRules {
+    let list: Vec<(String, String)> = list.iter().map(|(p, v)| (p.to_string(), v.to_string())).collect();
+    CacheRules::new(&list)
+}
+
+// files written behind our back get their etag once the background hash is done
+async fn wait_for_etag(app: &Router, uri: &str) -> String {
+    for _ in 0..100 {
+        if let Some(etag) = fetch(app, uri, &[]).await.1.get(header::ETAG) {
+            return etag.to_str().unwrap().to_string();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no etag for {}", uri);
+}
+
+#[test]
+fn test_cache_rule_matching() {
+    let rules = rules(&[
+        ("/assets/*", "public, max-age=31536000, immutable"),
+        ("*.html", "no-cache"),
+        ("*.bad", "bad\nvalue"),
+        ("/*.json", "max-age=60"),
+    ]);
+    assert_eq!(rules.rules.len(), 3);
+    assert_eq!(rules.value_for("/assets/app.3f9a1c.js").unwrap(), "public, max-age=31536000, immutable");
+    // the first match wins
+    assert_eq!(rules.value_for("/assets/about.html").unwrap(), "public, max-age=31536000, immutable");
+    assert_eq!(rules.value_for("/docs/guide/index.html").unwrap(), "no-cache");
+    assert_eq!(rules.value_for("/data/feed.json").unwrap(), "max-age=60");
+    assert!(rules.value_for("/static/assets/app.js").is_none());
+    assert!(rules.value_for("/page.htm").is_none());
+    assert!(CacheRules::default().is_empty());
+}
+
+#[tokio::test]
+async fn test_uploads_get_strong_etags() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let chunks_dir = temp_dir.path().join(".chunks/upload-1");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), "hello ").unwrap();
+    std::fs::write(chunks_dir.join("chunk_1"), "world").unwrap();
+    let upload = ChunkedUploadMetadata {
+        filename: "greeting.txt".to_string(),
+        total_size: 11,
+        chunk_size: 6,
+        total_chunks: 2,
+        received_chunks: [0, 1].into_iter().collect(),
+        ..Default::default()
+    };
+    state.chunked_uploads.insert("upload-1".to_string(), upload);
+    let complete = ChunkedUploadComplete { upload_id: "upload-1".to_string() };
+    assert!(complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap().success);
+
+    let sha256 = hex::encode(Sha256::digest(b"hello world"));
+    assert_eq!(state.metadata.get("greeting.txt").hash.unwrap().sha256, sha256);
+    // it's saved with the rest of the metadata
+    let restarted = AppState::new(temp_dir.path().to_path_buf());
+    assert_eq!(restarted.metadata.get("greeting.txt").hash.unwrap().sha256, sha256);
+
+    let app = build_public_router(state);
+    let etag = format!("\"{}\"", sha256);
+    let (status, headers, body) = fetch(&app, "/greeting.txt", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "hello world"));
+    assert_eq!(headers[header::ETAG], etag.as_str());
+
+    let (status, headers, body) = fetch(&app, "/greeting.txt", &[(header::IF_NONE_MATCH, &etag)]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[header::ETAG], etag.as_str());
+    assert!(headers.contains_key(header::LAST_MODIFIED));
+    assert!(body.is_empty());
+    let weak_list = format!("\"other\", W/{}", etag);
+    assert_eq!(fetch(&app, "/greeting.txt", &[(header::IF_NONE_MATCH, &weak_list)]).await.0, StatusCode::NOT_MODIFIED);
+    // If-None-Match wins over If-Modified-Since
+    let stale = [(header::IF_NONE_MATCH, "\"other\""), (header::IF_MODIFIED_SINCE, "Sun, 01 Jan 2090 00:00:00 GMT")];
+    assert_eq!(fetch(&app, "/greeting.txt", &stale).await.0, StatusCode::OK);
+
+    // ranges only apply to the content If-Range names
+    let (status, _, body) = fetch(&app, "/greeting.txt", &[(header::RANGE, "bytes=0-4"), (header::IF_RANGE, &etag)]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::PARTIAL_CONTENT, "hello"));
+    let (status, _, body) = fetch(&app, "/greeting.txt", &[(header::RANGE, "bytes=0-4"), (header::IF_RANGE, "\"old\"")]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "hello world"));
+}
+
+// memory storage that counts reads of one key
+struct CountingStorage {
+    inner: MemoryStorage,
+    key: &'static str,
+    reads: AtomicUsize,
+}
+
+#[async_trait]
+impl Storage for CountingStorage {
+    async fn put(&self, key: &str, body: ByteStream) -> std::io::Result<u64> {
+        self.inner.put(key, body).await
+    }
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> std::io::Result<ObjectStream> {
+        if key == self.key {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+        }
+        self.inner.get(key, range).await
+    }
+    async fn stat(&self, key: &str) -> std::io::Result<ObjectMeta> {
+        self.inner.stat(key).await
+    }
+    async fn list(&self, prefix: &str, recursive: bool) -> std::io::Result<Listing> {
+        self.inner.list(prefix, recursive).await
+    }
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.inner.delete(key).await
+    }
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        self.inner.rename(from, to).await
+    }
+}
+
+#[tokio::test]
+async fn test_uploads_are_hashed_as_they_are_written() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(CountingStorage { inner: MemoryStorage::new(), key: "greeting.txt", reads: AtomicUsize::new(0) });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage.clone()));
+    put_bytes(storage.as_ref(), ".chunks/upload-1/chunk_0", Bytes::from("hello ")).await.unwrap();
+    put_bytes(storage.as_ref(), ".chunks/upload-1/chunk_1", Bytes::from("world")).await.unwrap();
+    state.chunked_uploads.insert("upload-1".to_string(), ChunkedUploadMetadata {
+        filename: "greeting.txt".to_string(),
+        total_size: 11,
+        chunk_size: 6,
+        total_chunks: 2,
+        received_chunks: [0, 1].into_iter().collect(),
+        ..Default::default()
+    });
+    let complete = ChunkedUploadComplete { upload_id: "upload-1".to_string() };
+    assert!(complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap().success);
+
+    // the hash is there without the stored file being read back
+    let hash = state.metadata.get("greeting.txt").hash.unwrap();
+    assert_eq!((hash.sha256, hash.size), (hex::encode(Sha256::digest(b"hello world")), 11));
+    assert_eq!(storage.reads.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_other_files_are_hashed_on_first_request() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("docs")).unwrap();
+    std::fs::write(temp_dir.path().join("docs/index.html"), "v1").unwrap();
+    let app = build_public_router(Arc::new(AppState::new(temp_dir.path().to_path_buf())));
+
+    let first = wait_for_etag(&app, "/docs/").await;
+    assert_eq!(first, format!("\"{}\"", hex::encode(Sha256::digest(b"v1"))));
+    assert_eq!(fetch(&app, "/docs/index.html", &[]).await.1[header::ETAG], first.as_str());
+
+    // a changed file doesn't keep the old etag
+    std::fs::write(temp_dir.path().join("docs/index.html"), "version 2").unwrap();
+    let (status, headers, body) = fetch(&app, "/docs/", &[(header::IF_NONE_MATCH, &first)]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "version 2"));
+    assert_ne!(headers.get(header::ETAG).map(|v| v.to_str().unwrap()), Some(first.as_str()));
+    assert_ne!(wait_for_etag(&app, "/docs/").await, first);
+
+    // dates work with or without a hash
+    let since = |date| [(header::IF_MODIFIED_SINCE, date)];
+    assert_eq!(fetch(&app, "/docs/", &since("Sun, 01 Jan 2090 00:00:00 GMT")).await.0, StatusCode::NOT_MODIFIED);
+    assert_eq!(fetch(&app, "/docs/", &since("Sat, 01 Jan 2000 00:00:00 GMT")).await.0, StatusCode::OK);
+    assert_eq!(fetch(&app, "/docs/", &since("not a date")).await.0, StatusCode::OK);
+    assert_eq!(fetch(&app, "/missing.txt", &[(header::IF_NONE_MATCH, "*")]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_cache_control_rules() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for (name, content) in [("index.html", "home"), ("assets/app.3f9a1c.js", "js"), ("app/index.html", "shell"), ("data.txt", "d")] {
+        let path = temp_dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    let state = AppState::new(temp_dir.path().to_path_buf())
+        .with_cache_rules(rules(&[("/assets/*", "public, max-age=31536000, immutable"), ("*.html", "no-cache")]))
+        .with_fallbacks(FallbackSettings::new(&["/app".to_string()], false));
+    let app = build_public_router(Arc::new(state));
+
+    let cache_control = |headers: HeaderMap| headers.get(header::CACHE_CONTROL).map(|v| v.to_str().unwrap().to_string());
+    assert_eq!(
+        cache_control(fetch(&app, "/assets/app.3f9a1c.js", &[]).await.1).as_deref(),
+        Some("public, max-age=31536000, immutable"),
+    );
+    assert_eq!(cache_control(fetch(&app, "/", &[]).await.1).as_deref(), Some("no-cache"));
+    assert_eq!(cache_control(fetch(&app, "/index.html", &[]).await.1).as_deref(), Some("no-cache"));
+    // spa routes go by the index they're answered with
+    assert_eq!(cache_control(fetch(&app, "/app/settings", &[]).await.1).as_deref(), Some("no-cache"));
+    // and revalidations keep the header
+    let (status, headers, _) = fetch(&app, "/", &[(header::IF_MODIFIED_SINCE, "Sun, 01 Jan 2090 00:00:00 GMT")]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(cache_control(headers).as_deref(), Some("no-cache"));
+
+    assert_eq!(cache_control(fetch(&app, "/data.txt", &[]).await.1), None);
+    assert_eq!(cache_control(fetch(&app, "/assets/missing.js", &[]).await.1), None);
+}
@@ -0,0 +1,128 @@
+use juicebox_omega::collections::{add_to_collection, collection_bulk_action, get_collection};
+use juicebox_omega::handlers::list_files;
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::tags::{add_tags, batch_tag_files, remove_tags};
+use juicebox_omega::state::AppState;
+use juicebox_omega::models::{
+    BatchTagRequest, CollectionBulkAction, CollectionBulkRequest, CollectionFilesRequest,
+    ListFilesQuery, TagsRequest,
+};
+use axum::body::Body;
+use axum::extract::{State, Path, Query};
+use axum::Json;
+use axum::http::{Request, StatusCode};
+use std::sync::Arc;
+use std::fs::File;
+use tower::util::ServiceExt;
+
+fn setup(files: &[&str]) -> (tempfile::TempDir, Arc<AppState>) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for name in files {
+        File::create(temp_dir.path().join(name)).unwrap();
+    }
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    (temp_dir, state)
+}
+
+#[tokio::test]
+async fn test_tags_add_remove_and_filter() {
+    let (_temp_dir, state) = setup(&["a.txt", "b.txt"]);
+
+    let response = add_tags(
+        State(state.clone()),
+        Path("a.txt".to_string()),
+        Json(TagsRequest { tags: vec!["Release".to_string(), "v1".to_string()] }),
+    ).await.unwrap();
+    assert_eq!(response.0.tags, vec!["release", "v1"]);
+
+    // filter listing by tag
+    let query = ListFilesQuery { tag: Some("release".to_string()), collection: None };
+    let listing = list_files(State(state.clone()), Query(query)).await.unwrap();
+    assert_eq!(listing.0.total, 1);
+    assert_eq!(listing.0.files[0].name, "a.txt");
+
+    let response = remove_tags(
+        State(state.clone()),
+        Path("a.txt".to_string()),
+        Json(TagsRequest { tags: vec!["release".to_string()] }),
+    ).await.unwrap();
+    assert_eq!(response.0.tags, vec!["v1"]);
+
+    // tagging a missing file is a 404
+    let result = add_tags(
+        State(state.clone()),
+        Path("missing.txt".to_string()),
+        Json(TagsRequest { tags: vec!["x".to_string()] }),
+    ).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_batch_tag_and_persistence() {
+    let (temp_dir, state) = setup(&["a.txt", "b.txt"]);
+
+    let payload = BatchTagRequest {
+        filenames: vec!["a.txt".to_string(), "b.txt".to_string(), "c.txt".to_string()],
+        add: vec!["screenshots".to_string()],
+        remove: vec![],
+    };
+    let response = batch_tag_files(State(state.clone()), Json(payload)).await;
+    assert_eq!(response.0.successful, 2);
+    assert_eq!(response.0.failed, 1);
+
+    // a fresh state picks the tags back up from disk
+    let reloaded = AppState::new(temp_dir.path().to_path_buf());
+    assert!(reloaded.metadata.get("b.txt").tags.contains("screenshots"));
+}
+
+#[tokio::test]
+async fn test_collection_bulk_delete() {
+    let (temp_dir, state) = setup(&["a.txt", "b.txt", "keep.txt"]);
+
+    let payload = CollectionFilesRequest {
+        filenames: vec!["a.txt".to_string(), "b.txt".to_string()],
+    };
+    let response = add_to_collection(State(state.clone()), Path("temp".to_string()), Json(payload)).await.unwrap();
+    assert_eq!(response.0.total, 2);
+
+    let query = ListFilesQuery { tag: None, collection: Some("temp".to_string()) };
+    let listing = list_files(State(state.clone()), Query(query)).await.unwrap();
+    assert_eq!(listing.0.total, 2);
+
+    let payload = CollectionBulkRequest { action: CollectionBulkAction::Delete, expires_at: None };
+    let response = collection_bulk_action(State(state.clone()), Path("temp".to_string()), Json(payload)).await.unwrap();
+    assert_eq!(response.0.successful, 2);
+
+    assert!(!temp_dir.path().join("a.txt").exists());
+    assert!(!temp_dir.path().join("b.txt").exists());
+    assert!(temp_dir.path().join("keep.txt").exists());
+
+    // collection is gone after deleting everything in it
+    let result = get_collection(State(state.clone()), Path("temp".to_string())).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_collection_bulk_expire() {
+    let (temp_dir, state) = setup(&["a.txt", "b.txt"]);
+
+    let payload = CollectionFilesRequest { filenames: vec!["a.txt".to_string(), "b.txt".to_string()] };
+    let response = add_to_collection(State(state.clone()), Path("shares".to_string()), Json(payload)).await.unwrap();
+    assert_eq!(response.0.files, vec!["a.txt", "b.txt"]);
+    std::fs::remove_file(temp_dir.path().join("b.txt")).unwrap();
+
+    // members whose file is gone are reported, not counted as expired
+    let payload = CollectionBulkRequest { action: CollectionBulkAction::Expire, expires_at: None };
+    let response = collection_bulk_action(State(state.clone()), Path("shares".to_string()), Json(payload)).await.unwrap();
+    assert_eq!((response.0.successful, response.0.failed), (1, 1));
+    assert!(state.metadata.get("a.txt").expires_at.is_some());
+    let missing = &response.0.results[1];
+    assert_eq!((missing.filename.as_str(), missing.success), ("b.txt", false));
+    assert_eq!(missing.error.as_deref(), Some("File not found: b.txt"));
+    assert!(!state.metadata.files.contains_key("b.txt"));
+
+    // and the expired file is no longer served
+    let app = build_public_router(state.clone());
+    let request = Request::builder().uri("/a.txt").body(Body::empty()).unwrap();
+    assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::GONE);
+}
@@ -1,4 +1,4 @@
-use juicebox_omega::config::Config;
+use juicebox_omega::config::{Config, SecurityHeaderValues, MAX_TRASH_RETENTION_SECS};
 use std::env;
 
 // helper to clear env vars
@@ -13,6 +13,84 @@ fn clear_env() {
     env::remove_var("ADMIN_API_KEY");
     env::remove_var("CORS_ORIGINS");
     env::remove_var("RATE_LIMIT_PER_MINUTE");
+    env::remove_var("EXPIRY_SWEEP_INTERVAL");
+    env::remove_var("TRASH_RETENTION");
+    env::remove_var("TRASH_PURGE_INTERVAL");
+    env::remove_var("VERSIONED_DIRS");
+    env::remove_var("MAX_VERSIONS");
+    env::remove_var("API_KEYS");
+    env::remove_var("QUOTA_MAX_BYTES");
+    env::remove_var("QUOTA_MAX_FILES");
+    env::remove_var("DISK_RESERVE");
+    env::remove_var("HEALTH_MIN_FREE");
+    env::remove_var("BUCKETS");
+    env::remove_var("BUCKETS_DIR");
+    env::remove_var("BUCKET_SHOP_APP_PUBLIC");
+    env::remove_var("BUCKET_SHOP_APP_HOSTS");
+    env::remove_var("BUCKET_BLOG_ROOT");
+    env::remove_var("BUCKET_BLOG_QUOTA_MAX_BYTES");
+    env::remove_var("VHOSTS");
+    env::remove_var("DEFAULT_VHOST");
+    env::remove_var("VHOST_CDN_HOSTS");
+    env::remove_var("VHOST_CDN_ROOT");
+    env::remove_var("VHOST_CDN_INDEX");
+    env::remove_var("VHOST_CDN_CACHE_CONTROL");
+    env::remove_var("VHOST_CDN_SECURITY_HEADERS");
+    env::remove_var("VHOST_BLOG_SITE");
+    env::remove_var("VHOST_BLOG_SPA_DIRS");
+    env::remove_var("SPA_DIRS");
+    env::remove_var("ERROR_PAGES");
+    env::remove_var("LISTING_DIRS");
+    env::remove_var("LISTING_HIDE");
+    env::remove_var("CACHE_RULES");
+    for name in ["CSP", "FRAME_OPTIONS", "HSTS", "REFERRER_POLICY", "PERMISSIONS_POLICY", "COOP", "COEP", "CORP"] {
+        env::remove_var(format!("SECURITY_{}", name));
+        env::remove_var(format!("SECURITY_PATH_EMBEDS_{}", name));
+    }
+    env::remove_var("SECURITY_PATHS");
+    env::remove_var("SECURITY_PATH_EMBEDS_PATTERN");
+    env::remove_var("SECURITY_HEADERS_ADMIN");
+    env::remove_var("DISPOSITION_ATTACHMENT");
+    env::remove_var("DISPOSITION_SANDBOX");
+    env::remove_var("DISPOSITION_SANDBOX_CSP");
+    env::remove_var("UPLOAD_ALLOW_EXTENSIONS");
+    env::remove_var("UPLOAD_DENY_EXTENSIONS");
+    env::remove_var("UPLOAD_ALLOW_TYPES");
+    env::remove_var("UPLOAD_DENY_TYPES");
+    env::remove_var("CLAMD_ADDRESS");
+    env::remove_var("SCAN_TIMEOUT");
+    env::remove_var("SCAN_QUARANTINE_DIR");
+    env::remove_var("SCAN_FAIL_OPEN");
+    env::remove_var("HOOKS");
+    for name in ["PATTERN", "COMMAND", "STEP", "TIMEOUT", "RETRIES"] {
+        env::remove_var(format!("HOOK_THUMBS_{}", name));
+        env::remove_var(format!("HOOK_NOTIFY_{}", name));
+    }
+    env::remove_var("HOOK_BROKEN_COMMAND");
+    env::remove_var("HOOK_BROKEN_STEP");
+    env::remove_var("HOOK_TIMEOUT");
+    env::remove_var("HOOK_CONCURRENCY");
+    env::remove_var("HOOK_RETRY_DELAY");
+    env::remove_var("VHOST_BLOG_SANDBOX");
+    env::remove_var("VHOST_BLOG_LISTING_DIRS");
+    env::remove_var("STORAGE_BACKEND");
+    env::remove_var("S3_ENDPOINT");
+    env::remove_var("S3_REGION");
+    env::remove_var("S3_BUCKET");
+    env::remove_var("S3_ACCESS_KEY");
+    env::remove_var("S3_SECRET_KEY");
+    env::remove_var("S3_PREFIX");
+    env::remove_var("S3_PATH_STYLE");
+    env::remove_var("S3_API_PORT");
+    env::remove_var("S3_API_HOST");
+    env::remove_var("S3_API_REGION");
+    env::remove_var("WEBDAV_PORT");
+    env::remove_var("WEBDAV_HOST");
+    env::remove_var("ARCHIVE_LINK_SECRET");
+    env::remove_var("ARCHIVE_MAX_FILES");
+    env::remove_var("EXTRACT_MAX_ENTRIES");
+    env::remove_var("EXTRACT_MAX_BYTES");
+    env::remove_var("DEPLOY_KEEP_RELEASES");
 }
 
 #[test]
@@ -44,6 +122,45 @@ fn test_config_behavior() {
     assert_eq!(config.admin_port, 4849);
     assert_eq!(config.worker_threads, 8);
     assert_eq!(config.rate_limit_per_minute, 60);
+    assert_eq!(config.expiry_sweep_interval_secs, 60);
+    assert_eq!(config.trash_retention_secs, 7 * 24 * 60 * 60);
+    assert!(config.versioned_dirs.is_empty());
+    assert!(config.api_keys.is_empty());
+    assert!(config.global_quota.is_unlimited());
+    assert_eq!(config.disk_reserve_bytes, 1024 * 1024 * 1024);
+    assert!(config.s3.is_none());
+    assert!(config.s3_api.is_none());
+    assert!(config.webdav.is_none());
+    assert!(config.archive_link_secret.is_none());
+    assert_eq!(config.archive_max_files, 10_000);
+    assert_eq!(config.extract_max_entries, 10_000);
+    assert_eq!(config.extract_max_bytes, 2 * 1024 * 1024 * 1024);
+    assert_eq!(config.deploy_keep_releases, 5);
+    assert!(config.spa_dirs.is_empty());
+    assert!(!config.error_pages);
+    assert!(config.listing_dirs.is_empty());
+    assert!(config.listing_hide.is_empty());
+    assert!(config.cache_rules.is_empty());
+    assert_eq!(config.security_headers, SecurityHeaderValues::default());
+    assert!(config.security_paths.is_empty());
+    assert!(!config.admin_security_headers);
+    assert!(config.attachment_types.is_empty());
+    assert!(!config.sandbox_uploads);
+    assert!(config.sandbox_csp.is_none());
+    assert!(config.upload_allow_extensions.is_empty() && config.upload_deny_extensions.is_empty());
+    assert!(config.upload_allow_types.is_empty() && config.upload_deny_types.is_empty());
+    assert!(config.clamd_address.is_none());
+    assert_eq!(config.scan_timeout_secs, 60);
+    assert_eq!(config.scan_quarantine_dir, std::path::PathBuf::from("./quarantine"));
+    assert!(!config.scan_fail_open);
+    assert!(config.hooks.is_empty());
+    assert_eq!(config.hook_timeout_secs, 300);
+    assert_eq!(config.hook_concurrency, 2);
+    assert_eq!(config.hook_retry_delay_secs, 5);
+    assert!(config.buckets.is_empty());
+    assert!(config.vhosts.is_empty());
+    assert!(config.default_vhost.is_none());
+    assert_eq!(config.health_min_free_bytes, config.disk_reserve_bytes);
     
     let expected_hash = Config::hash_api_key("changeme");
     assert_eq!(config.api_key_hash, expected_hash);
@@ -55,12 +172,184 @@ fn test_config_behavior() {
     env::set_var("PUBLIC_PORT", "9090");
     env::set_var("WORKER_THREADS", "4");
     env::set_var("ADMIN_API_KEY", "supersecret");
+    env::set_var("VERSIONED_DIRS", "/, releases");
+    env::set_var("API_KEYS", "ci:cikey:1000, alice:alicekey::5:blog|Shop-App, broken");
+    env::set_var("BUCKETS", "shop-app, Blog, ../escape, default");
+    env::set_var("BUCKETS_DIR", "/srv/buckets");
+    env::set_var("BUCKET_SHOP_APP_PUBLIC", "false");
+    env::set_var("BUCKET_SHOP_APP_HOSTS", "Shop.example.com, shop.test");
+    env::set_var("BUCKET_BLOG_ROOT", "/srv/blog");
+    env::set_var("BUCKET_BLOG_QUOTA_MAX_BYTES", "1000");
+    env::set_var("VHOSTS", "cdn, blog");
+    env::set_var("DEFAULT_VHOST", "CDN");
+    env::set_var("VHOST_CDN_HOSTS", "CDN.example.com, static.example.com");
+    env::set_var("VHOST_CDN_ROOT", "/srv/cdn");
+    env::set_var("VHOST_CDN_INDEX", "index.htm, index.html");
+    env::set_var("VHOST_CDN_CACHE_CONTROL", "public, max-age=60");
+    env::set_var("VHOST_CDN_SECURITY_HEADERS", "false");
+    env::set_var("VHOST_BLOG_SITE", "Docs");
+    env::set_var("VHOST_BLOG_SPA_DIRS", "/");
+    env::set_var("SPA_DIRS", "app, /dashboard/");
+    env::set_var("ERROR_PAGES", "true");
+    env::set_var("LISTING_DIRS", "/pub, !/pub/private");
+    env::set_var("LISTING_HIDE", "*.bak");
+    env::set_var("SECURITY_HSTS", "max-age=63072000");
+    env::set_var("SECURITY_FRAME_OPTIONS", "");
+    env::set_var("SECURITY_PATHS", "embeds, nopattern");
+    env::set_var("SECURITY_PATH_EMBEDS_PATTERN", "/videos/*");
+    env::set_var("SECURITY_PATH_EMBEDS_CORP", "cross-origin");
+    env::set_var("SECURITY_HEADERS_ADMIN", "yes");
+    env::set_var("DISPOSITION_ATTACHMENT", "Text/HTML, .svg,,");
+    env::set_var("DISPOSITION_SANDBOX", "true");
+    env::set_var("DISPOSITION_SANDBOX_CSP", " sandbox ");
+    env::set_var("VHOST_BLOG_SANDBOX", "no");
+    env::set_var("UPLOAD_ALLOW_EXTENSIONS", "JPG, png");
+    env::set_var("UPLOAD_DENY_TYPES", "text/html,, image/svg+xml");
+    env::set_var("CLAMD_ADDRESS", " tcp://127.0.0.1:3310 ");
+    env::set_var("SCAN_TIMEOUT", "0");
+    env::set_var("SCAN_QUARANTINE_DIR", "/var/lib/juicebox/quarantine");
+    env::set_var("SCAN_FAIL_OPEN", "1");
+    // a hook needs exactly one of a command and a step
+    env::set_var("HOOKS", "thumbs, notify,broken, empty");
+    env::set_var("HOOK_THUMBS_PATTERN", "/photos/*.jpg");
+    env::set_var("HOOK_THUMBS_COMMAND", "convert \"$JUICEBOX_FILE\" -resize 200x thumb.jpg");
+    env::set_var("HOOK_THUMBS_TIMEOUT", "30");
+    env::set_var("HOOK_THUMBS_RETRIES", "2");
+    env::set_var("HOOK_NOTIFY_STEP", "webhook:https://hooks.example.com/uploads");
+    env::set_var("HOOK_BROKEN_COMMAND", "true");
+    env::set_var("HOOK_BROKEN_STEP", "tag:done");
+    env::set_var("HOOK_TIMEOUT", "0");
+    env::set_var("HOOK_CONCURRENCY", "8");
+    env::set_var("HOOK_RETRY_DELAY", "0");
+    env::set_var("CACHE_RULES", "/assets/*=public, max-age=31536000, immutable ; *.html=no-cache;broken;");
+    env::set_var("VHOST_BLOG_LISTING_DIRS", "!/");
+    env::set_var("QUOTA_MAX_BYTES", "1073741824");
+    env::set_var("DISK_RESERVE", "5000");
+    env::set_var("STORAGE_BACKEND", "S3");
+    env::set_var("S3_BUCKET", "juicebox");
+    env::set_var("S3_REGION", "eu-central-1");
+    env::set_var("S3_PREFIX", "/prod/");
+    env::set_var("S3_ACCESS_KEY", "minio");
+    env::set_var("S3_API_PORT", "4850");
+    env::set_var("S3_API_REGION", "eu-west-1");
+    env::set_var("WEBDAV_PORT", "4860");
+    env::set_var("ARCHIVE_LINK_SECRET", "linksecret");
+    env::set_var("ARCHIVE_MAX_FILES", "50");
+    env::set_var("EXTRACT_MAX_ENTRIES", "20");
+    env::set_var("EXTRACT_MAX_BYTES", "4096");
+    env::set_var("DEPLOY_KEEP_RELEASES", "3");
+    env::set_var("TRASH_RETENTION", u64::MAX.to_string());
     
     let config = Config::from_env();
     
     assert_eq!(config.files_dir.to_str().unwrap(), "/tmp/test_files");
     assert_eq!(config.public_port, 9090);
     assert_eq!(config.worker_threads, 4);
+    // retention beyond what a date can hold is capped
+    assert_eq!(config.trash_retention_secs, MAX_TRASH_RETENTION_SECS);
+    assert_eq!(config.versioned_dirs, vec!["/", "releases"]);
+    assert_eq!(config.global_quota.max_bytes, Some(1073741824));
+    assert_eq!(config.global_quota.max_files, None);
+    assert_eq!(config.disk_reserve_bytes, 5000);
+    assert_eq!(config.health_min_free_bytes, 5000);
+    assert_eq!(config.api_keys.len(), 2);
+    assert_eq!(config.api_keys[0].name, "ci");
+    assert_eq!(config.api_keys[0].key_hash, Config::hash_api_key("cikey"));
+    assert_eq!(config.api_keys[0].quota.max_bytes, Some(1000));
+    assert_eq!(config.api_keys[1].quota.max_bytes, None);
+    assert_eq!(config.api_keys[1].quota.max_files, Some(5));
+    assert!(config.api_keys[0].buckets.is_empty());
+    assert_eq!(config.api_keys[1].buckets, vec!["blog", "shop-app"]);
+
+    let s3_api = config.s3_api.as_ref().unwrap();
+    assert_eq!((s3_api.host.as_str(), s3_api.port), ("127.0.0.1", 4850));
+    assert_eq!(s3_api.region, "eu-west-1");
+    assert_eq!(s3_api.credentials.len(), 3);
+    assert_eq!(s3_api.credentials["ci"], "cikey");
+    assert_eq!(s3_api.credentials["admin"], "supersecret");
+    assert!(!format!("{:?}", s3_api).contains("alicekey"));
+    let webdav = config.webdav.as_ref().unwrap();
+    assert_eq!((webdav.host.as_str(), webdav.port), ("127.0.0.1", 4860));
+    assert_eq!(config.archive_link_secret.as_deref(), Some("linksecret"));
+    assert_eq!(config.archive_max_files, 50);
+    assert_eq!(config.extract_max_entries, 20);
+    assert_eq!(config.extract_max_bytes, 4096);
+    assert_eq!(config.deploy_keep_releases, 3);
+
+    assert_eq!(config.buckets.len(), 2);
+    assert_eq!(config.buckets[0].name, "shop-app");
+    assert_eq!(config.buckets[0].root.to_str().unwrap(), "/srv/buckets/shop-app");
+    assert!(!config.buckets[0].public);
+    assert_eq!(config.buckets[0].hosts, vec!["shop.example.com", "shop.test"]);
+    assert_eq!(config.buckets[1].name, "blog");
+    assert_eq!(config.buckets[1].root.to_str().unwrap(), "/srv/blog");
+    assert!(config.buckets[1].public);
+    assert_eq!(config.buckets[1].quota.max_bytes, Some(1000));
+
+    let s3 = config.s3.as_ref().unwrap();
+    assert_eq!(s3.bucket, "juicebox");
+    assert_eq!(s3.endpoint, "https://s3.eu-central-1.amazonaws.com");
+    assert_eq!(s3.prefix, "prod/");
+    assert_eq!(s3.access_key, "minio");
+    assert!(s3.path_style);
+
+    assert_eq!(config.default_vhost.as_deref(), Some("cdn"));
+    assert_eq!(config.vhosts.len(), 2);
+    let cdn = &config.vhosts[0];
+    assert_eq!(cdn.hosts, vec!["cdn.example.com", "static.example.com"]);
+    assert_eq!(cdn.root.as_ref().unwrap().to_str().unwrap(), "/srv/cdn");
+    assert_eq!(cdn.index_files, vec!["index.htm", "index.html"]);
+    assert_eq!(cdn.cache_control.as_deref(), Some("public, max-age=60"));
+    assert!(!cdn.security_headers);
+    assert!(config.vhosts[1].hosts.is_empty());
+    assert!(config.vhosts[1].security_headers);
+    assert!(cdn.site.is_none());
+    assert_eq!(config.vhosts[1].site.as_deref(), Some("docs"));
+    assert!(cdn.spa_dirs.is_none());
+    assert_eq!(config.vhosts[1].spa_dirs, Some(vec!["/".to_string()]));
+    assert!(config.vhosts[1].error_pages.is_none());
+    assert_eq!(config.spa_dirs, vec!["app", "/dashboard/"]);
+    assert!(config.error_pages);
+    assert_eq!(config.listing_dirs, vec!["/pub", "!/pub/private"]);
+    assert_eq!(config.listing_hide, vec!["*.bak"]);
+    assert_eq!(config.security_headers.strict_transport_security.as_deref(), Some("max-age=63072000"));
+    assert_eq!(config.security_headers.frame_options.as_deref(), Some(""));
+    assert!(config.security_headers.content_security_policy.is_none());
+    // paths without a pattern are left out
+    assert_eq!(config.security_paths.len(), 1);
+    assert_eq!(config.security_paths[0].pattern, "/videos/*");
+    assert_eq!(config.security_paths[0].headers.cross_origin_resource_policy.as_deref(), Some("cross-origin"));
+    assert!(config.admin_security_headers);
+    assert_eq!(config.attachment_types, vec!["text/html", ".svg"]);
+    assert!(config.sandbox_uploads);
+    assert_eq!(config.sandbox_csp.as_deref(), Some("sandbox"));
+    assert_eq!(config.upload_allow_extensions, vec!["jpg", "png"]);
+    assert_eq!(config.upload_deny_types, vec!["text/html", "image/svg+xml"]);
+    assert!(config.upload_deny_extensions.is_empty());
+    assert_eq!(config.clamd_address.as_deref(), Some("tcp://127.0.0.1:3310"));
+    // a zero timeout would refuse every upload
+    assert_eq!(config.scan_timeout_secs, 60);
+    assert_eq!(config.scan_quarantine_dir, std::path::PathBuf::from("/var/lib/juicebox/quarantine"));
+    assert!(config.scan_fail_open);
+    assert_eq!(config.hooks.len(), 2);
+    assert_eq!(config.hooks[0].name, "thumbs");
+    assert_eq!(config.hooks[0].pattern, "/photos/*.jpg");
+    assert_eq!(config.hooks[0].command.as_deref(), Some("convert \"$JUICEBOX_FILE\" -resize 200x thumb.jpg"));
+    assert_eq!((config.hooks[0].timeout_secs, config.hooks[0].retries), (Some(30), 2));
+    assert_eq!(config.hooks[1].pattern, "*");
+    assert_eq!(config.hooks[1].step.as_deref(), Some("webhook:https://hooks.example.com/uploads"));
+    assert_eq!((config.hooks[1].timeout_secs, config.hooks[1].retries), (None, 0));
+    assert_eq!(config.hook_timeout_secs, 300);
+    assert_eq!(config.hook_concurrency, 8);
+    assert_eq!(config.hook_retry_delay_secs, 0);
+    assert_eq!(config.cache_rules, vec![
+        ("/assets/*".to_string(), "public, max-age=31536000, immutable".to_string()),
+        ("*.html".to_string(), "no-cache".to_string()),
+    ]);
+    assert!(cdn.listing_dirs.is_none());
+    assert_eq!(config.vhosts[1].listing_dirs, Some(vec!["!/".to_string()]));
+    assert!(cdn.sandbox.is_none());
+    assert_eq!(config.vhosts[1].sandbox, Some(false));
     
     let expected_hash = Config::hash_api_key("supersecret");
     assert_eq!(config.api_key_hash, expected_hash);
@@ -0,0 +1,249 @@
+use juicebox_omega::config::{Config, VirtualHostConfig};
+use juicebox_omega::deploy::{activate_release, delete_site, deploy_site, get_site, list_sites, rollback_site, DeploySettings};
+use juicebox_omega::models::ActivateReleaseRequest;
+use juicebox_omega::quota::{usage_report, Quota, QuotaSettings};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::{MemoryStorage, Storage};
+use juicebox_omega::vhosts::{VirtualHost, VirtualHosts};
+use axum::body::{to_bytes, Body};
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+use zip::write::SimpleFileOptions;
+
+fn make_zip(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in entries {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+// upload a release, returning the status and json body
+async fn deploy(state: &Arc<AppState>, uri: &str, archive: &[u8]) -> (StatusCode, serde_json::Value) {
+    let app = Router::new()
+        .route("/sites/:site", post(deploy_site))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+
+    let mut body = b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"site.zip\"\r\n\r\n".to_vec();
+    body.extend_from_slice(archive);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+async fn fetch(app: &Router, uri: &str, host: &str) -> (StatusCode, String) {
+    let request = Request::builder().uri(uri).header(header::HOST, host).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_deploy_switch_and_rollback() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let app = build_public_router(state.clone());
+
+    let (status, first) = deploy(&state, "/sites/Docs", &make_zip(&[("index.html", "v1"), ("old.css", "old")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    assert_eq!(first["site"], "docs");
+    assert_eq!(first["active"], true);
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await, (StatusCode::OK, "v1".to_string()));
+
+    let (status, second) = deploy(&state, "/sites/docs", &make_zip(&[("index.html", "v2"), ("new.css", "new")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+    assert_eq!(fetch(&app, "/.sites/docs/index.html", "localhost").await.1, "v2");
+    // the switch is to a whole tree, nothing of the old release shows through
+    assert_eq!(fetch(&app, "/.sites/docs/old.css", "localhost").await.0, StatusCode::NOT_FOUND);
+
+    let site = rollback_site(State(state.clone()), Path("docs".to_string())).await.unwrap().0;
+    assert_eq!(site.current.as_ref(), first["release"].as_str().map(String::from).as_ref());
+    assert_eq!(site.releases.len(), 2);
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await.1, "v1");
+    assert_eq!(fetch(&app, "/.sites/docs/new.css", "localhost").await.0, StatusCode::NOT_FOUND);
+
+    // the active release survives a restart
+    let restarted = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    assert_eq!(fetch(&build_public_router(restarted), "/.sites/docs/", "localhost").await.1, "v1");
+
+    // and any kept release can be picked again
+    let request = ActivateReleaseRequest { release: second["release"].as_str().unwrap().to_string() };
+    let site = activate_release(State(state.clone()), Path("docs".to_string()), Json(request)).await.unwrap().0;
+    assert_eq!(site.current.as_deref(), second["release"].as_str());
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await.1, "v2");
+
+    // names a site can't have aren't looked up at all
+    let long = format!("/.sites/{}/", "a".repeat(300));
+    for uri in ["/.sites/../docs/", "/.sites/docs.old/index.html", long.as_str()] {
+        assert_eq!(fetch(&app, uri, "localhost").await.0, StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_old_releases_are_pruned() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(
+        AppState::new(temp_dir.path().to_path_buf()).with_deploy_settings(DeploySettings { keep_releases: 2 }),
+    );
+
+    let mut releases = Vec::new();
+    for version in ["v1", "v2", "v3"] {
+        let (status, body) = deploy(&state, "/sites/blog", &make_zip(&[("index.html", version)])).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        releases.push(body["release"].as_str().unwrap().to_string());
+        if version == "v3" {
+            assert_eq!(body["pruned"], serde_json::json!([releases[0]]));
+        }
+    }
+
+    // a staged release doesn't go live, and the active one is never pruned
+    let (status, staged) = deploy(&state, "/sites/blog?activate=false", &make_zip(&[("index.html", "v4")])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(staged["active"], false);
+    assert_eq!(staged["pruned"], serde_json::json!([releases[1]]));
+
+    let site = get_site(State(state.clone()), Path("blog".to_string())).await.unwrap().0;
+    assert_eq!(site.current.as_deref(), Some(releases[2].as_str()));
+    assert_eq!(site.releases, vec![releases[2].clone(), staged["release"].as_str().unwrap().to_string()]);
+    let app = build_public_router(state.clone());
+    assert_eq!(fetch(&app, "/.sites/blog/", "localhost").await.1, "v3");
+
+    let sites = list_sites(State(state)).await.unwrap().0;
+    assert_eq!(sites.total, 1);
+    assert_eq!(sites.sites[0].name, "blog");
+}
+
+#[tokio::test]
+async fn test_deployment_errors() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let site = || Path("docs".to_string());
+
+    let (status, _) = deploy(&state, "/sites/..%2Fetc", &make_zip(&[("index.html", "x")])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = deploy(&state, "/sites/docs", b"not an archive").await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(get_site(State(state.clone()), site()).await.unwrap_err().0, StatusCode::NOT_FOUND);
+    assert_eq!(rollback_site(State(state.clone()), site()).await.unwrap_err().0, StatusCode::NOT_FOUND);
+
+    // nothing to roll back to yet
+    let (status, _) = deploy(&state, "/sites/docs", &make_zip(&[("index.html", "v1")])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rollback_site(State(state.clone()), site()).await.unwrap_err().0, StatusCode::CONFLICT);
+    let request = ActivateReleaseRequest { release: "../../elsewhere".to_string() };
+    let err = activate_release(State(state.clone()), site(), Json(request)).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::NOT_FOUND);
+
+    // deleting a site takes it offline
+    let app = build_public_router(state.clone());
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await.0, StatusCode::OK);
+    assert!(delete_site(State(state.clone()), site()).await.unwrap().success);
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(delete_site(State(state), site()).await.unwrap_err().0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/.sites/", "localhost").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_virtual_host_serves_site() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::write(temp_dir.path().join("index.html"), "bucket files").unwrap();
+    let config = VirtualHostConfig {
+        name: "docs".to_string(),
+        hosts: vec!["docs.example.com".to_string()],
+        root: None,
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: Some("public, max-age=60".to_string()),
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: None,
+        site: Some("docs".to_string()),
+        spa_dirs: None,
+        error_pages: None,
+        listing_dirs: None,
+        sandbox: None,
+    };
+    let vhosts = VirtualHosts { hosts: vec![VirtualHost::from_config(&Config::from_env(), &config)], fallback: None };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_vhosts(vhosts));
+    let app = build_public_router(state.clone());
+
+    // no release yet
+    assert_eq!(fetch(&app, "/", "docs.example.com").await.0, StatusCode::NOT_FOUND);
+
+    let archive = make_zip(&[("index.html", "docs home"), ("guide/index.html", "guide")]);
+    let (status, _) = deploy(&state, "/sites/docs", &archive).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let request = Request::builder().uri("/guide/").header(header::HOST, "docs.example.com").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=60");
+    assert!(response.headers().contains_key("x-content-type-options"));
+    assert_eq!(fetch(&app, "/", "docs.example.com").await.1, "docs home");
+    assert_eq!(fetch(&app, "/.deployments/", "docs.example.com").await.0, StatusCode::NOT_FOUND);
+
+    // the default host keeps serving the bucket, with the site under its prefix
+    assert_eq!(fetch(&app, "/", "localhost").await.1, "bucket files");
+    let request = Request::builder().uri("/.sites/docs").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "/.sites/docs/");
+    assert_eq!(fetch(&app, "/.sites/docs/guide/", "localhost").await.1, "guide");
+    assert_eq!(fetch(&app, "/.deployments/docs/current", "localhost").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_releases_live_in_storage_and_count_against_the_quota() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(MemoryStorage::new());
+    let quotas = QuotaSettings { global: Quota { max_bytes: Some(50), max_files: None }, per_key: Default::default() };
+    let state = Arc::new(
+        AppState::new(temp_dir.path().to_path_buf())
+            .with_storage(storage.clone())
+            .with_quotas(quotas)
+            .with_deploy_settings(DeploySettings { keep_releases: 1 }),
+    );
+    let app = build_public_router(state.clone());
+
+    let (status, first) = deploy(&state, "/sites/docs", &make_zip(&[("index.html", "v1")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await, (StatusCode::OK, "v1".to_string()));
+    assert!(!temp_dir.path().join(".deployments").exists());
+    let (usage, _) = usage_report(&state).await.unwrap();
+    // the release's file and the site's pointer to it
+    let pointer = first["release"].as_str().unwrap().len() as u64;
+    assert_eq!((usage.used_bytes, usage.used_files), (2 + pointer, 1));
+
+    // a staged release is kept next to the active one, whatever keep_releases says
+    let (status, staged) = deploy(&state, "/sites/docs?activate=false", &make_zip(&[("index.html", "v2")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", staged);
+    assert_eq!(staged["pruned"], serde_json::json!([]));
+    let site = get_site(State(state.clone()), Path("docs".to_string())).await.unwrap().0;
+    assert_eq!(site.releases.len(), 2);
+
+    // and releases that don't fit are refused
+    let (status, _) = deploy(&state, "/sites/docs", &make_zip(&[("index.html", &"x".repeat(30))])).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+
+    assert!(delete_site(State(state.clone()), Path("docs".to_string())).await.unwrap().success);
+    assert!(storage.list("", true).await.unwrap().objects.is_empty());
+    assert_eq!(fetch(&app, "/.sites/docs/", "localhost").await.0, StatusCode::NOT_FOUND);
+}
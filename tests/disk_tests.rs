@@ -0,0 +1,109 @@
+use juicebox_omega::disk::{disk_space, ensure_free_space, pending_write_bytes, DiskSettings};
+use juicebox_omega::handlers::{complete_chunked_upload, get_stats, health_check, init_chunked_upload};
+use juicebox_omega::models::{ChunkedUploadComplete, ChunkedUploadInit};
+use juicebox_omega::quota::{reserve_upload, QuotaError};
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use std::sync::Arc;
+
+fn disk_state(dir: &std::path::Path, reserve_bytes: u64, health_min_free_bytes: u64) -> Arc<AppState> {
+    let disk = DiskSettings { reserve_bytes, health_min_free_bytes };
+    Arc::new(AppState::new(dir.to_path_buf()).with_disk(disk))
+}
+
+#[tokio::test]
+async fn test_uploads_refused_when_disk_nearly_full() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let free = disk_space(temp_dir.path()).await.unwrap().free_bytes;
+
+    // a reserve larger than the disk leaves no room at all
+    let state = disk_state(temp_dir.path(), free + (1 << 40), 0);
+    assert!(ensure_free_space(&state, 1).await.is_err());
+
+    let payload = ChunkedUploadInit {
+        filename: "big.bin".to_string(),
+        total_size: 1024,
+        chunk_size: 256,
+        options: Default::default(),
+    };
+    let err = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::INSUFFICIENT_STORAGE);
+    assert!(state.chunked_uploads.is_empty());
+
+    // completing keeps the upload around so it can be retried once space frees up
+    let upload_id = "pending".to_string();
+    state.chunked_uploads.insert(upload_id.clone(), ChunkedUploadMetadata {
+        filename: "big.bin".to_string(),
+        total_size: 4,
+        chunk_size: 4,
+        total_chunks: 1,
+        received_chunks: [0].into_iter().collect(),
+        ..Default::default()
+    });
+    let err = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id: upload_id.clone() }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.0, StatusCode::INSUFFICIENT_STORAGE);
+    assert!(state.chunked_uploads.contains_key(&upload_id));
+    assert!(!temp_dir.path().join("big.bin").exists());
+}
+
+#[tokio::test]
+async fn test_pending_writes_count_against_free_space() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = disk_state(temp_dir.path(), 0, 0);
+    assert!(ensure_free_space(&state, 1024).await.is_ok());
+
+    // half received, the other half still has to land on disk
+    state.chunked_uploads.insert("a".to_string(), ChunkedUploadMetadata {
+        total_size: 1000,
+        chunk_size: 250,
+        total_chunks: 4,
+        received_chunks: [0, 1].into_iter().collect(),
+        ..Default::default()
+    });
+    assert_eq!(pending_write_bytes(&state), 500);
+
+    // leave some slack, other tests write to the same disk
+    let free = disk_space(temp_dir.path()).await.unwrap().free_bytes;
+    let slack = 1 << 20;
+    assert!(ensure_free_space(&state, free - 500 - slack).await.is_ok());
+    assert!(ensure_free_space(&state, free - 500 + slack).await.is_err());
+}
+
+#[tokio::test]
+async fn test_reservations_hold_disk_space() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    // about 100 MiB left above the reserve
+    let room = 100 << 20;
+    let free = disk_space(temp_dir.path()).await.unwrap().free_bytes;
+    let state = disk_state(temp_dir.path(), free.saturating_sub(room), 0);
+
+    // two uploads that each fit on their own can't both take the same free space
+    let first = reserve_upload(&state, None, room * 6 / 10, None).await.unwrap();
+    let second = reserve_upload(&state, None, room * 6 / 10, None).await;
+    assert!(matches!(second, Err(QuotaError::NoSpace(_))));
+    drop(first);
+    assert!(reserve_upload(&state, None, room * 6 / 10, None).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_health_and_stats_report_disk() {
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    let state = disk_state(temp_dir.path(), 0, 1);
+    let stats = get_stats(State(state.clone())).await.unwrap();
+    assert!(stats.0.disk_total_bytes > 0);
+    assert!(stats.0.disk_free_bytes <= stats.0.disk_total_bytes);
+
+    let (status, body) = health_check(State(state)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.0["status"], "healthy");
+
+    let state = disk_state(temp_dir.path(), 0, u64::MAX);
+    let (status, body) = health_check(State(state)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.0["status"], "unhealthy");
+}
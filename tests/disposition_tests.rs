@@ -0,0 +1,192 @@
+use juicebox_omega::config::{Config, VirtualHostConfig};
+use juicebox_omega::disposition::{set_disposition, DispositionPolicy, DEFAULT_SANDBOX_CSP};
+use juicebox_omega::handlers::{list_files, upload_file};
+use juicebox_omega::metadata::Disposition;
+use juicebox_omega::models::{DispositionRequest, ListFilesQuery};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::vhosts::{VirtualHost, VirtualHosts};
+use axum::body::{to_bytes, Body};
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+mod common;
+use common::write_files;
+
+fn list(entries: &[&str]) -> Vec<String> {
+    entries.iter().map(|s| s.to_string()).collect()
+}
+
+async fn fetch(app: &Router, method: &str, uri: &str, host: &str) -> (StatusCode, HeaderMap) {
+    let request = Request::builder().method(method).uri(uri).header(header::HOST, host).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    (response.status(), response.headers().clone())
+}
+
+fn header_of(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers.get(name).map(|v| v.to_str().unwrap().to_string())
+}
+
+#[test]
+fn test_disposition_policy() {
+    let policy = DispositionPolicy::new(&list(&["Text/HTML", ".SVG", "xml", "image/x-*", " "]), false);
+    assert_eq!(policy.attachment, ["text/html", "svg", "xml", "image/x-*"]);
+
+    assert!(policy.is_attachment("evil.txt", Some("text/html; charset=utf-8")));
+    assert!(policy.is_attachment("docs/Logo.Svg", None));
+    assert!(policy.is_attachment("icon.ico", Some("image/x-icon")));
+    assert!(!policy.is_attachment("photo.png", Some("image/png")));
+    assert!(!policy.is_attachment("svg", Some("text/plain")));
+    assert!(!policy.is_attachment("notes.xml.txt", Some("text/plain")));
+
+    // a file's own setting wins over the policy
+    assert_eq!(policy.resolve("page.html", Some("text/html"), None), Disposition::Attachment);
+    assert_eq!(policy.resolve("page.html", Some("text/html"), Some(Disposition::Inline)), Disposition::Inline);
+    assert_eq!(policy.resolve("photo.png", None, None), Disposition::Inline);
+    let sandboxed = policy.with_overrides(Some(true));
+    assert_eq!(sandboxed.resolve("photo.png", None, None), Disposition::Sandbox);
+    assert_eq!(sandboxed.resolve("photo.png", None, Some(Disposition::Attachment)), Disposition::Attachment);
+    assert_eq!(sandboxed.with_overrides(None).resolve("page.htm", None, None), Disposition::Sandbox);
+    assert_eq!("Sandbox".parse::<Disposition>(), Ok(Disposition::Sandbox));
+    assert!("download".parse::<Disposition>().is_err());
+}
+
+#[tokio::test]
+async fn test_untrusted_files_download_or_sandbox() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[
+        ("evil.html", "<script>alert(1)</script>"),
+        ("docs/drawing.svg", "<svg></svg>"),
+        ("docs/Ünïcode \"name\".html", "hi"),
+        ("photo.png", "png"),
+    ]);
+    let policy = DispositionPolicy::new(&list(&["text/html", ".svg"]), true);
+    let app = build_public_router(Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_disposition(policy)));
+
+    let (status, headers) = fetch(&app, "GET", "/evil.html", "localhost").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        header_of(&headers, header::CONTENT_DISPOSITION).as_deref(),
+        Some("attachment; filename=\"evil.html\"; filename*=UTF-8''evil%2Ehtml"),
+    );
+    let (_, headers) = fetch(&app, "HEAD", "/docs/drawing.svg", "localhost").await;
+    assert!(header_of(&headers, header::CONTENT_DISPOSITION).unwrap().contains("filename=\"drawing.svg\""));
+    // non-ascii names and quotes get an ascii fallback and the encoded original
+    let (_, headers) = fetch(&app, "GET", "/docs/%C3%9Cn%C3%AFcode%20%22name%22.html", "localhost").await;
+    let disposition = header_of(&headers, header::CONTENT_DISPOSITION).unwrap();
+    assert!(disposition.starts_with("attachment; filename=\"_n_code _name_.html\""), "{}", disposition);
+    assert!(disposition.ends_with("UTF-8''%C3%9Cn%C3%AFcode%20%22name%22%2Ehtml"), "{}", disposition);
+
+    // everything else renders under the sandbox policy instead of the usual one
+    let (_, headers) = fetch(&app, "GET", "/photo.png", "localhost").await;
+    assert!(!headers.contains_key(header::CONTENT_DISPOSITION));
+    assert_eq!(header_of(&headers, header::CONTENT_SECURITY_POLICY).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+
+    let (status, headers) = fetch(&app, "GET", "/missing.html", "localhost").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!headers.contains_key(header::CONTENT_DISPOSITION));
+    assert_ne!(header_of(&headers, header::CONTENT_SECURITY_POLICY).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+}
+
+#[tokio::test]
+async fn test_per_file_dispositions() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[("page.html", "<h1>page</h1>")]);
+    let policy = DispositionPolicy::new(&list(&["text/html"]), false);
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_disposition(policy));
+    let public = build_public_router(state.clone());
+
+    // an upload can choose its own
+    let admin = Router::new()
+        .route("/upload", post(upload_file))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let upload = |field: &'static str| {
+        let body = format!(
+            "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"disposition\"\r\n\r\n{}\r\n\
+             --XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"trusted.html\"\r\n\r\n<h1>ok</h1>\r\n\
+             --XBOUNDARY--\r\n",
+            field,
+        );
+        let request = Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+            .body(Body::from(body))
+            .unwrap();
+        let admin = admin.clone();
+        async move {
+            let response = admin.oneshot(request).await.unwrap();
+            let status = response.status();
+            (status, to_bytes(response.into_body(), usize::MAX).await.unwrap())
+        }
+    };
+    assert_eq!(upload("sideways").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(upload("inline").await.0, StatusCode::OK);
+    assert_eq!(state.metadata.get("trusted.html").disposition, Some(Disposition::Inline));
+    let (_, headers) = fetch(&public, "GET", "/trusted.html", "localhost").await;
+    assert!(!headers.contains_key(header::CONTENT_DISPOSITION));
+    assert!(fetch(&public, "GET", "/page.html", "localhost").await.1.contains_key(header::CONTENT_DISPOSITION));
+
+    // and existing files get one through the admin api, no restart needed
+    let request = |disposition| Json(DispositionRequest { disposition });
+    let response = set_disposition(State(state.clone()), Path("page.html".to_string()), request(Some(Disposition::Sandbox)))
+        .await
+        .unwrap();
+    assert_eq!(response.disposition, Some(Disposition::Sandbox));
+    let (_, headers) = fetch(&public, "GET", "/page.html", "localhost").await;
+    assert!(!headers.contains_key(header::CONTENT_DISPOSITION));
+    assert_eq!(header_of(&headers, header::CONTENT_SECURITY_POLICY).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+    let files = list_files(State(state.clone()), Query(ListFilesQuery::default())).await.unwrap();
+    let page = files.files.iter().find(|f| f.name == "page.html").unwrap();
+    assert_eq!(page.disposition, Some(Disposition::Sandbox));
+    // it's kept with the rest of the metadata
+    assert_eq!(AppState::new(temp_dir.path().to_path_buf()).metadata.get("page.html").disposition, Some(Disposition::Sandbox));
+
+    // null goes back to the policy
+    let cleared = set_disposition(State(state.clone()), Path("page.html".to_string()), request(None)).await.unwrap();
+    assert_eq!(cleared.disposition, None);
+    assert!(fetch(&public, "GET", "/page.html", "localhost").await.1.contains_key(header::CONTENT_DISPOSITION));
+    let missing = set_disposition(State(state), Path("missing.html".to_string()), request(None)).await;
+    assert_eq!(missing.unwrap_err().0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_sandbox_per_host() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[("app.js", "run()"), ("docs/index.html", "docs")]);
+    let config = VirtualHostConfig {
+        name: "www".to_string(),
+        hosts: vec!["www.example.com".to_string()],
+        root: None,
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: None,
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: None,
+        site: None,
+        spa_dirs: None,
+        error_pages: None,
+        listing_dirs: None,
+        sandbox: Some(false),
+    };
+    let state = AppState::new(temp_dir.path().to_path_buf())
+        .with_disposition(DispositionPolicy::new(&[], true))
+        .with_vhosts(VirtualHosts { hosts: vec![VirtualHost::from_config(&Config::from_env(), &config)], fallback: None });
+    let app = build_public_router(Arc::new(state));
+
+    let csp = |headers: HeaderMap| header_of(&headers, header::CONTENT_SECURITY_POLICY);
+    assert_eq!(csp(fetch(&app, "GET", "/app.js", "localhost").await.1).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+    // directory indexes aren't uploads
+    assert_ne!(csp(fetch(&app, "GET", "/docs/", "localhost").await.1).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+    // a host can opt out
+    let (status, headers) = fetch(&app, "GET", "/app.js", "www.example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(csp(headers).as_deref(), Some(DEFAULT_SANDBOX_CSP));
+}
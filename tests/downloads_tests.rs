@@ -0,0 +1,81 @@
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::util::ServiceExt;
+
+fn get(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_burn_after_reading() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::write(temp_dir.path().join("secret.txt"), b"top secret").unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    state.metadata.update("secret.txt", |m| m.max_downloads = Some(2));
+
+    let app = build_public_router(state.clone());
+
+    // range probes and HEAD requests don't count
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/secret.txt").header("Range", "bytes=0-2").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let response = app.clone()
+        .oneshot(Request::builder().method("HEAD").uri("/secret.txt").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(state.metadata.get("secret.txt").download_count, 0);
+
+    // a response that is never read doesn't count either
+    let response = app.clone().oneshot(get("/secret.txt")).await.unwrap();
+    drop(response);
+    assert_eq!(state.metadata.get("secret.txt").download_count, 0);
+    assert!(state.active_downloads.is_empty());
+
+    for _ in 0..2 {
+        let response = app.clone().oneshot(get("/secret.txt")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"top secret");
+    }
+
+    // the burn happens in the background
+    for _ in 0..50 {
+        if !temp_dir.path().join("secret.txt").exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(!temp_dir.path().join("secret.txt").exists());
+
+    // tombstone answers 410 rather than 404
+    let response = app.oneshot(get("/secret.txt")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn test_concurrent_download_is_reserved() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::write(temp_dir.path().join("once.txt"), b"only once").unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    state.metadata.update("once.txt", |m| m.max_downloads = Some(1));
+
+    let app = build_public_router(state.clone());
+
+    // first download is in flight while the second one arrives
+    let first = app.clone().oneshot(get("/once.txt")).await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = app.clone().oneshot(get("/once.txt")).await.unwrap();
+    assert_eq!(second.status(), StatusCode::GONE);
+
+    to_bytes(first.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(state.metadata.get("once.txt").download_count, 1);
+}
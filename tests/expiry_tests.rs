@@ -0,0 +1,95 @@
+use juicebox_omega::expiry::{resolve_expiry, sweep_expired};
+use juicebox_omega::handlers::init_chunked_upload;
+use juicebox_omega::models::{ChunkedUploadInit, UploadOptions};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::Json;
+use chrono::{Duration, Utc};
+use std::fs::File;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+#[test]
+fn test_resolve_expiry() {
+    // nothing set means no expiry
+    assert_eq!(resolve_expiry(&UploadOptions::default()).unwrap(), None);
+
+    let options = UploadOptions { ttl: Some(60), ..Default::default() };
+    let at = resolve_expiry(&options).unwrap().unwrap();
+    assert!(at > Utc::now() + Duration::seconds(50));
+
+    // past expiry and conflicting options are rejected
+    let options = UploadOptions { expires_at: Some(Utc::now() - Duration::seconds(5)), ..Default::default() };
+    assert!(resolve_expiry(&options).is_err());
+    let options = UploadOptions { expires_at: Some(Utc::now()), ttl: Some(5), ..Default::default() };
+    assert!(resolve_expiry(&options).is_err());
+}
+
+#[tokio::test]
+async fn test_expired_file_is_gone_on_public_router() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    File::create(temp_dir.path().join("old.txt")).unwrap();
+    File::create(temp_dir.path().join("fresh.txt")).unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+
+    state.metadata.update("old.txt", |m| m.expires_at = Some(Utc::now() - Duration::seconds(1)));
+    state.metadata.update("fresh.txt", |m| m.expires_at = Some(Utc::now() + Duration::hours(1)));
+
+    let app = build_public_router(state.clone());
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/old.txt").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    let response = app
+        .oneshot(Request::builder().uri("/fresh.txt").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_sweep_expired() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    File::create(temp_dir.path().join("old.txt")).unwrap();
+    File::create(temp_dir.path().join("keep.txt")).unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+
+    state.metadata.update("old.txt", |m| m.expires_at = Some(Utc::now() - Duration::seconds(1)));
+    // metadata for a file that vanished on its own gets cleaned up as well
+    state.metadata.update("vanished.txt", |m| m.expires_at = Some(Utc::now() - Duration::seconds(1)));
+
+    assert_eq!(sweep_expired(&state).await, 1);
+    assert!(!temp_dir.path().join("old.txt").exists());
+    assert!(temp_dir.path().join("keep.txt").exists());
+    assert!(state.metadata.files.is_empty());
+}
+
+#[tokio::test]
+async fn test_chunked_upload_rejects_bad_expiry() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+
+    let payload = ChunkedUploadInit {
+        filename: "big.bin".to_string(),
+        total_size: 10,
+        chunk_size: 5,
+        options: UploadOptions { ttl: Some(60), expires_at: Some(Utc::now()), ..Default::default() },
+    };
+    let result = init_chunked_upload(State(state.clone()), None, Json(payload)).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::BAD_REQUEST);
+
+    let payload = ChunkedUploadInit {
+        filename: "big.bin".to_string(),
+        total_size: 10,
+        chunk_size: 5,
+        options: UploadOptions { ttl: Some(60), ..Default::default() },
+    };
+    let response = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap();
+    let upload = state.chunked_uploads.get(&response.0.upload_id).unwrap();
+    assert!(upload.settings.expires_at.is_some());
+}
@@ -0,0 +1,267 @@
+use juicebox_omega::disk::DiskSettings;
+use juicebox_omega::extract::{upload_archive, ExtractLimits};
+use juicebox_omega::filetypes::UploadTypeRules;
+use juicebox_omega::middleware::ApiKeyName;
+use juicebox_omega::quota::{Quota, QuotaSettings};
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::put_bytes;
+use juicebox_omega::versions::VersioningSettings;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::DefaultBodyLimit;
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Extension, Router};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+use zip::write::SimpleFileOptions;
+
+fn make_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in entries {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(content).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn make_tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = tar::Builder::new(Vec::new());
+    for (name, content) in entries {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, *content).unwrap();
+    }
+    tar.into_inner().unwrap()
+}
+
+// post an archive as the "ci" api key, returning the status and json body
+async fn upload(state: &Arc<AppState>, query: &str, fields: &[(&str, &str)], archive: &[u8]) -> (StatusCode, serde_json::Value) {
+    let app = Router::new()
+        .route("/upload/archive", post(upload_archive))
+        .layer(Extension(ApiKeyName("ci".to_string())))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(format!("--XBOUNDARY\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", name, value).as_bytes());
+    }
+    body.extend_from_slice(b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"site.bin\"\r\n\r\n");
+    body.extend_from_slice(archive);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/upload/archive{}", query))
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_zip_extracts_into_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    std::fs::create_dir_all(temp_dir.path().join("site")).unwrap();
+    std::fs::write(temp_dir.path().join("site/keep.txt"), "kept").unwrap();
+
+    let archive = make_zip(&[
+        ("index.html", b"<h1>hi</h1>"),
+        ("./assets\\app.js", b"console.log(1)"),
+        (".env", b"SECRET=1"),
+        ("__MACOSX/._index.html", b"fork"),
+    ]);
+    let (status, body) = upload(&state, "?dir=site", &[], &archive).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["files"], 2);
+    assert_eq!(body["total_size"], 25);
+    assert_eq!(body["replaced"], false);
+    assert_eq!(body["skipped"], serde_json::json!([".env", "__MACOSX/._index.html"]));
+
+    let root = temp_dir.path();
+    assert_eq!(std::fs::read_to_string(root.join("site/index.html")).unwrap(), "<h1>hi</h1>");
+    assert_eq!(std::fs::read_to_string(root.join("site/assets/app.js")).unwrap(), "console.log(1)");
+    // merging leaves other files alone and hidden entries out
+    assert!(root.join("site/keep.txt").exists());
+    assert!(!root.join("site/.env").exists());
+    assert_eq!(state.metadata.get("site/index.html").owner.as_deref(), Some("ci"));
+    // nothing is left behind in the work directory
+    assert_eq!(std::fs::read_dir(root.join(".chunks")).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn test_compressed_tar_replaces_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("site/old")).unwrap();
+    std::fs::write(root.join("site/old/page.html"), "old").unwrap();
+    state.metadata.update("site/old/page.html", |m| m.owner = Some("ci".to_string()));
+
+    let tar = make_tar(&[("index.html", b"v2"), ("blog/post.html", b"post")]);
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&tar).unwrap();
+    let (status, body) = upload(&state, "", &[("dir", "site"), ("replace", "true")], &gz.finish().unwrap()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["replaced"], true);
+    assert_eq!(std::fs::read_to_string(root.join("site/blog/post.html")).unwrap(), "post");
+    // the old content is gone, files and metadata alike, the files to the trash
+    assert!(!root.join("site/old").exists());
+    assert!(state.metadata.get("site/old/page.html").owner.is_none());
+    assert!(state.metadata.trash.iter().any(|item| item.original_name == "site/old/page.html"));
+
+    // zstd works the same way
+    let zst = zstd::encode_all(&make_tar(&[("index.html", b"v3")])[..], 3).unwrap();
+    let (status, _) = upload(&state, "?dir=site&replace=true", &[], &zst).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(std::fs::read_to_string(root.join("site/index.html")).unwrap(), "v3");
+    assert!(!root.join("site/blog").exists());
+}
+
+#[tokio::test]
+async fn test_replacing_a_versioned_directory_keeps_the_old_content() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let versioning = VersioningSettings { directories: vec!["/site".to_string()], max_versions: 0 };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_versioning(versioning));
+    let root = temp_dir.path();
+    put_bytes(state.storage.as_ref(), "site/index.html", Bytes::from("v1")).await.unwrap();
+    put_bytes(state.storage.as_ref(), "site/about.html", Bytes::from("about")).await.unwrap();
+
+    let (status, body) = upload(&state, "?dir=site&replace=true", &[], &make_tar(&[("index.html", b"v2")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(std::fs::read_to_string(root.join("site/index.html")).unwrap(), "v2");
+    assert!(!root.join("site/about.html").exists());
+
+    // the overwritten file is a version, the dropped one is in the trash
+    assert_eq!(state.metadata.versions.get("site/index.html").map(|v| v.len()), Some(1));
+    let trashed: Vec<String> = state.metadata.trash.iter().map(|item| item.original_name.clone()).collect();
+    assert_eq!(trashed, ["site/about.html"]);
+}
+
+#[tokio::test]
+async fn test_unsafe_archives_are_rejected() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let root = temp_dir.path();
+
+    let cases: Vec<(&str, Vec<u8>, StatusCode)> = vec![
+        ("?dir=site", make_zip(&[("ok.txt", b"ok"), ("../../evil.sh", b"rm -rf /")]), StatusCode::BAD_REQUEST),
+        ("?dir=site", make_zip(&[("a\\..\\..\\evil.sh", b"x")]), StatusCode::BAD_REQUEST),
+        ("?dir=site", b"just some text, not an archive".to_vec(), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        ("?dir=site", b"PK\x03\x04broken".to_vec(), StatusCode::BAD_REQUEST),
+        ("?dir=../outside", make_zip(&[("ok.txt", b"ok")]), StatusCode::BAD_REQUEST),
+        ("?replace=true", make_zip(&[("ok.txt", b"ok")]), StatusCode::BAD_REQUEST),
+    ];
+    for (query, archive, expected) in cases {
+        let (status, body) = upload(&state, query, &[], &archive).await;
+        assert_eq!(status, expected, "{} {}", query, body);
+        assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()));
+    }
+    // a bad entry fails the whole archive, nothing gets written
+    assert!(!root.join("site").exists());
+    assert!(!root.join("evil.sh").exists());
+    assert!(!root.parent().unwrap().join("evil.sh").exists());
+}
+
+#[tokio::test]
+async fn test_extract_limits() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let limits = ExtractLimits { max_entries: 3, max_bytes: 1000 };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_extract_limits(limits));
+
+    // four entries, counted from the zip's central directory and while reading a tar
+    let entries: [(&str, &[u8]); 4] = [("a", b"1"), ("b", b"2"), ("c", b"3"), ("d", b"4")];
+    let (status, _) = upload(&state, "?dir=many", &[], &make_zip(&entries)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let (status, _) = upload(&state, "?dir=many", &[], &make_tar(&entries)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // a zip bomb: tiny compressed, too big once extracted
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("zeros.bin", SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated))
+        .unwrap();
+    zip.write_all(&vec![0u8; 1_000_000]).unwrap();
+    let bomb = zip.finish().unwrap().into_inner();
+    assert!(bomb.len() < 5000);
+    let (status, body) = upload(&state, "?dir=bomb", &[], &bomb).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+
+    // right at the limit is fine
+    let (status, body) = upload(&state, "?dir=fits", &[], &make_zip(&[("full.bin", &[7u8; 1000])])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(!temp_dir.path().join("bomb").exists());
+    assert!(!temp_dir.path().join("many").exists());
+}
+
+#[tokio::test]
+async fn test_extracted_files_are_checked() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let rules = UploadTypeRules::new(&[], &["exe".to_string()], &[], &["text/html".to_string()]);
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_upload_types(rules));
+
+    // one refused entry refuses the whole archive, nothing of it is kept
+    let archive = make_zip(&[("readme.txt", b"hello"), ("tools/setup.exe", b"MZ")]);
+    let (status, body) = upload(&state, "?dir=site", &[], &archive).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["error"], "tools/setup.exe: Extension .exe is not allowed");
+    let archive = make_tar(&[("readme.txt", b"hello"), ("page.txt", b"<!DOCTYPE html><html></html>")]);
+    let (status, body) = upload(&state, "?dir=site", &[], &archive).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(body["error"].as_str().unwrap().starts_with("page.txt: File type text/html"));
+    assert!(!temp_dir.path().join("site").exists());
+    assert_eq!(std::fs::read_dir(temp_dir.path().join(".chunks")).unwrap().count(), 0);
+
+    // accepted files keep their detected type
+    let archive = make_zip(&[("readme.txt", b"hello"), ("logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")]);
+    let (status, _) = upload(&state, "?dir=site", &[], &archive).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state.metadata.get("site/logo.png").detected_type.as_deref(), Some("image/png"));
+    assert_eq!(state.metadata.get("site/readme.txt").detected_type.as_deref(), Some("text/plain"));
+}
+
+#[tokio::test]
+async fn test_extraction_stops_at_the_quota() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let quotas = QuotaSettings {
+        global: Quota { max_bytes: Some(1000), max_files: None },
+        per_key: HashMap::from([("ci".to_string(), Quota { max_bytes: Some(300), max_files: None })]),
+    };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_quotas(quotas));
+    std::fs::write(temp_dir.path().join("other.bin"), [0u8; 600]).unwrap();
+
+    // the key's quota is the tighter one, the entry is refused before any of it is written
+    let (status, body) = upload(&state, "?dir=big", &[], &make_zip(&[("a.bin", &[1u8; 301])])).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE, "{}", body);
+    assert_eq!(body["error"], "Not enough storage left: the archive expands to more than 300 bytes");
+    let (status, _) = upload(&state, "?dir=big", &[], &make_tar(&[("a.bin", &[1u8; 200]), ("b.bin", &[2u8; 200])])).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    assert!(!temp_dir.path().join("big").exists());
+    assert_eq!(std::fs::read_dir(temp_dir.path().join(".chunks")).unwrap().count(), 0);
+
+    // then the global one, with what's already stored counted
+    put_bytes(state.storage.as_ref(), "other.bin", Bytes::from(vec![0u8; 900])).await.unwrap();
+    let (status, body) = upload(&state, "?dir=big", &[], &make_zip(&[("a.bin", &[1u8; 101])])).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(body["error"], "Not enough storage left: the archive expands to more than 100 bytes");
+    let (status, body) = upload(&state, "?dir=big", &[], &make_zip(&[("a.bin", &[1u8; 100])])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn test_archive_needs_free_disk_space() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let disk = DiskSettings { reserve_bytes: u64::MAX / 2, health_min_free_bytes: 0 };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_disk(disk));
+
+    let (status, body) = upload(&state, "?dir=site", &[], &make_zip(&[("a.txt", b"a")])).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE, "{}", body);
+    assert!(body["error"].as_str().unwrap().contains("Not enough disk space"));
+    assert_eq!(std::fs::read_dir(temp_dir.path().join(".chunks")).unwrap().count(), 0);
+}
@@ -0,0 +1,144 @@
+use juicebox_omega::config::{Config, VirtualHostConfig};
+use juicebox_omega::fallback::{FallbackSettings, MAX_ERROR_PAGE_DEPTH};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::vhosts::{VirtualHost, VirtualHosts};
+use axum::http::{header, StatusCode};
+use std::sync::Arc;
+
+mod common;
+use common::{fetch, write_files};
+
+#[tokio::test]
+async fn test_spa_directories_serve_their_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[
+        ("app/index.html", "app shell"),
+        ("app/admin/index.html", "admin shell"),
+        ("app/main.js", "js"),
+        ("other/index.html", "other"),
+    ]);
+    let fallbacks = FallbackSettings::new(&["/app/".to_string(), "app/admin".to_string()], false);
+    let state = AppState::new(temp_dir.path().to_path_buf()).with_fallbacks(fallbacks);
+    let app = build_public_router(Arc::new(state));
+
+    let (status, headers, body) = fetch(&app, "/app/users/42", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "app shell"));
+    assert_eq!(headers[header::CONTENT_TYPE], "text/html");
+    assert!(headers.contains_key("x-content-type-options"));
+    // the deepest spa directory wins
+    assert_eq!(fetch(&app, "/app/admin/settings", &[]).await.2, "admin shell");
+    // real files and missing assets are left alone
+    assert_eq!(fetch(&app, "/app/main.js", &[]).await.2, "js");
+    assert_eq!(fetch(&app, "/app/missing.js", &[]).await.0, StatusCode::NOT_FOUND);
+    // unless a browser navigates there
+    let html = [(header::ACCEPT, "text/html,application/xhtml+xml")];
+    assert_eq!(fetch(&app, "/app/profile/jane.doe", &html).await.2, "app shell");
+    // a conditional request for a route doesn't turn into a 304 of the index
+    let conditional = [(header::IF_MODIFIED_SINCE, "Sun, 01 Jan 2090 00:00:00 GMT")];
+    assert_eq!(fetch(&app, "/app/users/42", &conditional).await.0, StatusCode::OK);
+    // outside spa directories and for hidden paths nothing changes
+    assert_eq!(fetch(&app, "/other/route", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/app/.meta/metadata.json", &[]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_error_pages_keep_status_and_headers() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[
+        ("404.html", "root not found"),
+        ("410.html", "gone for good"),
+        ("docs/404.html", "docs not found"),
+        ("old.pdf", "old"),
+        (".meta/secret", "secret"),
+    ]);
+    let state = AppState::new(temp_dir.path().to_path_buf()).with_fallbacks(FallbackSettings::new(&[], true));
+    state.metadata.update("old.pdf", |m| m.expires_at = Some(chrono::Utc::now() - chrono::Duration::hours(1)));
+    let app = build_public_router(Arc::new(state));
+
+    let (status, headers, body) = fetch(&app, "/docs/guide/missing.html", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::NOT_FOUND, "docs not found"));
+    assert_eq!(headers[header::CONTENT_TYPE], "text/html");
+    assert_eq!(headers[header::CACHE_CONTROL], "no-store");
+    assert!(headers.contains_key("x-frame-options"));
+    assert!(!headers.contains_key(header::LAST_MODIFIED));
+
+    assert_eq!(fetch(&app, "/nope", &[]).await.2, "root not found");
+    assert_eq!(fetch(&app, "/docs/", &[]).await.2, "docs not found");
+    // expired files get the 410 page
+    let (status, _, body) = fetch(&app, "/old.pdf", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::GONE, "gone for good"));
+    // hidden paths get the page instead of their content
+    let (status, _, body) = fetch(&app, "/.meta/secret", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::NOT_FOUND, "root not found"));
+    // the pages themselves are still plain files
+    assert_eq!(fetch(&app, "/404.html", &[]).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_error_page_lookup_depth_is_capped() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let deep = format!("{}404.html", "d/".repeat(MAX_ERROR_PAGE_DEPTH + 1));
+    let shallow = format!("{}404.html", "d/".repeat(MAX_ERROR_PAGE_DEPTH));
+    write_files(temp_dir.path(), &[("404.html", "root not found"), (&deep, "too deep"), (&shallow, "deepest checked")]);
+    let state = AppState::new(temp_dir.path().to_path_buf()).with_fallbacks(FallbackSettings::new(&[], true));
+    let app = build_public_router(Arc::new(state));
+
+    // pages below the cap are never looked at, the nearest one within it answers
+    let path = format!("/{}missing", "d/".repeat(MAX_ERROR_PAGE_DEPTH + 1));
+    let (status, _, body) = fetch(&app, &path, &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::NOT_FOUND, "deepest checked"));
+    // a very long path still ends at the root page
+    let path = format!("/{}missing", "x/".repeat(200));
+    assert_eq!(fetch(&app, &path, &[]).await.2, "root not found");
+}
+
+#[tokio::test]
+async fn test_fallbacks_are_off_by_default() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[("404.html", "not found page"), ("index.html", "home")]);
+    let app = build_public_router(Arc::new(AppState::new(temp_dir.path().to_path_buf())));
+
+    let (status, _, body) = fetch(&app, "/missing", &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.is_empty());
+    assert_eq!(fetch(&app, "/", &[]).await.2, "home");
+}
+
+#[tokio::test]
+async fn test_virtual_host_overrides() {
+    let files = tempfile::tempdir().unwrap();
+    let spa = tempfile::tempdir().unwrap();
+    write_files(files.path(), &[("404.html", "bucket 404")]);
+    write_files(spa.path(), &[("index.html", "react app"), ("404.html", "spa 404")]);
+
+    let config = VirtualHostConfig {
+        name: "spa".to_string(),
+        hosts: vec!["app.example.com".to_string()],
+        root: Some(spa.path().to_path_buf()),
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: None,
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: None,
+        site: None,
+        spa_dirs: Some(vec!["/".to_string()]),
+        error_pages: Some(false),
+        listing_dirs: None,
+        sandbox: None,
+    };
+    let vhosts = VirtualHosts { hosts: vec![VirtualHost::from_config(&Config::from_env(), &config)], fallback: None };
+    let state = AppState::new(files.path().to_path_buf())
+        .with_fallbacks(FallbackSettings::new(&[], true))
+        .with_vhosts(vhosts);
+    let app = build_public_router(Arc::new(state));
+
+    let host = [(header::HOST, "app.example.com")];
+    assert_eq!(fetch(&app, "/checkout/step-2", &host).await.2, "react app");
+    let (status, _, body) = fetch(&app, "/bundle.js", &host).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.is_empty());
+    // the default host keeps its own settings
+    assert_eq!(fetch(&app, "/checkout/step-2", &[]).await.2, "bucket 404");
+}
@@ -0,0 +1,200 @@
+use juicebox_omega::filetypes::{detect_type, UploadTypeRules};
+use juicebox_omega::handlers::{complete_chunked_upload, init_chunked_upload, upload_chunk, upload_file};
+use juicebox_omega::models::{ChunkedUploadComplete, ChunkedUploadInit};
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use axum::body::{to_bytes, Body};
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+const PDF: &[u8] = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n1 0 obj\n<< /Type /Catalog >>\nendobj\n";
+
+fn list(entries: &[&str]) -> Vec<String> {
+    entries.iter().map(|s| s.to_string()).collect()
+}
+
+fn images_and_pdfs() -> UploadTypeRules {
+    UploadTypeRules::new(&[], &list(&["exe", "bat"]), &list(&["image/*", "application/pdf"]), &list(&["image/svg+xml"]))
+}
+
+// post a file as multipart, the part claiming whatever content type it's given
+async fn upload(state: &Arc<AppState>, filename: &str, claimed_type: &str, data: &[u8]) -> (StatusCode, serde_json::Value) {
+    let app = Router::new()
+        .route("/upload", post(upload_file))
+        .route("/chunk/:upload_id/:chunk_number", post(upload_chunk))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let mut body = format!(
+        "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        filename, claimed_type,
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri(if filename.starts_with("/chunk/") { filename.to_string() } else { "/upload".to_string() })
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+async fn send_chunk(state: &Arc<AppState>, upload_id: &str, chunk: usize, data: &[u8]) -> (StatusCode, serde_json::Value) {
+    upload(state, &format!("/chunk/{}/{}", upload_id, chunk), "application/octet-stream", data).await
+}
+
+#[test]
+fn test_detect_type() {
+    assert_eq!(detect_type(PNG), "image/png");
+    assert_eq!(detect_type(PDF), "application/pdf");
+    assert_eq!(detect_type(b"PK\x03\x04\x14\0\0\0\x08\0"), "application/zip");
+    let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+    elf.resize(64, 0);
+    assert_eq!(detect_type(&elf), "application/x-executable");
+    assert_eq!(detect_type(b"  <!DOCTYPE html>\n<html><script>alert(1)</script>"), "text/html");
+    // svg runs scripts too, with or without an xml prolog
+    assert_eq!(detect_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\" onload=\"alert(1)\"/>"), "image/svg+xml");
+    assert_eq!(detect_type(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), "image/svg+xml");
+    assert_eq!(detect_type(b"<?xml version=\"1.0\"?>\n<feed/>"), "text/xml");
+
+    assert_eq!(detect_type("plain notes, ünïcode too".as_bytes()), "text/plain");
+    // a character cut in half at the end of the sniffed bytes is still text
+    assert_eq!(detect_type(&"é".repeat(5000).as_bytes()[..8191]), "text/plain");
+    assert_eq!(detect_type(b"\x00\x01\x02\x03binary"), "application/octet-stream");
+    assert_eq!(detect_type(b""), "text/plain");
+}
+
+#[test]
+fn test_type_rules() {
+    let rules = images_and_pdfs();
+    assert_eq!(rules.deny_extensions, ["exe", "bat"]);
+    assert!(rules.check_extension("photo.JPG").is_ok());
+    assert!(rules.check_extension("README").is_ok());
+    assert_eq!(rules.check_extension("setup.EXE").unwrap_err(), "Extension .exe is not allowed");
+    assert!(rules.check_type("image/png").is_ok());
+    assert!(rules.check_type("application/pdf").is_ok());
+    // deny lists win over allow lists
+    assert_eq!(
+        rules.check_type("image/svg+xml").unwrap_err(),
+        "File type image/svg+xml is not allowed (detected from its content)",
+    );
+    assert!(rules.check_type("text/plain").is_err());
+
+    let rules = UploadTypeRules::new(&list(&[".PDF", "png", " "]), &[], &[], &list(&["text/html"]));
+    assert_eq!(rules.allow_extensions, ["pdf", "png"]);
+    assert!(rules.check_extension("report.pdf").is_ok());
+    assert_eq!(rules.check_extension("notes").unwrap_err(), "Files without an extension are not allowed");
+    assert!(rules.check_extension("archive.tar.gz").is_err());
+    assert!(rules.check_type("application/octet-stream").is_ok());
+    assert!(rules.check_type("text/html").is_err());
+    assert!(UploadTypeRules::default().check_type("application/x-executable").is_ok());
+}
+
+#[tokio::test]
+async fn test_single_uploads_are_checked() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_upload_types(images_and_pdfs()));
+
+    let (status, body) = upload(&state, "photo.png", "image/png", PNG).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["detected_type"], "image/png");
+    assert_eq!(state.metadata.get("photo.png").detected_type.as_deref(), Some("image/png"));
+
+    // the claimed name and type don't matter, the content does
+    let (status, body) = upload(&state, "evil.png", "image/png", b"<html><script>alert(1)</script></html>").await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["error"], "File type text/html is not allowed (detected from its content)");
+    let (status, body) = upload(&state, "setup.exe", "image/png", PNG).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["error"], "Extension .exe is not allowed");
+    assert!(!temp_dir.path().join("evil.png").exists() && !temp_dir.path().join("setup.exe").exists());
+
+    // without rules anything goes, and the type is still recorded
+    let open = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let (status, body) = upload(&open, "doc.bin", "application/octet-stream", PDF).await;
+    assert_eq!((status, body["detected_type"].as_str()), (StatusCode::OK, Some("application/pdf")));
+}
+
+#[tokio::test]
+async fn test_chunked_uploads_are_checked() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_upload_types(images_and_pdfs()));
+    let init = |filename: &str| ChunkedUploadInit {
+        filename: filename.to_string(),
+        total_size: 96,
+        chunk_size: 64,
+        options: Default::default(),
+    };
+
+    let refused = init_chunked_upload(State(state.clone()), None, Json(init("tool.bat"))).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // a bad first chunk ends the upload
+    let upload_id = init_chunked_upload(State(state.clone()), None, Json(init("scan.pdf"))).await.unwrap().0.upload_id;
+    let (status, body) = send_chunk(&state, &upload_id, 0, &[b'x'; 64]).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(body["error"].as_str().unwrap().contains("text/plain"));
+    assert!(!state.chunked_uploads.contains_key(&upload_id));
+    assert!(!temp_dir.path().join(".chunks").join(&upload_id).exists());
+    assert_eq!(send_chunk(&state, &upload_id, 1, b"rest").await.0, StatusCode::NOT_FOUND);
+
+    let upload_id = init_chunked_upload(State(state.clone()), None, Json(init("scan.pdf"))).await.unwrap().0.upload_id;
+    let mut pdf = PDF.to_vec();
+    pdf.resize(96, b' ');
+    assert_eq!(send_chunk(&state, &upload_id, 1, &pdf[64..]).await.0, StatusCode::OK);
+    assert_eq!(send_chunk(&state, &upload_id, 0, &pdf[..64]).await.0, StatusCode::OK);
+    let complete = ChunkedUploadComplete { upload_id };
+    let done = complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap().0;
+    assert_eq!(done.detected_type.as_deref(), Some("application/pdf"));
+    assert_eq!(state.metadata.get("scan.pdf").detected_type.as_deref(), Some("application/pdf"));
+
+    // chunks swapped out after the first check are caught on completion
+    let chunks_dir = temp_dir.path().join(".chunks/swapped");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), "#!/bin/sh\nrm -rf /").unwrap();
+    let upload = ChunkedUploadMetadata {
+        filename: "swapped.pdf".to_string(),
+        total_size: 18,
+        chunk_size: 64,
+        total_chunks: 1,
+        received_chunks: [0].into_iter().collect(),
+        ..Default::default()
+    };
+    state.chunked_uploads.insert("swapped".to_string(), upload);
+    let complete = ChunkedUploadComplete { upload_id: "swapped".to_string() };
+    let refused = complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(!chunks_dir.exists());
+    assert!(!temp_dir.path().join("swapped.pdf").exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_chunks_upload_concurrently() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let init = ChunkedUploadInit { filename: "big.bin".to_string(), total_size: 32 * 16, chunk_size: 16, options: Default::default() };
+    let upload_id = init_chunked_upload(State(state.clone()), None, Json(init)).await.unwrap().0.upload_id;
+
+    // every chunk at once, none of them waiting on another's write
+    let sends = (0..32).map(|chunk| {
+        let (state, upload_id) = (state.clone(), upload_id.clone());
+        tokio::spawn(async move { send_chunk(&state, &upload_id, chunk, &[b'a' + (chunk % 26) as u8; 16]).await.0 })
+    });
+    for status in futures_util::future::join_all(sends).await {
+        assert_eq!(status.unwrap(), StatusCode::OK);
+    }
+    assert_eq!(state.chunked_uploads.get(&upload_id).unwrap().received_chunks.len(), 32);
+
+    let done = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id })).await.unwrap().0;
+    assert_eq!(done.size, 32 * 16);
+    let content = std::fs::read(temp_dir.path().join("big.bin")).unwrap();
+    assert_eq!(&content[16 * 27..16 * 28], &[b'b'; 16]);
+}
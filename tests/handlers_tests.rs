@@ -3,31 +3,31 @@ use juicebox_omega::handlers::{
     batch_delete_files, complete_chunked_upload
 };
 use juicebox_omega::state::AppState;
-use juicebox_omega::models::{ChunkedUploadInit, BatchDeleteRequest, ChunkedUploadComplete};
-use axum::extract::{State, Path};
+use juicebox_omega::models::{ChunkedUploadInit, BatchDeleteRequest, ChunkedUploadComplete, ListFilesQuery};
+use axum::extract::{State, Path, Query};
 use axum::Json;
 use axum::http::StatusCode;
 use std::sync::Arc;
 use std::fs::File;
 use std::io::Write;
-use dashmap::DashMap;
 
 #[tokio::test]
 async fn test_health_check() {
-    let response = health_check().await;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+
+    let (status, response) = health_check(State(state)).await;
+    assert_eq!(status, StatusCode::OK);
     assert_eq!(response.0["status"], "healthy");
 }
 
 #[tokio::test]
 async fn test_list_files() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     // Empty dir
-    let response = list_files(State(state.clone())).await.unwrap();
+    let response = list_files(State(state.clone()), Query(ListFilesQuery::default())).await.unwrap();
     assert_eq!(response.0.files.len(), 0);
     assert_eq!(response.0.total, 0);
 
@@ -37,7 +37,7 @@ async fn test_list_files() {
     writeln!(file, "hello world").unwrap();
 
     // List again
-    let response = list_files(State(state.clone())).await.unwrap();
+    let response = list_files(State(state.clone()), Query(ListFilesQuery::default())).await.unwrap();
     assert_eq!(response.0.files.len(), 1);
     assert_eq!(response.0.files[0].name, "test.txt");
 }
@@ -45,10 +45,7 @@ async fn test_list_files() {
 #[tokio::test]
 async fn test_delete_file() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     // Create a file
     let file_path = temp_dir.path().join("delete_me.txt");
@@ -68,10 +65,7 @@ async fn test_delete_file() {
 #[tokio::test]
 async fn test_get_stats() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     // Create files
     let file1 = temp_dir.path().join("file1.txt");
@@ -90,18 +84,16 @@ async fn test_get_stats() {
 #[tokio::test]
 async fn test_init_chunked_upload() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     let payload = ChunkedUploadInit {
         filename: "large_file.bin".to_string(),
         total_size: 1024,
         chunk_size: 256,
+        options: Default::default(),
     };
 
-    let response = init_chunked_upload(State(state.clone()), Json(payload)).await.unwrap();
+    let response = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap();
     assert_eq!(response.0.chunk_size, 256);
     assert_eq!(response.0.total_chunks, 4);
     
@@ -116,10 +108,7 @@ async fn test_init_chunked_upload() {
 #[tokio::test]
 async fn test_batch_delete_files() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     // Create files
     let f1 = temp_dir.path().join("f1.txt");
@@ -143,10 +132,7 @@ async fn test_batch_delete_files() {
 #[tokio::test]
 async fn test_complete_chunked_upload() {
     let temp_dir = tempfile::tempdir().unwrap();
-    let state = Arc::new(AppState {
-        files_dir: temp_dir.path().to_path_buf(),
-        chunked_uploads: DashMap::new(),
-    });
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
 
     let upload_id = "test-upload-id".to_string();
     let filename = "completed.txt".to_string();
@@ -162,6 +148,7 @@ async fn test_complete_chunked_upload() {
         chunk_size: 5,
         total_chunks: 2,
         received_chunks,
+        ..Default::default()
     };
     state.chunked_uploads.insert(upload_id.clone(), metadata);
 
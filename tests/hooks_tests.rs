@@ -0,0 +1,362 @@
+use juicebox_omega::config::Config;
+use juicebox_omega::extract::upload_archive;
+use juicebox_omega::handlers::{complete_chunked_upload, upload_file};
+use juicebox_omega::hooks::{
+    get_hook_job, list_hook_jobs, rerun_hooks, run_hooks, Hook, HookAction, HookJob, HookPipeline, JobStatus,
+};
+use juicebox_omega::models::{ChunkedUploadComplete, HookJobsQuery};
+use juicebox_omega::server::build_webdav_router;
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use juicebox_omega::trash::move_to_trash;
+use juicebox_omega::webdav::WebDav;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use base64::Engine;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::util::ServiceExt;
+
+fn hook(name: &str, pattern: &str, action: HookAction) -> Hook {
+    Hook {
+        name: name.to_string(),
+        pattern: pattern.to_string(),
+        action,
+        timeout: Duration::from_secs(10),
+        retries: 0,
+    }
+}
+
+fn command(name: &str, pattern: &str, command: &str) -> Hook {
+    hook(name, pattern, HookAction::Command(command.to_string()))
+}
+
+async fn upload(state: &Arc<AppState>, filename: &str, data: &[u8]) -> StatusCode {
+    let app = Router::new()
+        .route("/upload", post(upload_file))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let mut body = format!(
+        "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n",
+        filename,
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri("/upload")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    status
+}
+
+// the file's job once it's done
+async fn finished(state: &Arc<AppState>, filename: &str) -> HookJob {
+    for _ in 0..200 {
+        if let Ok(Json(job)) = get_hook_job(State(state.clone()), Path(filename.to_string())).await {
+            if matches!(job.status, JobStatus::Succeeded | JobStatus::Failed) {
+                return job;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("hooks for {} never finished", filename);
+}
+
+#[test]
+fn test_hook_steps_and_patterns() {
+    assert_eq!(HookAction::parse_step("tag:processed"), Some(HookAction::Tag("processed".to_string())));
+    assert_eq!(HookAction::parse_step(" TAG : done "), Some(HookAction::Tag("done".to_string())));
+    assert_eq!(
+        HookAction::parse_step("webhook:https://hooks.example.com/a?b=c"),
+        Some(HookAction::Webhook("https://hooks.example.com/a?b=c".to_string()))
+    );
+    assert_eq!(HookAction::parse_step("webhook:ftp://example.com"), None);
+    assert_eq!(HookAction::parse_step("tag:"), None);
+    assert_eq!(HookAction::parse_step("transcode:mp4"), None);
+    assert_eq!(HookAction::parse_step("tag"), None);
+
+    let pipeline = HookPipeline::new(
+        vec![
+            command("thumbs", "/photos/*.jpg", "true"),
+            command("index", "*.pdf", "true"),
+            hook("all", "*", HookAction::Tag("seen".to_string())),
+        ],
+        1,
+        Duration::ZERO,
+    );
+    let names = |filename: &str| pipeline.matching(filename).iter().map(|h| h.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names("photos/cat.jpg"), ["thumbs", "all"]);
+    assert_eq!(names("photos/2024/cat.jpg"), ["thumbs", "all"]);
+    assert_eq!(names("cat.jpg"), ["all"]);
+    assert_eq!(names("docs/report.pdf"), ["index", "all"]);
+
+    // retries back off, doubling up to a cap, however large the delay or the attempt
+    let pipeline = HookPipeline::new(Vec::new(), 1, Duration::from_secs(5));
+    assert_eq!(pipeline.backoff(1), Duration::from_secs(5));
+    assert_eq!(pipeline.backoff(3), Duration::from_secs(20));
+    assert_eq!(pipeline.backoff(40), Duration::from_secs(3600));
+    assert_eq!(HookPipeline::new(Vec::new(), 1, Duration::MAX).backoff(2), Duration::from_secs(3600));
+}
+
+#[tokio::test]
+async fn test_hooks_run_after_uploads() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    let out = temp_dir.path().join("out");
+    std::fs::create_dir_all(&out).unwrap();
+
+    // a webhook receiver
+    let received = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/uploads", listener.local_addr().unwrap());
+    let receiver = received.clone();
+    let webhook = Router::new().route(
+        "/uploads",
+        post(move |body: Bytes| async move {
+            receiver.lock().unwrap().push(serde_json::from_slice(&body).unwrap());
+            StatusCode::NO_CONTENT
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, webhook).await.unwrap() });
+
+    let record = format!(
+        "cat > {0}/stdin.json; echo \"$JUICEBOX_HOOK $JUICEBOX_FILENAME $JUICEBOX_SIZE $(cat \"$JUICEBOX_FILE\")\" > {0}/env; echo recorded",
+        out.display()
+    );
+    let pipeline = HookPipeline::new(
+        vec![
+            command("record", "/docs-*", &record),
+            hook("tag", "*", HookAction::Tag("processed".to_string())),
+            hook("notify", "*.txt", HookAction::Webhook(url)),
+        ],
+        2,
+        Duration::ZERO,
+    );
+    let state = Arc::new(AppState::new(files_dir).with_hooks(pipeline));
+
+    assert_eq!(upload(&state, "docs-notes.txt", b"hello hooks").await, StatusCode::OK);
+    let job = finished(&state, "docs-notes.txt").await;
+    assert_eq!(job.status, JobStatus::Succeeded);
+    assert_eq!(job.steps.iter().map(|s| s.hook.as_str()).collect::<Vec<_>>(), ["record", "tag", "notify"]);
+    assert!(job.steps.iter().all(|s| s.status == JobStatus::Succeeded && s.attempts == 1));
+    assert_eq!((job.steps[0].exit_code, job.steps[0].output.as_deref()), (Some(0), Some("recorded")));
+    assert!(job.started_at.is_some() && job.finished_at.is_some());
+
+    assert_eq!(std::fs::read_to_string(out.join("env")).unwrap(), "record docs-notes.txt 11 hello hooks\n");
+    let stdin: serde_json::Value = serde_json::from_slice(&std::fs::read(out.join("stdin.json")).unwrap()).unwrap();
+    assert_eq!((stdin["filename"].as_str(), stdin["size"].as_u64()), (Some("docs-notes.txt"), Some(11)));
+    assert!(stdin["path"].as_str().unwrap().ends_with("files/docs-notes.txt"));
+    assert!(stdin["metadata"]["hash"].is_object());
+    assert!(state.metadata.get("docs-notes.txt").tags.contains("processed"));
+    let notified = received.lock().unwrap().clone();
+    assert_eq!(notified.len(), 1);
+    assert_eq!(notified[0]["hook"], "notify");
+    assert_eq!(notified[0]["metadata"]["tags"], serde_json::json!(["processed"]));
+
+    // chunked uploads get them too, only the hooks matching run
+    let chunks_dir = state.files_dir.join(".chunks/up");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), "%PDF-1.7").unwrap();
+    let chunked = ChunkedUploadMetadata {
+        filename: "report.pdf".to_string(),
+        total_size: 8,
+        chunk_size: 64,
+        total_chunks: 1,
+        received_chunks: [0].into_iter().collect(),
+        ..Default::default()
+    };
+    state.chunked_uploads.insert("up".to_string(), chunked);
+    let complete = ChunkedUploadComplete { upload_id: "up".to_string() };
+    assert_eq!(complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap().0.size, 8);
+    let job = finished(&state, "report.pdf").await;
+    assert_eq!((job.status, job.steps.len()), (JobStatus::Succeeded, 1));
+    assert!(state.metadata.get("report.pdf").tags.contains("processed"));
+
+    // nothing at all for files no hook matches
+    let plain = Arc::new(AppState::new(state.files_dir.clone()));
+    assert_eq!(upload(&plain, "other.txt", b"x").await, StatusCode::OK);
+    let missing = get_hook_job(State(plain.clone()), Path("other.txt".to_string())).await.unwrap_err();
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+    let refused = rerun_hooks(State(plain), Path("other.txt".to_string())).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_failed_hooks() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let marker = temp_dir.path().join("tried");
+    let flaky = format!("if [ -f {0} ]; then echo fine now; else touch {0}; echo not yet >&2; exit 3; fi", marker.display());
+    let mut retried = command("flaky", "*", &flaky);
+    retried.retries = 2;
+    let mut slow = command("slow", "slow.txt", "sleep 5");
+    slow.timeout = Duration::from_millis(200);
+    let pipeline = HookPipeline::new(
+        vec![
+            retried,
+            slow,
+            command("broken", "broken.txt", "echo giving up; exit 7"),
+            hook("tag", "*", HookAction::Tag("processed".to_string())),
+        ],
+        4,
+        Duration::from_millis(10),
+    );
+    let state = Arc::new(AppState::new(temp_dir.path().join("files")).with_hooks(pipeline));
+
+    // a retry gets it through
+    assert_eq!(upload(&state, "ok.txt", b"ok").await, StatusCode::OK);
+    let job = finished(&state, "ok.txt").await;
+    assert_eq!(job.status, JobStatus::Succeeded);
+    assert_eq!((job.steps[0].attempts, job.steps[0].output.as_deref()), (2, Some("fine now")));
+
+    // a failure skips the steps after it
+    assert_eq!(upload(&state, "broken.txt", b"x").await, StatusCode::OK);
+    let job = finished(&state, "broken.txt").await;
+    assert_eq!(job.status, JobStatus::Failed);
+    let broken = &job.steps[1];
+    assert_eq!((broken.status, broken.attempts, broken.exit_code), (JobStatus::Failed, 1, Some(7)));
+    assert_eq!(broken.error.as_deref(), Some("Exited with status 7"));
+    assert_eq!(broken.output.as_deref(), Some("giving up"));
+    assert_eq!(job.steps[2].status, JobStatus::Skipped);
+    assert!(!state.metadata.get("broken.txt").tags.contains("processed"));
+
+    assert_eq!(upload(&state, "slow.txt", b"x").await, StatusCode::OK);
+    let job = finished(&state, "slow.txt").await;
+    assert_eq!(job.steps[1].status, JobStatus::Failed);
+    assert_eq!(job.steps[1].error.as_deref(), Some("Timed out after 200ms"));
+
+    let Json(failed) = list_hook_jobs(State(state.clone()), Query(HookJobsQuery { status: Some(JobStatus::Failed) })).await;
+    let mut names: Vec<String> = failed.jobs.into_iter().map(|job| job.filename).collect();
+    names.sort();
+    assert_eq!(names, ["broken.txt", "slow.txt"]);
+    assert_eq!(list_hook_jobs(State(state.clone()), Query(HookJobsQuery::default())).await.0.total, 3);
+
+    // running them again replaces the job
+    let Json(again) = rerun_hooks(State(state.clone()), Path("broken.txt".to_string())).await.unwrap();
+    assert_ne!(again.id, job.id);
+    assert_eq!(finished(&state, "broken.txt").await.id, again.id);
+    let missing = rerun_hooks(State(state.clone()), Path("gone.txt".to_string())).await.unwrap_err();
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+    // a deleted file's job goes with it
+    move_to_trash(&state, "broken.txt").await.unwrap();
+    assert!(get_hook_job(State(state.clone()), Path("broken.txt".to_string())).await.is_err());
+    assert_eq!(list_hook_jobs(State(state.clone()), Query(HookJobsQuery::default())).await.0.total, 2);
+}
+
+#[tokio::test]
+async fn test_hooks_run_on_webdav_and_archive_uploads() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    std::fs::create_dir_all(&files_dir).unwrap();
+    let pipeline = HookPipeline::new(vec![hook("tag", "*", HookAction::Tag("processed".to_string()))], 2, Duration::ZERO);
+    let state = Arc::new(AppState::new(files_dir).with_hooks(pipeline));
+
+    let mut config = Config::from_env();
+    config.api_key_hash = Config::hash_api_key("admin-secret");
+    let dav = build_webdav_router(Arc::new(WebDav::new(state.clone(), &config)), &config)
+        .layer(Extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)))));
+    let credentials = base64::engine::general_purpose::STANDARD.encode("admin:admin-secret");
+    let request = Request::builder()
+        .method("PUT")
+        .uri("/default/notes.txt")
+        .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+        .body(Body::from("notes"))
+        .unwrap();
+    assert_eq!(dav.oneshot(request).await.unwrap().status(), StatusCode::CREATED);
+
+    let mut tar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_size(5);
+    header.set_mode(0o644);
+    tar.append_data(&mut header, "index.txt", &b"index"[..]).unwrap();
+    let app = Router::new()
+        .route("/upload/archive", post(upload_archive))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let mut body = b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"site.tar\"\r\n\r\n".to_vec();
+    body.extend_from_slice(&tar.into_inner().unwrap());
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri("/upload/archive")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+    for filename in ["notes.txt", "index.txt"] {
+        assert_eq!(finished(&state, filename).await.status, JobStatus::Succeeded);
+        assert!(state.metadata.get(filename).tags.contains("processed"));
+    }
+}
+
+#[tokio::test]
+async fn test_hook_concurrency() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let pipeline = HookPipeline::new(vec![command("slow", "*", "sleep 1")], 1, Duration::ZERO);
+    let state = Arc::new(AppState::new(temp_dir.path().join("files")).with_hooks(pipeline));
+
+    assert_eq!(upload(&state, "a.txt", b"a").await, StatusCode::OK);
+    assert_eq!(upload(&state, "b.txt", b"b").await, StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // one file at a time, the other waits its turn
+    let mut statuses: Vec<JobStatus> = state.hook_jobs.iter().map(|job| job.status).collect();
+    statuses.sort_by_key(|status| *status as u8);
+    assert_eq!(statuses, [JobStatus::Queued, JobStatus::Running]);
+
+    assert_eq!(finished(&state, "a.txt").await.status, JobStatus::Succeeded);
+    assert_eq!(finished(&state, "b.txt").await.status, JobStatus::Succeeded);
+}
+
+#[tokio::test]
+async fn test_retry_delay_frees_the_slot() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut flaky = command("flaky", "a.txt", "exit 1");
+    flaky.retries = 1;
+    let pipeline = HookPipeline::new(
+        vec![flaky, hook("tag", "b.txt", HookAction::Tag("processed".to_string()))],
+        1,
+        Duration::from_secs(3),
+    );
+    let state = Arc::new(AppState::new(temp_dir.path().join("files")).with_hooks(pipeline));
+
+    assert_eq!(upload(&state, "a.txt", b"a").await, StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(upload(&state, "b.txt", b"b").await, StatusCode::OK);
+
+    // b runs while a waits out its retry delay
+    let started = std::time::Instant::now();
+    assert_eq!(finished(&state, "b.txt").await.status, JobStatus::Succeeded);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    let a = get_hook_job(State(state.clone()), Path("a.txt".to_string())).await.unwrap().0;
+    assert_eq!((a.status, a.steps[0].attempts), (JobStatus::Running, 1));
+}
+
+#[tokio::test]
+async fn test_hook_queue_is_bounded() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let pipeline = HookPipeline::new(vec![hook("tag", "*", HookAction::Tag("processed".to_string()))], 1, Duration::ZERO);
+    let state = Arc::new(AppState::new(temp_dir.path().join("files")).with_hooks(pipeline));
+
+    // nothing gets to start while the jobs pile up, past the queue's size they fail right away
+    let jobs: Vec<HookJob> = (0..1100).map(|i| run_hooks(&state, &format!("{}.txt", i)).unwrap()).collect();
+    let refused: Vec<&HookJob> = jobs.iter().filter(|job| job.status == JobStatus::Failed).collect();
+    assert_eq!(refused.len(), 100);
+    assert_eq!(refused[0].filename, "1000.txt");
+    assert_eq!(refused[0].error.as_deref(), Some("Too many hook jobs waiting"));
+    assert_eq!(refused[0].steps[0].status, JobStatus::Skipped);
+
+    // the queued ones still run
+    assert_eq!(finished(&state, "999.txt").await.status, JobStatus::Failed);
+    assert_eq!(finished(&state, "999.txt").await.steps[0].error.as_deref(), Some("File not found"));
+}
@@ -0,0 +1,150 @@
+use juicebox_omega::config::{Config, VirtualHostConfig};
+use juicebox_omega::listing::ListingSettings;
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::vhosts::{VirtualHost, VirtualHosts};
+use axum::http::{header, StatusCode};
+use axum::Router;
+use std::sync::Arc;
+
+mod common;
+use common::{fetch, write_files};
+
+fn dirs(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+async fn names(app: &Router, uri: &str) -> Vec<String> {
+    let (status, _, body) = fetch(app, uri, &[]).await;
+    assert_eq!(status, StatusCode::OK, "{}", uri);
+    let listing: serde_json::Value = serde_json::from_str(&body).unwrap();
+    listing["entries"].as_array().unwrap().iter().map(|e| e["name"].as_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn test_listing_settings() {
+    let settings = ListingSettings::new(&dirs(&["/", "!/private/", "private/shared", "../etc"]), &dirs(&["*.bak", "draft-*.md"]));
+    assert_eq!(settings.dirs.len(), 3);
+    assert!(settings.is_enabled());
+    assert!(settings.is_listed("") && settings.is_listed("docs/api"));
+    assert!(!settings.is_listed("private") && !settings.is_listed("private/keys"));
+    assert!(settings.is_listed("private/shared/photos"));
+    // prefixes only match whole segments
+    assert!(settings.is_listed("private-ish"));
+
+    for hidden in [".chunks", ".env", "notes.bak", "draft-v2.md"] {
+        assert!(settings.is_hidden(hidden), "{}", hidden);
+    }
+    for shown in ["notes.bak.txt", "draft.md", "readme.md"] {
+        assert!(!settings.is_hidden(shown), "{}", shown);
+    }
+    assert!(!ListingSettings::new(&dirs(&["!/"]), &[]).is_enabled());
+    assert!(!ListingSettings::default().is_listed(""));
+}
+
+#[tokio::test]
+async fn test_html_and_json_listings() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[
+        ("pub/b.txt", "bb"),
+        ("pub/A <odd> & name.txt", "a"),
+        ("pub/zeta/file.txt", "z"),
+        ("pub/old.bak", "old"),
+        ("pub/.secret", "secret"),
+        ("site/index.html", "home"),
+        ("top.txt", "top"),
+    ]);
+    std::fs::create_dir_all(temp_dir.path().join("pub/empty")).unwrap();
+    let state = AppState::new(temp_dir.path().to_path_buf())
+        .with_listings(ListingSettings::new(&dirs(&["/"]), &dirs(&["*.bak"])));
+    let app = build_public_router(Arc::new(state));
+
+    let (status, headers, body) = fetch(&app, "/pub/", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(headers[header::VARY], "Accept");
+    assert!(headers.contains_key("x-content-type-options"));
+    assert!(body.contains("<title>Index of /pub/</title>"));
+    assert!(body.contains("<a href=\"./A%20%3Codd%3E%20&amp;%20name.txt\">A &lt;odd&gt; &amp; name.txt</a>"), "{}", body);
+    assert!(body.contains("href=\"./zeta/\"") && body.contains("href=\"../\""));
+    assert!(!body.contains("old.bak") && !body.contains(".secret"));
+
+    let (_, headers, body) = fetch(&app, "/pub/", &[(header::ACCEPT, "application/json")]).await;
+    assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+    let listing: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(listing["path"], "/pub/");
+    assert_eq!(listing["total"], 4);
+    assert_eq!(listing["entries"][2]["size"], 1);
+    assert!(listing["entries"][2]["modified"].is_string());
+
+    // the root leaves internal directories out
+    let root = names(&app, "/?format=json").await;
+    assert!(root.iter().all(|name| !name.starts_with('.')), "{:?}", root);
+    assert!(root.contains(&"top.txt".to_string()));
+    // empty directories list, missing ones and index files are left alone
+    assert_eq!(names(&app, "/pub/empty/?format=json").await, Vec::<String>::new());
+    assert_eq!(fetch(&app, "/pub/missing/", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/site/", &[]).await.2, "home");
+    assert_eq!(fetch(&app, "/.meta/", &[]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_listing_sort_order() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[("b.txt", "12345"), ("C.txt", "1"), ("a.txt", "123"), ("dir/x", "x")]);
+    let app = build_public_router(Arc::new(
+        AppState::new(temp_dir.path().to_path_buf()).with_listings(ListingSettings::new(&dirs(&["/"]), &[])),
+    ));
+
+    // directories first, then names without regard to case
+    assert_eq!(names(&app, "/?format=json").await, ["dir", "a.txt", "b.txt", "C.txt"]);
+    assert_eq!(names(&app, "/?format=json&order=desc").await, ["dir", "C.txt", "b.txt", "a.txt"]);
+    assert_eq!(names(&app, "/?format=json&sort=size").await, ["dir", "C.txt", "a.txt", "b.txt"]);
+    assert_eq!(names(&app, "/?format=json&sort=size&order=desc").await, ["dir", "b.txt", "a.txt", "C.txt"]);
+    // unknown options fall back to the default order
+    assert_eq!(names(&app, "/?format=json&sort=color").await, ["dir", "a.txt", "b.txt", "C.txt"]);
+
+    let body = fetch(&app, "/?sort=size", &[]).await.2;
+    assert!(body.contains("href=\"?sort=size&amp;order=desc\">Size ▲"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_listings_per_directory_and_host() {
+    let files = tempfile::tempdir().unwrap();
+    let other = tempfile::tempdir().unwrap();
+    write_files(files.path(), &[("pub/a.txt", "a"), ("pub/private/key.txt", "k"), ("secret/b.txt", "b")]);
+    write_files(other.path(), &[("docs/readme.md", "r")]);
+
+    let config = VirtualHostConfig {
+        name: "docs".to_string(),
+        hosts: vec!["docs.example.com".to_string()],
+        root: Some(other.path().to_path_buf()),
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: None,
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: None,
+        site: None,
+        spa_dirs: None,
+        error_pages: None,
+        listing_dirs: Some(vec!["/docs".to_string()]),
+        sandbox: None,
+    };
+    let state = AppState::new(files.path().to_path_buf())
+        .with_listings(ListingSettings::new(&dirs(&["/pub", "!/pub/private"]), &[]))
+        .with_vhosts(VirtualHosts { hosts: vec![VirtualHost::from_config(&Config::from_env(), &config)], fallback: None });
+    let app = build_public_router(Arc::new(state));
+
+    assert_eq!(names(&app, "/pub/?format=json").await, ["private", "a.txt"]);
+    assert_eq!(fetch(&app, "/pub/private/", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/pub/private/key.txt", &[]).await.2, "k");
+    assert_eq!(fetch(&app, "/secret/", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/", &[]).await.0, StatusCode::NOT_FOUND);
+
+    let host = [(header::HOST, "docs.example.com")];
+    let (status, _, body) = fetch(&app, "/docs/", &host).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("readme.md"));
+    assert_eq!(fetch(&app, "/", &host).await.0, StatusCode::NOT_FOUND);
+}
@@ -1,10 +1,17 @@
-use juicebox_omega::middleware::{add_security_headers, validate_api_key};
-use juicebox_omega::config::Config;
+use juicebox_omega::middleware::{
+    add_custom_security_headers, add_security_headers, deny_hidden_paths, validate_api_key, ApiKeyName, ApiKeyRegistry,
+    SecurityHeaders,
+};
+use juicebox_omega::config::{ApiKeyConfig, Config, SecurityHeaderValues, SecurityPathConfig, VirtualHostConfig};
+use juicebox_omega::server::{build_admin_router, build_public_router};
+use juicebox_omega::state::AppState;
+use juicebox_omega::vhosts::{VirtualHost, VirtualHosts};
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
 use axum::middleware::from_fn;
 use axum::routing::get;
 use axum::Router;
+use std::sync::Arc;
 use tower::util::ServiceExt;
 
 #[tokio::test]
@@ -66,3 +73,168 @@ async fn test_validate_api_key() {
         .unwrap();
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_validate_named_api_keys() {
+    let registry = ApiKeyRegistry(vec![ApiKeyConfig {
+        name: "ci".to_string(),
+        key_hash: Config::hash_api_key("cikey"),
+        quota: Default::default(),
+        buckets: Vec::new(),
+    }]);
+
+    let app = Router::new()
+        .route("/", get(|axum::Extension(ApiKeyName(name)): axum::Extension<ApiKeyName>| async move { name }))
+        .layer(from_fn(validate_api_key))
+        .layer(axum::Extension(Config::hash_api_key("secret")))
+        .layer(axum::Extension(registry));
+
+    for (key, expected) in [("secret", Some("admin")), ("cikey", Some("ci")), ("nope", None)] {
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/").header("X-API-Key", key).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        match expected {
+            Some(name) => {
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                assert_eq!(body, name);
+            }
+            None => assert_eq!(response.status(), StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_deny_hidden_paths() {
+    let app = Router::new()
+        .fallback(|| async { "hello" })
+        .layer(from_fn(deny_hidden_paths));
+
+    for (uri, expected) in [
+        ("/file.txt", StatusCode::OK),
+        ("/.meta/metadata.json", StatusCode::NOT_FOUND),
+        ("/.chunks/abc/chunk_0", StatusCode::NOT_FOUND),
+        ("/%2Emeta/metadata.json", StatusCode::NOT_FOUND),
+        ("/.well-known/security.txt", StatusCode::OK),
+    ] {
+        let response = app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected, "{}", uri);
+    }
+}
+
+fn policy() -> SecurityHeaders {
+    let defaults = SecurityHeaders::default();
+    let values = SecurityHeaderValues {
+        content_security_policy: Some("default-src 'self'".to_string()),
+        strict_transport_security: Some("max-age=63072000; includeSubDomains".to_string()),
+        referrer_policy: Some("no-referrer".to_string()),
+        permissions_policy: Some("camera=()".to_string()),
+        cross_origin_opener_policy: Some("same-origin".to_string()),
+        cross_origin_resource_policy: Some("same-origin".to_string()),
+        ..Default::default()
+    };
+    let embeds = SecurityPathConfig {
+        name: "embeds".to_string(),
+        pattern: "/videos/*".to_string(),
+        headers: SecurityHeaderValues {
+            content_security_policy: Some("frame-ancestors https://blog.example.com".to_string()),
+            frame_options: Some(String::new()),
+            cross_origin_resource_policy: Some("cross-origin".to_string()),
+            ..Default::default()
+        },
+    };
+    SecurityHeaders { values: defaults.values.merged(&values), paths: vec![embeds], ..defaults }
+}
+
+#[tokio::test]
+async fn test_security_header_policy() {
+    let app = Router::new()
+        .route("/page.html", get(|| async { "page" }))
+        .route("/videos/intro.mp4", get(|| async { "video" }))
+        .layer(axum::middleware::from_fn_with_state(Arc::new(policy()), add_custom_security_headers));
+    let fetch = |uri: &'static str| {
+        let app = app.clone();
+        async move { app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap() }
+    };
+
+    let response = fetch("/page.html").await;
+    let headers = response.headers();
+    assert_eq!(headers["content-security-policy"], "default-src 'self'");
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert_eq!(headers["strict-transport-security"], "max-age=63072000; includeSubDomains");
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert_eq!(headers["permissions-policy"], "camera=()");
+    assert_eq!(headers["cross-origin-opener-policy"], "same-origin");
+    assert_eq!(headers["cross-origin-resource-policy"], "same-origin");
+    assert!(!headers.contains_key("cross-origin-embedder-policy"));
+
+    // matching paths change only what their rule sets, an empty value drops the header
+    let response = fetch("/videos/intro.mp4").await;
+    let headers = response.headers();
+    assert_eq!(headers["content-security-policy"], "frame-ancestors https://blog.example.com");
+    assert!(!headers.contains_key("x-frame-options"));
+    assert_eq!(headers["cross-origin-resource-policy"], "cross-origin");
+    assert_eq!(headers["strict-transport-security"], "max-age=63072000; includeSubDomains");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    // not found responses get them too
+    assert_eq!(fetch("/missing").await.headers()["referrer-policy"], "no-referrer");
+}
+
+#[tokio::test]
+async fn test_public_and_admin_routers_use_the_policy() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("videos")).unwrap();
+    std::fs::write(temp_dir.path().join("videos/intro.mp4"), "video").unwrap();
+    let mut embed_host = VirtualHostConfig {
+        name: "embed".to_string(),
+        hosts: vec!["embed.example.com".to_string()],
+        root: None,
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: None,
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: Some("SAMEORIGIN".to_string()),
+        site: None,
+        spa_dirs: None,
+        error_pages: None,
+        listing_dirs: None,
+        sandbox: None,
+    };
+    let plain_host = VirtualHostConfig { name: "plain".to_string(), hosts: vec!["plain.example.com".to_string()], security_headers: false, ..embed_host.clone() };
+    embed_host.content_security_policy = Some("default-src *".to_string());
+    let hosts = [&embed_host, &plain_host].map(|host| VirtualHost::from_config(&Config::from_env(), host));
+    let vhosts = VirtualHosts { hosts: hosts.to_vec(), fallback: None };
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_security_headers(policy()).with_vhosts(vhosts));
+    let public = build_public_router(state.clone());
+
+    let fetch = |app: Router, uri: &'static str, host: &'static str| async move {
+        let request = Request::builder().uri(uri).header("Host", host).body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    };
+    let headers = fetch(public.clone(), "/videos/intro.mp4", "localhost").await;
+    assert_eq!(headers["cross-origin-resource-policy"], "cross-origin");
+    assert!(!headers.contains_key("x-frame-options"));
+    let headers = fetch(public.clone(), "/other", "localhost").await;
+    assert_eq!(headers["x-frame-options"], "DENY");
+
+    // virtual hosts keep the policy with their own overrides on top
+    let headers = fetch(public.clone(), "/other", "embed.example.com").await;
+    assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+    assert_eq!(headers["content-security-policy"], "default-src *");
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert!(!fetch(public, "/other", "plain.example.com").await.contains_key("referrer-policy"));
+
+    // the admin api only sends them when asked to, rejected requests included
+    let mut config = Config::from_env();
+    config.admin_security_headers = true;
+    let headers = fetch(build_admin_router(state.clone(), &config), "/admin/health", "localhost").await;
+    assert_eq!(headers["strict-transport-security"], "max-age=63072000; includeSubDomains");
+    config.admin_security_headers = false;
+    let headers = fetch(build_admin_router(state, &config), "/admin/health", "localhost").await;
+    assert!(!headers.contains_key("strict-transport-security"));
+}
@@ -0,0 +1,154 @@
+use juicebox_omega::handlers::{get_stats, init_chunked_upload, upload_file};
+use juicebox_omega::metadata::FileMetadata;
+use juicebox_omega::middleware::ApiKeyName;
+use juicebox_omega::models::ChunkedUploadInit;
+use juicebox_omega::quota::{reserve_upload, usage, Quota, QuotaError, QuotaSettings};
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::put_bytes;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Extension, State};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+fn quota_state(dir: &std::path::Path, global: Quota, per_key: &[(&str, Quota)]) -> Arc<AppState> {
+    let quotas = QuotaSettings {
+        global,
+        per_key: per_key.iter().map(|(name, q)| (name.to_string(), *q)).collect(),
+    };
+    Arc::new(AppState::new(dir.to_path_buf()).with_quotas(quotas))
+}
+
+fn init(filename: &str, total_size: u64) -> Json<ChunkedUploadInit> {
+    Json(ChunkedUploadInit {
+        filename: filename.to_string(),
+        total_size,
+        chunk_size: 1024,
+        options: Default::default(),
+    })
+}
+
+#[tokio::test]
+async fn test_global_quota() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = quota_state(temp_dir.path(), Quota { max_bytes: Some(100), max_files: Some(2) }, &[]);
+    std::fs::write(temp_dir.path().join("a.txt"), [0u8; 40]).unwrap();
+
+    // chunked uploads hold their declared size
+    let response = init_chunked_upload(State(state.clone()), None, init("b.bin", 50)).await.unwrap();
+    assert!(state.chunked_uploads.contains_key(&response.0.upload_id));
+
+    let err = init_chunked_upload(State(state.clone()), None, init("c.bin", 20)).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::INSUFFICIENT_STORAGE);
+
+    // overwriting a file gives its space back, but the file count is already at the limit
+    let used = usage(&state, None).await.unwrap();
+    assert_eq!((used.used_bytes, used.reserved_bytes, used.reserved_files), (40, 50, 1));
+    assert!(reserve_upload(&state, None, 45, Some("a.txt")).await.is_ok());
+    assert!(matches!(reserve_upload(&state, None, 5, None).await, Err(QuotaError::Exceeded(_))));
+
+    // a dropped reservation frees its space
+    state.chunked_uploads.clear();
+    let reservation = reserve_upload(&state, None, 60, None).await.unwrap();
+    assert!(reserve_upload(&state, None, 1, None).await.is_err());
+    drop(reservation);
+    assert!(reserve_upload(&state, None, 1, None).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_per_key_quota() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = quota_state(
+        temp_dir.path(),
+        Quota::default(),
+        &[("ci", Quota { max_bytes: Some(100), max_files: None })],
+    );
+    std::fs::write(temp_dir.path().join("build.zip"), [0u8; 80]).unwrap();
+    std::fs::write(temp_dir.path().join("other.zip"), [0u8; 500]).unwrap();
+    state.metadata.files.insert("build.zip".to_string(), FileMetadata { owner: Some("ci".to_string()), ..Default::default() });
+
+    let ci = || Some(Extension(ApiKeyName("ci".to_string())));
+    let err = init_chunked_upload(State(state.clone()), ci(), init("log.txt", 30)).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::INSUFFICIENT_STORAGE);
+    assert!(err.1.0.error.contains("ci"));
+
+    assert!(init_chunked_upload(State(state.clone()), ci(), init("log.txt", 20)).await.is_ok());
+
+    // keys without a quota (and the main admin key) are only bound by the global one
+    let admin = Some(Extension(ApiKeyName("admin".to_string())));
+    assert!(init_chunked_upload(State(state.clone()), admin, init("big.bin", 10_000)).await.is_ok());
+
+    let stats = get_stats(State(state.clone())).await.unwrap();
+    let ci_usage = &stats.0.key_quotas["ci"];
+    assert_eq!((ci_usage.used_bytes, ci_usage.used_files), (80, 1));
+    assert_eq!(ci_usage.reserved_bytes, 20);
+    assert_eq!(ci_usage.max_bytes, Some(100));
+    assert_eq!(stats.0.quota.used_bytes, 580);
+    assert_eq!(stats.0.quota.max_bytes, None);
+}
+
+#[tokio::test]
+async fn test_usage_is_kept_up_to_date_without_walking_the_store() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = quota_state(temp_dir.path(), Quota { max_bytes: Some(100), max_files: None }, &[]);
+    std::fs::write(temp_dir.path().join("a.txt"), [0u8; 40]).unwrap();
+    let used = |state: Arc<AppState>| async move {
+        let used = usage(&state, None).await.unwrap();
+        (used.used_bytes, used.used_files)
+    };
+    assert_eq!(used(state.clone()).await, (40, 1));
+
+    // writes, overwrites, moves and deletes through the storage adjust the counted totals
+    let storage = state.storage.clone();
+    put_bytes(storage.as_ref(), "b.txt", Bytes::from(vec![1u8; 30])).await.unwrap();
+    put_bytes(storage.as_ref(), "a.txt", Bytes::from(vec![2u8; 10])).await.unwrap();
+    assert_eq!(used(state.clone()).await, (40, 2));
+    storage.rename("b.txt", ".trash/b.txt").await.unwrap();
+    assert_eq!(used(state.clone()).await, (40, 1));
+    put_bytes(storage.as_ref(), ".chunks/x/chunk_0", Bytes::from(vec![3u8; 50])).await.unwrap();
+    storage.delete_prefix(".trash/").await.unwrap();
+    storage.delete("a.txt").await.unwrap();
+    assert_eq!(used(state.clone()).await, (0, 0));
+
+    // files dropped in behind the server's back are only seen once the totals are recounted
+    std::fs::write(temp_dir.path().join("c.txt"), [0u8; 20]).unwrap();
+    assert_eq!(used(state.clone()).await, (0, 0));
+    state.stored.invalidate();
+    assert_eq!(used(state.clone()).await, (20, 1));
+}
+
+#[tokio::test]
+async fn test_upload_that_exactly_fits_the_quota() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = quota_state(temp_dir.path(), Quota { max_bytes: Some(1000), max_files: None }, &[]);
+    let app = Router::new()
+        .route("/upload", post(upload_file))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let upload = |filename: &str, size: usize| {
+        let mut body = format!("--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n", filename).into_bytes();
+        body.extend(vec![b'x'; size]);
+        body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+        let length = body.len();
+        Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+            .header(header::CONTENT_LENGTH, length)
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    // the file is measured against the quota, not the form around it
+    let response = app.clone().oneshot(upload("big.bin", 1001)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+    let error = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&error).contains("1001 of 1000 bytes"));
+    assert!(!temp_dir.path().join("big.bin").exists());
+
+    let response = app.oneshot(upload("fits.bin", 1000)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(usage(&state, None).await.unwrap().used_bytes, 1000);
+}
@@ -0,0 +1,153 @@
+use juicebox_omega::buckets::Bucket;
+use juicebox_omega::config::{BucketConfig, Config};
+use juicebox_omega::redirects::{list_redirects, parse_rules, reload_redirects, update_redirects};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::{put_bytes, read_all, MemoryStorage};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use std::sync::Arc;
+
+mod common;
+use common::{fetch, write_files};
+
+#[test]
+fn test_parse_rules() {
+    let rules = parse_rules(
+        "# moved content\n\
+         /old.html /new.html\n\
+         \n\
+         /blog/:year/:slug  /posts/:year-:slug  308   # trailing comment\n\
+         /app/*  /app/index.html  200!  Cache-Control=\"no-cache, private\" X-Robots-Tag=noindex\n",
+    )
+    .unwrap();
+    assert_eq!(rules.len(), 3);
+    let info: Vec<_> = rules.iter().map(|rule| rule.info()).collect();
+    assert_eq!((info[0].status, info[0].force), (301, false));
+    assert_eq!(info[1].to, "/posts/:year-:slug");
+    assert_eq!((info[2].status, info[2].force), (200, true));
+    assert_eq!(info[2].headers["cache-control"], "no-cache, private");
+
+    let errors = parse_rules("/ok /fine\n/missing-target\nnot-a-path /x\n/a/*/b /c\n/a /b 404\n/a https://x.example 200\n/a /b X-Bad=\"oops")
+        .unwrap_err();
+    assert_eq!(errors.len(), 6);
+    assert!(errors[0].starts_with("line 2:"), "{:?}", errors);
+    assert!(errors[3].contains("unsupported status"), "{:?}", errors);
+}
+
+#[tokio::test]
+async fn test_redirects_and_rewrites() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[
+        ("current.txt", "current"),
+        ("app/index.html", "app shell"),
+        ("docs/page.html", "real page"),
+        (".meta/redirects", "\
+            /blog/:year/:slug   /posts/:year-:slug.html   302\n\
+            /docs/*             https://docs.example.com/:splat\n\
+            /docs/page.html     /elsewhere                 301\n\
+            /moved/page.html    /current.txt               301!\n\
+            /current.txt        /gone                      307\n\
+            /app/*              /app/index.html            200  Cache-Control=no-cache\n\
+            /peek               /.meta/redirects           200\n"),
+    ]);
+    let app = build_public_router(Arc::new(AppState::new(temp_dir.path().to_path_buf())));
+
+    let (status, headers, _) = fetch(&app, "/blog/2024/hello-world?ref=feed", &[]).await;
+    assert_eq!(status, StatusCode::FOUND);
+    assert_eq!(headers[header::LOCATION], "/posts/2024-hello-world.html?ref=feed");
+    let (status, headers, _) = fetch(&app, "/docs/guide/install", &[]).await;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[header::LOCATION], "https://docs.example.com/guide/install");
+
+    // an existing file shadows rules without a bang, and the first match wins either way
+    assert_eq!(fetch(&app, "/docs/page.html", &[]).await.2, "real page");
+    assert_eq!(fetch(&app, "/current.txt", &[]).await.2, "current");
+    assert_eq!(fetch(&app, "/moved/page.html", &[]).await.1[header::LOCATION], "/current.txt");
+
+    // rewrites serve another path in place and add the rule's headers
+    let (status, headers, body) = fetch(&app, "/app/settings/profile", &[]).await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "app shell"));
+    assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+    // but can't reach hidden files
+    assert_eq!(fetch(&app, "/peek", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(fetch(&app, "/unmatched", &[]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_rules_are_managed_without_restart() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    write_files(temp_dir.path(), &[("new.txt", "new")]);
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    let app = build_public_router(state.clone());
+    assert_eq!(fetch(&app, "/old.txt", &[]).await.0, StatusCode::NOT_FOUND);
+
+    let saved = update_redirects(State(state.clone()), "/old.txt /new.txt 308\n".to_string()).await.unwrap().0;
+    assert_eq!(saved.total, 1);
+    assert_eq!(fetch(&app, "/old.txt", &[]).await.1[header::LOCATION], "/new.txt");
+    assert!(temp_dir.path().join(".meta/redirects").exists());
+
+    // invalid rules are rejected and the old ones stay
+    let err = update_redirects(State(state.clone()), "/a /b 418\n".to_string()).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    assert!(err.1.error.contains("line 1"));
+    assert_eq!(fetch(&app, "/old.txt", &[]).await.0, StatusCode::PERMANENT_REDIRECT);
+
+    // edits made by hand show up after a reload
+    std::fs::write(temp_dir.path().join(".meta/redirects"), "/legacy/* /new.txt 200\n").unwrap();
+    assert_eq!(fetch(&app, "/legacy/file", &[]).await.0, StatusCode::NOT_FOUND);
+    let reloaded = reload_redirects(State(state.clone())).await.unwrap().0;
+    assert_eq!(reloaded.rules[0].from, "/legacy/*");
+    assert_eq!(fetch(&app, "/legacy/file", &[]).await.2, "new");
+    assert_eq!(fetch(&app, "/old.txt", &[]).await.0, StatusCode::NOT_FOUND);
+
+    std::fs::write(temp_dir.path().join(".meta/redirects"), "broken").unwrap();
+    assert_eq!(reload_redirects(State(state.clone())).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+    let listed = list_redirects(State(state)).await.0;
+    assert_eq!((listed.total, listed.text.as_str()), (1, "broken"));
+}
+
+#[tokio::test]
+async fn test_bucket_redirects_keep_their_prefix() {
+    let files = tempfile::tempdir().unwrap();
+    let buckets = tempfile::tempdir().unwrap();
+    let config = BucketConfig {
+        name: "blog".to_string(),
+        root: buckets.path().join("blog"),
+        public: true,
+        hosts: Vec::new(),
+        quota: Default::default(),
+    };
+    write_files(&config.root, &[("posts/hello.html", "hello"), (".meta/redirects", "/p/:slug /posts/:slug.html 301\n")]);
+    let bucket = Bucket::from_config(&Config::from_env(), &config);
+    let state = AppState::new(files.path().to_path_buf()).with_buckets(vec![bucket]);
+    let app = build_public_router(Arc::new(state));
+
+    let (status, headers, _) = fetch(&app, "/b/blog/p/hello", &[]).await;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[header::LOCATION], "/b/blog/posts/hello.html");
+    // the default files dir has no rules of its own
+    assert_eq!(fetch(&app, "/p/hello", &[]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_rules_live_in_the_storage_backend() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(MemoryStorage::new());
+    put_bytes(storage.as_ref(), "new.txt", Bytes::from("new")).await.unwrap();
+    put_bytes(storage.as_ref(), ".meta/redirects", Bytes::from("/a /new.txt 200\n")).await.unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage.clone()));
+    let app = build_public_router(state.clone());
+
+    // remote rules are picked up by a reload
+    assert_eq!(fetch(&app, "/a", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(reload_redirects(State(state.clone())).await.unwrap().total, 1);
+    assert_eq!(fetch(&app, "/a", &[]).await.2, "new");
+
+    let saved = update_redirects(State(state.clone()), "/b /new.txt 301\n".to_string()).await.unwrap();
+    assert_eq!(saved.total, 1);
+    assert_eq!(read_all(storage.as_ref(), ".meta/redirects").await.unwrap(), "/b /new.txt 301\n");
+    assert!(!temp_dir.path().join(".meta").exists());
+    assert_eq!(list_redirects(State(state)).await.0.text, "/b /new.txt 301\n");
+}
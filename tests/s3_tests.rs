@@ -0,0 +1,556 @@
+use juicebox_omega::config::S3Config;
+use juicebox_omega::filetypes::UploadTypeRules;
+use juicebox_omega::handlers::{complete_chunked_upload, init_chunked_upload, upload_chunk};
+use juicebox_omega::models::{ChunkedUploadComplete, ChunkedUploadInit};
+use juicebox_omega::scanning::{ClamdAddress, ScanSettings};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::sigv4::{self, CanonicalRequest, Scope, AMZ_DATE_FORMAT};
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::{put_bytes, read_all, S3Storage, Storage};
+use juicebox_omega::versions::VersioningSettings;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use percent_encoding::percent_decode_str;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tower::util::ServiceExt;
+
+const BUCKET: &str = "juicebox";
+const ACCESS_KEY: &str = "test-access";
+const SECRET_KEY: &str = "test-secret";
+
+// just enough of S3 to exercise the backend, checking every request's signature
+#[derive(Default)]
+struct FakeS3 {
+    objects: Mutex<BTreeMap<String, Bytes>>,
+    uploads: Mutex<HashMap<String, BTreeMap<u32, Bytes>>>,
+    /// operations seen, e.g. "UploadPart"
+    log: Mutex<Vec<String>>,
+}
+
+impl FakeS3 {
+    fn count(&self, operation: &str) -> usize {
+        self.log.lock().unwrap().iter().filter(|op| *op == operation).count()
+    }
+}
+
+fn xml(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+fn no_such_key() -> Response {
+    xml(StatusCode::NOT_FOUND, "<Error><Code>NoSuchKey</Code><Message>missing</Message></Error>".to_string())
+}
+
+fn query_params(uri: &Uri) -> Vec<(String, String)> {
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().to_string();
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+fn signature_ok(method: &Method, uri: &Uri, headers: &HeaderMap) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let auth = header("authorization");
+    let Some(fields) = auth.strip_prefix("AWS4-HMAC-SHA256 ") else { return false };
+    let field = |name: &str| {
+        fields
+            .split(", ")
+            .find_map(|f| f.strip_prefix(name))
+            .unwrap_or_default()
+            .to_string()
+    };
+    let credential = field("Credential=");
+    let parts: Vec<&str> = credential.split('/').collect();
+    if parts.len() != 5 || parts[0] != ACCESS_KEY {
+        return false;
+    }
+    let Ok(time) = NaiveDateTime::parse_from_str(&header("x-amz-date"), AMZ_DATE_FORMAT) else { return false };
+
+    let request = CanonicalRequest {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        query: sigv4::canonical_query(&query_params(uri)),
+        headers: field("SignedHeaders=")
+            .split(';')
+            .map(|name| (name.to_string(), header(name).trim().to_string()))
+            .collect(),
+        payload_hash: header("x-amz-content-sha256"),
+    };
+    let scope = Scope { date: parts[1].to_string(), region: parts[2].to_string(), service: parts[3].to_string() };
+    sigv4::signature(SECRET_KEY, &scope, Utc.from_utc_datetime(&time), &request) == field("Signature=")
+}
+
+fn byte_range(headers: &HeaderMap, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = headers.get(header::RANGE)?.to_str().ok()?.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let start: usize = start.parse().ok()?;
+    if start >= len {
+        return Some(Err(()));
+    }
+    let end = end.parse::<usize>().map(|e| e.min(len - 1)).unwrap_or(len - 1);
+    Some(Ok((start, end)))
+}
+
+async fn fake_s3(State(s3): State<Arc<FakeS3>>, req: Request<Body>) -> Response {
+    let (parts, body) = req.into_parts();
+    if !signature_ok(&parts.method, &parts.uri, &parts.headers) {
+        return xml(StatusCode::FORBIDDEN, "<Error><Code>SignatureDoesNotMatch</Code></Error>".to_string());
+    }
+    let body = to_bytes(body, usize::MAX).await.unwrap();
+    let path = percent_decode_str(&parts.uri.path()[1..]).decode_utf8_lossy().to_string();
+    let (bucket, key) = path.split_once('/').unwrap_or((&path, ""));
+    assert_eq!(bucket, BUCKET);
+    let key = key.to_string();
+    let query: HashMap<String, String> = query_params(&parts.uri).into_iter().collect();
+    let copy_source = parts
+        .headers
+        .get("x-amz-copy-source")
+        .map(|v| percent_decode_str(v.to_str().unwrap()).decode_utf8_lossy().to_string())
+        .map(|v| v.trim_start_matches(&format!("/{}/", BUCKET)).to_string());
+    let log = |op: &str| s3.log.lock().unwrap().push(op.to_string());
+
+    match (parts.method.clone(), query.get("uploadId")) {
+        (Method::POST, None) if query.contains_key("uploads") => {
+            log("CreateMultipartUpload");
+            let id = uuid::Uuid::new_v4().to_string();
+            s3.uploads.lock().unwrap().insert(id.clone(), BTreeMap::new());
+            xml(StatusCode::OK, format!("<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>", id))
+        }
+        (Method::PUT, Some(id)) => {
+            let part_number: u32 = query["partNumber"].parse().unwrap();
+            let data = match &copy_source {
+                Some(source) => {
+                    log("UploadPartCopy");
+                    let range = parts.headers["x-amz-copy-source-range"].to_str().unwrap();
+                    let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+                    let object = s3.objects.lock().unwrap()[source].clone();
+                    object.slice(start.parse::<usize>().unwrap()..=end.parse::<usize>().unwrap())
+                }
+                None => {
+                    log("UploadPart");
+                    body
+                }
+            };
+            let etag = format!("\"{}\"", &sigv4::sha256_hex(&data)[..32]);
+            let mut uploads = s3.uploads.lock().unwrap();
+            let Some(upload) = uploads.get_mut(id) else {
+                return xml(StatusCode::NOT_FOUND, "<Error><Code>NoSuchUpload</Code></Error>".to_string());
+            };
+            upload.insert(part_number, data);
+            match copy_source {
+                Some(_) => xml(StatusCode::OK, format!("<CopyPartResult><ETag>{}</ETag></CopyPartResult>", etag.replace('"', "&quot;"))),
+                None => (StatusCode::OK, [(header::ETAG, etag)]).into_response(),
+            }
+        }
+        (Method::GET, Some(id)) => {
+            log("ListParts");
+            let uploads = s3.uploads.lock().unwrap();
+            let parts: String = uploads[id]
+                .iter()
+                .map(|(n, data)| {
+                    format!(
+                        "<Part><PartNumber>{}</PartNumber><ETag>&quot;{}&quot;</ETag><Size>{}</Size></Part>",
+                        n,
+                        &sigv4::sha256_hex(data)[..32],
+                        data.len()
+                    )
+                })
+                .collect();
+            xml(StatusCode::OK, format!("<ListPartsResult><IsTruncated>false</IsTruncated>{}</ListPartsResult>", parts))
+        }
+        (Method::POST, Some(id)) => {
+            log("CompleteMultipartUpload");
+            let upload = s3.uploads.lock().unwrap().remove(id).unwrap();
+            let requested = String::from_utf8_lossy(&body).matches("<PartNumber>").count();
+            assert_eq!(requested, upload.len());
+            let data: Vec<u8> = upload.values().flat_map(|d| d.to_vec()).collect();
+            s3.objects.lock().unwrap().insert(key, data.into());
+            xml(StatusCode::OK, "<CompleteMultipartUploadResult><ETag>x</ETag></CompleteMultipartUploadResult>".to_string())
+        }
+        (Method::DELETE, Some(id)) => {
+            log("AbortMultipartUpload");
+            s3.uploads.lock().unwrap().remove(id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        (Method::GET, None) if key.is_empty() => {
+            log("ListObjectsV2");
+            list_objects(&s3, &query)
+        }
+        (Method::PUT, None) => {
+            let data = match copy_source {
+                Some(source) => {
+                    log("CopyObject");
+                    match s3.objects.lock().unwrap().get(&source) {
+                        Some(data) => data.clone(),
+                        None => return no_such_key(),
+                    }
+                }
+                None => {
+                    log("PutObject");
+                    body
+                }
+            };
+            s3.objects.lock().unwrap().insert(key, data);
+            xml(StatusCode::OK, "<CopyObjectResult><ETag>x</ETag></CopyObjectResult>".to_string())
+        }
+        (Method::GET | Method::HEAD, None) => {
+            log(if parts.method == Method::GET { "GetObject" } else { "HeadObject" });
+            let Some(data) = s3.objects.lock().unwrap().get(&key).cloned() else {
+                return no_such_key();
+            };
+            let last_modified = (header::LAST_MODIFIED, "Wed, 21 Oct 2026 07:28:00 GMT");
+            match byte_range(&parts.headers, data.len()) {
+                Some(Err(())) => StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+                Some(Ok((start, end))) => (
+                    StatusCode::PARTIAL_CONTENT,
+                    [(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, data.len()))],
+                    [last_modified],
+                    data.slice(start..=end),
+                )
+                    .into_response(),
+                None if parts.method == Method::HEAD => {
+                    (StatusCode::OK, [(header::CONTENT_LENGTH, data.len().to_string())], [last_modified]).into_response()
+                }
+                None => (StatusCode::OK, [last_modified], data).into_response(),
+            }
+        }
+        (Method::DELETE, None) => {
+            log("DeleteObject");
+            s3.objects.lock().unwrap().remove(&key);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => StatusCode::NOT_IMPLEMENTED.into_response(),
+    }
+}
+
+// two keys per page so pagination gets exercised
+fn list_objects(s3: &FakeS3, query: &HashMap<String, String>) -> Response {
+    let prefix = query.get("prefix").cloned().unwrap_or_default();
+    let delimiter = query.get("delimiter").cloned();
+    let after = query.get("continuation-token").cloned().unwrap_or_default();
+
+    let mut entries: Vec<(String, Option<usize>)> = Vec::new();
+    for (key, data) in s3.objects.lock().unwrap().iter() {
+        let Some(rest) = key.strip_prefix(&prefix) else { continue };
+        match delimiter.as_deref().and_then(|d| rest.find(d)) {
+            Some(slash) => {
+                let common = format!("{}{}", prefix, &rest[..=slash]);
+                if entries.last().map(|e| &e.0) != Some(&common) {
+                    entries.push((common, None));
+                }
+            }
+            None => entries.push((key.clone(), Some(data.len()))),
+        }
+    }
+    let remaining: Vec<_> = entries.into_iter().filter(|(key, _)| *key > after).collect();
+    let page = &remaining[..remaining.len().min(2)];
+    let truncated = remaining.len() > 2;
+
+    let mut body = format!("<ListBucketResult><IsTruncated>{}</IsTruncated>", truncated);
+    for (key, size) in page {
+        match size {
+            Some(size) => body.push_str(&format!(
+                "<Contents><Key>{}</Key><Size>{}</Size><LastModified>2026-10-21T07:28:00.000Z</LastModified></Contents>",
+                key, size
+            )),
+            None => body.push_str(&format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", key)),
+        }
+    }
+    if truncated {
+        body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", page.last().unwrap().0));
+    }
+    body.push_str("</ListBucketResult>");
+    xml(StatusCode::OK, body)
+}
+
+async fn start_fake_s3() -> (Arc<FakeS3>, S3Config) {
+    let s3 = Arc::new(FakeS3::default());
+    let app = Router::new().fallback(fake_s3).with_state(s3.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let config = S3Config {
+        endpoint: format!("http://{}", addr),
+        region: "us-east-1".to_string(),
+        bucket: BUCKET.to_string(),
+        access_key: ACCESS_KEY.to_string(),
+        secret_key: SECRET_KEY.to_string(),
+        prefix: "site/".to_string(),
+        path_style: true,
+    };
+    (s3, config)
+}
+
+#[tokio::test]
+async fn test_s3_storage_operations() {
+    let (s3, config) = start_fake_s3().await;
+    let storage = S3Storage::new(&config, &format!("{}default/", config.prefix));
+
+    put_bytes(&storage, "a.txt", Bytes::from("hello world")).await.unwrap();
+    put_bytes(&storage, "b c.txt", Bytes::from("spaced")).await.unwrap();
+    put_bytes(&storage, "docs/one.txt", Bytes::from("1")).await.unwrap();
+    put_bytes(&storage, "docs/two.txt", Bytes::from("22")).await.unwrap();
+    put_bytes(&storage, "docs/deep/three.txt", Bytes::from("333")).await.unwrap();
+    assert!(s3.objects.lock().unwrap().contains_key("site/default/b c.txt"));
+
+    assert_eq!(read_all(&storage, "b c.txt").await.unwrap(), "spaced");
+    let object = storage.get("a.txt", Some(6..100)).await.unwrap();
+    assert_eq!((object.meta.size, object.range.clone()), (11, 6..11));
+    assert!(object.meta.modified.is_some());
+    let past_end = storage.get("a.txt", Some(20..30)).await.unwrap();
+    assert!(past_end.range.is_empty());
+
+    assert_eq!(storage.stat("docs/two.txt").await.unwrap().size, 2);
+    assert_eq!(storage.stat("nope").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(storage.stat("docs/").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    let top = storage.list("", false).await.unwrap();
+    let keys: Vec<&str> = top.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["a.txt", "b c.txt"]);
+    assert_eq!(top.prefixes, ["docs/"]);
+    let all = storage.list("docs/", true).await.unwrap();
+    let keys: Vec<&str> = all.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["docs/deep/three.txt", "docs/one.txt", "docs/two.txt"]);
+    assert!(s3.count("ListObjectsV2") > 2);
+
+    storage.rename("a.txt", ".trash/1").await.unwrap();
+    assert_eq!(read_all(&storage, ".trash/1").await.unwrap(), "hello world");
+    assert_eq!(storage.stat("a.txt").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(s3.count("CopyObject"), 1);
+
+    storage.delete("b c.txt").await.unwrap();
+    assert_eq!(storage.delete("b c.txt").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    storage.delete_prefix("docs/").await.unwrap();
+    assert!(storage.list("docs/", true).await.unwrap().objects.is_empty());
+
+    let err = put_bytes(&storage, "../other/x", Bytes::from("x")).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    // a wrong secret gets refused
+    let wrong = S3Config { secret_key: "nope".to_string(), ..config };
+    let err = S3Storage::new(&wrong, "").stat(".trash/1").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_large_writes_use_multipart_uploads() {
+    let (s3, config) = start_fake_s3().await;
+    let storage: Arc<dyn Storage> = Arc::new(S3Storage::new(&config, ""));
+
+    let data: Vec<u8> = (0..17 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<std::io::Result<Bytes>> = data.chunks(1024 * 1024).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+    let written = storage.put("big.bin", Box::pin(futures_util::stream::iter(chunks))).await.unwrap();
+
+    assert_eq!(written, data.len() as u64);
+    assert_eq!(s3.count("CreateMultipartUpload"), 1);
+    assert_eq!(s3.count("UploadPart"), 2);
+    assert_eq!(s3.count("PutObject"), 0);
+    assert_eq!(read_all(storage.as_ref(), "big.bin").await.unwrap(), data);
+}
+
+// upload_chunk behind a route without the 2MB default body limit
+async fn send_chunk(state: &Arc<AppState>, upload_id: &str, chunk: usize, data: Vec<u8>) -> StatusCode {
+    let app = Router::new()
+        .route("/chunk/:upload_id/:chunk_number", post(upload_chunk))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+
+    let mut body = b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"chunk\"; filename=\"blob\"\r\n\r\n".to_vec();
+    body.extend_from_slice(&data);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/chunk/{}/{}", upload_id, chunk))
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    app.oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn test_chunked_uploads_map_to_multipart_parts() {
+    let (s3, config) = start_fake_s3().await;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(S3Storage::new(&config, "site/default/"));
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage.clone()));
+
+    let part_size = 5 * 1024 * 1024;
+    let payload = ChunkedUploadInit {
+        filename: "video.mp4".to_string(),
+        total_size: part_size as u64 + 3,
+        chunk_size: part_size,
+        options: Default::default(),
+    };
+    let init = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap().0;
+    assert_eq!(init.total_chunks, 2);
+    assert_eq!(s3.count("CreateMultipartUpload"), 1);
+
+    // out of order, with a retried chunk
+    for (chunk, data) in [(1, b"end".to_vec()), (0, vec![1u8; part_size]), (1, b"END".to_vec())] {
+        assert_eq!(send_chunk(&state, &init.upload_id, chunk, data).await, StatusCode::OK);
+    }
+    let status = send_chunk(&state, &init.upload_id, 2, b"extra".to_vec()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(s3.count("UploadPart"), 3);
+
+    let done = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id: init.upload_id }))
+        .await
+        .unwrap()
+        .0;
+    assert_eq!(done.size, part_size as u64 + 3);
+    let stored = read_all(storage.as_ref(), "video.mp4").await.unwrap();
+    assert_eq!(&stored[part_size..], b"END");
+    // the parts were assembled under a hidden key, nothing of it is left there
+    assert!(!s3.objects.lock().unwrap().keys().any(|k| k.contains(".chunks")));
+
+    // chunks too small for S3 parts are stored on their own and joined at the end
+    let payload = ChunkedUploadInit {
+        filename: "small.txt".to_string(),
+        total_size: 6,
+        chunk_size: 3,
+        options: Default::default(),
+    };
+    let init = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap().0;
+    assert_eq!(s3.count("CreateMultipartUpload"), 1);
+    for (chunk, data) in [(0, b"abc".to_vec()), (1, b"def".to_vec())] {
+        assert_eq!(send_chunk(&state, &init.upload_id, chunk, data).await, StatusCode::OK);
+    }
+    let done = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id: init.upload_id }))
+        .await
+        .unwrap();
+    assert_eq!(done.0.size, 6);
+    assert_eq!(read_all(storage.as_ref(), "small.txt").await.unwrap(), "abcdef");
+    assert!(storage.list(".chunks/", true).await.unwrap().objects.is_empty());
+}
+
+#[tokio::test]
+async fn test_multipart_uploads_stay_hidden_until_checked() {
+    let (s3, config) = start_fake_s3().await;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(S3Storage::new(&config, "site/default/"));
+    put_bytes(storage.as_ref(), "video.mp4", Bytes::from("old cut")).await.unwrap();
+    // nothing listens on a port that was just freed, so every scan fails
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let scanning = ScanSettings {
+        clamd: Some(ClamdAddress::Tcp(format!("127.0.0.1:{}", port))),
+        quarantine_dir: temp_dir.path().join("quarantine"),
+        ..Default::default()
+    };
+    let state = Arc::new(
+        AppState::new(temp_dir.path().to_path_buf())
+            .with_storage(storage.clone())
+            .with_scanning(scanning)
+            .with_versioning(VersioningSettings { directories: vec!["/".to_string()], max_versions: 0 }),
+    );
+
+    let part_size = 5 * 1024 * 1024;
+    let payload = ChunkedUploadInit {
+        filename: "video.mp4".to_string(),
+        total_size: part_size as u64 + 3,
+        chunk_size: part_size,
+        options: Default::default(),
+    };
+    let init = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap().0;
+    for (chunk, data) in [(0, vec![1u8; part_size]), (1, b"end".to_vec())] {
+        assert_eq!(send_chunk(&state, &init.upload_id, chunk, data).await, StatusCode::OK);
+    }
+
+    // the scan fails: the parts were completed, but the live file and its history are untouched
+    let complete = || Json(ChunkedUploadComplete { upload_id: init.upload_id.clone() });
+    let refused = complete_chunked_upload(State(state.clone()), complete()).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(read_all(storage.as_ref(), "video.mp4").await.unwrap(), "old cut");
+    assert!(state.metadata.versions.is_empty());
+    assert_eq!(s3.count("CompleteMultipartUpload"), 1);
+
+    // a retry picks up the completed object instead of completing it again
+    let refused = complete_chunked_upload(State(state.clone()), complete()).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(s3.count("CompleteMultipartUpload"), 1);
+
+    // once it can be checked, the previous file becomes a version and the new one takes its place
+    let mut scanning = state.scanning.clone();
+    scanning.fail_open = true;
+    let retried = Arc::new(
+        AppState::new(temp_dir.path().to_path_buf())
+            .with_storage(storage.clone())
+            .with_scanning(scanning)
+            .with_versioning(state.versioning.clone()),
+    );
+    let upload = state.chunked_uploads.remove(&init.upload_id).unwrap().1;
+    retried.chunked_uploads.insert(init.upload_id.clone(), upload);
+    let done = complete_chunked_upload(State(retried.clone()), complete()).await.unwrap().0;
+    assert_eq!(done.size, part_size as u64 + 3);
+    assert_eq!(&read_all(storage.as_ref(), "video.mp4").await.unwrap()[part_size..], b"end");
+    assert_eq!(retried.metadata.versions.get("video.mp4").unwrap().len(), 1);
+    assert!(storage.list(".chunks/", true).await.unwrap().objects.is_empty());
+}
+
+#[tokio::test]
+async fn test_multipart_uploads_are_type_checked_on_completion() {
+    let (s3, config) = start_fake_s3().await;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(S3Storage::new(&config, "site/default/"));
+    let rules = UploadTypeRules::new(&[], &[], &[], &["application/x-executable".to_string()]);
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage.clone()).with_upload_types(rules));
+
+    let part_size = 5 * 1024 * 1024;
+    let payload = ChunkedUploadInit {
+        filename: "video.mp4".to_string(),
+        total_size: part_size as u64 + 3,
+        chunk_size: part_size,
+        options: Default::default(),
+    };
+    let init = init_chunked_upload(State(state.clone()), None, Json(payload)).await.unwrap().0;
+    for (chunk, data) in [(0, vec![1u8; part_size]), (1, b"end".to_vec())] {
+        assert_eq!(send_chunk(&state, &init.upload_id, chunk, data).await, StatusCode::OK);
+    }
+
+    // the first part gets swapped for an executable behind the first chunk's check
+    let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+    elf.resize(part_size, 0);
+    for upload in s3.uploads.lock().unwrap().values_mut() {
+        upload.insert(1, Bytes::from(elf.clone()));
+    }
+
+    let complete = Json(ChunkedUploadComplete { upload_id: init.upload_id.clone() });
+    let refused = complete_chunked_upload(State(state.clone()), complete).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(s3.objects.lock().unwrap().is_empty());
+    assert!(!state.chunked_uploads.contains_key(&init.upload_id));
+}
+
+#[tokio::test]
+async fn test_public_router_serves_ranges_from_s3() {
+    let (_s3, config) = start_fake_s3().await;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage: Arc<dyn Storage> = Arc::new(S3Storage::new(&config, "site/default/"));
+    put_bytes(storage.as_ref(), "movie.txt", Bytes::from("0123456789")).await.unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage));
+    let app = build_public_router(state);
+
+    let request = Request::builder().uri("/movie.txt").header("Range", "bytes=-3").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 7-9/10");
+    assert_eq!(response.headers()["last-modified"], "Wed, 21 Oct 2026 07:28:00 GMT");
+    assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "789");
+
+    let request = Request::builder().uri("/movie.txt").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "0123456789");
+}
@@ -0,0 +1,387 @@
+use juicebox_omega::buckets::Bucket;
+use juicebox_omega::config::{ApiKeyConfig, BucketConfig, Config, S3ApiConfig, S3Config};
+use juicebox_omega::filetypes::UploadTypeRules;
+use juicebox_omega::quota::{Quota, QuotaSettings};
+use juicebox_omega::s3api::S3Api;
+use juicebox_omega::server::build_s3_router;
+use juicebox_omega::sigv4::{self, CanonicalRequest, Scope, AMZ_DATE_FORMAT, UNSIGNED_PAYLOAD};
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::{put_bytes, read_all, S3Storage, Storage};
+use axum::body::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Method;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+const REGION: &str = "eu-test-1";
+
+struct Server {
+    endpoint: String,
+    state: Arc<AppState>,
+    _dir: tempfile::TempDir,
+}
+
+// serve the s3 api for a default bucket plus a "photos" bucket only "photographer" may use
+async fn start_server(quota: Option<Quota>) -> Server {
+    start_server_with(quota, UploadTypeRules::default()).await
+}
+
+async fn start_server_with(quota: Option<Quota>, upload_types: UploadTypeRules) -> Server {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = BucketConfig {
+        name: "photos".to_string(),
+        root: dir.path().join("photos"),
+        public: false,
+        hosts: Vec::new(),
+        quota: Default::default(),
+    };
+    std::fs::create_dir_all(&photos.root).unwrap();
+    let files_dir = dir.path().join("files");
+    std::fs::create_dir_all(&files_dir).unwrap();
+
+    let mut config = Config::from_env();
+    config.api_keys = vec![
+        ApiKeyConfig {
+            name: "writer".to_string(),
+            key_hash: Config::hash_api_key("writer-secret"),
+            quota: quota.unwrap_or_default(),
+            buckets: vec!["default".to_string()],
+        },
+        ApiKeyConfig {
+            name: "photographer".to_string(),
+            key_hash: Config::hash_api_key("photo-secret"),
+            quota: Default::default(),
+            buckets: vec!["photos".to_string()],
+        },
+    ];
+    let s3_api = S3ApiConfig {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        region: REGION.to_string(),
+        credentials: HashMap::from([
+            ("writer".to_string(), "writer-secret".to_string()),
+            ("photographer".to_string(), "photo-secret".to_string()),
+        ]),
+    };
+
+    let state = Arc::new(
+        AppState::new(files_dir)
+            .with_quotas(QuotaSettings::from_config(&config))
+            .with_upload_types(upload_types)
+            .with_buckets(vec![Bucket::from_config(&config, &photos)]),
+    );
+    let api = Arc::new(S3Api::new(state.clone(), &s3_api, config.api_keys.clone()));
+    let app = build_s3_router(api, &config);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap()
+    });
+
+    Server { endpoint: format!("http://{}", addr), state, _dir: dir }
+}
+
+// our own s3 backend doubles as a client
+fn client(server: &Server, bucket: &str, access_key: &str, secret_key: &str) -> S3Storage {
+    let config = S3Config {
+        endpoint: server.endpoint.clone(),
+        region: REGION.to_string(),
+        bucket: bucket.to_string(),
+        access_key: access_key.to_string(),
+        secret_key: secret_key.to_string(),
+        prefix: String::new(),
+        path_style: true,
+    };
+    S3Storage::new(&config, "")
+}
+
+struct Signed {
+    request: reqwest::RequestBuilder,
+    signature: String,
+    scope: Scope,
+    time: DateTime<Utc>,
+}
+
+// a request signed in the Authorization header, with the host, x-amz-* headers and payload hash signed
+fn signed(method: Method, url: &str, secret: &str, payload_hash: &str, headers: &[(&str, &str)]) -> Signed {
+    let url = reqwest::Url::parse(url).unwrap();
+    let time = Utc::now();
+    let scope = Scope {
+        date: time.format("%Y%m%d").to_string(),
+        region: REGION.to_string(),
+        service: "s3".to_string(),
+    };
+    let host = format!("{}:{}", url.host_str().unwrap(), url.port().unwrap());
+    let mut signed_headers = vec![
+        ("host".to_string(), host),
+        ("x-amz-content-sha256".to_string(), payload_hash.to_string()),
+        ("x-amz-date".to_string(), time.format(AMZ_DATE_FORMAT).to_string()),
+    ];
+    signed_headers.extend(
+        headers
+            .iter()
+            .filter(|(name, _)| name.starts_with("x-amz-"))
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+    signed_headers.sort();
+    let query: Vec<(String, String)> = url.query_pairs().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let canonical = CanonicalRequest {
+        method: method.to_string(),
+        path: url.path().to_string(),
+        query: sigv4::canonical_query(&query),
+        headers: signed_headers.clone(),
+        payload_hash: payload_hash.to_string(),
+    };
+    let access_key = if secret == "photo-secret" { "photographer" } else { "writer" };
+    let signature = sigv4::signature(secret, &scope, time, &canonical);
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key,
+        scope,
+        canonical.signed_headers(),
+        signature
+    );
+
+    let mut request = reqwest::Client::new()
+        .request(method, url)
+        .header("authorization", authorization);
+    for (name, value) in signed_headers.iter().filter(|(name, _)| name != "host") {
+        request = request.header(name, value);
+    }
+    for (name, value) in headers.iter().filter(|(name, _)| !name.starts_with("x-amz-")) {
+        request = request.header(*name, *value);
+    }
+    Signed { request, signature, scope, time }
+}
+
+// a url anyone can use until it expires
+fn presigned(url: &str, secret: &str, time: DateTime<Utc>, expires: i64) -> String {
+    let url = reqwest::Url::parse(url).unwrap();
+    let scope = Scope {
+        date: time.format("%Y%m%d").to_string(),
+        region: REGION.to_string(),
+        service: "s3".to_string(),
+    };
+    let query = vec![
+        ("X-Amz-Algorithm".to_string(), "AWS4-HMAC-SHA256".to_string()),
+        ("X-Amz-Credential".to_string(), format!("writer/{}", scope)),
+        ("X-Amz-Date".to_string(), time.format(AMZ_DATE_FORMAT).to_string()),
+        ("X-Amz-Expires".to_string(), expires.to_string()),
+        ("X-Amz-SignedHeaders".to_string(), "host".to_string()),
+    ];
+    let canonical = CanonicalRequest {
+        method: "GET".to_string(),
+        path: url.path().to_string(),
+        query: sigv4::canonical_query(&query),
+        headers: vec![("host".to_string(), format!("{}:{}", url.host_str().unwrap(), url.port().unwrap()))],
+        payload_hash: UNSIGNED_PAYLOAD.to_string(),
+    };
+    let signature = sigv4::signature(secret, &scope, time, &canonical);
+    format!(
+        "{}://{}:{}{}?{}&X-Amz-Signature={}",
+        url.scheme(),
+        url.host_str().unwrap(),
+        url.port().unwrap(),
+        url.path(),
+        canonical.query,
+        signature
+    )
+}
+
+// an aws-chunked body, every chunk signed after the one before it
+fn chunked_body(signed: &Signed, secret: &str, chunks: &[&[u8]]) -> Vec<u8> {
+    let key = sigv4::signing_key(secret, &signed.scope);
+    let mut previous = signed.signature.clone();
+    let mut body = Vec::new();
+    for data in chunks.iter().copied().chain(std::iter::once(&b""[..])) {
+        let signature = sigv4::chunk_signature(&key, &signed.scope, signed.time, &previous, data);
+        body.extend_from_slice(format!("{:x};chunk-signature={}\r\n", data.len(), signature).as_bytes());
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+        previous = signature;
+    }
+    body
+}
+
+async fn error_code(response: reqwest::Response) -> (u16, String) {
+    let status = response.status().as_u16();
+    let body = response.text().await.unwrap();
+    let code = body
+        .split_once("<Code>")
+        .and_then(|(_, rest)| rest.split_once("</Code>"))
+        .map(|(code, _)| code.to_string())
+        .unwrap_or_default();
+    (status, code)
+}
+
+#[tokio::test]
+async fn test_s3_clients_manage_objects_in_files_dir() {
+    let server = start_server(None).await;
+    let s3 = client(&server, "default", "writer", "writer-secret");
+
+    put_bytes(&s3, "hello.txt", Bytes::from("hello world")).await.unwrap();
+    put_bytes(&s3, "docs/a b.txt", Bytes::from("spaced")).await.unwrap();
+    put_bytes(&s3, "docs/deep/c.txt", Bytes::from("333")).await.unwrap();
+
+    // objects are plain files, owned by the key that wrote them
+    let files_dir = server.state.files_dir.clone();
+    assert_eq!(std::fs::read_to_string(files_dir.join("docs/a b.txt")).unwrap(), "spaced");
+    assert_eq!(server.state.metadata.get("hello.txt").owner.as_deref(), Some("writer"));
+
+    assert_eq!(read_all(&s3, "docs/a b.txt").await.unwrap(), "spaced");
+    let object = s3.get("hello.txt", Some(6..11)).await.unwrap();
+    assert_eq!((object.meta.size, object.range.clone()), (11, 6..11));
+    assert_eq!(s3.stat("docs/deep/c.txt").await.unwrap().size, 3);
+    assert_eq!(s3.stat("nope").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    // internal directories never show up
+    std::fs::create_dir_all(files_dir.join(".chunks/x")).unwrap();
+    std::fs::write(files_dir.join(".chunks/x/0"), "chunk").unwrap();
+    std::fs::create_dir_all(files_dir.join(".deployments/site")).unwrap();
+    std::fs::write(files_dir.join(".deployments/site/index.html"), "release").unwrap();
+    let top = s3.list("", false).await.unwrap();
+    let keys: Vec<&str> = top.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["hello.txt"]);
+    assert_eq!(top.prefixes, ["docs/"]);
+    let all = s3.list("docs/", true).await.unwrap();
+    let keys: Vec<&str> = all.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["docs/a b.txt", "docs/deep/c.txt"]);
+
+    // rename is CopyObject + DeleteObject, deletes go to the trash
+    s3.rename("hello.txt", "moved.txt").await.unwrap();
+    assert_eq!(std::fs::read_to_string(files_dir.join("moved.txt")).unwrap(), "hello world");
+    assert!(!files_dir.join("hello.txt").exists());
+    assert_eq!(server.state.metadata.trash.len(), 1);
+    s3.delete("moved.txt").await.unwrap();
+    assert_eq!(server.state.metadata.trash.len(), 2);
+
+    // large objects arrive as multipart uploads
+    let big: Vec<u8> = (0..17 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    put_bytes(&s3, "big.bin", Bytes::from(big.clone())).await.unwrap();
+    assert_eq!(std::fs::read(files_dir.join("big.bin")).unwrap(), big);
+    let leftovers = server.state.storage.list(".chunks/", true).await.unwrap().objects;
+    assert_eq!(leftovers.len(), 1);
+}
+
+#[tokio::test]
+async fn test_requests_must_be_signed_by_a_key_allowed_in_the_bucket() {
+    let server = start_server(None).await;
+
+    let wrong_secret = client(&server, "default", "writer", "nope");
+    let err = put_bytes(&wrong_secret, "x.txt", Bytes::from("x")).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+    let unknown = client(&server, "default", "stranger", "writer-secret");
+    assert!(unknown.stat("x.txt").await.is_err());
+
+    // photographer can only use the photos bucket
+    let photographer = client(&server, "default", "photographer", "photo-secret");
+    let err = put_bytes(&photographer, "x.txt", Bytes::from("x")).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    let photos = client(&server, "photos", "photographer", "photo-secret");
+    put_bytes(&photos, "cat.jpg", Bytes::from("meow")).await.unwrap();
+    assert!(server.state.buckets["photos"].state.files_dir.join("cat.jpg").exists());
+
+    let url = format!("{}/default/x.txt", server.endpoint);
+    let response = reqwest::Client::new().get(&url).send().await.unwrap();
+    assert_eq!(error_code(response).await, (403, "AccessDenied".to_string()));
+
+    let response = signed(Method::GET, &format!("{}/nope/x.txt", server.endpoint), "writer-secret", UNSIGNED_PAYLOAD, &[])
+        .request
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(error_code(response).await, (404, "NoSuchBucket".to_string()));
+
+    // internal data can't be reached
+    let writer = client(&server, "default", "writer", "writer-secret");
+    for key in [".meta/metadata.json", ".deployments/site/index.html", "docs/.hidden"] {
+        let err = put_bytes(&writer, key, Bytes::from("{}")).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied, "{}", key);
+    }
+
+    // the body has to match its signed hash
+    let url = format!("{}/default/hashed.txt", server.endpoint);
+    let hash = sigv4::sha256_hex(b"expected");
+    let response = signed(Method::PUT, &url, "writer-secret", &hash, &[]).request.body("tampered").send().await.unwrap();
+    assert_eq!(error_code(response).await, (400, "XAmzContentSHA256Mismatch".to_string()));
+    assert!(!server.state.files_dir.join("hashed.txt").exists());
+    let response = signed(Method::PUT, &url, "writer-secret", &hash, &[]).request.body("expected").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_streaming_signed_uploads_and_presigned_urls() {
+    let server = start_server(None).await;
+    let url = format!("{}/default/streamed.txt", server.endpoint);
+    let headers = [
+        ("content-encoding", "aws-chunked"),
+        ("x-amz-decoded-content-length", "11"),
+    ];
+
+    let upload = signed(Method::PUT, &url, "writer-secret", "STREAMING-AWS4-HMAC-SHA256-PAYLOAD", &headers);
+    let body = chunked_body(&upload, "writer-secret", &[b"hello ", b"world"]);
+    let response = upload.request.body(body).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(std::fs::read_to_string(server.state.files_dir.join("streamed.txt")).unwrap(), "hello world");
+
+    // a chunk that doesn't match its signature fails the whole upload
+    let upload = signed(Method::PUT, &url, "writer-secret", "STREAMING-AWS4-HMAC-SHA256-PAYLOAD", &headers);
+    let mut body = chunked_body(&upload, "writer-secret", &[b"hello ", b"world"]);
+    let at = body.windows(5).position(|w| w == b"world").unwrap();
+    body[at..at + 5].copy_from_slice(b"WORLD");
+    let response = upload.request.body(body).send().await.unwrap();
+    assert_eq!(error_code(response).await, (403, "SignatureDoesNotMatch".to_string()));
+    assert_eq!(std::fs::read_to_string(server.state.files_dir.join("streamed.txt")).unwrap(), "hello world");
+
+    let response = reqwest::get(presigned(&url, "writer-secret", Utc::now(), 60)).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "hello world");
+
+    let expired = presigned(&url, "writer-secret", Utc::now() - Duration::seconds(120), 60);
+    let response = reqwest::get(expired).await.unwrap();
+    assert_eq!(error_code(response).await, (403, "AccessDenied".to_string()));
+
+    let forged = presigned(&url, "not-the-secret", Utc::now(), 60);
+    let response = reqwest::get(forged).await.unwrap();
+    assert_eq!(error_code(response).await, (403, "SignatureDoesNotMatch".to_string()));
+}
+
+#[tokio::test]
+async fn test_uploads_count_against_the_signing_keys_quota() {
+    let server = start_server(Some(Quota { max_bytes: Some(10), max_files: None })).await;
+    let url = format!("{}/default/small.txt", server.endpoint);
+
+    let response = signed(Method::PUT, &url, "writer-secret", UNSIGNED_PAYLOAD, &[]).request.body("12345").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let url = format!("{}/default/large.txt", server.endpoint);
+    let response = signed(Method::PUT, &url, "writer-secret", UNSIGNED_PAYLOAD, &[]).request.body("123456789").send().await.unwrap();
+    assert_eq!(error_code(response).await, (507, "QuotaExceeded".to_string()));
+    assert!(!server.state.files_dir.join("large.txt").exists());
+}
+
+#[tokio::test]
+async fn test_put_object_checks_upload_types() {
+    let rules = UploadTypeRules::new(&[], &["exe".to_string()], &[], &["text/html".to_string()]);
+    let server = start_server_with(None, rules).await;
+    let files_dir = server.state.files_dir.clone();
+    let put = |key: &str, body: &'static str| {
+        let url = format!("{}/default/{}", server.endpoint, key);
+        signed(Method::PUT, &url, "writer-secret", UNSIGNED_PAYLOAD, &[]).request.body(body).send()
+    };
+
+    let response = put("notes.txt", "just text").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.state.metadata.get("notes.txt").detected_type.as_deref(), Some("text/plain"));
+
+    let response = put("setup.exe", "just text").await.unwrap();
+    assert_eq!(error_code(response).await, (415, "UnsupportedMediaType".to_string()));
+    // the content is sniffed before it replaces anything
+    let response = put("notes.txt", "<!DOCTYPE html><html><script>alert(1)</script>").await.unwrap();
+    assert_eq!(error_code(response).await, (415, "UnsupportedMediaType".to_string()));
+    assert_eq!(std::fs::read_to_string(files_dir.join("notes.txt")).unwrap(), "just text");
+    assert!(!files_dir.join("setup.exe").exists());
+    assert!(server.state.storage.list(".chunks/", true).await.unwrap().objects.is_empty());
+}
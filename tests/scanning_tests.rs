@@ -0,0 +1,339 @@
+use juicebox_omega::config::Config;
+use juicebox_omega::extract::upload_archive;
+use juicebox_omega::handlers::{complete_chunked_upload, upload_file};
+use juicebox_omega::metadata::{MetadataStore, ScanStatus};
+use juicebox_omega::models::ChunkedUploadComplete;
+use juicebox_omega::scanning::{ClamdAddress, ScanSettings, ScanVerdict};
+use juicebox_omega::server::build_webdav_router;
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use juicebox_omega::webdav::WebDav;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, State};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use base64::Engine;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tower::util::ServiceExt;
+
+const EICAR: &str = r"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+// a stand-in clamd: reads one INSTREAM and flags anything with the EICAR string in it
+async fn answer<S: AsyncRead + AsyncWrite + Unpin>(mut conn: S) {
+    let mut command = [0u8; 10];
+    if conn.read_exact(&mut command).await.is_err() || &command != b"zINSTREAM\0" {
+        let _ = conn.write_all(b"UNKNOWN COMMAND\0").await;
+        return;
+    }
+    let mut data = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        if conn.read_exact(&mut len).await.is_err() {
+            return;
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 {
+            break;
+        }
+        let start = data.len();
+        data.resize(start + len, 0);
+        if conn.read_exact(&mut data[start..]).await.is_err() {
+            return;
+        }
+    }
+    let reply: &[u8] = if String::from_utf8_lossy(&data).contains("EICAR-STANDARD") {
+        b"stream: Eicar-Test-Signature FOUND\0"
+    } else {
+        b"stream: OK\0"
+    };
+    let _ = conn.write_all(reply).await;
+}
+
+async fn tcp_daemon() -> ClamdAddress {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            tokio::spawn(answer(conn));
+        }
+    });
+    ClamdAddress::Tcp(addr.to_string())
+}
+
+async fn unix_daemon(dir: &Path) -> ClamdAddress {
+    let path = dir.join("clamd.sock");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            tokio::spawn(answer(conn));
+        }
+    });
+    ClamdAddress::Unix(path)
+}
+
+fn scanning(clamd: ClamdAddress, quarantine_dir: PathBuf) -> ScanSettings {
+    ScanSettings {
+        clamd: Some(clamd),
+        timeout: Duration::from_secs(5),
+        quarantine_dir,
+        fail_open: false,
+    }
+}
+
+fn body(data: &'static [u8]) -> juicebox_omega::storage::ByteStream {
+    Box::pin(futures_util::stream::once(async move { Ok(Bytes::from_static(data)) }))
+}
+
+async fn upload(state: &Arc<AppState>, filename: &str, data: &[u8]) -> (StatusCode, serde_json::Value) {
+    let app = Router::new()
+        .route("/upload", post(upload_file))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let mut body = format!(
+        "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n",
+        filename,
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri("/upload")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_clamd_addresses_and_instream() {
+    assert_eq!(ClamdAddress::parse("tcp://127.0.0.1:3310"), Some(ClamdAddress::Tcp("127.0.0.1:3310".to_string())));
+    assert_eq!(ClamdAddress::parse(" clamav:3310 "), Some(ClamdAddress::Tcp("clamav:3310".to_string())));
+    assert_eq!(ClamdAddress::parse("unix:///run/clamav/clamd.ctl"), Some(ClamdAddress::Unix("/run/clamav/clamd.ctl".into())));
+    assert_eq!(ClamdAddress::parse("/tmp/clamd.sock"), Some(ClamdAddress::Unix("/tmp/clamd.sock".into())));
+    assert_eq!(ClamdAddress::parse("clamav"), None);
+    assert_eq!(ClamdAddress::parse("unix://"), None);
+
+    // the same exchange over both kinds of socket, with content bigger than one INSTREAM chunk
+    let temp_dir = tempfile::tempdir().unwrap();
+    let big: &'static [u8] = Box::leak(format!("{}{}", "a".repeat(200_000), EICAR).into_bytes().into_boxed_slice());
+    for clamd in [tcp_daemon().await, unix_daemon(temp_dir.path()).await] {
+        let settings = scanning(clamd, temp_dir.path().join("quarantine"));
+        assert_eq!(settings.scan(body(b"hello")).await.unwrap(), ScanVerdict::Clean);
+        assert_eq!(settings.scan(body(big)).await.unwrap(), ScanVerdict::Infected("Eicar-Test-Signature".to_string()));
+    }
+
+    // no daemon, no scanning
+    assert_eq!(ScanSettings::default().scan(body(EICAR.as_bytes())).await.unwrap(), ScanVerdict::Clean);
+}
+
+#[tokio::test]
+async fn test_single_uploads_are_scanned() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    let quarantine_dir = temp_dir.path().join("quarantine");
+    let settings = scanning(tcp_daemon().await, quarantine_dir.clone());
+    let state = Arc::new(AppState::new(files_dir.clone()).with_scanning(settings));
+
+    let (status, _) = upload(&state, "notes.txt", b"nothing to see").await;
+    assert_eq!(status, StatusCode::OK);
+    let scan = state.metadata.get("notes.txt").scan.unwrap();
+    assert_eq!((scan.status, scan.detail), (ScanStatus::Clean, None));
+
+    let (status, body) = upload(&state, "nasty.txt", EICAR.as_bytes()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "File is infected (Eicar-Test-Signature) and was quarantined");
+    assert!(!files_dir.join("nasty.txt").exists());
+    assert!(state.metadata.get("nasty.txt").scan.is_none());
+
+    // the file sits in quarantine with its scan result next to it
+    let mut names: Vec<String> = std::fs::read_dir(&quarantine_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 2);
+    assert!(names[0].ends_with("-nasty.txt"));
+    assert_eq!(std::fs::read_to_string(quarantine_dir.join(&names[0])).unwrap(), EICAR);
+    let sidecar: serde_json::Value = serde_json::from_slice(&std::fs::read(quarantine_dir.join(&names[1])).unwrap()).unwrap();
+    assert_eq!(sidecar["filename"], "nasty.txt");
+    assert_eq!(sidecar["scan"]["status"], "infected");
+    assert_eq!(sidecar["scan"]["detail"], "Eicar-Test-Signature");
+
+    // and it's on record in the metadata store
+    let quarantined = MetadataStore::load(&files_dir).quarantine;
+    assert_eq!(quarantined.len(), 1);
+    let item = quarantined.iter().next().unwrap().value().clone();
+    assert_eq!((item.filename.as_str(), item.size), ("nasty.txt", EICAR.len() as u64));
+    assert_eq!(item.path, quarantine_dir.join(&names[0]));
+    assert_eq!(item.scan.status, ScanStatus::Infected);
+}
+
+#[tokio::test]
+async fn test_webdav_and_archive_uploads_are_scanned() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    std::fs::create_dir_all(&files_dir).unwrap();
+    std::fs::write(files_dir.join("notes.txt"), "the old notes").unwrap();
+    let settings = scanning(tcp_daemon().await, temp_dir.path().join("quarantine"));
+    let state = Arc::new(AppState::new(files_dir.clone()).with_scanning(settings));
+
+    let mut config = Config::from_env();
+    config.api_key_hash = Config::hash_api_key("admin-secret");
+    let dav = build_webdav_router(Arc::new(WebDav::new(state.clone(), &config)), &config)
+        .layer(Extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)))));
+    let credentials = base64::engine::general_purpose::STANDARD.encode("admin:admin-secret");
+    let put = |data: &'static str| {
+        Request::builder()
+            .method("PUT")
+            .uri("/default/notes.txt")
+            .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+            .body(Body::from(data))
+            .unwrap()
+    };
+    let response = dav.clone().oneshot(put(EICAR)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(std::fs::read_to_string(files_dir.join("notes.txt")).unwrap(), "the old notes");
+    let response = dav.oneshot(put("the new notes")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(state.metadata.get("notes.txt").scan.unwrap().status, ScanStatus::Clean);
+
+    // each file of an archive is scanned, one infected file refuses the archive
+    let mut tar = tar::Builder::new(Vec::new());
+    for (name, content) in [("site/index.txt", "welcome"), ("site/payload.txt", EICAR)] {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, content.as_bytes()).unwrap();
+    }
+    let app = Router::new()
+        .route("/upload/archive", post(upload_archive))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let mut body = b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"site.tar\"\r\n\r\n".to_vec();
+    body.extend_from_slice(&tar.into_inner().unwrap());
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+    let request = Request::builder()
+        .method("POST")
+        .uri("/upload/archive")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["error"], "site/payload.txt: File is infected (Eicar-Test-Signature) and was quarantined");
+    assert!(!files_dir.join("site").exists());
+    assert_eq!(state.metadata.quarantine.len(), 2);
+}
+
+#[test]
+fn test_quarantine_dir_must_not_be_served() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    let bucket_root = temp_dir.path().join("buckets/photos");
+    std::fs::create_dir_all(&files_dir).unwrap();
+    std::fs::create_dir_all(&bucket_root).unwrap();
+    let served = [files_dir.as_path(), bucket_root.as_path()];
+    let settings = |dir: PathBuf| ScanSettings { quarantine_dir: dir, ..Default::default() };
+
+    assert!(settings(temp_dir.path().join("quarantine")).check_quarantine_dir(served).is_ok());
+    assert!(settings(temp_dir.path().join("buckets/quarantine")).check_quarantine_dir(served).is_ok());
+    assert!(settings(files_dir.join(".quarantine")).check_quarantine_dir(served).is_err());
+    assert!(settings(files_dir.clone()).check_quarantine_dir(served).is_err());
+    // however the path is spelled
+    let sneaky = temp_dir.path().join("elsewhere/../buckets/./photos/infected");
+    assert!(settings(sneaky).check_quarantine_dir(served).is_err());
+}
+
+#[tokio::test]
+async fn test_chunked_uploads_are_scanned() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    let settings = scanning(unix_daemon(temp_dir.path()).await, temp_dir.path().join("quarantine"));
+    let state = Arc::new(AppState::new(files_dir.clone()).with_scanning(settings));
+
+    // the signature is split across the chunks
+    let (first, second) = EICAR.split_at(30);
+    let chunks_dir = files_dir.join(".chunks/infected");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), first).unwrap();
+    std::fs::write(chunks_dir.join("chunk_1"), second).unwrap();
+    let upload = ChunkedUploadMetadata {
+        filename: "split.txt".to_string(),
+        total_size: EICAR.len() as u64,
+        chunk_size: 30,
+        total_chunks: 2,
+        received_chunks: [0, 1].into_iter().collect(),
+        ..Default::default()
+    };
+    state.chunked_uploads.insert("infected".to_string(), upload.clone());
+    let complete = ChunkedUploadComplete { upload_id: "infected".to_string() };
+    let refused = complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(!chunks_dir.exists());
+    assert!(!files_dir.join("split.txt").exists());
+    assert!(!state.chunked_uploads.contains_key("infected"));
+
+    let chunks_dir = files_dir.join(".chunks/clean");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), "a".repeat(30)).unwrap();
+    std::fs::write(chunks_dir.join("chunk_1"), "b".repeat(38)).unwrap();
+    state.chunked_uploads.insert("clean".to_string(), upload);
+    let complete = ChunkedUploadComplete { upload_id: "clean".to_string() };
+    let done = complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap().0;
+    assert_eq!(done.size, 68);
+    assert!(files_dir.join("split.txt").exists());
+    assert_eq!(state.metadata.get("split.txt").scan.unwrap().status, ScanStatus::Clean);
+}
+
+#[tokio::test]
+async fn test_failed_scans() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let files_dir = temp_dir.path().join("files");
+    // nothing listens on a port that was just freed
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut settings = scanning(ClamdAddress::Tcp(format!("127.0.0.1:{}", port)), temp_dir.path().join("quarantine"));
+    let state = Arc::new(AppState::new(files_dir.clone()).with_scanning(settings.clone()));
+
+    let (status, body) = upload(&state, "report.txt", b"quarterly numbers").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["error"].as_str().unwrap().starts_with("Malware scan failed"));
+    assert!(!files_dir.join("report.txt").exists());
+
+    // a chunked upload stays around to be completed once the scanner is back
+    let chunks_dir = files_dir.join(".chunks/waiting");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), "quarterly numbers").unwrap();
+    let upload_meta = ChunkedUploadMetadata {
+        filename: "report.txt".to_string(),
+        total_size: 17,
+        chunk_size: 64,
+        total_chunks: 1,
+        received_chunks: [0].into_iter().collect(),
+        ..Default::default()
+    };
+    state.chunked_uploads.insert("waiting".to_string(), upload_meta);
+    let complete = ChunkedUploadComplete { upload_id: "waiting".to_string() };
+    let refused = complete_chunked_upload(State(state.clone()), Json(complete)).await.unwrap_err();
+    assert_eq!(refused.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(state.chunked_uploads.contains_key("waiting"));
+    assert!(chunks_dir.join("chunk_0").exists());
+
+    // failing open keeps the file, marked as not scanned
+    settings.fail_open = true;
+    let state = Arc::new(AppState::new(files_dir.clone()).with_scanning(settings));
+    let (status, _) = upload(&state, "report.txt", b"quarterly numbers").await;
+    assert_eq!(status, StatusCode::OK);
+    let scan = state.metadata.get("report.txt").scan.unwrap();
+    assert_eq!(scan.status, ScanStatus::Error);
+    assert!(scan.detail.is_some());
+}
@@ -0,0 +1,151 @@
+use juicebox_omega::handlers::complete_chunked_upload;
+use juicebox_omega::models::ChunkedUploadComplete;
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use juicebox_omega::storage::{
+    concat, exists, put_bytes, read_all, FsStorage, MemoryStorage, Storage,
+};
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::Json;
+use futures_util::TryStreamExt;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+// the same behaviour is expected from every backend
+async fn check_backend(storage: Arc<dyn Storage>) {
+    let put = |key: &'static str, data: &'static str| {
+        let storage = storage.clone();
+        async move { put_bytes(storage.as_ref(), key, Bytes::from(data)).await.unwrap() }
+    };
+    assert_eq!(put("a.txt", "hello world").await, 11);
+    put("docs/b.txt", "bee").await;
+    put("docs/deep/c.txt", "sea").await;
+    put(".trash/x", "gone").await;
+
+    assert_eq!(read_all(storage.as_ref(), "a.txt").await.unwrap(), "hello world");
+    let object = storage.get("a.txt", Some(6..100)).await.unwrap();
+    assert_eq!((object.meta.size, object.range.clone()), (11, 6..11));
+    let chunks: Vec<Bytes> = object.body.try_collect().await.unwrap();
+    assert_eq!(chunks.concat(), b"world");
+
+    assert_eq!(storage.stat("docs/b.txt").await.unwrap().size, 3);
+    assert_eq!(storage.stat("docs").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert!(!exists(storage.as_ref(), "missing").await.unwrap());
+
+    let top = storage.list("", false).await.unwrap();
+    let keys: Vec<&str> = top.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["a.txt"]);
+    assert_eq!(top.prefixes, [".trash/", "docs/"]);
+
+    let docs = storage.list("docs/", true).await.unwrap();
+    let keys: Vec<&str> = docs.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["docs/b.txt", "docs/deep/c.txt"]);
+
+    let joined = concat(storage.clone(), vec!["docs/b.txt".to_string(), "docs/deep/c.txt".to_string()]);
+    storage.put("joined.txt", joined).await.unwrap();
+    assert_eq!(read_all(storage.as_ref(), "joined.txt").await.unwrap(), "beesea");
+
+    storage.rename("a.txt", ".versions/1").await.unwrap();
+    assert!(!exists(storage.as_ref(), "a.txt").await.unwrap());
+    assert_eq!(read_all(storage.as_ref(), ".versions/1").await.unwrap(), "hello world");
+
+    // a move that mustn't replace anything leaves both objects alone
+    let err = storage.rename_new(".versions/1", "joined.txt").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(read_all(storage.as_ref(), "joined.txt").await.unwrap(), "beesea");
+    storage.rename_new(".versions/1", "restored/a.txt").await.unwrap();
+    assert!(!exists(storage.as_ref(), ".versions/1").await.unwrap());
+    assert_eq!(read_all(storage.as_ref(), "restored/a.txt").await.unwrap(), "hello world");
+
+    storage.delete("joined.txt").await.unwrap();
+    assert_eq!(storage.delete("joined.txt").await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    storage.delete_prefix("docs/").await.unwrap();
+    assert!(storage.list("docs/", true).await.unwrap().objects.is_empty());
+
+    for key in ["../escape", "/etc/passwd", "a/./b", "a\\b"] {
+        let err = put_bytes(storage.as_ref(), key, Bytes::from("x")).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn test_fs_storage() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    check_backend(Arc::new(FsStorage::new(temp_dir.path().to_path_buf()))).await;
+
+    // no temp files left behind by writes
+    let leftovers: Vec<_> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(".upload-"))
+        .collect();
+    assert!(leftovers.is_empty());
+}
+
+#[tokio::test]
+async fn test_memory_storage() {
+    check_backend(Arc::new(MemoryStorage::new())).await;
+}
+
+#[tokio::test]
+async fn test_chunked_upload_into_memory_storage() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(MemoryStorage::new());
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage.clone()));
+
+    let upload_id = "abc".to_string();
+    put_bytes(storage.as_ref(), ".chunks/abc/chunk_0", Bytes::from("hello ")).await.unwrap();
+    put_bytes(storage.as_ref(), ".chunks/abc/chunk_1", Bytes::from("world")).await.unwrap();
+    state.chunked_uploads.insert(upload_id.clone(), ChunkedUploadMetadata {
+        filename: "greeting.txt".to_string(),
+        total_size: 11,
+        chunk_size: 6,
+        total_chunks: 2,
+        received_chunks: [0, 1].into_iter().collect(),
+        ..Default::default()
+    });
+
+    let response = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id }))
+        .await
+        .unwrap();
+    assert_eq!(response.0.size, 11);
+    assert_eq!(read_all(storage.as_ref(), "greeting.txt").await.unwrap(), "hello world");
+    assert!(storage.list(".chunks/", true).await.unwrap().objects.is_empty());
+    // nothing was written to the directory except bookkeeping
+    assert!(!temp_dir.path().join("greeting.txt").exists());
+}
+
+#[tokio::test]
+async fn test_public_router_serves_from_storage() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(MemoryStorage::new());
+    put_bytes(storage.as_ref(), "notes.txt", Bytes::from("0123456789")).await.unwrap();
+    put_bytes(storage.as_ref(), "site/index.html", Bytes::from("<h1>hi</h1>")).await.unwrap();
+    put_bytes(storage.as_ref(), ".trash/secret", Bytes::from("nope")).await.unwrap();
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()).with_storage(storage));
+    let app = build_public_router(state);
+
+    let request = Request::builder().uri("/notes.txt").header("Range", "bytes=2-4").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 2-4/10");
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "234");
+
+    let request = Request::builder().uri("/notes.txt").header("Range", "bytes=20-").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let request = Request::builder().uri("/site/").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "<h1>hi</h1>");
+
+    for uri in ["/.trash/secret", "/missing.txt"] {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
@@ -0,0 +1,76 @@
+use juicebox_omega::handlers::{batch_delete_files, delete_file};
+use juicebox_omega::trash::{delete_trash_item, list_trash, purge_expired_trash, restore_trash_item};
+use juicebox_omega::state::AppState;
+use juicebox_omega::models::BatchDeleteRequest;
+use axum::extract::{State, Path};
+use axum::Json;
+use axum::http::StatusCode;
+use std::sync::Arc;
+
+fn setup(files: &[&str]) -> (tempfile::TempDir, Arc<AppState>) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for name in files {
+        std::fs::write(temp_dir.path().join(name), name.as_bytes()).unwrap();
+    }
+    let state = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    (temp_dir, state)
+}
+
+#[tokio::test]
+async fn test_delete_and_restore() {
+    let (temp_dir, state) = setup(&["report.pdf"]);
+    state.metadata.update("report.pdf", |m| { m.tags.insert("q3".to_string()); });
+    state.metadata.collections.entry("reports".to_string()).or_default().insert("report.pdf".to_string());
+
+    let response = delete_file(State(state.clone()), Path("report.pdf".to_string())).await.unwrap();
+    assert!(response.0.success);
+    assert!(!temp_dir.path().join("report.pdf").exists());
+
+    let trash = list_trash(State(state.clone())).await;
+    assert_eq!(trash.0.total, 1);
+    let item = &trash.0.items[0];
+    assert_eq!(item.original_name, "report.pdf");
+    assert_eq!(item.size, 10);
+
+    let response = restore_trash_item(State(state.clone()), Path(item.id.clone())).await.unwrap();
+    assert_eq!(response.0.filename, "report.pdf");
+    assert_eq!(std::fs::read(temp_dir.path().join("report.pdf")).unwrap(), b"report.pdf");
+
+    // tags and collections come back too
+    assert!(state.metadata.get("report.pdf").tags.contains("q3"));
+    assert_eq!(state.metadata.collections_of("report.pdf"), vec!["reports"]);
+    assert!(state.metadata.trash.is_empty());
+}
+
+#[tokio::test]
+async fn test_restore_conflict_and_purge() {
+    let (temp_dir, state) = setup(&["a.txt", "b.txt"]);
+
+    let payload = BatchDeleteRequest {
+        filenames: vec!["a.txt".to_string(), "b.txt".to_string()],
+    };
+    let response = batch_delete_files(State(state.clone()), Json(payload)).await;
+    assert_eq!(response.0.successful, 2);
+
+    let trash = list_trash(State(state.clone())).await;
+    let a_id = trash.0.items.iter().find(|i| i.original_name == "a.txt").unwrap().id.clone();
+    let b_id = trash.0.items.iter().find(|i| i.original_name == "b.txt").unwrap().id.clone();
+
+    // a new a.txt blocks the restore
+    std::fs::write(temp_dir.path().join("a.txt"), b"new").unwrap();
+    let result = restore_trash_item(State(state.clone()), Path(a_id.clone())).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::CONFLICT);
+
+    let response = delete_trash_item(State(state.clone()), Path(b_id.clone())).await.unwrap();
+    assert_eq!(response.0.filename, "b.txt");
+    assert!(!temp_dir.path().join(".trash").join(&b_id).exists());
+    let result = delete_trash_item(State(state.clone()), Path(b_id)).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::NOT_FOUND);
+
+    // nothing is old enough yet with a day of retention, everything is with none
+    assert_eq!(purge_expired_trash(&state, chrono::Duration::days(1)).await, 0);
+    assert_eq!(purge_expired_trash(&state, chrono::Duration::MAX).await, 0);
+    assert_eq!(purge_expired_trash(&state, chrono::Duration::zero()).await, 1);
+    assert!(!temp_dir.path().join(".trash").join(&a_id).exists());
+    assert!(state.metadata.trash.is_empty());
+}
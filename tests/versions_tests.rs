@@ -0,0 +1,168 @@
+use juicebox_omega::handlers::{complete_chunked_upload, upload_file};
+use juicebox_omega::versions::{
+    download_version, list_versions, prune_versions, restore_version, snapshot_version, VersioningSettings,
+};
+use juicebox_omega::state::{AppState, ChunkedUploadMetadata};
+use juicebox_omega::models::{ChunkedUploadComplete, PruneVersionsRequest};
+use axum::body::{to_bytes, Body};
+use axum::extract::{DefaultBodyLimit, State, Path};
+use axum::http::{header, Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+fn versioned_state(dir: &std::path::Path, max_versions: usize) -> Arc<AppState> {
+    let versioning = VersioningSettings {
+        directories: vec!["/".to_string()],
+        max_versions,
+    };
+    Arc::new(AppState::new(dir.to_path_buf()).with_versioning(versioning))
+}
+
+// upload content through the chunked path (single chunk)
+async fn upload(state: &Arc<AppState>, filename: &str, content: &[u8]) {
+    let upload_id = uuid::Uuid::new_v4().to_string();
+    let chunks_dir = state.files_dir.join(".chunks").join(&upload_id);
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("chunk_0"), content).unwrap();
+
+    state.chunked_uploads.insert(upload_id.clone(), ChunkedUploadMetadata {
+        filename: filename.to_string(),
+        total_size: content.len() as u64,
+        chunk_size: content.len(),
+        total_chunks: 1,
+        received_chunks: [0].into_iter().collect(),
+        ..Default::default()
+    });
+
+    let response = complete_chunked_upload(State(state.clone()), Json(ChunkedUploadComplete { upload_id })).await.unwrap();
+    assert!(response.0.success);
+}
+
+#[test]
+fn test_is_versioned() {
+    let settings = VersioningSettings { directories: vec!["/".to_string(), "releases".to_string()], max_versions: 0 };
+    assert!(settings.is_versioned("app.zip"));
+    assert!(settings.is_versioned("releases/app.zip"));
+    assert!(!settings.is_versioned("tmp/app.zip"));
+
+    assert!(!VersioningSettings::default().is_versioned("app.zip"));
+    let everything = VersioningSettings { directories: vec!["*".to_string()], max_versions: 0 };
+    assert!(everything.is_versioned("tmp/app.zip"));
+}
+
+#[tokio::test]
+async fn test_overwrite_keeps_versions() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = versioned_state(temp_dir.path(), 0);
+
+    upload(&state, "app.txt", b"v1").await;
+    upload(&state, "app.txt", b"v2").await;
+    upload(&state, "app.txt", b"v3").await;
+
+    let versions = list_versions(State(state.clone()), Path("app.txt".to_string())).await;
+    assert_eq!(versions.0.total, 2);
+    // newest first
+    let v2 = versions.0.versions[0].id.clone();
+    let v1 = versions.0.versions[1].id.clone();
+
+    let response = download_version(State(state.clone()), Path(("app.txt".to_string(), v1.clone()))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(&to_bytes(response.into_body(), usize::MAX).await.unwrap()[..], b"v1");
+
+    // restoring v1 keeps v3 around as a version
+    let response = restore_version(State(state.clone()), Path(("app.txt".to_string(), v1))).await.unwrap();
+    assert_eq!(response.0.filename, "app.txt");
+    assert_eq!(std::fs::read(temp_dir.path().join("app.txt")).unwrap(), b"v1");
+    let versions = list_versions(State(state.clone()), Path("app.txt".to_string())).await;
+    assert_eq!(versions.0.total, 2);
+
+    let payload = PruneVersionsRequest { keep: Some(1), older_than: None };
+    let response = prune_versions(State(state.clone()), Path("app.txt".to_string()), Json(payload)).await.unwrap();
+    assert_eq!(response.0.pruned, 1);
+    assert_eq!(response.0.remaining, 1);
+    assert!(!temp_dir.path().join(".versions").join(&v2).exists());
+}
+
+#[tokio::test]
+async fn test_max_versions_and_unversioned() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = versioned_state(temp_dir.path(), 2);
+
+    for content in [b"1", b"2", b"3", b"4"] {
+        upload(&state, "log.txt", content).await;
+    }
+    let versions = list_versions(State(state.clone()), Path("log.txt".to_string())).await;
+    assert_eq!(versions.0.total, 2);
+
+    // without versioning an overwrite leaves nothing behind
+    let plain = Arc::new(AppState::new(temp_dir.path().to_path_buf()));
+    upload(&plain, "plain.txt", b"a").await;
+    upload(&plain, "plain.txt", b"b").await;
+    let versions = list_versions(State(plain.clone()), Path("plain.txt".to_string())).await;
+    assert_eq!(versions.0.total, 0);
+
+    let result = download_version(State(plain), Path(("plain.txt".to_string(), "nope".to_string()))).await;
+    assert_eq!(result.err().unwrap().0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_snapshots_leave_the_file_in_place() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = versioned_state(temp_dir.path(), 0);
+    upload(&state, "app.txt", b"v1").await;
+
+    // the version is a copy, so a write failing after it still finds the file there
+    let version = snapshot_version(&state, "app.txt").await.unwrap().unwrap();
+    assert_eq!(std::fs::read(temp_dir.path().join("app.txt")).unwrap(), b"v1");
+    assert_eq!(std::fs::read(temp_dir.path().join(".versions").join(&version.id)).unwrap(), b"v1");
+
+    // ages too large for a date are refused, not a panic
+    let payload = PruneVersionsRequest { keep: None, older_than: Some(u64::MAX) };
+    let err = prune_versions(State(state.clone()), Path("app.txt".to_string()), Json(payload)).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    let payload = PruneVersionsRequest { keep: None, older_than: Some(i64::MAX as u64 / 1000) };
+    let response = prune_versions(State(state.clone()), Path("app.txt".to_string()), Json(payload)).await.unwrap();
+    assert_eq!((response.0.pruned, response.0.remaining), (0, 1));
+}
+
+#[tokio::test]
+async fn test_form_uploads_keep_one_version_per_overwrite() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = versioned_state(temp_dir.path(), 0);
+    let app = Router::new()
+        .route("/upload", post(upload_file))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
+    let form = |content: &str| {
+        let body = format!(
+            "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"résumé.txt\"\r\n\r\n{}\r\n--XBOUNDARY--\r\n",
+            content,
+        );
+        Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    for content in ["v1", "v2"] {
+        assert_eq!(app.clone().oneshot(form(content)).await.unwrap().status(), StatusCode::OK);
+    }
+
+    // each write is staged first, nothing of it is left behind
+    let versions = list_versions(State(state.clone()), Path("résumé.txt".to_string())).await;
+    assert_eq!(versions.0.total, 1);
+    assert_eq!(std::fs::read(temp_dir.path().join("résumé.txt")).unwrap(), b"v2");
+    assert_eq!(std::fs::read_dir(temp_dir.path().join(".chunks")).unwrap().count(), 0);
+
+    // the name in the download header survives being non-ascii
+    let id = versions.0.versions[0].id.clone();
+    let response = download_version(State(state.clone()), Path(("résumé.txt".to_string(), id))).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"r_sum_.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9%2Etxt"
+    );
+    assert_eq!(&to_bytes(response.into_body(), usize::MAX).await.unwrap()[..], b"v1");
+}
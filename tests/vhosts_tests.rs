@@ -0,0 +1,133 @@
+use juicebox_omega::config::{Config, VirtualHostConfig};
+use juicebox_omega::server::build_public_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::vhosts::{request_host, VirtualHost, VirtualHosts};
+use axum::body::{to_bytes, Body};
+use axum::http::{HeaderMap, Request, StatusCode};
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+fn vhost(name: &str, hosts: &[&str], root: Option<&std::path::Path>) -> VirtualHostConfig {
+    VirtualHostConfig {
+        name: name.to_string(),
+        hosts: hosts.iter().map(|h| h.to_string()).collect(),
+        root: root.map(|r| r.to_path_buf()),
+        bucket: None,
+        index_files: Vec::new(),
+        cache_control: None,
+        security_headers: true,
+        content_security_policy: None,
+        frame_options: None,
+        site: None,
+        spa_dirs: None,
+        error_pages: None,
+        listing_dirs: None,
+        sandbox: None,
+    }
+}
+
+async fn fetch(app: &axum::Router, uri: &str, host: &str) -> (StatusCode, HeaderMap, String) {
+    let request = Request::builder().uri(uri).header("Host", host).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, headers, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_hosts_serve_their_own_roots() {
+    let files = tempfile::tempdir().unwrap();
+    let cdn = tempfile::tempdir().unwrap();
+    let dl = tempfile::tempdir().unwrap();
+    std::fs::write(files.path().join("app.js"), "default").unwrap();
+    std::fs::write(cdn.path().join("app.js"), "cdn").unwrap();
+    std::fs::create_dir_all(dl.path().join("releases")).unwrap();
+    std::fs::write(dl.path().join("releases/index.html"), "html index").unwrap();
+    std::fs::write(dl.path().join("releases/index.txt"), "text index").unwrap();
+
+    let mut cdn_host = vhost("cdn", &["cdn.example.com"], Some(cdn.path()));
+    cdn_host.cache_control = Some("public, max-age=31536000, immutable".to_string());
+    cdn_host.frame_options = Some("SAMEORIGIN".to_string());
+    let mut dl_host = vhost("dl", &["dl.example.com"], Some(dl.path()));
+    dl_host.index_files = vec!["index.txt".to_string(), "index.html".to_string()];
+    dl_host.security_headers = false;
+
+    let vhosts = VirtualHosts {
+        hosts: vec![
+            VirtualHost::from_config(&Config::from_env(), &cdn_host),
+            VirtualHost::from_config(&Config::from_env(), &dl_host),
+        ],
+        fallback: None,
+    };
+    let app = build_public_router(Arc::new(AppState::new(files.path().to_path_buf()).with_vhosts(vhosts)));
+
+    let (status, headers, body) = fetch(&app, "/app.js", "cdn.example.com").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "cdn"));
+    assert_eq!(headers["cache-control"], "public, max-age=31536000, immutable");
+    assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+    assert!(headers.contains_key("content-security-policy"));
+
+    // not found responses aren't cached
+    let (status, headers, _) = fetch(&app, "/missing.js", "cdn.example.com").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!headers.contains_key("cache-control"));
+
+    // index files are tried in the configured order
+    let (status, headers, body) = fetch(&app, "/releases/", "dl.example.com").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "text index"));
+    assert!(!headers.contains_key("x-frame-options"));
+
+    // unknown hosts get the default bucket with the default headers
+    let (status, headers, body) = fetch(&app, "/app.js", "other.example.com").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "default"));
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert!(!headers.contains_key("cache-control"));
+}
+
+#[tokio::test]
+async fn test_fallback_host() {
+    let files = tempfile::tempdir().unwrap();
+    let site = tempfile::tempdir().unwrap();
+    std::fs::write(files.path().join("page.txt"), "default").unwrap();
+    std::fs::write(site.path().join("page.txt"), "site").unwrap();
+
+    let vhosts = VirtualHosts {
+        hosts: vec![VirtualHost::from_config(&Config::from_env(), &vhost("site", &["site.example.com"], Some(site.path())))],
+        fallback: Some("site".to_string()),
+    };
+    let app = build_public_router(Arc::new(AppState::new(files.path().to_path_buf()).with_vhosts(vhosts)));
+
+    assert_eq!(fetch(&app, "/page.txt", "unknown.example.com").await.2, "site");
+    assert_eq!(fetch(&app, "/page.txt", "site.example.com").await.2, "site");
+}
+
+#[tokio::test]
+async fn test_dir_hosts_follow_the_serving_settings() {
+    let files = tempfile::tempdir().unwrap();
+    let site = tempfile::tempdir().unwrap();
+    std::fs::write(site.path().join("page.html"), "<p>hi</p>").unwrap();
+    std::fs::write(site.path().join("notes.txt"), "notes").unwrap();
+
+    let mut config = Config::from_env();
+    config.attachment_types = vec!["html".to_string()];
+    let vhosts = VirtualHosts {
+        hosts: vec![VirtualHost::from_config(&config, &vhost("site", &["site.example.com"], Some(site.path())))],
+        fallback: None,
+    };
+    let app = build_public_router(Arc::new(AppState::new(files.path().to_path_buf()).with_vhosts(vhosts)));
+
+    let (status, headers, _) = fetch(&app, "/page.html", "site.example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers["content-disposition"].to_str().unwrap().starts_with("attachment"));
+    let (_, headers, _) = fetch(&app, "/notes.txt", "site.example.com").await;
+    assert!(!headers.get("content-disposition").is_some_and(|v| v.to_str().unwrap().starts_with("attachment")));
+}
+
+#[test]
+fn test_request_host() {
+    let host = |h: &str| request_host(&Request::builder().uri("/").header("Host", h).body(Body::empty()).unwrap());
+    assert_eq!(host("Example.COM:8080").as_deref(), Some("example.com"));
+    assert_eq!(host("example.com.").as_deref(), Some("example.com"));
+    assert_eq!(host("[::1]:80").as_deref(), Some("[::1]"));
+}
@@ -0,0 +1,342 @@
+use juicebox_omega::buckets::Bucket;
+use juicebox_omega::config::{ApiKeyConfig, BucketConfig, Config};
+use juicebox_omega::filetypes::UploadTypeRules;
+use juicebox_omega::quota::{Quota, QuotaSettings};
+use juicebox_omega::server::build_webdav_router;
+use juicebox_omega::state::AppState;
+use juicebox_omega::storage::read_all;
+use juicebox_omega::webdav::WebDav;
+use axum::body::{to_bytes, Body};
+use axum::extract::ConnectInfo;
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use axum::{Extension, Router};
+use base64::Engine;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::util::ServiceExt;
+
+struct Server {
+    app: Router,
+    state: Arc<AppState>,
+    photos: Arc<AppState>,
+    _dir: tempfile::TempDir,
+}
+
+// a default bucket "writer" may use (within `quota`) plus a "photos" bucket only the admin may use
+fn server(quota: Option<Quota>) -> Server {
+    server_with(quota, UploadTypeRules::default())
+}
+
+fn server_with(quota: Option<Quota>, upload_types: UploadTypeRules) -> Server {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = BucketConfig {
+        name: "photos".to_string(),
+        root: dir.path().join("photos"),
+        public: false,
+        hosts: Vec::new(),
+        quota: Default::default(),
+    };
+    std::fs::create_dir_all(&photos.root).unwrap();
+    let files_dir = dir.path().join("files");
+    std::fs::create_dir_all(&files_dir).unwrap();
+
+    let mut config = Config::from_env();
+    config.api_key_hash = Config::hash_api_key("admin-secret");
+    config.api_keys = vec![ApiKeyConfig {
+        name: "writer".to_string(),
+        key_hash: Config::hash_api_key("writer-secret"),
+        quota: quota.unwrap_or_default(),
+        buckets: vec!["default".to_string()],
+    }];
+
+    let state = Arc::new(
+        AppState::new(files_dir)
+            .with_quotas(QuotaSettings::from_config(&config))
+            .with_upload_types(upload_types)
+            .with_buckets(vec![Bucket::from_config(&config, &photos)]),
+    );
+    let photos = state.buckets["photos"].state.clone();
+    let app = build_webdav_router(Arc::new(WebDav::new(state.clone(), &config)), &config)
+        .layer(Extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)))));
+    Server { app, state, photos, _dir: dir }
+}
+
+fn basic(user: &str, password: &str) -> String {
+    let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+    format!("Basic {}", credentials)
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+fn dav(method: &str, uri: &str) -> axum::http::request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, basic("writer", "writer-secret"))
+}
+
+async fn body_text(response: Response) -> String {
+    String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_webdav_basic_auth_with_api_keys() {
+    let server = server(None);
+
+    // OPTIONS advertises class 2 without credentials, everything else needs them
+    let response = send(&server.app, Request::builder().method("OPTIONS").uri("/").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["dav"], "1, 2");
+    assert!(response.headers()[header::ALLOW].to_str().unwrap().contains("PROPFIND"));
+
+    let response = send(&server.app, Request::builder().method("PROPFIND").uri("/").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers()[header::WWW_AUTHENTICATE].to_str().unwrap().starts_with("Basic realm="));
+
+    for (user, password) in [("writer", "wrong"), ("admin", "writer-secret"), ("", "writer-secret")] {
+        let request = Request::builder()
+            .method("PROPFIND")
+            .uri("/")
+            .header("depth", "1")
+            .header(header::AUTHORIZATION, basic(user, password))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&server.app, request).await.status(), StatusCode::UNAUTHORIZED, "{}", user);
+    }
+
+    // the root lists only the buckets a key may use
+    let response = send(&server.app, dav("PROPFIND", "/").header("depth", "1").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let xml = body_text(response).await;
+    assert!(xml.contains("<D:href>/default/</D:href>"));
+    assert!(!xml.contains("/photos/"));
+    let response = send(&server.app, dav("PROPFIND", "/photos/").header("depth", "1").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let request = Request::builder()
+        .method("PROPFIND")
+        .uri("/")
+        .header("depth", "1")
+        .header(header::AUTHORIZATION, basic("admin", "admin-secret"))
+        .body(Body::empty())
+        .unwrap();
+    let xml = body_text(send(&server.app, request).await).await;
+    assert!(xml.contains("<D:href>/photos/</D:href>"));
+
+    // depth infinity isn't supported
+    let response = send(&server.app, dav("PROPFIND", "/default/").header("depth", "infinity").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(body_text(response).await.contains("propfind-finite-depth"));
+}
+
+#[tokio::test]
+async fn test_webdav_collections_and_files() {
+    let server = server(None);
+    let app = &server.app;
+
+    // folders need their parent to exist
+    assert_eq!(send(app, dav("MKCOL", "/default/docs/2024").body(Body::empty()).unwrap()).await.status(), StatusCode::CONFLICT);
+    assert_eq!(send(app, dav("MKCOL", "/default/docs").body(Body::empty()).unwrap()).await.status(), StatusCode::CREATED);
+    assert_eq!(send(app, dav("MKCOL", "/default/docs").body(Body::empty()).unwrap()).await.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    // uploads get stored, owned and listed
+    let put = |body: &'static str| dav("PUT", "/default/docs/my%20notes.txt").body(Body::from(body)).unwrap();
+    assert_eq!(send(app, put("first draft")).await.status(), StatusCode::CREATED);
+    assert_eq!(send(app, put("second draft")).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(read_all(server.state.storage.as_ref(), "docs/my notes.txt").await.unwrap(), "second draft".as_bytes());
+    assert_eq!(server.state.metadata.get("docs/my notes.txt").owner.as_deref(), Some("writer"));
+    assert_eq!(send(app, dav("PUT", "/default/missing/file.txt").body(Body::from("x")).unwrap()).await.status(), StatusCode::CONFLICT);
+
+    let body = r#"<?xml version="1.0"?><D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/><D:resourcetype/><Z:Win32FileAttributes xmlns:Z="urn:schemas-microsoft-com:"/></D:prop></D:propfind>"#;
+    let response = send(app, dav("PROPFIND", "/default/docs/").header("depth", "1").body(Body::from(body)).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let xml = body_text(response).await;
+    assert!(xml.contains("<D:href>/default/docs/</D:href>"));
+    assert!(xml.contains("<D:href>/default/docs/my%20notes.txt</D:href>"));
+    assert!(xml.contains("<D:getcontentlength>12</D:getcontentlength>"));
+    assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(xml.contains("Win32FileAttributes"));
+    assert!(xml.contains("HTTP/1.1 404 Not Found"));
+
+    let response = send(app, dav("GET", "/default/docs/my%20notes.txt").header(header::RANGE, "bytes=0-5").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body_text(response).await, "second");
+
+    // hidden files can't be reached or created
+    assert_eq!(send(app, dav("GET", "/default/.meta/metadata.json").body(Body::empty()).unwrap()).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(send(app, dav("PUT", "/default/docs/.htaccess").body(Body::from("x")).unwrap()).await.status(), StatusCode::FORBIDDEN);
+
+    // deleting a folder trashes what's in it
+    assert_eq!(send(app, dav("DELETE", "/default/docs").body(Body::empty()).unwrap()).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(send(app, dav("PROPFIND", "/default/docs").header("depth", "0").body(Body::empty()).unwrap()).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(server.state.metadata.trash.len(), 1);
+}
+
+#[tokio::test]
+async fn test_webdav_copy_and_move() {
+    let server = server(None);
+    let app = &server.app;
+    let admin = basic("admin", "admin-secret");
+
+    let put = |uri: &str, body: &'static str| {
+        Request::builder().method("PUT").uri(uri).header(header::AUTHORIZATION, &admin).body(Body::from(body)).unwrap()
+    };
+    assert_eq!(send(app, put("/default/a.txt", "alpha")).await.status(), StatusCode::CREATED);
+    assert_eq!(send(app, put("/default/b.txt", "bravo")).await.status(), StatusCode::CREATED);
+
+    let request = |method: &str, from: &str, to: &str, overwrite: &str| {
+        Request::builder()
+            .method(method)
+            .uri(from)
+            .header(header::AUTHORIZATION, &admin)
+            .header("destination", format!("http://localhost{}", to))
+            .header("overwrite", overwrite)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // Overwrite: F keeps existing files
+    assert_eq!(send(app, request("COPY", "/default/a.txt", "/default/b.txt", "F")).await.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(send(app, request("COPY", "/default/a.txt", "/default/b.txt", "T")).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(read_all(server.state.storage.as_ref(), "b.txt").await.unwrap(), "alpha".as_bytes());
+
+    // moves keep the file's settings, across buckets too
+    server.state.metadata.update("a.txt", |m| m.max_downloads = Some(3));
+    assert_eq!(send(app, request("MOVE", "/default/a.txt", "/default/c.txt", "T")).await.status(), StatusCode::CREATED);
+    assert!(server.state.storage.stat("a.txt").await.is_err());
+    assert_eq!(server.state.metadata.get("c.txt").max_downloads, Some(3));
+
+    assert_eq!(send(app, request("MOVE", "/default/c.txt", "/photos/c.txt", "T")).await.status(), StatusCode::CREATED);
+    assert!(server.state.storage.stat("c.txt").await.is_err());
+    assert_eq!(read_all(server.photos.storage.as_ref(), "c.txt").await.unwrap(), "alpha".as_bytes());
+    assert_eq!(server.photos.metadata.get("c.txt").max_downloads, Some(3));
+
+    // folders move with everything in them, but not into themselves
+    let mkcol = Request::builder().method("MKCOL").uri("/default/dir").header(header::AUTHORIZATION, &admin).body(Body::empty()).unwrap();
+    assert_eq!(send(app, mkcol).await.status(), StatusCode::CREATED);
+    assert_eq!(send(app, put("/default/dir/inner.txt", "inner")).await.status(), StatusCode::CREATED);
+    assert_eq!(send(app, request("MOVE", "/default/dir/", "/default/dir/sub/", "T")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send(app, request("MOVE", "/default/dir/", "/default/renamed/", "T")).await.status(), StatusCode::CREATED);
+    assert_eq!(read_all(server.state.storage.as_ref(), "renamed/inner.txt").await.unwrap(), "inner".as_bytes());
+    assert!(!server._dir.path().join("files/dir").exists());
+}
+
+#[tokio::test]
+async fn test_webdav_locks_and_quota() {
+    let server = server(Some(Quota { max_bytes: Some(10), max_files: None }));
+    let app = &server.app;
+
+    // locking a new name creates an empty file
+    let lockinfo = r#"<?xml version="1.0" encoding="utf-8"?><D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype><D:owner><D:href>writer</D:href></D:owner></D:lockinfo>"#;
+    let response = send(app, dav("LOCK", "/default/report.txt").header("timeout", "Second-600").body(Body::from(lockinfo)).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = response.headers()["lock-token"].to_str().unwrap().to_string();
+    assert!(token.starts_with("<opaquelocktoken:"));
+    assert!(body_text(response).await.contains("<D:timeout>Second-"));
+    assert_eq!(server.state.storage.stat("report.txt").await.unwrap().size, 0);
+
+    // writes without the token, and a second lock, are refused
+    assert_eq!(send(app, dav("PUT", "/default/report.txt").body(Body::from("v1")).unwrap()).await.status(), StatusCode::LOCKED);
+    assert_eq!(send(app, dav("LOCK", "/default/report.txt").body(Body::from(lockinfo)).unwrap()).await.status(), StatusCode::LOCKED);
+    let response = send(app, dav("PUT", "/default/report.txt").header("if", format!("({})", token)).body(Body::from("v1")).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // an empty LOCK refreshes, PROPFIND shows the lock
+    let response = send(app, dav("LOCK", "/default/report.txt").header("if", format!("({})", token)).body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let xml = body_text(send(app, dav("PROPFIND", "/default/report.txt").header("depth", "0").body(Body::empty()).unwrap()).await).await;
+    assert!(xml.contains(&token[1..token.len() - 1]));
+
+    assert_eq!(send(app, dav("UNLOCK", "/default/report.txt").header("lock-token", "<opaquelocktoken:nope>").body(Body::empty()).unwrap()).await.status(), StatusCode::CONFLICT);
+    assert_eq!(send(app, dav("UNLOCK", "/default/report.txt").header("lock-token", &token).body(Body::empty()).unwrap()).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(send(app, dav("DELETE", "/default/report.txt").body(Body::empty()).unwrap()).await.status(), StatusCode::NO_CONTENT);
+
+    // quotas apply to the key that logs in
+    let response = send(app, dav("PUT", "/default/big.bin").body(Body::from("more than ten bytes")).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+    assert!(server.state.storage.stat("big.bin").await.is_err());
+
+    // uploads must say how big they are
+    let request = dav("PUT", "/default/stream.bin").body(Body::from_stream(futures_util::stream::iter(vec![
+        Ok::<_, std::io::Error>(axum::body::Bytes::from("abc")),
+    ]))).unwrap();
+    assert_eq!(send(app, request).await.status(), StatusCode::LENGTH_REQUIRED);
+}
+
+#[tokio::test]
+async fn test_webdav_recursive_changes_need_every_lock() {
+    let server = server(None);
+    let app = &server.app;
+    let admin = |method: &str, uri: &str| {
+        Request::builder().method(method).uri(uri).header(header::AUTHORIZATION, basic("admin", "admin-secret"))
+    };
+    assert_eq!(send(app, dav("MKCOL", "/default/dir").body(Body::empty()).unwrap()).await.status(), StatusCode::CREATED);
+    for name in ["a.txt", "b.txt"] {
+        let response = send(app, dav("PUT", &format!("/default/dir/{}", name)).body(Body::from(name)).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // each key locks one of the siblings
+    let lockinfo = r#"<?xml version="1.0" encoding="utf-8"?><D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+    let lock = |request: axum::http::request::Builder| async move {
+        let response = send(app, request.body(Body::from(lockinfo)).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers()["lock-token"].to_str().unwrap().to_string()
+    };
+    let mine = lock(dav("LOCK", "/default/dir/a.txt")).await;
+    let theirs = lock(admin("LOCK", "/default/dir/b.txt")).await;
+
+    // the writer's own token doesn't cover the admin's lock, nor does knowing the admin's token
+    let delete = |tokens: String| dav("DELETE", "/default/dir/").header("if", tokens).body(Body::empty()).unwrap();
+    assert_eq!(send(app, delete(format!("({})", mine))).await.status(), StatusCode::LOCKED);
+    assert_eq!(send(app, delete(format!("({}) ({})", mine, theirs))).await.status(), StatusCode::LOCKED);
+    let moved = dav("MOVE", "/default/dir/").header("destination", "/default/moved/").header("if", format!("({})", mine));
+    assert_eq!(send(app, moved.body(Body::empty()).unwrap()).await.status(), StatusCode::LOCKED);
+    assert_eq!(read_all(server.state.storage.as_ref(), "dir/b.txt").await.unwrap(), "b.txt");
+
+    let unlock = admin("UNLOCK", "/default/dir/b.txt").header("lock-token", &theirs);
+    assert_eq!(send(app, unlock.body(Body::empty()).unwrap()).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(send(app, delete(format!("({})", mine))).await.status(), StatusCode::NO_CONTENT);
+    assert!(server.state.storage.stat("dir/b.txt").await.is_err());
+}
+
+#[tokio::test]
+async fn test_webdav_uploads_check_types() {
+    let rules = UploadTypeRules::new(&[], &["exe".to_string()], &[], &["text/html".to_string()]);
+    let server = server_with(None, rules);
+    let files_dir = server.state.files_dir.clone();
+
+    let response = send(&server.app, dav("PUT", "/default/notes.txt").body(Body::from("just text")).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(server.state.metadata.get("notes.txt").detected_type.as_deref(), Some("text/plain"));
+
+    let response = send(&server.app, dav("PUT", "/default/setup.exe").body(Body::from("just text")).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let html = "<!DOCTYPE html><html><script>alert(1)</script>";
+    let response = send(&server.app, dav("PUT", "/default/notes.txt").body(Body::from(html)).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(std::fs::read_to_string(files_dir.join("notes.txt")).unwrap(), "just text");
+
+    // renaming doesn't get around the extension rules either
+    let moved = dav("MOVE", "/default/notes.txt").header("destination", "http://localhost/default/setup.exe").body(Body::empty()).unwrap();
+    assert_eq!(send(&server.app, moved).await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(files_dir.join("notes.txt").exists() && !files_dir.join("setup.exe").exists());
+    assert!(server.state.storage.list(".chunks/", true).await.unwrap().objects.is_empty());
+}
+
+#[tokio::test]
+async fn test_webdav_is_rate_limited() {
+    let server = server(None);
+
+    // a browsing burst goes through, a flood doesn't
+    let mut statuses = Vec::new();
+    for _ in 0..250 {
+        let request = dav("PROPFIND", "/default/").header("depth", "1").body(Body::empty()).unwrap();
+        statuses.push(send(&server.app, request).await.status());
+    }
+    assert!(statuses[..100].iter().all(|s| *s == StatusCode::MULTI_STATUS));
+    assert!(statuses.contains(&StatusCode::TOO_MANY_REQUESTS));
+}